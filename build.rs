@@ -0,0 +1,47 @@
+// build.rs：把构建元数据（Git 提交、构建时间、rustc 版本）写入编译期环境变量，供 /info 展示；
+// 并从 proto/cyber_lookup.proto 生成 gRPC 服务端代码
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 执行命令并取第一行输出，失败（如不在 Git 仓库中、交叉编译环境没有 git）时返回 None
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let text = String::from_utf8(output.stdout).ok()?;
+    text.lines().next().map(|line| line.trim().to_string()).filter(|line| !line.is_empty())
+}
+
+fn main() {
+    let commit = command_output("git", &["rev-parse", "--short=12", "HEAD"]).unwrap_or_else(|| "unknown".to_string());
+    let dirty = command_output("git", &["status", "--porcelain", "--untracked-files=no"]).is_some();
+    let commit = if dirty && commit != "unknown" { format!("{}-dirty", commit) } else { commit };
+
+    // 可复现构建：优先使用 SOURCE_DATE_EPOCH
+    let build_time = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0));
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = command_output(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=CYBER_LOOKUP_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=CYBER_LOOKUP_BUILD_EPOCH={}", build_time);
+    println!("cargo:rustc-env=CYBER_LOOKUP_RUSTC_VERSION={}", rustc_version);
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    // 使用自带的 protoc；已设置 PROTOC 时（如发行版打包）尊重用户的选择
+    if std::env::var_os("PROTOC").is_none() {
+        if let Ok(protoc) = protoc_bin_vendored::protoc_bin_path() {
+            std::env::set_var("PROTOC", protoc);
+        }
+    }
+    tonic_build::configure()
+        .build_client(false)
+        .compile_protos(&["proto/cyber_lookup.proto"], &["proto"])
+        .expect("failed to compile proto/cyber_lookup.proto");
+}
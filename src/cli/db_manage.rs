@@ -0,0 +1,1328 @@
+//! 交互式数据库管理 (db-manage)：记录浏览、只读 SQL 控制台、撤销、事务会话、脚本执行与配置编辑。
+
+use crate::*;
+
+// --- 记录浏览 (db-manage 'list') ---
+
+/// 'list' 的排序方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ListOrder {
+    Rowid,
+    Uid,
+}
+
+impl ListOrder {
+    fn column(self) -> &'static str {
+        match self {
+            ListOrder::Rowid => "rowid",
+            ListOrder::Uid => "uid",
+        }
+    }
+}
+
+/// 解析 `list [页码] [每页条数] [uid|rowid]`，页码从 1 开始。
+fn parse_list_args(arg: &str) -> Result<(u32, u32, ListOrder), String> {
+    let mut numbers = Vec::new();
+    let mut order = ListOrder::Rowid;
+    for token in arg.split_whitespace() {
+        match token.to_lowercase().as_str() {
+            "uid" => order = ListOrder::Uid,
+            "rowid" => order = ListOrder::Rowid,
+            _ => match token.parse::<u32>() {
+                Ok(n) if n > 0 => numbers.push(n),
+                _ => return Err(tr!("参数 '{}' 无效，应为正整数或 uid/rowid。", "Invalid argument '{}': expected a positive integer or uid/rowid.", token)),
+            },
+        }
+    }
+    if numbers.len() > 2 {
+        return Err(tr!("用法: list [页码] [每页条数] [uid|rowid]", "Usage: list [page] [page_size] [uid|rowid]").to_string());
+    }
+    let page = numbers.first().copied().unwrap_or(1);
+    let page_size = numbers.get(1).copied().unwrap_or(DEFAULT_LIST_PAGE_SIZE).min(MAX_LIST_PAGE_SIZE);
+    Ok((page, page_size, order))
+}
+
+/// (rowid, uid, phone_number)
+type MappingRow = (i64, String, String);
+
+/// 按页读取映射记录，返回 (总条数, 当前页记录)。
+fn list_mappings(conn: &Connection, order: ListOrder, page: u32, page_size: u32) -> SqlResult<(i64, Vec<MappingRow>)> {
+    let total: i64 = conn.query_row("SELECT COUNT(*) FROM user_mapping", [], |row| row.get(0))?;
+    let sql = format!("SELECT rowid, uid, phone_dec(phone_number) FROM user_mapping ORDER BY {} LIMIT ?1 OFFSET ?2", order.column());
+    let offset = (page as i64 - 1) * page_size as i64;
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map([page_size as i64, offset], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<SqlResult<Vec<_>>>()?;
+    Ok((total, rows))
+}
+
+/// 终端显示宽度：中日韩等全角字符按 2 列计算。
+fn display_width(text: &str) -> usize {
+    text.chars().map(|c| if c >= '\u{2E80}' { 2 } else { 1 }).sum()
+}
+
+/// 以对齐的文本表格打印结果。
+pub(crate) fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = headers.iter().map(|h| display_width(h)).collect();
+    for row in rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(display_width(cell));
+        }
+    }
+    let line = |cells: Vec<String>| {
+        cells.iter().zip(&widths)
+            .map(|(c, w)| format!("{}{}", c, " ".repeat(w - display_width(c))))
+            .collect::<Vec<_>>()
+            .join(" | ")
+            .trim_end()
+            .to_string()
+    };
+    println!("{}", line(headers.iter().map(|h| h.to_string()).collect()).bold());
+    println!("{}", widths.iter().map(|w| "-".repeat(*w)).collect::<Vec<_>>().join("-+-"));
+    for row in rows {
+        println!("{}", line(row.clone()));
+    }
+}
+
+// --- 只读 SQL 控制台 (db-manage 'sql') ---
+
+/// 'sql' 命令的查询结果，单元格均已转成文本
+struct QueryResult {
+    columns: Vec<String>,
+    rows: Vec<Vec<String>>,
+    truncated: bool, // 超过 MAX_SQL_ROWS 行
+}
+
+/// 执行一条只读查询。先按首个关键字拦截非 SELECT 语句，再由 SQLite 判定语句是否只读，
+/// 避免 `WITH ... DELETE` 之类的写法绕过检查。
+fn run_readonly_query(conn: &Connection, sql: &str) -> Result<QueryResult, String> {
+    let keyword: String = sql.trim_start().chars().take_while(|c| c.is_ascii_alphabetic()).collect::<String>().to_lowercase();
+    if keyword != "select" && keyword != "with" {
+        return Err(tr!("只允许 SELECT 查询。", "Only SELECT queries are allowed.").to_string());
+    }
+    // 由 SQLite 切分语句，只计数不执行：字符串字面量中的 ';' 与末尾多余的 ';' 不算作另一条语句
+    if rusqlite::Batch::new(conn, sql).count() > 1 {
+        return Err(tr!("一次只能执行一条语句。", "Only a single statement is allowed.").to_string());
+    }
+    let mut stmt = conn.prepare(sql).map_err(|e| e.to_string())?;
+    if !stmt.readonly() {
+        return Err(tr!("只允许只读查询。", "Only read-only queries are allowed.").to_string());
+    }
+    let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+    let mut rows = stmt.query([]).map_err(|e| e.to_string())?;
+    let mut out = Vec::new();
+    while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        if out.len() == MAX_SQL_ROWS {
+            return Ok(QueryResult { columns, rows: out, truncated: true });
+        }
+        let mut cells = Vec::with_capacity(columns.len());
+        for i in 0..columns.len() {
+            let cell = match row.get_ref(i).map_err(|e| e.to_string())? {
+                rusqlite::types::ValueRef::Null => "NULL".to_string(),
+                rusqlite::types::ValueRef::Integer(v) => v.to_string(),
+                rusqlite::types::ValueRef::Real(v) => v.to_string(),
+                rusqlite::types::ValueRef::Text(v) => String::from_utf8_lossy(v).into_owned(),
+                rusqlite::types::ValueRef::Blob(v) => format!("<blob {} bytes>", v.len()),
+            };
+            cells.push(cell);
+        }
+        out.push(cells);
+    }
+    Ok(QueryResult { columns, rows: out, truncated: false })
+}
+
+// --- 交互式数据库管理 (高交互性 & 防御性增强) ---
+pub(crate) fn run_db_management(state: Arc<AppState>) {
+    println!("{}", tr!("\n--- 交互式数据库管理模式 ---", "\n--- Interactive database management ---").magenta().bold());
+    println!("{}", tr!("命令: 'insert [upsert|insert]' (增，insert 为严格插入), 'lookup' (查), 'delete' (删), 'count' (查总数), 'list [页码] [条数] [uid|rowid]' (分页浏览), 'update [ID]' (修改记录), 'delete-file <文件> [--dry-run]' (批量删除), 'sql <SELECT>' (只读查询), 'stats' (库统计), 'seed <条数>' (生成测试数据), 'import <文件/URL> [--format csv|ndjson|xlsx] [--on-conflict skip|replace|error] [--dry-run]' / 'export <文件> [--format csv|ndjson|xlsx|sql] [--uid-prefix 前缀] [--gzip]' (导入/导出), 'clear' (清空), 'backup' (立即备份), 'restore <文件>' (从备份恢复), 'vacuum' (压缩), 'analyze' (更新统计), 'integrity' (完整性检查), 'check [明细文件]' (数据质量检查), 'merge [--apply]' (合并重复记录), 'key-usage [密钥名]' (API 密钥用量), 'cache-clear' (清空缓存), 'output json|text' (输出模式), 'run <脚本> [--continue]' (执行脚本), 'begin' / 'commit' / 'rollback' (事务会话), 'undo' (撤销上一次删除/清空/覆盖), 'back' (返回)", "Commands: 'insert [upsert|insert]' (insert = strict, rejects existing keys), 'lookup', 'delete', 'count', 'list [page] [size] [uid|rowid]' (browse), 'update [id]' (edit a record), 'delete-file <file> [--dry-run]' (bulk delete), 'sql <SELECT>' (read-only query), 'stats' (DB statistics), 'seed <count>' (generate test data), 'import <file/url> [--format csv|ndjson|xlsx] [--on-conflict skip|replace|error] [--dry-run]' / 'export <file> [--format csv|ndjson|xlsx|sql] [--uid-prefix PREFIX] [--gzip]', 'clear', 'backup' (back up now), 'restore <file>' (restore from backup), 'vacuum' (compact), 'analyze' (update statistics), 'integrity' (integrity check), 'check [detail-file]' (data quality check), 'merge [--apply]' (merge duplicates), 'key-usage [key]' (API key usage), 'cache-clear' (flush cache), 'output json|text' (output mode), 'run <script> [--continue]' (run script), 'begin' / 'commit' / 'rollback' (transaction session), 'undo' (revert the last delete/clear/overwrite), 'back'").cyan());
+
+    let config = state.current_config();
+    if !config.single_sqlite_file() {
+        let backend = state.store().map(|store| store.name()).unwrap_or("unavailable");
+        println!("{} {}", "WARN".yellow(), tr!("当前存储后端为 {}，除 export 外的命令操作的是 SQLite 文件 {}。", "The storage backend is {}; commands other than export operate on the SQLite file {}.", backend, config.db_path));
+    }
+
+    // 第一次连接尝试
+    if let Err(e) = state.get_db_connection() {
+        eprintln!("{} {}", "DB ERR".red(), tr!("无法连接数据库: {}", "Cannot connect to the database: {}", e));
+        return;
+    }
+
+    loop {
+        let scope = if in_db_session() { "DB, TX" } else { "DB" };
+        match read_command(&format!("{} ({}) > ", "MANAGE".magenta(), scope), DB_COMMANDS) {
+            Ok(line) => {
+                match execute_db_command(&state, &line) {
+                    CommandOutcome::Back | CommandOutcome::Aborted => break,
+                    CommandOutcome::Done | CommandOutcome::Failed => {}
+                }
+            }
+            // 输入结束（Ctrl+D 或管道关闭）时安静地返回
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => {
+                eprintln!("{} {}", "FATAL".red(), tr!("I/O 读取失败，退出管理模式: {}", "I/O read failed, leaving management mode: {}", e));
+                break;
+            }
+        }
+    }
+    abandon_db_session();
+    println!("{}", tr!("返回主管理菜单...", "Returning to the main menu...").magenta());
+}
+
+/// 拆分命令行：命令词不区分大小写，参数（如文件路径）保持原样。
+pub(crate) fn split_command(line: &str) -> (String, String) {
+    match line.split_once(char::is_whitespace) {
+        Some((cmd, rest)) => (cmd.to_lowercase(), rest.trim().to_string()),
+        None => (line.to_lowercase(), String::new()),
+    }
+}
+
+/// db-manage 单条命令的执行结果，脚本模式据此决定是否继续。
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CommandOutcome {
+    Done,
+    Failed,
+    Back,     // 'back' / 'exit'：离开 db-manage
+    Aborted,  // 数据库连接中断，无法继续
+}
+
+/// 执行一条 db-manage 命令。需要额外输入的命令（如 insert）通过 `read_line` 继续读取。
+fn execute_db_command(state: &Arc<AppState>, line: &str) -> CommandOutcome {
+    let (command, arg) = split_command(line);
+
+    if command.is_empty() { return CommandOutcome::Done; }
+    if command == "back" || command == "exit" { return CommandOutcome::Back; }
+    if matches!(command.as_str(), "begin" | "commit" | "rollback") {
+        return db_session_command(state, &command);
+    }
+    if in_db_session() && SESSION_EXCLUDED_COMMANDS.contains(&command.as_str()) {
+        println!("{} {}", "WARN".yellow(), tr!("{} 不能在事务会话中执行，请先 commit 或 rollback。", "{} cannot run inside a transaction session; commit or roll back first.", command));
+        return CommandOutcome::Failed;
+    }
+
+    // 核心：在每次 DB 操作前都重新获取连接（会话中使用会话连接），并检查是否成功
+    let conn = match session_or_new_connection(state) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{} {}", "DB ERR".red(), tr!("数据库连接中断，退出管理模式: {}", "Database connection lost, leaving management mode: {}", e));
+            return CommandOutcome::Aborted;
+        }
+    };
+    let retry = state.current_config().db_retry;
+
+    match command.as_str() {
+        "insert" => {
+            // `insert insert` 为严格插入：uid 或手机号已存在时拒绝；默认 (upsert) 覆盖已有记录
+            let mode = match arg.to_lowercase().as_str() {
+                "" | "upsert" => WriteMode::Upsert,
+                "insert" => WriteMode::Insert,
+                _ => {
+                    println!("{}", tr!("用法: insert [upsert|insert]", "Usage: insert [upsert|insert]").red());
+                    return CommandOutcome::Failed;
+                }
+            };
+            let uid = match read_line(tr!("请输入 UID: ", "Enter UID: ")) {
+                Ok(s) if !s.is_empty() => s,
+                _ => { println!("{}", tr!("UID不能为空。", "UID must not be empty.").red()); return CommandOutcome::Failed; },
+            };
+            let phone = match read_line(tr!("请输入 Phone Number: ", "Enter phone number: ")) {
+                Ok(s) if !s.is_empty() => s,
+                _ => { println!("{}", tr!("手机号不能为空。", "Phone number must not be empty.").red()); return CommandOutcome::Failed; },
+            };
+
+            // 防御性：检查数据长度
+            if uid.len() > MAX_DATA_LENGTH || phone.len() > MAX_DATA_LENGTH {
+                eprintln!("{} {}", "DB ERR".red(), tr!("输入数据过长，请保持在 {} 字符以内。", "Input too long, keep it within {} characters.", MAX_DATA_LENGTH));
+                return CommandOutcome::Failed;
+            }
+            let phone = match state.current_config().phone_rules.normalize_for_write(&phone) {
+                Ok(phone) => phone,
+                Err(e) => { eprintln!("{} {}", "DB ERR".red(), e); return CommandOutcome::Failed; }
+            };
+
+            if mode == WriteMode::Insert {
+                let existing = with_retry(&retry, || {
+                    let mut stmt = conn.prepare("SELECT uid, phone_dec(phone_number) FROM user_mapping WHERE uid = ?1 OR phone_number = phone_enc(?2)")?;
+                    let rows = stmt.query_map([&uid, &phone], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+                    rows.collect::<SqlResult<Vec<_>>>()
+                });
+                match existing {
+                    Ok(rows) if !rows.is_empty() => {
+                        eprintln!("{} {}", "CONFLICT".red(), tr!("严格插入被拒绝，已存在的记录:", "Strict insert rejected; existing records:"));
+                        for (existing_uid, existing_phone) in rows {
+                            eprintln!("  UID={}, Phone={}", existing_uid, existing_phone);
+                        }
+                        return CommandOutcome::Failed;
+                    }
+                    Ok(_) => {}
+                    Err(e) => { report_db_error(tr!("插入失败", "Insert failed").to_string(), &e); return CommandOutcome::Failed; }
+                }
+            }
+            // 覆盖式写入会替换已有记录，保存其前像供 undo
+            let before = match mode {
+                WriteMode::Upsert => snapshot_for_undo(&conn, Some(&[uid.clone(), phone.clone()])),
+                WriteMode::Insert => None,
+            };
+            let keys = affected_keys(&conn, &[&uid, &phone]);
+            let sql = match mode {
+                WriteMode::Upsert => "INSERT OR REPLACE INTO user_mapping (uid, phone_number) VALUES (?1, phone_enc(?2))",
+                WriteMode::Insert => "INSERT INTO user_mapping (uid, phone_number) VALUES (?1, phone_enc(?2))",
+            };
+            let result = with_retry(&retry, || conn.execute(sql, [&uid, &phone]));
+            state.invalidate_cache(keys.ok().flatten());
+            if result.is_ok() {
+                state.bloom_insert(&[&uid, &phone]);
+            }
+
+            if let (Ok(_), Some(before)) = (&result, before) {
+                if !before.is_empty() {
+                    remember_undo(format!("insert {}", uid), before, vec![(uid.clone(), phone.clone())]);
+                }
+            }
+            match result {
+                Ok(_) => println!("{} {}", "OK".green(), tr!("插入/更新成功：UID={}, Phone={}", "Inserted/updated: UID={}, Phone={}", uid, phone)),
+                Err(e) => { eprintln!("{} {}", "DB ERR".red(), tr!("插入失败: {}", "Insert failed: {}", e)); return CommandOutcome::Failed; }
+            }
+        },
+        "lookup" => {
+            // 支持 `lookup <ID>` 直接带参数，便于脚本调用
+            let id = if !arg.is_empty() { arg } else { match read_line(tr!("请输入要查找的 UID 或 Phone Number: ", "Enter the UID or phone number to look up: ")) {
+                Ok(s) if !s.is_empty() => s,
+                _ => return CommandOutcome::Failed,
+            } };
+
+            match with_retry(&retry, || lookup_one(&conn, &id)) {
+                Ok(resp) if json_output() => {
+                    print_json(serde_json::json!({ "id": id, "status": resp.status, "uid": resp.uid, "phone_number": resp.phone_number }));
+                },
+                Ok(resp) => {
+                    match resp.status.as_str() {
+                        "not_found" => println!("{} {}", "NOT FOUND".yellow(), tr!("未找到 ID: {}", "ID not found: {}", id)),
+                        _ => println!("{} {}", "FOUND".green(), tr!("找到匹配: UID={}, Phone={}", "Match found: UID={}, Phone={}", resp.uid.unwrap_or_default(), resp.phone_number.unwrap_or_default())),
+                    }
+                },
+                Err(e) => { report_db_error(tr!("查找失败", "Lookup failed").to_string(), &e); return CommandOutcome::Failed; }
+            }
+        },
+        "delete" => {
+            let id = match read_line(tr!("请输入要删除的 UID 或 Phone Number: ", "Enter the UID or phone number to delete: ")) {
+                Ok(s) if !s.is_empty() => s,
+                _ => return CommandOutcome::Failed,
+            };
+
+            // 防御性：确认删除
+            let confirm = match read_line(&format!("{} {}", "WARN".yellow(), tr!("警告: 确认删除 ID '{}'? (yes/no): ", "Warning: really delete ID '{}'? (yes/no): ", id))) {
+                Ok(s) => s.to_lowercase(),
+                _ => return CommandOutcome::Failed,
+            };
+
+            if confirm == "yes" {
+                let before = snapshot_for_undo(&conn, Some(std::slice::from_ref(&id)));
+                let keys = affected_keys(&conn, &[&id]);
+                let result = with_retry(&retry, || conn.execute(
+                    "DELETE FROM user_mapping WHERE uid = ?1 OR phone_number = phone_enc(?1)",
+                    [&id],
+                ));
+                state.invalidate_cache(keys.ok().flatten());
+                if let (Ok(count), Some(before)) = (&result, before) {
+                    if *count > 0 {
+                        remember_undo(format!("delete {}", id), before, Vec::new());
+                    }
+                }
+
+                match result {
+                    Ok(count) => println!("{} {}", "OK".green(), tr!("成功删除 {} 条记录 (ID: {})", "Deleted {} record(s) (ID: {})", count, id)),
+                    Err(e) => { eprintln!("{} {}", "DB ERR".red(), tr!("删除失败: {}", "Delete failed: {}", e)); return CommandOutcome::Failed; }
+                }
+            } else {
+                println!("{} {}", "INFO".cyan(), tr!("操作取消。", "Cancelled."));
+            }
+        },
+        "update" => {
+            // 原地修改一条记录，避免 delete + insert 之间的查询空窗
+            let id = if !arg.is_empty() { arg } else { match read_line(tr!("请输入要修改的 UID 或 Phone Number: ", "Enter the UID or phone number to update: ")) {
+                Ok(s) if !s.is_empty() => s,
+                _ => return CommandOutcome::Failed,
+            } };
+
+            let (old_uid, old_phone) = match with_retry(&retry, || lookup_one(&conn, &id)) {
+                Ok(resp) if resp.status != "not_found" => (resp.uid.unwrap_or_default(), resp.phone_number.unwrap_or_default()),
+                Ok(_) => { println!("{} {}", "NOT FOUND".yellow(), tr!("未找到 ID: {}", "ID not found: {}", id)); return CommandOutcome::Failed; },
+                Err(e) => { report_db_error(tr!("查找失败", "Lookup failed").to_string(), &e); return CommandOutcome::Failed; }
+            };
+            println!("{} {}", "FOUND".green(), tr!("当前记录: UID={}, Phone={}", "Current record: UID={}, Phone={}", old_uid, old_phone));
+
+            // 留空表示保持原值
+            let uid = match read_line(tr!("新的 UID (留空保持不变): ", "New UID (empty keeps current): ")) {
+                Ok(s) if s.is_empty() => old_uid.clone(),
+                Ok(s) => s,
+                Err(_) => return CommandOutcome::Failed,
+            };
+            let phone = match read_line(tr!("新的 Phone Number (留空保持不变): ", "New phone number (empty keeps current): ")) {
+                Ok(s) if s.is_empty() => old_phone.clone(),
+                Ok(s) => s,
+                Err(_) => return CommandOutcome::Failed,
+            };
+            if uid == old_uid && phone == old_phone {
+                println!("{} {}", "INFO".cyan(), tr!("没有变化，无需修改。", "Nothing changed."));
+                return CommandOutcome::Done;
+            }
+            if uid.len() > MAX_DATA_LENGTH || phone.len() > MAX_DATA_LENGTH {
+                eprintln!("{} {}", "DB ERR".red(), tr!("输入数据过长，请保持在 {} 字符以内。", "Input too long, keep it within {} characters.", MAX_DATA_LENGTH));
+                return CommandOutcome::Failed;
+            }
+            // 未修改的号码保持原样，已有的旧格式号码不会因改 uid 而被换算
+            let phone = if phone == old_phone {
+                phone
+            } else {
+                match state.current_config().phone_rules.normalize_for_write(&phone) {
+                    Ok(phone) => phone,
+                    Err(e) => { eprintln!("{} {}", "DB ERR".red(), e); return CommandOutcome::Failed; }
+                }
+            };
+
+            let confirm = match read_line(&format!("{} {}", "WARN".yellow(), tr!("确认修改为 UID={}, Phone={}? (yes/no): ", "Change to UID={}, Phone={}? (yes/no): ", uid, phone))) {
+                Ok(s) => s.to_lowercase(),
+                _ => return CommandOutcome::Failed,
+            };
+            if confirm != "yes" {
+                println!("{} {}", "INFO".cyan(), tr!("操作取消。", "Cancelled."));
+                return CommandOutcome::Done;
+            }
+
+            // 单条 UPDATE 语句，读者不会看到记录缺失的中间状态；与其他记录冲突时由唯一索引拒绝
+            let before = snapshot_for_undo(&conn, Some(std::slice::from_ref(&old_uid)));
+            let keys = affected_keys(&conn, &[&old_uid, &old_phone, &uid, &phone]);
+            let result = with_retry(&retry, || conn.execute(
+                "UPDATE user_mapping SET uid = ?1, phone_number = phone_enc(?2) WHERE uid = ?3 AND phone_number = phone_enc(?4)",
+                [&uid, &phone, &old_uid, &old_phone],
+            ));
+            state.invalidate_cache(keys.ok().flatten());
+
+            match result {
+                Ok(1) => {
+                    state.bloom_insert(&[&uid, &phone]);
+                    if let Some(before) = before {
+                        remember_undo(format!("update {}", id), before, vec![(uid.clone(), phone.clone())]);
+                    }
+                    println!("{} {}", "OK".green(), tr!("修改成功：UID={}, Phone={}", "Updated: UID={}, Phone={}", uid, phone));
+                },
+                Ok(_) => { eprintln!("{} {}", "WARN".yellow(), tr!("记录已被并发修改或删除，请重新查询。", "The record was changed or deleted concurrently; look it up again.")); return CommandOutcome::Failed; },
+                Err(SqlError::SqliteFailure(err, _)) if err.code == ErrorCode::ConstraintViolation => {
+                    eprintln!("{} {}", "DB ERR".red(), tr!("修改失败：新的 UID 或 Phone 已被其他记录占用。", "Update failed: the new UID or phone already belongs to another record."));
+                    return CommandOutcome::Failed;
+                },
+                Err(e) => { eprintln!("{} {}", "DB ERR".red(), tr!("修改失败: {}", "Update failed: {}", e)); return CommandOutcome::Failed; }
+            }
+        },
+        "delete-file" => {
+            let (path, dry_run) = match arg.strip_suffix("--dry-run") {
+                Some(path) => (path.trim(), true),
+                None => (arg.as_str(), false),
+            };
+            if path.is_empty() {
+                eprintln!("{} {}", "WARN".yellow(), tr!("用法: delete-file <文件> [--dry-run]", "Usage: delete-file <file> [--dry-run]"));
+                return CommandOutcome::Failed;
+            }
+            let ids = match read_id_file(path) {
+                Ok(ids) => ids,
+                Err(e) => { eprintln!("{} {}", "ERR".red(), tr!("无法读取 {}: {}", "Cannot read {}: {}", path, e)); return CommandOutcome::Failed; }
+            };
+            if let Some(id) = ids.iter().find(|id| id.len() > MAX_DATA_LENGTH) {
+                eprintln!("{} {}", "DB ERR".red(), tr!("ID 过长 ({} 字符以内): {}", "ID too long (max {} characters): {}", MAX_DATA_LENGTH, id));
+                return CommandOutcome::Failed;
+            }
+
+            // 先在回滚的事务中统计影响条数，供确认与 dry-run 使用
+            let matched = match with_retry(&retry, || delete_mappings(&conn, &ids, true)) {
+                Ok(rows) => rows.len(),
+                Err(e) => { report_db_error(tr!("统计失败", "Counting failed").to_string(), &e); return CommandOutcome::Failed; }
+            };
+            if dry_run {
+                if json_output() {
+                    print_json(serde_json::json!({ "ids": ids.len(), "deleted": matched, "dry_run": true }));
+                } else {
+                    println!("{} {}", "DRY RUN".cyan(), tr!("{} 个 ID，将删除 {} 条记录（未做任何修改）。", "{} ID(s), {} record(s) would be deleted (nothing changed).", ids.len(), matched));
+                }
+                return CommandOutcome::Done;
+            }
+            if matched == 0 {
+                println!("{} {}", "INFO".cyan(), tr!("{} 个 ID 均无匹配记录，无需删除。", "None of the {} ID(s) match any record.", ids.len()));
+                return CommandOutcome::Done;
+            }
+
+            let confirm = match read_line(&format!("{} {}", "WARN".yellow(), tr!("警告: {} 个 ID 将删除 {} 条记录，确认? (yes/no): ", "Warning: {} ID(s) will delete {} record(s). Proceed? (yes/no): ", ids.len(), matched))) {
+                Ok(s) => s.to_lowercase(),
+                _ => return CommandOutcome::Failed,
+            };
+            if confirm != "yes" {
+                println!("{} {}", "INFO".cyan(), tr!("操作取消。", "Cancelled."));
+                return CommandOutcome::Done;
+            }
+
+            let before = if matched <= UNDO_MAX_ROWS { snapshot_for_undo(&conn, Some(&ids)) } else { None };
+            let refs: Vec<&str> = ids.iter().map(String::as_str).collect();
+            let keys = affected_keys(&conn, &refs);
+            let result = with_retry(&retry, || delete_mappings(&conn, &ids, false)).map(|rows| rows.len());
+            state.invalidate_cache(keys.ok().flatten());
+            if let (Ok(_), Some(before)) = (&result, before) {
+                remember_undo(format!("delete-file {}", path), before, Vec::new());
+            }
+
+            match result {
+                Ok(n) if json_output() => print_json(serde_json::json!({ "ids": ids.len(), "deleted": n, "dry_run": false })),
+                Ok(n) => println!("{} {}", "OK".green(), tr!("成功删除 {} 条记录（{} 个 ID）。", "Deleted {} record(s) ({} ID(s)).", n, ids.len())),
+                Err(e) => { report_db_error(tr!("删除失败，事务已回滚", "Delete failed, transaction rolled back").to_string(), &e); return CommandOutcome::Failed; }
+            }
+        },
+        "count" => {
+            let count: SqlResult<i64> = conn.query_row("SELECT COUNT(*) FROM user_mapping", [], |row| row.get(0));
+            match count {
+                Ok(c) if json_output() => print_json(serde_json::json!({ "count": c })),
+                Ok(c) => println!("{} {}", "INFO".yellow(), tr!("总记录数: {}", "Total records: {}", c)),
+                Err(e) => { report_db_error(tr!("查询失败", "Query failed").to_string(), &e); return CommandOutcome::Failed; }
+            }
+        },
+        "output" => {
+            match arg.to_lowercase().as_str() {
+                "json" => JSON_OUTPUT.store(true, Ordering::Relaxed),
+                "text" => JSON_OUTPUT.store(false, Ordering::Relaxed),
+                _ => {
+                    eprintln!("{} {}", "WARN".yellow(), tr!("用法: output json|text", "Usage: output json|text"));
+                    return CommandOutcome::Failed;
+                }
+            }
+            println!("{} {}", "OK".green(), tr!("输出模式: {}", "Output mode: {}", if json_output() { "json" } else { "text" }));
+        },
+        "clear" => {
+            // 过大的表不保存前像，提前说明此次清空无法 undo
+            let total: usize = conn.query_row("SELECT COUNT(*) FROM user_mapping", [], |row| row.get::<_, i64>(0)).map(|n| n as usize).unwrap_or(0);
+            if total > UNDO_MAX_ROWS {
+                println!("{} {}", "WARN".yellow(), tr!("共 {} 条记录，超过撤销上限 {}，清空后无法 undo，只能从备份恢复。", "{} records exceed the undo limit of {}; this clear cannot be undone except from a backup.", total, UNDO_MAX_ROWS));
+            }
+            // 防御性：确认清空
+            let confirm = match read_line(&format!("{} {}", "WARN".red(), tr!("警告：这将清空所有数据。确认清空? (yes/no): ", "Warning: this deletes all data. Really clear? (yes/no): "))) {
+                Ok(s) => s.to_lowercase(),
+                _ => return CommandOutcome::Failed,
+            };
+
+            if confirm == "yes" {
+                let before = if total <= UNDO_MAX_ROWS { snapshot_for_undo(&conn, None) } else { None };
+                let result = with_retry(&retry, || conn.execute("DELETE FROM user_mapping", []));
+                state.clear_cache();
+                if let (Ok(_), Some(before)) = (&result, before) {
+                    remember_undo("clear".to_string(), before, Vec::new());
+                }
+                match result {
+                    Ok(count) => println!("{} {}", "OK".green(), tr!("成功清空 {} 条记录。", "Cleared {} record(s).", count)),
+                    Err(e) => { eprintln!("{} {}", "DB ERR".red(), tr!("清空失败: {}", "Clear failed: {}", e)); return CommandOutcome::Failed; }
+                }
+            } else {
+                println!("{} {}", "INFO".cyan(), tr!("操作取消。", "Cancelled."));
+            }
+        },
+        "restore" => {
+            // 替换文件前释放本命令持有的连接
+            drop(conn);
+            let dest_dir = state.current_config().backup.dest_dir;
+            let file = if arg.is_empty() {
+                match list_backups(&dest_dir) {
+                    Ok(backups) if !backups.is_empty() => {
+                        println!("{} {}", "INFO".yellow(), tr!("可用备份 ({}):", "Available backups ({}):", dest_dir));
+                        for b in &backups {
+                            println!("  {}", b.file_name().unwrap_or_default().to_string_lossy());
+                        }
+                    }
+                    _ => println!("{} {}", "INFO".yellow(), tr!("备份目录 {} 中没有可用备份。", "No backups found in {}.", dest_dir)),
+                }
+                match read_line(tr!("请输入要恢复的备份文件: ", "Enter the backup file to restore: ")) {
+                    Ok(s) if !s.is_empty() => s,
+                    _ => return CommandOutcome::Failed,
+                }
+            } else {
+                arg
+            };
+            let path = resolve_backup_path(&file, &dest_dir);
+
+            match verify_backup(&path, state.current_config().database_key().as_deref()) {
+                Ok(count) => println!("{} {}", "OK".green(), tr!("备份校验通过: {} ({} 条记录)", "Backup verified: {} ({} records)", path.display(), count)),
+                Err(e) => {
+                    eprintln!("{} {}", "DB ERR".red(), tr!("备份校验失败: {:?}", "Backup verification failed: {:?}", e));
+                    return CommandOutcome::Failed;
+                }
+            }
+
+            // 防御性：确认恢复
+            let confirm = match read_line(&format!("{} {}", "WARN".red(), tr!("警告：当前数据库将被替换。确认恢复? (yes/no): ", "Warning: the current database will be replaced. Really restore? (yes/no): "))) {
+                Ok(s) => s.to_lowercase(),
+                _ => return CommandOutcome::Failed,
+            };
+            if confirm == "yes" {
+                match restore_database(state, &path) {
+                    Ok(Some(copy)) => println!("{} {}", "OK".green(), tr!("恢复完成，原数据库已另存为: {}", "Restore complete, previous database saved as: {}", copy.display())),
+                    Ok(None) => println!("{} {}", "OK".green(), tr!("恢复完成。", "Restore complete.")),
+                    Err(e) => { eprintln!("{} {}", "DB ERR".red(), tr!("恢复失败: {:?}", "Restore failed: {:?}", e)); return CommandOutcome::Failed; }
+                }
+            } else {
+                println!("{} {}", "INFO".cyan(), tr!("操作取消。", "Cancelled."));
+            }
+        },
+        "import" => {
+            let dry_run = arg.split_whitespace().any(|t| t == "--dry-run");
+            let (arg, format) = match take_format_arg(&arg.replace("--dry-run", "")) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    eprintln!("{} {}", "WARN".yellow(), e);
+                    return CommandOutcome::Failed;
+                }
+            };
+            let (path, strategy) = match arg.split_once("--on-conflict") {
+                Some((path, strategy)) => (path.trim(), ConflictStrategy::from_str(strategy.trim(), true).ok()),
+                None => (arg.trim(), Some(ConflictStrategy::default())),
+            };
+            let strategy = match strategy {
+                Some(s) if !path.is_empty() => s,
+                _ => {
+                    eprintln!("{} {}", "WARN".yellow(), tr!("用法: import <文件或 URL> [--format csv|ndjson|xlsx] [--on-conflict skip|replace|error] [--dry-run]", "Usage: import <file or URL> [--format csv|ndjson|xlsx] [--on-conflict skip|replace|error] [--dry-run]"));
+                    return CommandOutcome::Failed;
+                }
+            };
+            drop(conn);
+            let started = Instant::now();
+            match import_mappings(state, path, format, strategy, dry_run) {
+                Ok(summary) if json_output() => print_json(serde_json::json!({ "dry_run": dry_run, "summary": summary })),
+                Ok(summary) if dry_run => println!("{} {}", "DRY RUN".cyan(), tr!("{}（未做任何修改）", "{} (nothing changed)", summary.describe())),
+                Ok(summary) => println!("{} {}", "OK".green(), tr!("导入完成，用时 {:.2?}: {}", "Import finished in {:.2?}: {}", started.elapsed(), summary.describe())),
+                Err(e) => { eprintln!("{} {}", "ERR".red(), tr!("导入失败: {:?}", "Import failed: {:?}", e)); return CommandOutcome::Failed; }
+            }
+        },
+        "export" => {
+            let (path, options) = match parse_export_args(&arg) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    eprintln!("{} {}", "WARN".yellow(), e);
+                    return CommandOutcome::Failed;
+                }
+            };
+            drop(conn);
+            let started = Instant::now();
+            match export_mappings(state, &path, &options) {
+                Ok(n) if json_output() => print_json(serde_json::json!({ "exported": n, "file": path })),
+                Ok(n) => println!("{} {}", "OK".green(), tr!("已导出 {} 条记录到 {}，用时 {:.2?}。", "Exported {} records to {} in {:.2?}.", n, path, started.elapsed())),
+                Err(e) => { eprintln!("{} {}", "ERR".red(), tr!("导出失败: {:?}", "Export failed: {:?}", e)); return CommandOutcome::Failed; }
+            }
+        },
+        "check" => {
+            drop(conn);
+            let detail = (!arg.is_empty()).then_some(arg.as_str());
+            match run_quality_check(state, detail) {
+                Ok(report) => {
+                    if json_output() {
+                        print_json(serde_json::to_value(&report).unwrap_or_default());
+                    } else {
+                        print_quality_report(&report);
+                    }
+                    if report.total_issues > 0 {
+                        return CommandOutcome::Failed;
+                    }
+                }
+                Err(e) => { eprintln!("{} {}", "ERR".red(), tr!("数据质量检查失败: {:?}", "Data quality check failed: {:?}", e)); return CommandOutcome::Failed; }
+            }
+        },
+        "merge" => {
+            drop(conn);
+            let apply = match arg.as_str() {
+                "" => false,
+                "--apply" => true,
+                _ => {
+                    eprintln!("{} {}", "WARN".yellow(), tr!("用法: merge [--apply]", "Usage: merge [--apply]"));
+                    return CommandOutcome::Failed;
+                }
+            };
+            let (scanned, groups) = match find_merge_groups(state) {
+                Ok(found) => found,
+                Err(e) => { eprintln!("{} {}", "ERR".red(), tr!("扫描重复记录失败: {:?}", "Scanning for duplicates failed: {:?}", e)); return CommandOutcome::Failed; }
+            };
+            let preview = MergePlan { scanned, groups, applied: false, merged: 0 };
+            if !apply || preview.groups.is_empty() {
+                if json_output() {
+                    print_json(serde_json::to_value(&preview).unwrap_or_default());
+                } else {
+                    print_merge_plan(&preview);
+                    if !preview.groups.is_empty() {
+                        println!("{} {}", "HINT".yellow(), tr!("确认无误后执行 'merge --apply' 合并。", "Run 'merge --apply' to merge them."));
+                    }
+                }
+                return CommandOutcome::Done;
+            }
+            if !json_output() {
+                print_merge_plan(&preview);
+            }
+            let confirm = match read_line(&format!("{} {}", "WARN".yellow(), tr!("合并以上 {} 组记录? (yes/no): ", "Merge the {} group(s) above? (yes/no): ", preview.groups.len()))) {
+                Ok(s) => s.to_lowercase(),
+                _ => return CommandOutcome::Failed,
+            };
+            if confirm != "yes" {
+                println!("{} {}", "INFO".cyan(), tr!("操作取消。", "Cancelled."));
+                return CommandOutcome::Done;
+            }
+            match apply_merge(state) {
+                Ok(plan) if json_output() => print_json(serde_json::to_value(&plan).unwrap_or_default()),
+                Ok(plan) => println!("{} {}", "OK".green(), tr!("已合并 {} 组共 {} 条记录，历史写入 merge_history 表。", "Merged {} group(s), {} record(s); history written to the merge_history table.", plan.groups.len(), plan.merged)),
+                Err(e) => { eprintln!("{} {}", "ERR".red(), tr!("合并失败: {:?}", "Merge failed: {:?}", e)); return CommandOutcome::Failed; }
+            }
+        },
+        "seed" => {
+            let count = match arg.parse::<u64>() {
+                Ok(n) if n > 0 => n,
+                _ => {
+                    eprintln!("{} {}", "WARN".yellow(), tr!("用法: seed <条数>", "Usage: seed <count>"));
+                    return CommandOutcome::Failed;
+                }
+            };
+            drop(conn);
+            let started = Instant::now();
+            match seed_database(state, count, SEED_BATCH_SIZE) {
+                Ok(n) if json_output() => print_json(serde_json::json!({ "seeded": n, "elapsed_ms": started.elapsed().as_millis() as u64 })),
+                Ok(n) => println!("{} {}", "OK".green(), tr!("已生成 {} 条测试数据，用时 {:.2?}。", "Seeded {} records in {:.2?}.", n, started.elapsed())),
+                Err(e) => { report_db_error(tr!("生成测试数据失败", "Seeding failed").to_string(), &e); return CommandOutcome::Failed; }
+            }
+        },
+        "stats" => {
+            match with_retry(&retry, || collect_db_stats(&conn, &state.current_config())) {
+                Ok(stats) if json_output() => print_json(serde_json::to_value(&stats).unwrap_or_default()),
+                Ok(stats) => {
+                    println!("{} {}", "INFO".yellow(), tr!("记录数: {}（不同 UID {}，不同手机号 {}）", "Records: {} ({} distinct UIDs, {} distinct phones)", stats.rows, stats.distinct_uids, stats.distinct_phones));
+                    println!("{} {}", "INFO".yellow(), tr!("数据库文件: {}，WAL: {}", "Database file: {}, WAL: {}", format_bytes(stats.db_bytes), format_bytes(stats.wal_bytes)));
+                    match &stats.objects {
+                        Some(objects) => {
+                            let rows: Vec<Vec<String>> = objects.iter().map(|o| vec![o.name.clone(), format_bytes(o.bytes)]).collect();
+                            print_table(&[tr!("表/索引", "table/index"), tr!("大小", "size")], &rows);
+                        }
+                        None => println!("{} {}", "WARN".yellow(), tr!("当前 SQLite 不支持 dbstat，无法统计索引大小。", "This SQLite build lacks dbstat, index sizes unavailable.")),
+                    }
+                    match &stats.last_backup {
+                        Some(backup) => println!("{} {}", "INFO".yellow(), tr!("最近备份: {}", "Last backup: {}", backup)),
+                        None => println!("{} {}", "INFO".yellow(), tr!("最近备份: 无", "Last backup: none")),
+                    }
+                },
+                Err(e) => { report_db_error(tr!("统计失败", "Counting failed").to_string(), &e); return CommandOutcome::Failed; }
+            }
+        },
+        "vacuum" => {
+            let db_path = state.current_config().db_path;
+            let before = file_size(&db_path);
+            println!("{} {}", "INFO".yellow(), tr!("正在执行 VACUUM (当前大小 {})，大库可能需要较长时间...", "Running VACUUM (current size {}), this may take a while on large databases...", format_bytes(before)));
+            let started = Instant::now();
+            match conn.execute_batch("VACUUM") {
+                Ok(_) => {
+                    let after = file_size(&db_path);
+                    println!("{} {}", "OK".green(), tr!("VACUUM 完成，用时 {:.2?}: {} -> {} (回收 {})", "VACUUM finished in {:.2?}: {} -> {} (reclaimed {})", started.elapsed(), format_bytes(before), format_bytes(after), format_bytes(before.saturating_sub(after))));
+                }
+                Err(e) => { eprintln!("{} {}", "DB ERR".red(), tr!("VACUUM 失败: {}", "VACUUM failed: {}", e)); return CommandOutcome::Failed; }
+            }
+        },
+        "analyze" => {
+            println!("{} {}", "INFO".yellow(), tr!("正在执行 ANALYZE ...", "Running ANALYZE ..."));
+            let started = Instant::now();
+            match conn.execute_batch("ANALYZE") {
+                Ok(_) => println!("{} {}", "OK".green(), tr!("ANALYZE 完成，用时 {:.2?}，查询规划器统计信息已更新。", "ANALYZE finished in {:.2?}, query planner statistics updated.", started.elapsed())),
+                Err(e) => { eprintln!("{} {}", "DB ERR".red(), tr!("ANALYZE 失败: {}", "ANALYZE failed: {}", e)); return CommandOutcome::Failed; }
+            }
+        },
+        "integrity" => {
+            println!("{} {}", "INFO".yellow(), tr!("正在执行完整性检查，大库可能需要较长时间...", "Running integrity check, this may take a while on large databases..."));
+            let started = Instant::now();
+            match check_integrity(&conn) {
+                Ok(problems) if problems.is_empty() => {
+                    println!("{} {}", "OK".green(), tr!("完整性检查通过，用时 {:.2?}。", "Integrity check passed in {:.2?}.", started.elapsed()));
+                }
+                Ok(problems) => {
+                    eprintln!("{} {}", "CORRUPT".red().bold(), tr!("发现 {} 个问题 (最多显示 {} 条):", "Found {} problem(s) (showing at most {}):", problems.len(), INTEGRITY_MAX_ERRORS));
+                    for p in &problems {
+                        eprintln!("  - {}", p);
+                    }
+                    eprintln!("{} {}", "HINT".yellow(), tr!("建议使用 'restore' 从最近的备份恢复。", "Consider using 'restore' to recover from the latest backup."));
+                    return CommandOutcome::Failed;
+                }
+                Err(e) => { eprintln!("{} {}", "DB ERR".red(), tr!("完整性检查失败: {}", "Integrity check failed: {}", e)); return CommandOutcome::Failed; }
+            }
+        },
+        "key-usage" => {
+            let config = state.current_config();
+            let mut keys: Vec<String> = config.api_keys.iter().map(|k| k.name.clone()).collect();
+            keys.extend(state.key_usage.lock().unwrap().keys().cloned());
+            keys.sort();
+            keys.dedup();
+            if !arg.is_empty() {
+                keys.retain(|key| *key == arg);
+            }
+            let reports: Vec<KeyUsageReport> = keys.iter().filter_map(|key| state.key_usage_report(&config, key)).collect();
+            if reports.is_empty() && !arg.is_empty() {
+                eprintln!("{} {}", "WARN".yellow(), tr!("未知的 API 密钥: {}", "Unknown API key: {}", arg));
+                return CommandOutcome::Failed;
+            }
+            if json_output() {
+                print_json(serde_json::to_value(&reports).unwrap_or_default());
+            } else if reports.is_empty() {
+                println!("{} {}", "INFO".cyan(), tr!("未配置 API 密钥。", "No API keys are configured."));
+            } else {
+                let rows: Vec<Vec<String>> = reports.iter().map(|r| vec![
+                    r.key.clone(),
+                    r.usage.requests.to_string(),
+                    r.usage.errors.to_string(),
+                    r.usage.lookups.to_string(),
+                    r.usage.ids.to_string(),
+                    format!("{:.1}%", r.hit_rate * 100.0),
+                    format!("{:.1}", r.avg_batch_size),
+                    r.usage.max_batch_size.to_string(),
+                    r.usage.last_seen.clone().unwrap_or_else(|| "-".to_string()),
+                ]).collect();
+                print_table(&[tr!("密钥", "key"), tr!("请求", "requests"), tr!("错误", "errors"), tr!("查询", "lookups"), "ids", tr!("命中率", "hit rate"), tr!("平均批量", "avg batch"), tr!("最大批量", "max batch"), tr!("最近请求", "last seen")], &rows);
+                println!("{} {}", "INFO".yellow(), tr!("统计自进程启动 ({}) 起。", "Counted since the process started ({}).", state.started_at.to_rfc3339()));
+            }
+        },
+        "cache-clear" => {
+            match state.flush_cache() {
+                Ok(count) => println!("{} {}", "OK".green(), tr!("已清空 {} 条缓存并重建布隆过滤器。", "Flushed {} cache entries and rebuilt the bloom filter.", count)),
+                Err(e) => { eprintln!("{} {}", "DB ERR".red(), tr!("缓存已清空，但布隆过滤器重建失败: {}", "Cache flushed, but rebuilding the bloom filter failed: {}", e)); return CommandOutcome::Failed; }
+            }
+        },
+        "backup" => {
+            let backup = state.current_config().backup;
+            println!("{} {}", "INFO".yellow(), tr!("正在备份到 {} ...", "Backing up to {} ...", backup.dest_dir));
+            match run_backup_once(state, &backup, true) {
+                Ok((dest, removed)) => {
+                    println!("{} {}", "OK".green(), tr!("备份完成: {}", "Backup complete: {}", dest.display()));
+                    if !removed.is_empty() {
+                        println!("{} {}", "INFO".yellow(), tr!("已按保留策略 (keep={}) 清理 {} 份旧备份。", "Retention policy (keep={}): pruned {} old backup(s).", backup.keep, removed.len()));
+                    }
+                }
+                Err(e) => { eprintln!("{} {}", "DB ERR".red(), tr!("备份失败: {:?}", "Backup failed: {:?}", e)); return CommandOutcome::Failed; }
+            }
+        },
+        "list" => {
+            let (page, page_size, order) = match parse_list_args(&arg) {
+                Ok(args) => args,
+                Err(e) => {
+                    eprintln!("{} {}", "WARN".yellow(), e);
+                    return CommandOutcome::Failed;
+                }
+            };
+            match with_retry(&retry, || list_mappings(&conn, order, page, page_size)) {
+                Ok((total, records)) if json_output() => {
+                    let records: Vec<_> = records.into_iter()
+                        .map(|(rowid, uid, phone)| serde_json::json!({ "rowid": rowid, "uid": uid, "phone_number": phone }))
+                        .collect();
+                    print_json(serde_json::json!({ "page": page, "page_size": page_size, "total": total, "order": order.column(), "records": records }));
+                },
+                Ok((total, records)) => {
+                    let pages = (total.max(1) as u64).div_ceil(page_size as u64);
+                    if records.is_empty() {
+                        println!("{} {}", "INFO".yellow(), tr!("第 {} 页没有记录（共 {} 页，{} 条）。", "No records on page {} ({} page(s), {} records).", page, pages, total));
+                    } else {
+                        let rows: Vec<Vec<String>> = records.into_iter()
+                            .map(|(rowid, uid, phone)| vec![rowid.to_string(), uid, phone])
+                            .collect();
+                        print_table(&["rowid", "uid", "phone_number"], &rows);
+                        println!("{} {}", "INFO".yellow(), tr!("第 {}/{} 页，共 {} 条，按 {} 排序", "Page {}/{}, {} records, ordered by {}", page, pages, total, order.column()));
+                    }
+                },
+                Err(e) => { report_db_error(tr!("查询失败", "Query failed").to_string(), &e); return CommandOutcome::Failed; }
+            }
+        },
+        "sql" => {
+            if arg.is_empty() {
+                eprintln!("{} {}", "WARN".yellow(), tr!("用法: sql <SELECT ...>", "Usage: sql <SELECT ...>"));
+                return CommandOutcome::Failed;
+            }
+            match run_readonly_query(&conn, arg.trim_end().trim_end_matches(';')) {
+                Ok(result) if json_output() => {
+                    let rows: Vec<_> = result.rows.into_iter()
+                        .map(|r| serde_json::Value::Object(result.columns.iter().cloned().zip(r.into_iter().map(serde_json::Value::String)).collect()))
+                        .collect();
+                    print_json(serde_json::json!({ "columns": result.columns, "rows": rows, "truncated": result.truncated }));
+                },
+                Ok(result) => {
+                    let headers: Vec<&str> = result.columns.iter().map(String::as_str).collect();
+                    print_table(&headers, &result.rows);
+                    println!("{} {}", "INFO".yellow(), tr!("{} 行", "{} row(s)", result.rows.len()));
+                    if result.truncated {
+                        println!("{} {}", "WARN".yellow(), tr!("结果超过 {} 行，已截断。", "More than {} rows, output truncated.", MAX_SQL_ROWS));
+                    }
+                },
+                Err(e) => { eprintln!("{} {}", "DB ERR".red(), tr!("查询失败: {}", "Query failed: {}", e)); return CommandOutcome::Failed; }
+            }
+        },
+        "undo" => {
+            let Some(record) = LAST_UNDO.lock().unwrap().take() else {
+                println!("{} {}", "INFO".cyan(), tr!("没有可撤销的操作。", "Nothing to undo."));
+                return CommandOutcome::Done;
+            };
+            let confirm = match read_line(&format!("{} {}", "WARN".yellow(), tr!("撤销 '{}'：恢复 {} 条记录、移除 {} 条写入的记录，确认? (yes/no): ", "Undo '{}': restore {} record(s) and remove {} written record(s). Proceed? (yes/no): ", record.action, record.removed.len(), record.added.len()))) {
+                Ok(s) => s.to_lowercase(),
+                _ => { *LAST_UNDO.lock().unwrap() = Some(record); return CommandOutcome::Failed; }
+            };
+            if confirm != "yes" {
+                *LAST_UNDO.lock().unwrap() = Some(record);
+                println!("{} {}", "INFO".cyan(), tr!("操作取消。", "Cancelled."));
+                return CommandOutcome::Done;
+            }
+            let result = with_retry(&retry, || restore_undo(&conn, &record));
+            state.clear_cache();
+            match result {
+                Ok(()) => {
+                    for (uid, _, phone) in &record.removed {
+                        state.bloom_insert(&[uid, phone]);
+                    }
+                    println!("{} {}", "OK".green(), tr!("已撤销 '{}'，恢复 {} 条记录。", "Undid '{}'; {} record(s) restored.", record.action, record.removed.len()));
+                }
+                Err(e) => {
+                    // 撤销在保存点中执行，失败时未做任何修改，前像保留以便重试
+                    let action = record.action.clone();
+                    *LAST_UNDO.lock().unwrap() = Some(record);
+                    if matches!(&e, SqlError::SqliteFailure(err, _) if err.code == ErrorCode::ConstraintViolation) {
+                        eprintln!("{} {}", "DB ERR".red(), tr!("撤销 '{}' 失败：要恢复的 uid 或手机号已被其他记录占用，未做任何修改。", "Undo of '{}' failed: a uid or phone to restore now belongs to another record; nothing was changed.", action));
+                    } else {
+                        report_db_error(tr!("撤销失败，未做任何修改", "Undo failed; nothing was changed").to_string(), &e);
+                    }
+                    return CommandOutcome::Failed;
+                }
+            }
+        },
+        "run" => {
+            // 脚本中的命令自行获取连接（会话中为同一会话连接）
+            drop(conn);
+            let (file, continue_on_error) = parse_run_args(&arg);
+            if file.is_empty() {
+                eprintln!("{} {}", "WARN".yellow(), tr!("用法: run <脚本文件> [--continue]", "Usage: run <script file> [--continue]"));
+                return CommandOutcome::Failed;
+            }
+            if let Err(e) = run_script(state, file, continue_on_error) {
+                eprintln!("{} {}", "ERR".red(), e);
+                return CommandOutcome::Failed;
+            }
+        },
+        _ => { println!("{} {}", "WARN".yellow(), tr!("未知命令: {}", "Unknown command: {}", command)); return CommandOutcome::Failed; }
+    }
+    CommandOutcome::Done
+}
+
+// --- 撤销 ---
+
+/// db-manage 中最近一次破坏性操作 (delete / delete-file / clear / 覆盖式 insert / update) 的前像，供 `undo` 恢复。
+/// 只保存在本进程内存中，且只保留最近一次。
+static LAST_UNDO: Mutex<Option<UndoRecord>> = Mutex::new(None);
+
+/// 超过这个条数的 clear / delete-file 不保存前像（避免把整张表载入内存），执行前提示无法撤销
+const UNDO_MAX_ROWS: usize = 1_000_000;
+
+struct UndoRecord {
+    action: String,                          // 提示用的操作描述，如 "delete 138..."
+    removed: Vec<(String, String, String)>,  // 被删除或覆盖的记录：(uid, 存储形式的手机号, 明文手机号)
+    added: Vec<(String, String)>,            // 操作写入的记录 (uid, 手机号)，撤销时先删除
+}
+
+/// 读取将被修改的记录的前像：`ids` 中任一 uid 或手机号匹配的记录，`None` 为整张表。
+/// 手机号按存储形式保存，恢复时原样写回，不受加密/哈希模式影响。
+fn undo_snapshot(conn: &Connection, ids: Option<&[String]>) -> SqlResult<Vec<(String, String, String)>> {
+    let row_of = |row: &rusqlite::Row| Ok((row.get(0)?, row.get(1)?, row.get(2)?));
+    let mut rows: Vec<(String, String, String)> = match ids {
+        None => conn.prepare("SELECT uid, phone_number, phone_dec(phone_number) FROM user_mapping")?
+            .query_map([], row_of)?.collect::<SqlResult<_>>()?,
+        Some(ids) => {
+            let mut stmt = conn.prepare("SELECT uid, phone_number, phone_dec(phone_number) FROM user_mapping WHERE uid = ?1 OR phone_number = phone_enc(?1)")?;
+            let mut rows = Vec::new();
+            for id in ids {
+                for row in stmt.query_map([id], row_of)? {
+                    rows.push(row?);
+                }
+            }
+            rows
+        }
+    };
+    rows.sort();
+    rows.dedup();
+    Ok(rows)
+}
+
+/// 读取前像失败时只提示，操作照常执行但不可撤销
+fn snapshot_for_undo(conn: &Connection, ids: Option<&[String]>) -> Option<Vec<(String, String, String)>> {
+    match undo_snapshot(conn, ids) {
+        Ok(rows) => Some(rows),
+        Err(e) => {
+            eprintln!("{} {}", "WARN".yellow(), tr!("无法保存撤销所需的前像，此操作将不可撤销: {}", "Cannot save the before-image; this operation cannot be undone: {}", e));
+            None
+        }
+    }
+}
+
+fn remember_undo(action: String, removed: Vec<(String, String, String)>, added: Vec<(String, String)>) {
+    *LAST_UNDO.lock().unwrap() = Some(UndoRecord { action, removed, added });
+}
+
+/// 在保存点中删除操作写入的记录并写回前像；任何一步失败都整体回滚。
+fn restore_undo(conn: &Connection, record: &UndoRecord) -> SqlResult<()> {
+    conn.execute_batch("SAVEPOINT undo")?;
+    let result = (|| {
+        let mut remove = conn.prepare("DELETE FROM user_mapping WHERE uid = ?1 AND phone_number = phone_enc(?2)")?;
+        for (uid, phone) in &record.added {
+            remove.execute([uid, phone])?;
+        }
+        let mut restore = conn.prepare("INSERT INTO user_mapping (uid, phone_number) VALUES (?1, ?2)")?;
+        for (uid, stored, _) in &record.removed {
+            restore.execute([uid, stored])?;
+        }
+        Ok(())
+    })();
+    if result.is_err() {
+        conn.execute_batch("ROLLBACK TO undo; RELEASE undo")?;
+    } else {
+        conn.execute_batch("RELEASE undo")?;
+    }
+    result
+}
+
+// --- 事务会话 ---
+
+/// `begin` 打开的事务会话连接。为 Some 时 db-manage 命令都在这个连接的事务中执行，
+/// 直到 `commit` 或 `rollback`；否则每条命令各自新建连接并立即提交。
+static DB_SESSION: Mutex<Option<Connection>> = Mutex::new(None);
+
+/// 会话中不能执行的命令：使用独立连接或自行分批提交 (import/seed/backup/restore)，或无法在事务中运行 (vacuum)
+const SESSION_EXCLUDED_COMMANDS: &[&str] = &["import", "seed", "merge", "backup", "restore", "vacuum"];
+
+fn in_db_session() -> bool {
+    DB_SESSION.lock().unwrap().is_some()
+}
+
+/// db-manage 命令使用的连接：会话中借用会话连接，命令结束（drop）时放回会话。
+enum DbCommandConnection {
+    Fresh(Connection),
+    Session(Option<Connection>),
+}
+
+impl std::ops::Deref for DbCommandConnection {
+    type Target = Connection;
+    fn deref(&self) -> &Connection {
+        match self {
+            DbCommandConnection::Fresh(conn) => conn,
+            DbCommandConnection::Session(conn) => conn.as_ref().expect("session connection is returned only on drop"),
+        }
+    }
+}
+
+impl Drop for DbCommandConnection {
+    fn drop(&mut self) {
+        if let DbCommandConnection::Session(conn) = self {
+            *DB_SESSION.lock().unwrap() = conn.take();
+        }
+    }
+}
+
+fn session_or_new_connection(state: &AppState) -> SqlResult<DbCommandConnection> {
+    match DB_SESSION.lock().unwrap().take() {
+        Some(conn) => Ok(DbCommandConnection::Session(Some(conn))),
+        None => state.get_db_connection().map(DbCommandConnection::Fresh),
+    }
+}
+
+/// `begin` / `commit` / `rollback`
+fn db_session_command(state: &AppState, command: &str) -> CommandOutcome {
+    let mut session = DB_SESSION.lock().unwrap();
+    match (command, session.take()) {
+        ("begin", Some(conn)) => {
+            *session = Some(conn);
+            println!("{} {}", "WARN".yellow(), tr!("已在事务会话中，请先 commit 或 rollback。", "Already in a transaction session; commit or roll back first."));
+            CommandOutcome::Failed
+        }
+        ("begin", None) => {
+            // IMMEDIATE：开始时即取得写锁，避免会话中途因其他写入者而失败
+            let opened = state.get_db_connection().and_then(|conn| conn.execute_batch("BEGIN IMMEDIATE").map(|_| conn));
+            match opened {
+                Ok(conn) => {
+                    *session = Some(conn);
+                    println!("{} {}", "OK".green(), tr!("事务会话已开始，之后的修改在 commit 前不会生效 (rollback 放弃)。", "Transaction session started; changes take effect on commit (rollback discards them)."));
+                    CommandOutcome::Done
+                }
+                Err(e) => { report_db_error(tr!("无法开始事务", "Cannot begin a transaction").to_string(), &e); CommandOutcome::Failed }
+            }
+        }
+        (_, None) => {
+            println!("{} {}", "WARN".yellow(), tr!("当前没有事务会话，先执行 begin。", "No transaction session is open; run begin first."));
+            CommandOutcome::Failed
+        }
+        ("commit", Some(conn)) => match conn.execute_batch("COMMIT") {
+            Ok(()) => {
+                // 会话期间其他连接可能读到并缓存了旧值，提交后整体失效
+                state.clear_cache();
+                println!("{} {}", "OK".green(), tr!("事务已提交。", "Transaction committed."));
+                CommandOutcome::Done
+            }
+            Err(e) => {
+                // 提交失败时会话保持打开，可重试 commit 或 rollback
+                *session = Some(conn);
+                report_db_error(tr!("提交失败，会话仍然打开", "Commit failed; the session is still open").to_string(), &e);
+                CommandOutcome::Failed
+            }
+        },
+        (_, Some(conn)) => match conn.execute_batch("ROLLBACK") {
+            Ok(()) => {
+                state.clear_cache();
+                // 被回滚的修改无需也不能再撤销
+                LAST_UNDO.lock().unwrap().take();
+                println!("{} {}", "OK".green(), tr!("事务已回滚，会话中的修改均已放弃。", "Transaction rolled back; all changes in the session were discarded."));
+                CommandOutcome::Done
+            }
+            Err(e) => { report_db_error(tr!("回滚失败，连接已关闭", "Rollback failed; the connection was closed").to_string(), &e); CommandOutcome::Failed }
+        },
+    }
+}
+
+/// 离开 db-manage 或 `--exec` 结束时仍未提交的会话：回滚并提示
+fn abandon_db_session() {
+    if let Some(conn) = DB_SESSION.lock().unwrap().take() {
+        let _ = conn.execute_batch("ROLLBACK");
+        println!("{} {}", "WARN".yellow(), tr!("事务会话未提交，修改已回滚。", "The transaction session was not committed; its changes were rolled back."));
+    }
+}
+
+// --- 脚本执行 ---
+
+/// 脚本模式下待读取的行 (行号, 内容)。为 Some 时 `read_line` 从这里取输入而不是终端，
+/// 因此 insert 等命令的后续输入与 yes/no 确认都直接写在脚本的下一行。
+static SCRIPT_INPUT: Mutex<Option<VecDeque<(usize, String)>>> = Mutex::new(None);
+
+/// 脚本模式下取下一行；返回 None 表示当前不在脚本模式。
+pub(crate) fn next_script_line() -> Option<io::Result<String>> {
+    let mut guard = SCRIPT_INPUT.lock().unwrap();
+    let queue = guard.as_mut()?;
+    Some(queue.pop_front().map(|(_, line)| line).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::UnexpectedEof, tr!("脚本在等待输入时结束", "script ended while waiting for input"))
+    }))
+}
+
+/// 解析 `run <文件> [--continue]` 的参数。
+pub(crate) fn parse_run_args(arg: &str) -> (&str, bool) {
+    match arg.strip_suffix("--continue") {
+        Some(file) => (file.trim(), true),
+        None => (arg.trim(), false),
+    }
+}
+
+/// 退出脚本模式（包括出错提前返回时）。
+struct ScriptModeGuard;
+
+impl Drop for ScriptModeGuard {
+    fn drop(&mut self) {
+        *SCRIPT_INPUT.lock().unwrap() = None;
+    }
+}
+
+/// 依次执行脚本中的 db-manage 命令。空行与 `#` 开头的行被忽略；默认遇到失败的命令即停止，
+/// `continue_on_error` 时记录失败并继续。返回执行的命令数，任何命令失败时返回错误。
+pub(crate) fn run_script(state: &Arc<AppState>, path: &str, continue_on_error: bool) -> Result<usize, String> {
+    let content = fs::read_to_string(path).map_err(|e| tr!("无法读取脚本 {}: {}", "Cannot read script {}: {}", path, e))?;
+    let lines: VecDeque<(usize, String)> = content.lines().enumerate()
+        .map(|(i, line)| (i + 1, line.trim().to_string()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+    {
+        let mut guard = SCRIPT_INPUT.lock().unwrap();
+        if guard.is_some() {
+            return Err(tr!("脚本不能嵌套执行。", "Scripts cannot be nested.").to_string());
+        }
+        *guard = Some(lines);
+    }
+    let _guard = ScriptModeGuard;
+
+    let mut executed = 0;
+    let mut failures = Vec::new();
+    loop {
+        let next = SCRIPT_INPUT.lock().unwrap().as_mut().and_then(VecDeque::pop_front);
+        let Some((line_no, line)) = next else { break };
+        if !json_output() {
+            println!("{} {}:{} > {}", "RUN".cyan(), path, line_no, line);
+        }
+        executed += 1;
+        match execute_db_command(state, &line) {
+            CommandOutcome::Done => {}
+            CommandOutcome::Back => break,
+            CommandOutcome::Failed if continue_on_error => failures.push(line_no),
+            CommandOutcome::Failed | CommandOutcome::Aborted => {
+                return Err(tr!("脚本在第 {} 行失败: {}", "Script failed at line {}: {}", line_no, line));
+            }
+        }
+    }
+    if failures.is_empty() {
+        println!("{} {}", "OK".green(), tr!("脚本执行完成: {} 条命令", "Script finished: {} command(s)", executed));
+        Ok(executed)
+    } else {
+        let lines = failures.iter().map(usize::to_string).collect::<Vec<_>>().join(", ");
+        Err(tr!("脚本执行完成，但以下行的命令失败: {}", "Script finished, but commands failed at line(s): {}", lines))
+    }
+}
+
+/// 执行 `--exec` 指定的命令，遇到第一个失败的命令即停止。"-" 从 stdin 逐行读取命令直到 EOF，
+/// 命令所需的后续输入（如 insert 的 UID）同样取自后续行。
+pub(crate) fn run_exec(state: &Arc<AppState>, commands: &[String]) -> Result<(), String> {
+    let result = exec_commands(state, commands);
+    abandon_db_session();
+    result
+}
+
+fn exec_commands(state: &Arc<AppState>, commands: &[String]) -> Result<(), String> {
+    for command in commands {
+        if command.trim() != "-" {
+            if exec_failed(execute_db_command(state, command)) {
+                return Err(tr!("命令执行失败: {}", "Command failed: {}", command));
+            }
+            continue;
+        }
+        loop {
+            let line = match read_line("") {
+                Ok(line) => line,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(tr!("读取 stdin 失败: {}", "Failed to read stdin: {}", e)),
+            };
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match execute_db_command(state, &line) {
+                CommandOutcome::Back => break,
+                outcome if exec_failed(outcome) => return Err(tr!("命令执行失败: {}", "Command failed: {}", line)),
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+fn exec_failed(outcome: CommandOutcome) -> bool {
+    matches!(outcome, CommandOutcome::Failed | CommandOutcome::Aborted)
+}
+
+// --- 交互式配置编辑函数 (使用防御性辅助函数) ---
+pub(crate) fn edit_config(state: Arc<AppState>) {
+    println!("{}", tr!("\n--- 正在编辑配置 ---", "\n--- Editing configuration ---").blue().bold());
+    let config = state.current_config();
+    let mut new_config = config.clone();
+    
+    // 1. 修改 DB 路径
+    if let Ok(Some(path)) = read_optional_string(tr!("[1] DB路径", "[1] DB path"), &new_config.db_path) {
+        new_config.db_path = path;
+    }
+    
+    // 2. 修改 绑定地址 (IP:端口)
+    if let Ok(Some(addr)) = read_optional_string(tr!("[2] 绑定地址 (IP:端口)", "[2] Bind address (IP:port)"), &new_config.bind_address) {
+        // 防御性：即时验证地址格式
+        match addr.parse::<SocketAddr>() {
+            Ok(_) => new_config.bind_address = addr,
+            Err(e) => eprintln!("{} {}", "ERROR".red(), tr!("地址格式无效 ('{}')，未修改: {}", "Invalid address ('{}'), unchanged: {}", addr, e)),
+        }
+    }
+    
+    // 3. 修改 批次大小限制
+    match read_u32(tr!("[3] 批次大小限制", "[3] Batch size limit"), new_config.batch_size_limit) {
+        Ok(Some(size)) => new_config.batch_size_limit = size,
+        Err(e) => eprintln!("{} {}", "ERROR".red(), e),
+        _ => {},
+    }
+    
+    // 4. 修改 日志级别
+    if let Ok(Some(level)) = read_optional_string(tr!("[4] 日志级别 (如 info、debug 或 info,cyber_lookup::db=trace)", "[4] Log level (e.g. info, debug or info,cyber_lookup::db=trace)"), &new_config.log_level) {
+        match LogFilter::parse(&level) {
+            Ok(filter) => new_config.log_level = filter.to_string(),
+            Err(e) => eprintln!("{} {}", "ERROR".red(), tr!("{}，保持不变。", "{}; unchanged.", e)),
+        }
+    }
+
+    // 5. 自动备份设置
+    let backup_state = if new_config.backup.enabled { "on" } else { "off" };
+    if let Ok(Some(flag)) = read_optional_string(tr!("[5] 自动备份 (on/off)", "[5] Scheduled backups (on/off)"), backup_state) {
+        match flag.to_lowercase().as_str() {
+            "on" => new_config.backup.enabled = true,
+            "off" => new_config.backup.enabled = false,
+            _ => eprintln!("{} {}", "ERROR".red(), tr!("无效输入 ('{}')，保持不变。", "Invalid input ('{}'), unchanged.", flag)),
+        }
+    }
+    if new_config.backup.enabled {
+        match u32::try_from(new_config.backup.interval_secs) {
+            Ok(current) => match read_u32(tr!("[6] 备份间隔 (秒)", "[6] Backup interval (seconds)"), current) {
+                Ok(Some(secs)) => new_config.backup.interval_secs = secs as u64,
+                Err(e) => eprintln!("{} {}", "ERROR".red(), e),
+                _ => {},
+            },
+            Err(_) => eprintln!("{} {}", "WARN".yellow(), tr!("当前备份间隔 {} 秒超出可编辑的范围，保持不变（可直接修改 config.txt）。", "The current backup interval of {} s is outside the editable range; unchanged (edit config.txt directly).", new_config.backup.interval_secs)),
+        }
+        if let Ok(Some(dir)) = read_optional_string(tr!("[7] 备份目录", "[7] Backup directory"), &new_config.backup.dest_dir) {
+            new_config.backup.dest_dir = dir;
+        }
+        match read_u32(tr!("[8] 保留备份份数", "[8] Backups to keep"), new_config.backup.keep) {
+            Ok(Some(keep)) => new_config.backup.keep = keep,
+            Err(e) => eprintln!("{} {}", "ERROR".red(), e),
+            _ => {},
+        }
+    }
+
+    // 9. 界面语言
+    let current_language = if new_config.language == Language::En { "en" } else { "zh" };
+    if let Ok(Some(lang)) = read_optional_string(tr!("[9] 语言 (zh/en)", "[9] Language (zh/en)"), current_language) {
+        match lang.to_lowercase().as_str() {
+            "zh" => new_config.language = Language::Zh,
+            "en" => new_config.language = Language::En,
+            _ => eprintln!("{} {}", "ERROR".red(), tr!("无效输入 ('{}')，保持不变。", "Invalid input ('{}'), unchanged.", lang)),
+        }
+    }
+
+    // 保存并验证新配置
+    if let Err(e) = save_config(&new_config) {
+        eprintln!("{} {}", "ERROR".red(), tr!("配置保存失败: {:?}", "Failed to save config: {:?}", e));
+    } else {
+        state.set_config(new_config);
+        println!("{}", tr!("\n配置已更新并保存到 config.txt", "\nConfiguration updated and saved to config.txt").green().bold());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        initialize_database(&conn).unwrap();
+        conn.execute("INSERT INTO user_mapping (uid, phone_number) VALUES ('1001', '13800000001')", []).unwrap();
+        conn
+    }
+
+    #[test]
+    fn readonly_query_accepts_semicolons_inside_literals() {
+        let conn = mapping_db();
+        let result = run_readonly_query(&conn, "SELECT 'a;b', uid AS \"x;y\" FROM user_mapping").unwrap();
+        assert_eq!(result.columns, ["'a;b'", "x;y"]);
+        assert_eq!(result.rows, [["a;b", "1001"]]);
+        assert_eq!(run_readonly_query(&conn, "SELECT COUNT(*) FROM user_mapping;").unwrap().rows, [["1"]]);
+    }
+
+    #[test]
+    fn readonly_query_rejects_a_second_statement() {
+        let conn = mapping_db();
+        assert!(run_readonly_query(&conn, "SELECT 1; DELETE FROM user_mapping").is_err());
+        assert!(run_readonly_query(&conn, "SELECT ';'; SELECT 2").is_err());
+        assert_eq!(conn.query_row("SELECT COUNT(*) FROM user_mapping", [], |row| row.get::<_, i64>(0)).unwrap(), 1);
+    }
+
+    #[test]
+    fn readonly_query_refuses_writes() {
+        let conn = mapping_db();
+        for sql in [
+            "DELETE FROM user_mapping",
+            "update user_mapping SET uid = 'x'",
+            "WITH doomed AS (SELECT uid FROM user_mapping) DELETE FROM user_mapping WHERE uid IN (SELECT uid FROM doomed)",
+            "PRAGMA writable_schema = 1",
+        ] {
+            assert!(run_readonly_query(&conn, sql).is_err(), "{}", sql);
+        }
+        assert_eq!(conn.query_row("SELECT COUNT(*) FROM user_mapping", [], |row| row.get::<_, i64>(0)).unwrap(), 1);
+    }
+}
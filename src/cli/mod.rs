@@ -0,0 +1,671 @@
+//! 命令行参数、子命令与启动诊断 (doctor)。
+
+use crate::*;
+
+mod db_manage;
+
+pub(crate) use self::db_manage::*;
+
+// --- 命令行参数 ---
+#[derive(Parser)]
+#[command(version = concat!(env!("CARGO_PKG_VERSION"), " (", env!("CYBER_LOOKUP_GIT_COMMIT"), ")"), about = "UID / 手机号映射查询服务")]
+pub(crate) struct Cli {
+    #[command(subcommand)]
+    pub(crate) command: Option<CliCommand>,
+    /// 管理命令以 JSON 输出结果（每行一个对象），便于脚本处理
+    #[arg(long, global = true)]
+    pub(crate) json: bool,
+    /// 先切换到该目录再读取 config.txt 与相对路径（由服务管理器启动时工作目录不可控）
+    #[arg(long, global = true, value_name = "DIR")]
+    pub(crate) workdir: Option<PathBuf>,
+    /// 依次执行脚本文件中的 db-manage 命令后退出（不启动 HTTP 服务）
+    #[arg(long, value_name = "FILE")]
+    pub(crate) script: Option<String>,
+    /// 脚本中某条命令失败后继续执行后续命令（默认遇错停止）
+    #[arg(long, requires = "script")]
+    pub(crate) continue_on_error: bool,
+    /// 执行 db-manage 命令后退出，可重复指定；"-" 表示逐行读取 stdin 中的命令
+    #[arg(long, value_name = "COMMAND", conflicts_with = "script")]
+    pub(crate) exec: Vec<String>,
+    /// 集成测试模式：忽略 config.txt，在临时目录中以默认配置启动（临时数据库、系统分配的端口），
+    /// 写入固定的测试数据，就绪后输出一行 `READY {JSON}`；收到停止信号后删除临时目录
+    #[arg(long, conflicts_with_all = ["script", "exec"])]
+    pub(crate) test_mode: bool,
+}
+
+#[derive(Subcommand)]
+pub(crate) enum ServiceAction {
+    /// 注册为开机自动启动的服务，以当前目录作为工作目录（需要管理员权限）
+    Install,
+    /// 停止并注销服务
+    Uninstall,
+    /// 由服务管理器调用：以服务方式运行，输出写入 log_file
+    Run,
+}
+
+#[derive(Subcommand)]
+pub(crate) enum CliCommand {
+    /// 从备份文件恢复数据库（HTTP 服务运行时拒绝执行）
+    Restore {
+        /// 备份文件路径，或备份目录中的文件名
+        file: String,
+        /// 跳过交互式确认
+        #[arg(long)]
+        yes: bool,
+    },
+    /// 生成随机的 UID / 手机号测试数据，用于压测和演示
+    Seed {
+        /// 要生成的记录数
+        #[arg(long)]
+        count: u64,
+        /// 每个事务写入的记录数
+        #[arg(long, default_value_t = SEED_BATCH_SIZE)]
+        batch_size: u64,
+    },
+    /// 以非交互方式运行 HTTP 服务：不进入管理模式，出错时以非零状态退出，适合由进程管理器托管
+    Serve {
+        /// 脱离终端在后台运行（仅 Unix），输出追加到 log_file
+        #[arg(long)]
+        daemon: bool,
+        /// 写入进程 ID 的文件，服务退出时删除；文件中的进程仍在运行时拒绝启动
+        #[arg(long, value_name = "FILE")]
+        pid_file: Option<String>,
+    },
+    /// 注册、注销或以 Windows 服务运行（仅 Windows）
+    Service {
+        #[command(subcommand)]
+        action: ServiceAction,
+    },
+    /// 将 sqlite 数据重新分布到指定数量的分片文件（1 表示合并回 db_path），完成后更新配置（HTTP 服务运行时拒绝执行）
+    Reshard {
+        /// 目标分片数
+        shards: u32,
+    },
+    /// 按 phone_encryption 配置加密或哈希数据库中的手机号（可重复执行；HTTP 服务运行时拒绝执行）
+    EncryptPhones {
+        /// 先生成随机密钥写入 phone_encryption.key_file（文件已存在时报错）
+        #[arg(long)]
+        generate_key: bool,
+    },
+    /// 立即按 retention 配置的规则执行一次数据保留策略（不要求 retention.enabled）
+    Retention {
+        /// 只报告将删除的条数，不实际删除
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// 校验审计日志中每条记录的签名（使用 audit 配置的签名密钥）
+    VerifyAudit {
+        /// 审计日志文件，默认为 audit.path
+        file: Option<String>,
+    },
+    /// 从 CSV、NDJSON 或 xlsx (uid, phone_number) 导入映射，支持 http(s) URL 与 gzip 压缩
+    Import {
+        /// 本地文件路径或 http(s) URL
+        file: String,
+        /// 文件格式，默认按内容自动识别
+        #[arg(long, value_enum)]
+        format: Option<DataFormat>,
+        /// uid 或手机号已属于其他记录时的处理方式
+        #[arg(long, value_enum, default_value_t = ConflictStrategy::Replace)]
+        on_conflict: ConflictStrategy,
+        /// 只解析、校验并统计将发生的变化，不提交任何修改
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// 将映射导出为 CSV、NDJSON、xlsx 或 SQL 转储，可按 uid 前缀过滤并以 gzip 压缩
+    Export {
+        file: String,
+        /// 文件格式，默认按扩展名选择 (.csv / .ndjson / .jsonl / .xlsx / .sql)
+        #[arg(long, value_enum)]
+        format: Option<DataFormat>,
+        /// 只导出 uid 以此开头的记录
+        #[arg(long)]
+        uid_prefix: Option<String>,
+        /// 以 gzip 压缩输出（文件名以 .gz 结尾时自动启用）
+        #[arg(long)]
+        gzip: bool,
+    },
+    /// 诊断配置文件、数据库与监听端口的常见问题并给出修复建议（配置无法加载时同样可用）
+    Doctor {
+        /// 不逐项确认，直接执行全部可自动完成的修复
+        #[arg(long)]
+        fix: bool,
+    },
+    /// 扫描数据质量：格式异常的手机号、可疑 uid、超长字段、重复与交叉映射；发现问题时以非零状态退出
+    Check {
+        /// 将每个问题以 NDJSON 逐行写入此文件
+        #[arg(long, value_name = "FILE")]
+        detail: Option<String>,
+    },
+    /// 通过本机控制套接字管理运行中的服务: status / count / stats / cache-flush / config [get|set|rollback] / loglevel（仅 Unix）
+    Ctl {
+        /// 命令及其参数，如 `ctl config set slow_query_ms 200`；`ctl help` 列出全部命令
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// 并发压测查询接口（或直接压测数据库层），报告延迟分位数与吞吐量
+    Bench {
+        /// 总请求数
+        #[arg(long, default_value_t = 10_000)]
+        requests: u64,
+        /// 并发数
+        #[arg(long, default_value_t = 16)]
+        concurrency: u64,
+        /// 每个请求查询的 ID 数，大于 1 时使用批量查询
+        #[arg(long, default_value_t = 1)]
+        batch: usize,
+        /// 目标实例地址（如 http://127.0.0.1:3000）；省略时直接压测数据库层
+        #[arg(long)]
+        url: Option<String>,
+    },
+}
+
+// --- 命令行子命令 ---
+pub(crate) async fn run_cli_command(state: &Arc<AppState>, command: CliCommand) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        CliCommand::Restore { file, yes } => {
+            let path = resolve_backup_path(&file, &state.current_config().backup.dest_dir);
+            let count = verify_backup(&path, state.current_config().database_key().as_deref()).map_err(|e| tr!("备份校验失败: {:?}", "Backup verification failed: {:?}", e))?;
+            println!("{} {}", "OK".green(), tr!("备份校验通过: {} ({} 条记录)", "Backup verified: {} ({} records)", path.display(), count));
+
+            if !yes {
+                // 非交互环境下无法确认，必须显式传入 --yes
+                if !is_interactive() {
+                    return Err(tr!("非交互环境下恢复需要 --yes 参数。", "Restoring without a terminal requires --yes.").into());
+                }
+                let confirm = read_line(&format!("{} {}", "WARN".red(), tr!("警告：当前数据库将被替换。确认恢复? (yes/no): ", "Warning: the current database will be replaced. Really restore? (yes/no): ")))?;
+                if confirm.to_lowercase() != "yes" {
+                    println!("{} {}", "INFO".cyan(), tr!("操作取消。", "Cancelled."));
+                    return Ok(());
+                }
+            }
+            match restore_database(state, &path).map_err(|e| tr!("恢复失败: {:?}", "Restore failed: {:?}", e))? {
+                Some(copy) => println!("{} {}", "OK".green(), tr!("恢复完成，原数据库已另存为: {}", "Restore complete, previous database saved as: {}", copy.display())),
+                None => println!("{} {}", "OK".green(), tr!("恢复完成。", "Restore complete.")),
+            }
+        }
+        CliCommand::Seed { count, batch_size } => {
+            let started = Instant::now();
+            let seeded = seed_database(state, count, batch_size).map_err(|e| tr!("生成测试数据失败: {}", "Seeding failed: {}", e))?;
+            println!("{} {}", "OK".green(), tr!("已生成 {} 条测试数据，用时 {:.2?}。", "Seeded {} records in {:.2?}.", seeded, started.elapsed()));
+        }
+        CliCommand::Serve { daemon: true, pid_file } => {
+            let log_file = daemonize(&state.current_config(), pid_file.as_deref())?;
+            println!("{} {}", "OK".green(), tr!("服务已在后台启动，输出写入 {}", "Server started in the background, logging to {}", log_file));
+        }
+        CliCommand::Serve { daemon: false, pid_file } => {
+            serve(state, pid_file.as_deref()).await?;
+        }
+        CliCommand::Service { action } => {
+            #[cfg(windows)]
+            match action {
+                ServiceAction::Install => {
+                    windows_service_host::install()?;
+                    println!("{} {}", "OK".green(), tr!("已注册服务 {}，工作目录 {}", "Service {} installed with working directory {}", windows_service_host::SERVICE_NAME, env::current_dir()?.display()));
+                }
+                ServiceAction::Uninstall => {
+                    windows_service_host::uninstall()?;
+                    println!("{} {}", "OK".green(), tr!("已注销服务 {}", "Service {} uninstalled", windows_service_host::SERVICE_NAME));
+                }
+                ServiceAction::Run => windows_service_host::run()?,
+            }
+            #[cfg(not(windows))]
+            {
+                let _ = action;
+                return Err(tr!("service 子命令仅支持 Windows；其他系统请使用 serve（可配合 systemd）。", "The service subcommand is only supported on Windows; use serve (optionally under systemd) elsewhere.").into());
+            }
+        }
+        CliCommand::Reshard { shards } => {
+            let started = Instant::now();
+            let summary = reshard_database(state, shards).map_err(|e| tr!("重新分片失败: {:?}", "Resharding failed: {:?}", e))?;
+            println!("{} {}", "OK".green(), tr!("已将 {} 条记录分布到 {} 个文件，用时 {:.2?}，配置已更新 (storage.shards = {})。", "Redistributed {} records into {} file(s) in {:.2?}; config updated (storage.shards = {}).", summary.rows, shards, started.elapsed(), shards));
+            for moved in &summary.moved_aside {
+                println!("{} {}", "INFO".yellow(), tr!("目标位置原有的文件已改名为: {}", "Existing file at the target location renamed to: {}", moved.display()));
+            }
+            println!("{} {}", "INFO".yellow(), tr!("原数据文件未删除，确认新布局无误后可手动清理: {}", "The previous data files were kept; delete them once the new layout is verified: {}", summary.previous.join(", ")));
+        }
+        CliCommand::Retention { dry_run } => {
+            let mut retention = state.current_config().retention;
+            retention.dry_run |= dry_run;
+            let report = apply_retention(state, &retention).map_err(|e| tr!("保留策略执行失败: {:?}", "Retention run failed: {:?}", e))?;
+            if json_output() {
+                print_json(serde_json::json!(report));
+            } else {
+                print_retention_report(&report);
+            }
+        }
+        CliCommand::VerifyAudit { file } => {
+            let config = state.current_config();
+            let key = config.audit.key().ok_or_else(|| tr!("未配置 audit.signing_key。", "audit.signing_key is not configured.").to_string())?;
+            let path = file.unwrap_or(config.audit.path);
+            let (total, invalid) = verify_audit_log(&path, &key).map_err(|e| tr!("读取 {} 失败: {}", "Failed to read {}: {}", path, e))?;
+            if !invalid.is_empty() {
+                let lines: Vec<String> = invalid.iter().map(ToString::to_string).collect();
+                return Err(tr!("{} 条记录签名无效 (共 {} 条，行 {})", "{} of {} record(s) have invalid signatures (lines {})", invalid.len(), total, lines.join(", ")).into());
+            }
+            println!("{} {}", "OK".green(), tr!("{} 条审计记录签名均有效。", "All {} audit record(s) have valid signatures.", total));
+        }
+        CliCommand::EncryptPhones { generate_key } => {
+            let config = state.current_config();
+            if generate_key {
+                if config.phone_encryption.key_file.is_empty() {
+                    return Err(tr!("请先配置 phone_encryption.key_file。", "Configure phone_encryption.key_file first.").into());
+                }
+                generate_phone_key(&config.phone_encryption.key_file).map_err(|e| format!("{:?}", e))?;
+                println!("{} {}", "OK".green(), tr!("已生成密钥: {}（请妥善备份，丢失后数据无法解密）", "Key generated: {} (back it up; encrypted data cannot be recovered without it)", config.phone_encryption.key_file));
+                if !config.phone_encryption.enabled {
+                    println!("{} {}", "HINT".yellow(), tr!("在配置中设置 phone_encryption.enabled = true 后重新运行 encrypt-phones。", "Set phone_encryption.enabled = true in the config and run encrypt-phones again."));
+                    return Ok(());
+                }
+            }
+            let started = Instant::now();
+            let encrypted = encrypt_phones(state).map_err(|e| tr!("加密失败: {:?}", "Encryption failed: {:?}", e))?;
+            println!("{} {}", "OK".green(), tr!("已转换 {} 条记录，用时 {:.2?}。", "Converted {} record(s) in {:.2?}.", encrypted, started.elapsed()));
+            println!("{} {}", "HINT".yellow(), tr!("迁移前的备份仍包含明文手机号，请按需清理。", "Backups taken before the migration still contain plaintext phone numbers; remove them as appropriate."));
+        }
+        CliCommand::Import { file, format, on_conflict, dry_run } => {
+            let started = Instant::now();
+            let summary = import_mappings(state, &file, format, on_conflict, dry_run).map_err(|e| tr!("导入失败: {:?}", "Import failed: {:?}", e))?;
+            if json_output() {
+                print_json(serde_json::json!({ "dry_run": dry_run, "summary": summary }));
+            } else if dry_run {
+                println!("{} {}", "DRY RUN".cyan(), tr!("{}（未做任何修改）", "{} (nothing changed)", summary.describe()));
+            } else {
+                println!("{} {}", "OK".green(), tr!("导入完成，用时 {:.2?}: {}", "Import finished in {:.2?}: {}", started.elapsed(), summary.describe()));
+            }
+        }
+        CliCommand::Export { file, format, uid_prefix, gzip } => {
+            let started = Instant::now();
+            let exported = export_mappings(state, &file, &ExportOptions { uid_prefix, gzip, format }).map_err(|e| tr!("导出失败: {:?}", "Export failed: {:?}", e))?;
+            println!("{} {}", "OK".green(), tr!("已导出 {} 条记录到 {}，用时 {:.2?}。", "Exported {} records to {} in {:.2?}.", exported, file, started.elapsed()));
+        }
+        CliCommand::Doctor { fix } => doctor_command(fix)?,
+        CliCommand::Ctl { args } => ctl_command(&state.current_config(), args)?,
+        CliCommand::Check { detail } => {
+            let report = run_quality_check(state, detail.as_deref()).map_err(|e| tr!("数据质量检查失败: {:?}", "Data quality check failed: {:?}", e))?;
+            if json_output() {
+                print_json(serde_json::to_value(&report)?);
+            } else {
+                print_quality_report(&report);
+            }
+            if report.total_issues > 0 {
+                return Err(tr!("发现 {} 个数据质量问题", "Found {} data quality issue(s)", report.total_issues).into());
+            }
+        }
+        CliCommand::Bench { requests, concurrency, batch, url } => {
+            let target = url.clone().unwrap_or_else(|| tr!("数据库层", "database layer").to_string());
+            if !json_output() {
+                println!("{} {}", "INFO".yellow(), tr!("正在压测 {}: {} 个请求，并发 {}，每请求 {} 个 ID ...", "Benchmarking {}: {} requests, concurrency {}, {} ID(s) per request ...", target, requests, concurrency, batch));
+            }
+            let report = run_bench(state.clone(), requests, concurrency, batch, url).await
+                .map_err(|e| tr!("压测失败: {}", "Benchmark failed: {}", e))?;
+            if json_output() {
+                print_json(serde_json::to_value(&report)?);
+            } else {
+                println!("{} {}", "OK".green(), tr!("完成 {} 个请求 (失败 {})，用时 {} ms", "Completed {} requests ({} failed) in {} ms", report.requests, report.errors, report.elapsed_ms));
+                println!("{} {}", "INFO".yellow(), tr!("吞吐: {:.0} 请求/秒, {:.0} ID/秒", "Throughput: {:.0} req/s, {:.0} IDs/s", report.requests_per_sec, report.ids_per_sec));
+                println!("{} {}", "INFO".yellow(), tr!("延迟: p50 {:.2} ms, p95 {:.2} ms, p99 {:.2} ms, 最大 {:.2} ms", "Latency: p50 {:.2} ms, p95 {:.2} ms, p99 {:.2} ms, max {:.2} ms", report.p50_ms, report.p95_ms, report.p99_ms, report.max_ms));
+            }
+        }
+    }
+    Ok(())
+}
+
+// --- 启动诊断 (doctor) ---
+
+/// 一次诊断最多进行的轮数：修复后重新诊断，以发现被前一个问题掩盖的问题
+const DOCTOR_MAX_ROUNDS: usize = 3;
+/// 端口被占用时向后寻找空闲端口的范围
+const DOCTOR_PORT_SEARCH: u16 = 100;
+
+/// doctor 能够自动执行的修复
+#[derive(Debug, Clone)]
+enum DoctorFix {
+    RegenerateConfig,           // 备份无法解析的配置文件，按默认值重新生成
+    ResetConfigField(String),   // 把导致校验失败的顶层配置项恢复为默认值
+    CreateDir(PathBuf),         // 创建数据库所在的目录
+    ChangeBindAddress(String),  // 改用空闲的监听地址
+}
+
+impl DoctorFix {
+    fn describe(&self) -> String {
+        match self {
+            DoctorFix::RegenerateConfig => tr!("备份 {} 后按默认值重新生成", "back up {} and regenerate it with defaults", DEFAULT_CONFIG_FILE),
+            DoctorFix::ResetConfigField(key) => tr!("将配置项 {} 恢复为默认值", "reset the {} setting to its default", key),
+            DoctorFix::CreateDir(dir) => tr!("创建目录 {}", "create the directory {}", dir.display()),
+            DoctorFix::ChangeBindAddress(addr) => tr!("将 bind_address 改为空闲的 {}", "change bind_address to the free address {}", addr),
+        }
+    }
+}
+
+struct DoctorFinding {
+    problem: String,
+    hint: Option<String>,
+    fix: Option<DoctorFix>,
+}
+
+impl DoctorFinding {
+    fn new(problem: String, hint: Option<String>, fix: Option<DoctorFix>) -> Self {
+        DoctorFinding { problem, hint, fix }
+    }
+}
+
+/// 解析并校验配置，错误以文字返回
+pub(crate) fn parse_config_value(value: &serde_json::Value) -> Result<ServiceConfig, String> {
+    let config: ServiceConfig = serde_json::from_value(value.clone()).map_err(|e| e.to_string())?;
+    config.validate()?;
+    Ok(config)
+}
+
+/// 找出单独恢复默认值即可通过校验的顶层配置项（缺失的字段按默认值处理，直接去掉即可）
+fn find_faulty_field(value: &serde_json::Value) -> Option<String> {
+    let object = value.as_object()?;
+    object.keys().find(|key| {
+        let mut candidate = object.clone();
+        candidate.remove(*key);
+        parse_config_value(&serde_json::Value::Object(candidate)).is_ok()
+    }).cloned()
+}
+
+/// 依次检查配置文件、数据库与监听端口。配置有误时后续检查使用修复后的配置，无法修复则就此停止。
+fn diagnose() -> Vec<DoctorFinding> {
+    let mut findings = Vec::new();
+    let raw = match fs::read_to_string(DEFAULT_CONFIG_FILE) {
+        Ok(raw) => Some(raw),
+        // 不存在时启动会按默认值创建
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => {
+            findings.push(DoctorFinding::new(tr!("无法读取 {}: {}", "Cannot read {}: {}", DEFAULT_CONFIG_FILE, e), Some(tr!("检查文件权限。", "Check the file permissions.").to_string()), None));
+            return findings;
+        }
+    };
+    let config = match raw {
+        None => ServiceConfig::default(),
+        Some(raw) => {
+            let value: serde_json::Value = match serde_json::from_str(&raw) {
+                Ok(value) => value,
+                Err(e) => {
+                    findings.push(DoctorFinding::new(
+                        tr!("{} 不是有效的 JSON (第 {} 行第 {} 列): {}", "{} is not valid JSON (line {}, column {}): {}", DEFAULT_CONFIG_FILE, e.line(), e.column(), e),
+                        Some(tr!("也可以手动修正该位置后重新启动。", "You can also correct it by hand and start again.").to_string()),
+                        Some(DoctorFix::RegenerateConfig)));
+                    return findings;
+                }
+            };
+            match parse_config_value(&value) {
+                Ok(config) => config,
+                Err(e) => {
+                    let faulty = find_faulty_field(&value);
+                    let hint = match &faulty {
+                        Some(_) => None,
+                        None => Some(tr!("无法定位到单个配置项，请手动编辑 {}。", "Could not pin it to a single setting; edit {} by hand.", DEFAULT_CONFIG_FILE)),
+                    };
+                    findings.push(DoctorFinding::new(tr!("配置校验失败: {}", "Config validation failed: {}", e), hint, faulty.clone().map(DoctorFix::ResetConfigField)));
+                    let mut fixed = value;
+                    match (faulty, fixed.as_object_mut()) {
+                        (Some(key), Some(object)) => { object.remove(&key); }
+                        _ => return findings,
+                    }
+                    match parse_config_value(&fixed) {
+                        Ok(config) => config,
+                        Err(_) => return findings,
+                    }
+                }
+            }
+        }
+    };
+    set_language(config.language);
+    diagnose_database(&config, &mut findings);
+    diagnose_port(&config, &mut findings);
+    findings
+}
+
+fn diagnose_database(config: &ServiceConfig, findings: &mut Vec<DoctorFinding>) {
+    if config.storage_backend() != StorageBackend::Sqlite {
+        return;
+    }
+    let path = FilePath::new(&config.db_path);
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty() && !dir.exists()) {
+        findings.push(DoctorFinding::new(tr!("数据库目录 {} 不存在", "The database directory {} does not exist", dir.display()), None, Some(DoctorFix::CreateDir(dir.to_path_buf()))));
+        return;
+    }
+    // 文件不存在时启动会自动创建
+    if !path.exists() {
+        return;
+    }
+    let probe = AppState::new(config.clone());
+    let checked = probe.get_db_connection().and_then(|conn| {
+        conn.busy_timeout(Duration::from_millis(200))?;
+        conn.query_row("PRAGMA schema_version", [], |row| row.get::<_, i64>(0))?;
+        // 取得写锁再立即释放，确认没有其他进程长期持有
+        conn.execute_batch("BEGIN IMMEDIATE; ROLLBACK;")
+    });
+    let Err(e) = checked else { return };
+    let code = match &e {
+        SqlError::SqliteFailure(failure, _) => Some(failure.code),
+        _ => None,
+    };
+    let finding = match code {
+        Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) => {
+            let holders = file_holders(path);
+            let hint = if holders.is_empty() {
+                tr!("确认没有其他 cyber_lookup 实例或工具正在使用该文件。", "Make sure no other cyber_lookup instance or tool is using the file.").to_string()
+            } else {
+                let list: Vec<String> = holders.iter().map(|(pid, name)| format!("{} ({})", pid, name)).collect();
+                tr!("持有该文件的进程: {}；停止它们后重试。", "Processes holding the file: {}; stop them and retry.", list.join(", "))
+            };
+            DoctorFinding::new(tr!("数据库 {} 被其他进程锁定", "The database {} is locked by another process", config.db_path), Some(hint), None)
+        }
+        Some(ErrorCode::NotADatabase) => DoctorFinding::new(
+            tr!("{} 不是 SQLite 数据库，或 database_key 与加密时使用的密钥不符", "{} is not an SQLite database, or database_key does not match the key it was encrypted with", config.db_path),
+            Some(tr!("使用 restore 从备份恢复，或修正 database_key。", "Restore from a backup, or correct database_key.").to_string()), None),
+        Some(ErrorCode::ReadOnly | ErrorCode::CannotOpen | ErrorCode::PermissionDenied) => DoctorFinding::new(
+            tr!("无法以读写方式打开数据库 {}: {}", "Cannot open the database {} for writing: {}", config.db_path, e),
+            Some(tr!("检查文件及其目录的权限。", "Check the permissions of the file and its directory.").to_string()), None),
+        _ => DoctorFinding::new(tr!("无法打开数据库 {}: {}", "Cannot open the database {}: {}", config.db_path, e), None, None),
+    };
+    findings.push(finding);
+}
+
+fn diagnose_port(config: &ServiceConfig, findings: &mut Vec<DoctorFinding>) {
+    let Ok(addr) = config.bind_address.parse::<SocketAddr>() else { return };
+    if addr.port() == 0 || std::net::TcpListener::bind(addr).is_ok() {
+        return;
+    }
+    let owner = port_owner(addr.port())
+        .map(|(pid, name)| tr!("，占用进程 {} ({})", " by process {} ({})", pid, name))
+        .unwrap_or_default();
+    let free = (1..=DOCTOR_PORT_SEARCH)
+        .filter_map(|offset| addr.port().checked_add(offset))
+        .map(|port| SocketAddr::new(addr.ip(), port))
+        .find(|candidate| std::net::TcpListener::bind(candidate).is_ok());
+    findings.push(DoctorFinding::new(
+        tr!("监听地址 {} 已被占用{}", "The listen address {} is already in use{}", addr, owner),
+        Some(tr!("也可以停止占用端口的进程。", "Alternatively, stop the process holding the port.").to_string()),
+        free.map(|free| DoctorFix::ChangeBindAddress(free.to_string()))));
+}
+
+/// 打开了指定文件的进程 (PID, 名称)，通过 /proc/*/fd 查找（仅 Linux）
+#[cfg(target_os = "linux")]
+fn file_holders(path: &FilePath) -> Vec<(u32, String)> {
+    match fs::canonicalize(path) {
+        Ok(target) => fd_holders(|link| link == target),
+        Err(_) => Vec::new(),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn file_holders(_path: &FilePath) -> Vec<(u32, String)> {
+    Vec::new()
+}
+
+/// 在指定端口上监听的进程 (PID, 名称)：从 /proc/net/tcp{,6} 找到监听套接字的 inode，再查找持有它的进程（仅 Linux）
+#[cfg(target_os = "linux")]
+fn port_owner(port: u16) -> Option<(u32, String)> {
+    const TCP_LISTEN: &str = "0A";
+    let mut sockets = HashSet::new();
+    for table in ["/proc/net/tcp", "/proc/net/tcp6"] {
+        for line in fs::read_to_string(table).unwrap_or_default().lines().skip(1) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            // 第 2 列为十六进制的 地址:端口，第 4 列为状态，第 10 列为 inode
+            let local_port = fields.get(1).and_then(|local| local.rsplit(':').next()).and_then(|p| u16::from_str_radix(p, 16).ok());
+            if local_port == Some(port) && fields.get(3) == Some(&TCP_LISTEN) {
+                if let Some(inode) = fields.get(9) {
+                    sockets.insert(PathBuf::from(format!("socket:[{}]", inode)));
+                }
+            }
+        }
+    }
+    fd_holders(|link| sockets.contains(link)).into_iter().next()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn port_owner(_port: u16) -> Option<(u32, String)> {
+    None
+}
+
+/// 有文件描述符指向满足条件的目标的进程；无权查看的进程被跳过
+#[cfg(target_os = "linux")]
+fn fd_holders(matches: impl Fn(&FilePath) -> bool) -> Vec<(u32, String)> {
+    let mut holders = Vec::new();
+    for entry in fs::read_dir("/proc").into_iter().flatten().flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|name| name.parse::<u32>().ok()) else { continue };
+        let Ok(fds) = fs::read_dir(entry.path().join("fd")) else { continue };
+        if fds.flatten().any(|fd| fs::read_link(fd.path()).is_ok_and(|link| matches(&link))) {
+            let name = fs::read_to_string(entry.path().join("comm")).map(|name| name.trim().to_string()).unwrap_or_default();
+            holders.push((pid, name));
+        }
+    }
+    holders
+}
+
+/// 执行修复，返回所做操作的说明
+fn apply_doctor_fix(fix: &DoctorFix) -> Result<String, AppError> {
+    // 在原始 JSON 上修改后重新保存，保留其余配置项
+    let edit_config = |edit: &dyn Fn(&mut serde_json::Map<String, serde_json::Value>)| -> Result<(), AppError> {
+        let mut value: serde_json::Value = serde_json::from_str(&fs::read_to_string(DEFAULT_CONFIG_FILE)?)?;
+        if let Some(object) = value.as_object_mut() {
+            edit(object);
+        }
+        let config = parse_config_value(&value).map_err(AppError::FatalError)?;
+        save_config(&config)
+    };
+    match fix {
+        DoctorFix::RegenerateConfig => {
+            let backup = format!("{}.broken-{}", DEFAULT_CONFIG_FILE, Local::now().format("%Y%m%d%H%M%S"));
+            fs::rename(DEFAULT_CONFIG_FILE, &backup)?;
+            save_config(&ServiceConfig::default())?;
+            Ok(tr!("已按默认值重新生成 {}，原文件另存为 {}", "Regenerated {} with defaults; the original was saved as {}", DEFAULT_CONFIG_FILE, backup))
+        }
+        DoctorFix::ResetConfigField(key) => {
+            edit_config(&|object| { object.remove(key); })?;
+            Ok(tr!("已将 {} 恢复为默认值", "Reset {} to its default", key))
+        }
+        DoctorFix::CreateDir(dir) => {
+            fs::create_dir_all(dir)?;
+            Ok(tr!("已创建目录 {}", "Created the directory {}", dir.display()))
+        }
+        DoctorFix::ChangeBindAddress(addr) => {
+            edit_config(&|object| { object.insert("bind_address".to_string(), serde_json::Value::String(addr.clone())); })?;
+            Ok(tr!("bind_address 已改为 {}", "bind_address changed to {}", addr))
+        }
+    }
+}
+
+/// 诊断并修复：`fix_all` 时直接执行全部修复，否则在交互终端中逐项确认。
+/// 修复后重新诊断，直到没有新的修复可做。返回 (已执行的修复数, 仍未解决的问题数)。
+pub(crate) fn run_doctor(fix_all: bool) -> (usize, usize) {
+    let mut applied = 0;
+    let mut reported = HashSet::new();
+    let mut unresolved = Vec::new();
+    for _ in 0..DOCTOR_MAX_ROUNDS {
+        let mut progressed = false;
+        unresolved.clear();
+        for finding in diagnose() {
+            let first_report = reported.insert(finding.problem.clone());
+            if first_report {
+                eprintln!("{} {}", "PROBLEM".red(), finding.problem);
+                if let Some(hint) = &finding.hint {
+                    println!("{} {}", "HINT".yellow(), hint);
+                }
+            }
+            let Some(fix) = finding.fix else {
+                unresolved.push(finding.problem);
+                continue;
+            };
+            let confirmed = fix_all || (first_report && is_interactive() && read_line(&format!("{} {}", "FIX".cyan(), tr!("建议{}。现在执行? (yes/no): ", "Suggested fix: {}. Apply it now? (yes/no): ", fix.describe())))
+                .is_ok_and(|answer| answer.eq_ignore_ascii_case("yes")));
+            if !confirmed {
+                if first_report {
+                    println!("{} {}", "HINT".yellow(), tr!("可运行 'cyber_lookup doctor --fix' 自动{}。", "Run 'cyber_lookup doctor --fix' to {} automatically.", fix.describe()));
+                }
+                unresolved.push(finding.problem);
+                continue;
+            }
+            match apply_doctor_fix(&fix) {
+                Ok(done) => {
+                    println!("{} {}", "OK".green(), done);
+                    applied += 1;
+                    progressed = true;
+                }
+                Err(e) => {
+                    eprintln!("{} {}", "ERR".red(), tr!("修复失败: {:?}", "Fix failed: {:?}", e));
+                    unresolved.push(finding.problem);
+                }
+            }
+        }
+        if !progressed {
+            break;
+        }
+    }
+    if unresolved.is_empty() {
+        println!("{} {}", "OK".green(), tr!("未发现需要处理的问题。", "No outstanding problems found."));
+    }
+    (applied, unresolved.len())
+}
+
+/// `cyber_lookup doctor [--fix]`
+pub(crate) fn doctor_command(fix: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let (_, unresolved) = run_doctor(fix);
+    if unresolved > 0 {
+        return Err(tr!("仍有 {} 个问题未解决", "{} problem(s) remain unresolved", unresolved).into());
+    }
+    Ok(())
+}
+
+/// 服务因数据库或监听失败无法启动时，在交互终端中运行诊断；执行了修复则重新加载配置并返回 true，供调用方重试启动
+pub(crate) fn recover_with_doctor(state: &AppState) -> bool {
+    if !is_interactive() {
+        println!("{} {}", "HINT".yellow(), tr!("可运行 'cyber_lookup doctor' 诊断启动问题。", "Run 'cyber_lookup doctor' to diagnose startup problems."));
+        return false;
+    }
+    println!("{} {}", "INFO".yellow(), tr!("正在运行启动诊断...", "Running startup diagnostics..."));
+    let (applied, _) = run_doctor(false);
+    if applied == 0 {
+        return false;
+    }
+    match load_config() {
+        Ok(config) => {
+            state.set_config(config);
+            true
+        }
+        Err(e) => {
+            eprintln!("{} {}", "ERR".red(), tr!("重新加载配置失败: {:?}", "Reloading the configuration failed: {:?}", e));
+            false
+        }
+    }
+}
+
+/// 报告配置加载失败，返回给调用方的错误信息
+pub(crate) fn config_load_error(e: &AppError) -> String {
+    match e {
+        AppError::FatalError(m) => {
+            eprintln!("{} {}", "FATAL".red(), tr!("致命错误: {}", "Fatal: {}", m));
+            tr!("配置校验失败。", "Configuration failed validation.").to_string()
+        }
+        e => {
+            eprintln!("{} {}", "FATAL".red(), tr!("致命错误: 配置加载失败: {:?}", "Fatal: failed to load configuration: {:?}", e));
+            tr!("配置加载失败。", "Configuration failed to load.").to_string()
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! GraphQL 接口。
+
+use crate::*;
+
+/// 一条映射（手机号按调用方权限脱敏）
+#[derive(async_graphql::SimpleObject, Clone)]
+#[graphql(name = "Mapping")]
+struct GraphqlMapping {
+    uid: String,
+    phone_number: String,
+}
+
+/// 服务统计，字段含义同 /stats
+#[derive(async_graphql::SimpleObject)]
+#[graphql(name = "Stats")]
+struct GraphqlStats {
+    records: Option<u64>,            // 存储后端不可用时为 null
+    cache_entries: u64,
+    cache_hits: u64,
+    cache_misses: u64,
+    cache_hit_rate: f64,
+    bloom_negatives: u64,
+    slow_queries: u64,
+    ip_denied: u64,
+}
+
+/// 把同一请求中的 byUid / byPhone 合并为一次批量查询（经过布隆过滤器与缓存）
+struct MappingLoader {
+    state: Arc<AppState>,
+}
+
+impl async_graphql::dataloader::Loader<String> for MappingLoader {
+    type Value = (String, String);
+    type Error = Arc<AppError>;
+
+    async fn load(&self, keys: &[String]) -> Result<HashMap<String, (String, String)>, Self::Error> {
+        let config = self.state.current_config();
+        let results = resolve_batch(&self.state, &config, keys).await.map_err(Arc::new)?;
+        Ok(keys.iter().zip(results)
+            .filter_map(|(key, resp)| Some((key.clone(), (resp.uid?, resp.phone_number?))))
+            .collect())
+    }
+}
+
+type GraphqlLoader = async_graphql::dataloader::DataLoader<MappingLoader>;
+type GraphqlSchema = async_graphql::Schema<GraphqlQuery, async_graphql::EmptyMutation, async_graphql::EmptySubscription>;
+
+fn graphql_schema() -> &'static GraphqlSchema {
+    static SCHEMA: OnceLock<GraphqlSchema> = OnceLock::new();
+    SCHEMA.get_or_init(|| {
+        async_graphql::Schema::build(GraphqlQuery, async_graphql::EmptyMutation, async_graphql::EmptySubscription)
+            .limit_depth(GRAPHQL_MAX_DEPTH)
+            .limit_complexity(GRAPHQL_MAX_COMPLEXITY)
+            .finish()
+    })
+}
+
+/// 错误信息同 HTTP 接口，错误码放在 extensions.code
+fn graphql_error(e: &AppError) -> async_graphql::Error {
+    let code = e.code().as_str();
+    async_graphql::ErrorExtensions::extend_with(async_graphql::Error::new(e.to_string()), |_, ext| ext.set("code", code))
+}
+
+struct GraphqlQuery;
+
+impl GraphqlQuery {
+    /// 经 DataLoader 按 uid 或手机号查询，返回脱敏前的记录
+    async fn load(ctx: &async_graphql::Context<'_>, id: &String) -> async_graphql::Result<Option<(String, String)>> {
+        validate_ids([id]).map_err(|e| graphql_error(&e))?;
+        ctx.data_unchecked::<Arc<GraphqlLoader>>().load_one(canonicalize_id(id)).await.map_err(|e| graphql_error(&e))
+    }
+
+    fn masked(ctx: &async_graphql::Context<'_>, (uid, phone_number): (String, String)) -> GraphqlMapping {
+        let config = ctx.data_unchecked::<Arc<AppState>>().current_config();
+        let phone_number = if should_mask(&config) { mask_phone(&phone_number) } else { phone_number };
+        GraphqlMapping { uid, phone_number }
+    }
+}
+
+#[async_graphql::Object]
+impl GraphqlQuery {
+    /// 按 uid 查询映射，不存在时为 null
+    async fn by_uid(&self, ctx: &async_graphql::Context<'_>, uid: String) -> async_graphql::Result<Option<GraphqlMapping>> {
+        let found = Self::load(ctx, &uid).await?.filter(|(found_uid, _)| *found_uid == canonicalize_id(&uid));
+        Ok(found.map(|mapping| Self::masked(ctx, mapping)))
+    }
+
+    /// 按手机号查询映射，不存在时为 null
+    async fn by_phone(&self, ctx: &async_graphql::Context<'_>, phone: String) -> async_graphql::Result<Option<GraphqlMapping>> {
+        let mut found = Self::load(ctx, &phone).await?.filter(|(_, found_phone)| *found_phone == canonicalize_id(&phone));
+        let rules = ctx.data_unchecked::<Arc<AppState>>().current_config().phone_rules;
+        if let (None, Some(variant)) = (&found, rules.lookup_variant(&canonicalize_id(&phone))) {
+            found = Self::load(ctx, &variant).await?.filter(|(_, found_phone)| *found_phone == variant);
+        }
+        Ok(found.map(|mapping| Self::masked(ctx, mapping)))
+    }
+
+    /// 按 uid 前缀列出映射（按写入顺序），最多 `limit` 条（不超过 batch_size_limit）
+    async fn search(&self, ctx: &async_graphql::Context<'_>, uid_prefix: String, #[graphql(default = 20)] limit: u32) -> async_graphql::Result<Vec<GraphqlMapping>> {
+        let state = ctx.data_unchecked::<Arc<AppState>>();
+        let config = state.current_config();
+        check_batch_size(&config, limit as usize).map_err(|e| graphql_error(&e))?;
+        if !uid_prefix.is_empty() {
+            validate_ids([&uid_prefix]).map_err(|e| graphql_error(&e))?;
+        }
+        let store = state.store().map_err(|e| graphql_error(&e))?;
+        let found = Arc::new(Mutex::new(Vec::new()));
+        let sink_found = found.clone();
+        let limit = limit as usize;
+        // 取够 limit 条后让 sink 出错以中止导出
+        let exported = store.export(Some(uid_prefix), Box::new(move |uid, phone| {
+            let mut found = sink_found.lock().unwrap();
+            if found.len() >= limit {
+                return Err(io::Error::new(io::ErrorKind::Interrupted, "limit reached"));
+            }
+            found.push((uid.to_string(), phone.to_string()));
+            Ok(())
+        })).await;
+        let found = std::mem::take(&mut *found.lock().unwrap());
+        if let Err(e) = exported {
+            if found.len() < limit {
+                return Err(graphql_error(&e));
+            }
+        }
+        Ok(found.into_iter().map(|mapping| Self::masked(ctx, mapping)).collect())
+    }
+
+    /// 服务统计
+    async fn stats(&self, ctx: &async_graphql::Context<'_>) -> async_graphql::Result<GraphqlStats> {
+        let state = ctx.data_unchecked::<Arc<AppState>>().clone();
+        let records = match state.store() {
+            Ok(store) => store.count(None).await.ok(),
+            Err(_) => None,
+        };
+        // 估算缓存内存需要遍历全部条目，放到阻塞线程池执行
+        let stats = task::spawn_blocking(move || state.stats()).await
+            .map_err(|e| async_graphql::Error::new(e.to_string()))?;
+        Ok(GraphqlStats {
+            records,
+            cache_entries: stats.cache.entries,
+            cache_hits: stats.cache.hits,
+            cache_misses: stats.cache.misses,
+            cache_hit_rate: stats.cache.hit_rate,
+            bloom_negatives: stats.bloom.negatives,
+            slow_queries: stats.slow_queries.total,
+            ip_denied: stats.ip_access.denied,
+        })
+    }
+}
+
+/// GraphQL 查询 (byUid / byPhone / search / stats)，需要 read 权限。请求体为标准的
+/// `{"query": ..., "variables": ...}`，或其数组（批量，共用一个 DataLoader）。
+pub(crate) async fn api_graphql(State(state): State<Arc<AppState>>, Json(request): Json<async_graphql::BatchRequest>) -> Json<async_graphql::BatchResponse> {
+    let config = state.current_config();
+    // DataLoader 在新任务中执行查询，沿用本请求的上下文（调用方、请求 ID）
+    let loader = async_graphql::dataloader::DataLoader::new(MappingLoader { state: state.clone() }, |future| {
+        tokio::spawn(RequestContext::capture().scope(future))
+    }).max_batch_size(config.batch_size_limit as usize);
+    let request = request.data(state).data(Arc::new(loader));
+    Json(graphql_schema().execute_batch(request).await)
+}
@@ -0,0 +1,154 @@
+//! gRPC 接口。
+
+use crate::*;
+
+mod grpc_proto {
+    tonic::include_proto!("cyber_lookup.v1");
+}
+
+use grpc_proto::mapping_lookup_server::{MappingLookup, MappingLookupServer};
+use grpc_proto::{BatchLookupReply, BatchLookupRequest, LookupReply, LookupRequest, WatchRequest};
+
+/// gRPC 服务：与 HTTP 接口共用存储层、缓存、API 密钥与 ip_allowlist / ip_denylist，要求 read 权限
+struct GrpcLookup {
+    state: Arc<AppState>,
+}
+
+/// 绑定 grpc_bind_address 并在后台提供 gRPC 服务；返回值被丢弃时服务停止
+pub(crate) async fn start_grpc_server(state: Arc<AppState>, bind_address: &str) -> Result<AbortOnDrop<()>, AppError> {
+    let addr: SocketAddr = bind_address.parse()
+        .map_err(|e| AppError::FatalError(tr!("配置错误: grpc_bind_address 格式无效: {}", "Config Error: Invalid grpc_bind_address: {}", e)))?;
+    let listener = tokio::net::TcpListener::bind(addr).await.map_err(AppError::NetworkBindError)?;
+    let addr = listener.local_addr().map_err(AppError::NetworkBindError)?;
+    let incoming = tonic::transport::server::TcpIncoming::from_listener(listener, true, None)
+        .map_err(|e| AppError::FatalError(e.to_string()))?;
+    println!("{} {}", "STARTED".green().bold(), tr!("gRPC 服务监听地址: {} (cyber_lookup.v1.MappingLookup)", "gRPC server listening on {} (cyber_lookup.v1.MappingLookup)", addr));
+    state.listening.lock().unwrap().grpc = Some(addr);
+    let service = MappingLookupServer::new(GrpcLookup { state });
+    Ok(AbortOnDrop(tokio::spawn(async move {
+        if let Err(e) = tonic::transport::Server::builder().add_service(service).serve_with_incoming(incoming).await {
+            eprintln!("{} {}", "ERR".red(), tr!("gRPC 服务异常停止: {}", "gRPC server stopped: {}", e));
+        }
+    })))
+}
+
+fn grpc_status(e: AppError) -> tonic::Status {
+    let message = e.to_string();
+    match e {
+        AppError::Unauthorized | AppError::InvalidSignature(_) => tonic::Status::unauthenticated(message),
+        AppError::Forbidden(_) => tonic::Status::permission_denied(message),
+        AppError::Rejected(..) | AppError::PayloadTooLarge(_) => tonic::Status::invalid_argument(message),
+        AppError::Timeout(_) => tonic::Status::deadline_exceeded(message),
+        AppError::CircuitOpen(_) | AppError::StoreUnavailable(_) => tonic::Status::unavailable(message),
+        AppError::DbError(_) if e.code() == ApiErrorCode::DbUnavailable => tonic::Status::unavailable(message),
+        _ => tonic::Status::internal(message),
+    }
+}
+
+fn lookup_reply(id: &str, resp: LookupResponse) -> LookupReply {
+    let matched_by = resp.matched_by.map(|field| field.as_str().to_string());
+    LookupReply { id: id.to_string(), status: resp.status, uid: resp.uid, phone_number: resp.phone_number, matched_by, error: resp.error }
+}
+
+impl GrpcLookup {
+    /// 检查客户端地址并按 metadata 识别调用方，要求 read 权限；返回当前配置与调用方
+    async fn authorize<T>(&self, request: &tonic::Request<T>) -> Result<(ServiceConfig, Caller), tonic::Status> {
+        let config = self.state.current_config();
+        if let Some(addr) = request.remote_addr() {
+            check_ip_access(&self.state, &config, addr.ip()).map_err(grpc_status)?;
+        }
+        let headers = request.metadata().clone().into_headers();
+        let caller = resolve_caller(&self.state, &config, &headers).await.map_err(grpc_status)?;
+        if !caller.has_scope(ApiScope::Read) {
+            return Err(tonic::Status::permission_denied(tr!("调用方没有 read 权限。", "The caller lacks the read scope.")));
+        }
+        Ok((config, caller))
+    }
+}
+
+#[tonic::async_trait]
+impl MappingLookup for GrpcLookup {
+    async fn lookup(&self, request: tonic::Request<LookupRequest>) -> Result<tonic::Response<LookupReply>, tonic::Status> {
+        let (config, caller) = self.authorize(&request).await?;
+        let id = request.into_inner().id;
+        let state = self.state.clone();
+        CALLER.scope(caller, async move {
+            validate_ids([&id])?;
+            let started = Instant::now();
+            let mut resp = lookup(&state, &id).await?;
+            state.record_query(Some(&id), std::slice::from_ref(&resp), started);
+            apply_post_process_hook(&state, std::slice::from_mut(&mut resp))?;
+            mask_results(&config, std::slice::from_mut(&mut resp));
+            Ok(tonic::Response::new(lookup_reply(&id, resp)))
+        }).await.map_err(grpc_status)
+    }
+
+    async fn batch_lookup(&self, request: tonic::Request<BatchLookupRequest>) -> Result<tonic::Response<BatchLookupReply>, tonic::Status> {
+        let (config, caller) = self.authorize(&request).await?;
+        let ids = request.into_inner().ids;
+        let state = self.state.clone();
+        CALLER.scope(caller, async move {
+            check_batch_size(&config, ids.len())?;
+            let mut results = resolve_batch(&state, &config, &ids).await?;
+            apply_post_process_hook(&state, &mut results)?;
+            mask_results(&config, &mut results);
+            let results = ids.iter().zip(results).map(|(id, resp)| lookup_reply(id, resp)).collect();
+            Ok(tonic::Response::new(BatchLookupReply { results }))
+        }).await.map_err(grpc_status)
+    }
+
+    type WatchStream = ReceiverStream<Result<LookupReply, tonic::Status>>;
+
+    /// 先推送每个 ID 的当前结果，之后在本进程的写入后、以及每 WATCH_POLL_SECS 秒（覆盖其他实例的写入）
+    /// 直接查询存储（不经缓存），只推送结果有变化的 ID。客户端取消时停止。
+    async fn watch(&self, request: tonic::Request<WatchRequest>) -> Result<tonic::Response<Self::WatchStream>, tonic::Status> {
+        let (config, caller) = self.authorize(&request).await?;
+        let mut ids: Vec<String> = Vec::new();
+        for id in request.into_inner().ids.iter().map(|id| canonicalize_id(id)) {
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+        check_batch_size(&config, ids.len()).and_then(|_| validate_ids(&ids)).map_err(grpc_status)?;
+        let mask = CALLER.sync_scope(caller, || should_mask(&config));
+
+        let (tx, rx) = mpsc::channel(WATCH_CHANNEL_CAPACITY);
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            let mut changes = state.changes.subscribe();
+            let mut last: HashMap<String, LookupReply> = HashMap::new();
+            loop {
+                let results = match state.store() {
+                    Ok(store) => store.batch_lookup(ids.clone()).await,
+                    Err(e) => Err(e),
+                };
+                let mut results = match results.and_then(|mut results| {
+                    apply_post_process_hook(&state, &mut results)?;
+                    Ok(results)
+                }) {
+                    Ok(results) => results,
+                    Err(e) => {
+                        let _ = tx.send(Err(grpc_status(e))).await;
+                        return;
+                    }
+                };
+                redact_phones(&mut results, mask);
+                for (id, resp) in ids.iter().zip(results) {
+                    let reply = lookup_reply(id, resp);
+                    if last.get(id) != Some(&reply) {
+                        last.insert(id.clone(), reply.clone());
+                        if tx.send(Ok(reply)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                tokio::select! {
+                    _ = tx.closed() => return,
+                    _ = changes.changed() => {}
+                    _ = sleep(Duration::from_secs(WATCH_POLL_SECS)) => {}
+                }
+            }
+        });
+        Ok(tonic::Response::new(ReceiverStream::new(rx)))
+    }
+}
@@ -0,0 +1,478 @@
+//! 调用方认证与授权：管理令牌、API 密钥、JWT、请求签名校验与查询响应签名。
+
+use crate::*;
+
+// --- 管理接口与控制台 ---
+
+/// 常量时间比较，避免通过响应时间逐字节猜测令牌。
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// 从 Authorization 头取出客户端提供的令牌：`Bearer <令牌>`，或 HTTP Basic 的密码部分（用户名任意）。
+pub(crate) fn presented_token(headers: &HeaderMap) -> Option<String> {
+    use base64::Engine;
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?.trim();
+    if let Some(token) = value.strip_prefix("Bearer ") {
+        return Some(token.trim().to_string());
+    }
+    let encoded = value.strip_prefix("Basic ")?;
+    let decoded = base64::engine::general_purpose::STANDARD.decode(encoded.trim()).ok()?;
+    let credentials = String::from_utf8(decoded).ok()?;
+    credentials.split_once(':').map(|(_, password)| password.to_string())
+}
+
+/// 管理接口的认证层：配置了 admin_token 时要求携带该令牌（Bearer 或 Basic 密码），
+/// 未配置时只允许本机回环地址访问。
+/// 带有 admin 权限的 API 密钥同样放行。
+pub(crate) async fn require_admin(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Result<Response, AppError> {
+    if CALLER.try_with(|caller| caller.key_name.is_some() && caller.has_scope(ApiScope::Admin)).unwrap_or(false) {
+        return Ok(next.run(req).await);
+    }
+    let token = state.current_config().admin_token;
+    if token.is_empty() {
+        let local = req.extensions().get::<ClientIp>().is_some_and(|ip| ip.0.is_loopback());
+        if !local {
+            return Err(AppError::Forbidden(tr!("未配置 admin_token，管理接口只允许本机访问。", "admin_token is not configured; admin endpoints only accept local connections.").to_string()));
+        }
+        return Ok(next.run(req).await);
+    }
+    match presented_token(req.headers()) {
+        Some(presented) if constant_time_eq(presented.as_bytes(), token.as_bytes()) => Ok(next.run(req).await),
+        _ => Err(AppError::Unauthorized),
+    }
+}
+
+/// 发起请求的调用方：匹配的 API 密钥，或持有管理令牌的管理员（拥有全部权限）
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Caller {
+    pub(crate) key_name: Option<String>,
+    pub(crate) scopes: Vec<ApiScope>,
+    pub(crate) admin: bool,
+}
+
+impl Caller {
+    pub(crate) fn has_scope(&self, scope: ApiScope) -> bool {
+        self.admin || self.scopes.contains(&scope)
+    }
+}
+
+/// 识别调用方：X-API-Key 头必须匹配 api_keys 中的某一项（否则 401）；
+/// 未出示密钥时，携带管理令牌的请求视为管理员；启用 JWT 时其他 Bearer 令牌须通过 JWT 校验（否则 401）；
+/// 其余为匿名调用方。
+pub(crate) async fn identify_caller(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Result<Response, AppError> {
+    let config = state.current_config();
+    let caller = resolve_caller(&state, &config, req.headers()).await?;
+    let key = caller.key_name.clone();
+    let response = CALLER.scope(caller, next.run(req)).await;
+    if let Some(key) = key {
+        state.record_key_request(&key, response.status());
+    }
+    Ok(response)
+}
+
+/// 按请求头（HTTP 头或 gRPC metadata）识别调用方，规则见 `identify_caller`
+pub(crate) async fn resolve_caller(state: &AppState, config: &ServiceConfig, headers: &HeaderMap) -> Result<Caller, AppError> {
+    Ok(match headers.get(API_KEY_HEADER) {
+        Some(presented) => {
+            let api_key = config.api_keys.iter()
+                .find(|k| constant_time_eq(presented.as_bytes(), k.key.as_bytes()))
+                .ok_or(AppError::Unauthorized)?;
+            Caller { key_name: Some(api_key.name.clone()), scopes: api_key.scopes.clone(), admin: false }
+        }
+        None => {
+            let token = presented_token(headers);
+            let admin = !config.admin_token.is_empty()
+                && token.as_ref().is_some_and(|t| constant_time_eq(t.as_bytes(), config.admin_token.as_bytes()));
+            match bearer_token(headers).filter(|t| config.jwt.enabled && !admin && looks_like_jwt(t)) {
+                Some(jwt) => verify_jwt(state, &config.jwt, &jwt).await.map_err(|e| {
+                    log_debug("cyber_lookup::auth", &format!("JWT rejected: {}", e));
+                    AppError::Unauthorized
+                })?,
+                None => Caller { key_name: None, scopes: config.anonymous_scopes.clone(), admin },
+            }
+        }
+    })
+}
+
+// --- JWT 认证 ---
+
+/// JWT 验签公钥的缓存：PEM 文件按路径与修改时间缓存，JWKS 按 kid 索引（没有 kid 的公钥记为空串）
+#[derive(Default)]
+pub(crate) struct JwtKeyCache {
+    pem: Option<(String, Option<std::time::SystemTime>, jsonwebtoken::DecodingKey)>,
+    jwks_url: String,
+    jwks: HashMap<String, jsonwebtoken::DecodingKey>,
+    jwks_attempt: Option<(String, Instant)>, // 最近一次拉取的地址与时间（无论成功与否）
+}
+
+/// Authorization 头中的 Bearer 令牌（不含 Basic 凭证）
+fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?.trim();
+    value.strip_prefix("Bearer ").map(|token| token.trim().to_string())
+}
+
+/// 形如 `header.payload.signature` 的令牌，用于与 admin_token 区分
+fn looks_like_jwt(token: &str) -> bool {
+    token.split('.').count() == 3 && token.split('.').all(|part| !part.is_empty())
+}
+
+pub(crate) fn load_rsa_public_key(path: &str) -> Result<jsonwebtoken::DecodingKey, String> {
+    let pem = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    jsonwebtoken::DecodingKey::from_rsa_pem(&pem).map_err(|e| e.to_string())
+}
+
+/// 拉取 jwks_url 并替换缓存中的公钥集合。距上次拉取同一地址不足 `min_interval` 时跳过。
+async fn refresh_jwks(state: &AppState, url: &str, min_interval: Duration) -> Result<(), String> {
+    {
+        let mut cache = state.jwt_keys.write().unwrap();
+        if cache.jwks_attempt.as_ref().is_some_and(|(last, at)| last == url && at.elapsed() < min_interval) {
+            return Ok(());
+        }
+        cache.jwks_attempt = Some((url.to_string(), Instant::now()));
+    }
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(JWKS_FETCH_TIMEOUT_SECS))
+        .build()
+        .map_err(|e| e.to_string())?;
+    let set: jsonwebtoken::jwk::JwkSet = client.get(url).send().await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| e.to_string())?
+        .json().await
+        .map_err(|e| e.to_string())?;
+    let keys: HashMap<String, jsonwebtoken::DecodingKey> = set.keys.iter()
+        .filter_map(|jwk| Some((jwk.common.key_id.clone().unwrap_or_default(), jsonwebtoken::DecodingKey::from_jwk(jwk).ok()?)))
+        .collect();
+    if keys.is_empty() {
+        return Err(tr!("JWKS 中没有可用的公钥", "the JWKS contains no usable keys").to_string());
+    }
+    let mut cache = state.jwt_keys.write().unwrap();
+    cache.jwks_url = url.to_string();
+    cache.jwks = keys;
+    Ok(())
+}
+
+/// 定时刷新 JWKS（启用 JWT 且配置了 jwks_url 时）
+pub(crate) async fn run_jwks_refresher(state: Arc<AppState>) {
+    loop {
+        let config = state.current_config();
+        if !config.jwt.enabled || config.jwt.jwks_url.is_empty() {
+            sleep(Duration::from_secs(JWKS_POLL_SECS)).await;
+            continue;
+        }
+        if let Err(e) = refresh_jwks(&state, &config.jwt.jwks_url, Duration::ZERO).await {
+            eprintln!("{} {}", "WARN".yellow(), tr!("JWKS 拉取失败 ({}): {}", "Failed to fetch the JWKS ({}): {}", config.jwt.jwks_url, e));
+        }
+        sleep(Duration::from_secs(config.jwt.jwks_refresh_secs)).await;
+    }
+}
+
+/// RS256 验签公钥：配置了 PEM 文件时使用该文件，否则按 kid 从 JWKS 中选取（未知 kid 时提前刷新一次）
+async fn rsa_decoding_key(state: &AppState, jwt: &JwtConfig, kid: Option<&str>) -> Result<jsonwebtoken::DecodingKey, String> {
+    if !jwt.rs256_public_key_file.is_empty() {
+        let path = &jwt.rs256_public_key_file;
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        if let Some((cached_path, cached_modified, key)) = &state.jwt_keys.read().unwrap().pem {
+            if cached_path == path && *cached_modified == modified {
+                return Ok(key.clone());
+            }
+        }
+        let key = load_rsa_public_key(path)?;
+        state.jwt_keys.write().unwrap().pem = Some((path.clone(), modified, key.clone()));
+        return Ok(key);
+    }
+    if jwt.jwks_url.is_empty() {
+        return Err("no RS256 public key configured".to_string());
+    }
+    let cached = || {
+        let cache = state.jwt_keys.read().unwrap();
+        if cache.jwks_url != jwt.jwks_url {
+            return None;
+        }
+        match kid {
+            Some(kid) => cache.jwks.get(kid).cloned(),
+            None if cache.jwks.len() == 1 => cache.jwks.values().next().cloned(),
+            None => None,
+        }
+    };
+    if let Some(key) = cached() {
+        return Ok(key);
+    }
+    refresh_jwks(state, &jwt.jwks_url, Duration::from_secs(JWKS_MIN_REFRESH_SECS)).await?;
+    cached().ok_or_else(|| format!("no JWKS key for kid {:?}", kid))
+}
+
+/// 校验 JWT（签名、exp / nbf、iss、aud）并把其中的权限声明换算为调用方。错误信息只用于调试日志。
+async fn verify_jwt(state: &AppState, jwt: &JwtConfig, token: &str) -> Result<Caller, String> {
+    use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+    let header = jsonwebtoken::decode_header(token).map_err(|e| e.to_string())?;
+    // 每种算法只接受对应类型的密钥，避免以公钥作 HMAC 密钥的算法混淆
+    let key = match header.alg {
+        Algorithm::HS256 => DecodingKey::from_secret(jwt.secret().ok_or("no HS256 secret configured")?.as_bytes()),
+        Algorithm::RS256 => rsa_decoding_key(state, jwt, header.kid.as_deref()).await?,
+        other => return Err(format!("unsupported algorithm {:?}", other)),
+    };
+    let mut validation = Validation::new(header.alg);
+    validation.leeway = jwt.leeway_secs;
+    if !jwt.issuer.is_empty() {
+        validation.set_issuer(&[&jwt.issuer]);
+    }
+    if jwt.audience.is_empty() {
+        validation.validate_aud = false;
+    } else {
+        validation.set_audience(&[&jwt.audience]);
+    }
+    let claims = jsonwebtoken::decode::<serde_json::Map<String, serde_json::Value>>(token, &key, &validation)
+        .map_err(|e| e.to_string())?
+        .claims;
+
+    let subject = claims.get(&jwt.subject_claim).and_then(|v| v.as_str()).unwrap_or("unknown");
+    let values: Vec<&str> = match claims.get(&jwt.scope_claim) {
+        Some(serde_json::Value::String(value)) => value.split_whitespace().collect(),
+        Some(serde_json::Value::Array(items)) => items.iter().filter_map(|v| v.as_str()).collect(),
+        _ => Vec::new(),
+    };
+    let mut scopes = Vec::new();
+    for value in values {
+        let granted = jwt.scope_mapping.get(value).cloned()
+            .unwrap_or_else(|| serde_json::from_value::<ApiScope>(serde_json::Value::from(value)).into_iter().collect());
+        for scope in granted {
+            if !scopes.contains(&scope) {
+                scopes.push(scope);
+            }
+        }
+    }
+    Ok(Caller { key_name: Some(format!("jwt:{}", subject)), scopes, admin: false })
+}
+
+/// 路由分组的权限检查：调用方缺少 `scope` 时拒绝（匿名调用方 401，提示出示密钥；已识别的密钥 403）。
+/// 未经 identify_caller 识别（缺少调用方上下文）的请求一律按匿名拒绝。
+pub(crate) async fn require_scope(State(scope): State<ApiScope>, req: Request, next: Next) -> Result<Response, AppError> {
+    let (allowed, anonymous) = CALLER.try_with(|caller| (caller.has_scope(scope), caller.key_name.is_none() && !caller.admin))
+        .unwrap_or((false, true));
+    match (allowed, anonymous) {
+        (true, _) => Ok(next.run(req).await),
+        (false, true) => Err(AppError::Unauthorized),
+        (false, false) => {
+            let name = serde_json::to_value(scope).ok().and_then(|v| v.as_str().map(String::from)).unwrap_or_default();
+            Err(AppError::Forbidden(tr!("API 密钥没有 {} 权限。", "The API key lacks the {} scope.", name)))
+        }
+    }
+}
+
+/// 解析 X-Timestamp 并检查与服务器时间 `now` 的偏差不超过 `window` 秒，返回时间戳
+fn check_signature_timestamp(timestamp: &str, now: i64, window: u64) -> Result<i64, AppError> {
+    let ts = timestamp.parse::<i64>()
+        .map_err(|_| AppError::InvalidSignature(tr!("X-Timestamp 必须是 Unix 秒数。", "X-Timestamp must be Unix seconds.").to_string()))?;
+    // 时间戳由客户端提供，abs_diff 不会因 i64::MIN 之类的极端值溢出
+    if now.abs_diff(ts) > window {
+        return Err(AppError::InvalidSignature(tr!("请求时间戳超出允许的 {} 秒偏差。", "The request timestamp is outside the allowed {} s skew.", window)));
+    }
+    Ok(ts)
+}
+
+/// 请求签名校验（批量与写入接口）：调用方的 API 密钥配置了 signing_secret 时，请求须携带
+/// X-Timestamp（Unix 秒）与 X-Signature（HMAC-SHA256 的十六进制），签名内容为
+/// `时间戳 \n 方法 \n 路径与查询串 \n 请求体 SHA-256 的十六进制`，以 \n 连接。
+/// 时间戳与服务器时间相差超过 signature_window_secs 时拒绝；窗口内重复出现的签名视为重放。
+pub(crate) async fn verify_signature(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Result<Response, AppError> {
+    use sha2::Digest;
+    let config = state.current_config();
+    let secret = CALLER.try_with(|caller| caller.key_name.clone()).ok().flatten()
+        .and_then(|name| config.api_keys.iter().find(|k| k.name == name).map(|k| k.signing_secret.clone()))
+        .filter(|secret| !secret.is_empty());
+    let Some(secret) = secret else {
+        return Ok(next.run(req).await);
+    };
+
+    let headers = req.headers();
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).map(|v| v.trim().to_string());
+    let (Some(timestamp), Some(signature)) = (header(TIMESTAMP_HEADER), header(SIGNATURE_HEADER)) else {
+        return Err(AppError::InvalidSignature(tr!("该 API 密钥要求请求签名，缺少 X-Timestamp 或 X-Signature 头。", "This API key requires signed requests; the X-Timestamp or X-Signature header is missing.").to_string()));
+    };
+    let window = config.signature_window_secs;
+    let now = Local::now().timestamp();
+    let ts = check_signature_timestamp(&timestamp, now, window)?;
+
+    // 挂载在 /v1 下时 uri 已去掉前缀，签名按客户端实际请求的路径计算
+    let target = req.extensions().get::<axum::extract::OriginalUri>().map(|uri| uri.0.clone()).unwrap_or_else(|| req.uri().clone());
+    let target = target.path_and_query().map(|pq| pq.as_str().to_string()).unwrap_or_else(|| target.path().to_string());
+    let (parts, body) = req.into_parts();
+    let body = axum::body::to_bytes(body, config.max_body_bytes as usize).await
+        .map_err(|_| AppError::PayloadTooLarge(tr!("请求体超过上限 {} 字节", "Request body exceeds the limit of {} bytes", config.max_body_bytes)))?;
+    let body_digest: String = sha2::Sha256::digest(&body).iter().map(|b| format!("{:02x}", b)).collect();
+    let expected = hmac_hex(&secret, format!("{}\n{}\n{}\n{}", timestamp, parts.method, target, body_digest).as_bytes());
+    if !constant_time_eq(signature.to_ascii_lowercase().as_bytes(), expected.as_bytes()) {
+        return Err(AppError::InvalidSignature(tr!("请求签名不匹配。", "The request signature does not match.").to_string()));
+    }
+    {
+        let mut seen = state.seen_signatures.lock().unwrap();
+        seen.retain(|_, seen_ts| now.abs_diff(*seen_ts) <= window);
+        if seen.insert(expected, ts).is_some() {
+            return Err(AppError::InvalidSignature(tr!("重复的请求签名（重放）。", "Duplicate request signature (replay).").to_string()));
+        }
+    }
+    Ok(next.run(Request::from_parts(parts, Body::from(body))).await)
+}
+
+// --- 查询响应签名 ---
+
+/// 签名所用的私钥及其标识，连同读取时的配置与密钥文件修改时间（用于判断是否需要重新读取）
+pub(crate) struct ResponseSigner {
+    key: ed25519_dalek::SigningKey,
+    key_id: String,
+    source: ResponseSigningConfig,
+    modified: Option<std::time::SystemTime>,
+}
+
+/// 读取 PKCS#8 PEM 格式的 Ed25519 私钥；key_id 为空时取公钥 SHA-256 的前 16 位十六进制
+pub(crate) fn load_response_signer(signing: &ResponseSigningConfig) -> Result<ResponseSigner, String> {
+    use ed25519_dalek::pkcs8::DecodePrivateKey;
+    use sha2::Digest;
+    let pem = fs::read_to_string(&signing.key_file).map_err(|e| format!("{}: {}", signing.key_file, e))?;
+    let key = ed25519_dalek::SigningKey::from_pkcs8_pem(&pem).map_err(|e| format!("{}: {}", signing.key_file, e))?;
+    let key_id = if signing.key_id.is_empty() {
+        sha2::Sha256::digest(key.verifying_key().as_bytes()).iter().take(8).map(|b| format!("{:02x}", b)).collect()
+    } else {
+        signing.key_id.clone()
+    };
+    let modified = fs::metadata(&signing.key_file).and_then(|m| m.modified()).ok();
+    Ok(ResponseSigner { key, key_id, source: signing.clone(), modified })
+}
+
+impl AppState {
+    /// 当前的签名私钥；密钥文件被替换（修改时间变化）后重新读取，便于轮换
+    fn response_signer(&self, signing: &ResponseSigningConfig) -> Result<Arc<ResponseSigner>, String> {
+        let modified = fs::metadata(&signing.key_file).and_then(|m| m.modified()).ok();
+        if let Some(signer) = self.response_signer.read().unwrap().as_ref() {
+            if signer.source.key_file == signing.key_file && signer.source.key_id == signing.key_id && signer.modified == modified {
+                return Ok(signer.clone());
+            }
+        }
+        let signer = Arc::new(load_response_signer(signing)?);
+        *self.response_signer.write().unwrap() = Some(signer.clone());
+        Ok(signer)
+    }
+}
+
+/// 规范化 JSON：对象的键按字节序排列，不含空白，字符串与数字按 serde_json 的格式输出
+fn canonical_json(value: &serde_json::Value, out: &mut String) {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            out.push('{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::Value::String(key.clone()).to_string());
+                out.push(':');
+                canonical_json(value, out);
+            }
+            out.push('}');
+        }
+        serde_json::Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                canonical_json(item, out);
+            }
+            out.push(']');
+        }
+        scalar => out.push_str(&scalar.to_string()),
+    }
+}
+
+/// 启用 response_signing 时，把查询接口的 JSON 响应（含错误响应）改写为规范化 JSON，并在 X-Response-Signature 头中
+/// 附上对该响应体的 Ed25519 签名（标准 Base64），X-Response-Key-Id 标明所用密钥。流式 (NDJSON) 等非 JSON 响应不签名。
+/// 私钥无法读取时返回错误而不是未签名的结果。
+pub(crate) async fn sign_response(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Result<Response, AppError> {
+    use base64::Engine;
+    use ed25519_dalek::Signer;
+    let config = state.current_config();
+    if !config.response_signing.enabled {
+        return Ok(next.run(req).await);
+    }
+    let response = next.run(req).await;
+    let is_json = response.headers().get(CONTENT_TYPE).and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct.starts_with("application/json") || ct.starts_with(PROBLEM_CONTENT_TYPE));
+    if !is_json {
+        return Ok(response);
+    }
+    let signer = state.response_signer(&config.response_signing)
+        .map_err(|e| AppError::ConfigError(tr!("无法读取响应签名私钥: {}", "Cannot read the response signing key: {}", e)))?;
+    let (mut parts, body) = response.into_parts();
+    let body = axum::body::to_bytes(body, usize::MAX).await
+        .map_err(|e| AppError::FatalError(e.to_string()))?;
+    let value: serde_json::Value = serde_json::from_slice(&body).map_err(|e| AppError::FatalError(e.to_string()))?;
+    let mut canonical = String::with_capacity(body.len());
+    canonical_json(&value, &mut canonical);
+    let signature = base64::engine::general_purpose::STANDARD.encode(signer.key.sign(canonical.as_bytes()).to_bytes());
+    parts.headers.remove(CONTENT_LENGTH);
+    if let (Ok(signature), Ok(key_id)) = (HeaderValue::from_str(&signature), HeaderValue::from_str(&signer.key_id)) {
+        parts.headers.insert(HeaderName::from_static(RESPONSE_SIGNATURE_HEADER), signature);
+        parts.headers.insert(HeaderName::from_static(RESPONSE_KEY_ID_HEADER), key_id);
+    }
+    Ok(Response::from_parts(parts, Body::from(canonical)))
+}
+
+#[derive(Serialize)]
+pub(crate) struct SigningKeyResponse {
+    algorithm: &'static str,
+    key_id: String,
+    public_key: String,              // 32 字节公钥的标准 Base64
+    public_key_pem: String,          // SubjectPublicKeyInfo PEM
+}
+
+/// 验证响应签名所需的公钥
+pub(crate) async fn api_signing_key(State(state): State<Arc<AppState>>) -> Result<Json<SigningKeyResponse>, AppError> {
+    use base64::Engine;
+    use ed25519_dalek::pkcs8::{spki::der::pem::LineEnding, EncodePublicKey};
+    let config = state.current_config();
+    if !config.response_signing.enabled {
+        return Err(AppError::Rejected(ApiErrorCode::InvalidRequest, tr!("未启用响应签名 (response_signing.enabled)。", "Response signing is not enabled (response_signing.enabled).").to_string()));
+    }
+    let signer = state.response_signer(&config.response_signing)
+        .map_err(|e| AppError::ConfigError(tr!("无法读取响应签名私钥: {}", "Cannot read the response signing key: {}", e)))?;
+    let public = signer.key.verifying_key();
+    Ok(Json(SigningKeyResponse {
+        algorithm: "ed25519",
+        key_id: signer.key_id.clone(),
+        public_key: base64::engine::general_purpose::STANDARD.encode(public.as_bytes()),
+        public_key_pem: public.to_public_key_pem(LineEnding::LF).map_err(|e| AppError::FatalError(e.to_string()))?,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_timestamp_window() {
+        let now = 1_700_000_000;
+        assert_eq!(check_signature_timestamp("1700000000", now, 300).unwrap(), now);
+        assert_eq!(check_signature_timestamp("1699999700", now, 300).unwrap(), now - 300);
+        assert_eq!(check_signature_timestamp("1700000300", now, 300).unwrap(), now + 300);
+        for outside in ["1699999699", "1700000301", "0"] {
+            assert!(matches!(check_signature_timestamp(outside, now, 300), Err(AppError::InvalidSignature(_))), "{}", outside);
+        }
+    }
+
+    #[test]
+    fn signature_timestamp_extremes_are_rejected_without_overflow() {
+        for now in [0, 1_700_000_000, i64::MIN, i64::MAX] {
+            for extreme in [i64::MIN, i64::MAX] {
+                if extreme == now {
+                    continue;
+                }
+                let result = check_signature_timestamp(&extreme.to_string(), now, 300);
+                assert!(matches!(result, Err(AppError::InvalidSignature(_))), "now={} ts={}", now, extreme);
+            }
+        }
+        assert!(check_signature_timestamp(&i64::MIN.to_string(), 0, u64::MAX).is_ok());
+        assert!(matches!(check_signature_timestamp("not-a-number", 0, 300), Err(AppError::InvalidSignature(_))));
+    }
+}
@@ -0,0 +1,517 @@
+//! HTTP 中间件：请求体上限、请求 ID、写请求幂等、客户端 IP、访问控制、自动封禁、限流与请求时限。
+
+use crate::*;
+
+/// 防御性：在读取请求体之前按 Content-Length 拒绝超限请求，避免先把巨大的请求体读进内存。
+/// 未声明长度的分块请求由 `DefaultBodyLimit` 在读取过程中截断（同样返回 413）。
+/// JSON 嵌套深度由固定的请求结构与 serde_json 的递归上限 (128) 共同约束，无需额外检查。
+pub(crate) async fn enforce_body_limit(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let limit = state.current_config().max_body_bytes;
+    let declared = req.headers().get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if let Some(len) = declared {
+        if len > limit {
+            return Err(AppError::PayloadTooLarge(tr!("请求体 {} 字节超过上限 {} 字节", "Request body of {} bytes exceeds limit of {} bytes", len, limit)));
+        }
+    }
+    Ok(next.run(req).await)
+}
+
+/// 旧的无版本前缀路径：照常处理，并以 Deprecation 头和指向 /v1 路径的 Link 头提示客户端迁移。
+pub(crate) async fn mark_deprecated(req: Request, next: Next) -> Response {
+    let successor = format!("{}{}", API_V1_PREFIX, req.uri().path());
+    let mut response = next.run(req).await;
+    let headers = response.headers_mut();
+    headers.insert(HeaderName::from_static("deprecation"), HeaderValue::from_static("true"));
+    if let Ok(link) = HeaderValue::from_str(&format!("<{}>; rel=\"successor-version\"", successor)) {
+        headers.insert(LINK, link);
+    }
+    response
+}
+
+/// 为每个请求分配 ID（沿用客户端提供的合法 X-Request-Id），在响应头中回传，并使错误响应体可以引用它。
+pub(crate) async fn assign_request_id(req: Request, next: Next) -> Response {
+    let request_id = req.headers().get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty() && v.len() <= MAX_REQUEST_ID_LENGTH)
+        .map(String::from)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let mut response = REQUEST_ID.scope(request_id.clone(), next.run(req)).await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+/// 记录请求匹配的路由模板，供慢查询日志标明来源接口（不含路径参数中的 ID）。
+pub(crate) async fn track_endpoint(req: Request, next: Next) -> Response {
+    match req.extensions().get::<MatchedPath>().map(|path| path.as_str().to_string()) {
+        Some(endpoint) => ENDPOINT.scope(endpoint, next.run(req)).await,
+        None => next.run(req).await,
+    }
+}
+
+/// 统计响应总数与 5xx 数，供告警计算错误率。位于最外层，负载卸载产生的 503 也计入。
+pub(crate) async fn count_responses(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let response = next.run(req).await;
+    state.responses_total.fetch_add(1, Ordering::Relaxed);
+    if response.status().is_server_error() {
+        state.responses_failed.fetch_add(1, Ordering::Relaxed);
+    }
+    response
+}
+
+// --- 写请求幂等 ---
+
+/// 按 Idempotency-Key 保存的写请求：处理中只记录指纹，完成后连同响应一起保存
+#[derive(Clone)]
+pub(crate) enum IdempotencyEntry {
+    InFlight { fingerprint: String },
+    Done { fingerprint: String, status: StatusCode, content_type: Option<HeaderValue>, body: Bytes },
+}
+
+impl IdempotencyEntry {
+    fn fingerprint(&self) -> &str {
+        match self {
+            IdempotencyEntry::InFlight { fingerprint } | IdempotencyEntry::Done { fingerprint, .. } => fingerprint,
+        }
+    }
+}
+
+/// 请求处理未正常结束（超时中断、出错）时移除处理中标记，使客户端可以重试
+struct InFlightGuard {
+    state: Arc<AppState>,
+    key: String,
+    armed: bool,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.armed {
+            self.state.idempotency.invalidate(&self.key);
+        }
+    }
+}
+
+/// 写接口的幂等支持：携带 Idempotency-Key 的请求按 租户 + 调用方 + 键 记录请求指纹（方法、路径与请求体的 SHA-256）。
+/// idempotency_ttl_secs 内以相同键重试时不再执行，直接重放首次的响应（带 Idempotent-Replayed: true）；
+/// 同一个键用于不同的请求返回 400，首次请求仍在处理中返回 409。5xx 响应不保存，重试会重新执行。
+pub(crate) async fn apply_idempotency(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Result<Response, AppError> {
+    use sha2::Digest;
+    let config = state.current_config();
+    let Some(key) = req.headers().get(IDEMPOTENCY_KEY_HEADER).filter(|_| config.idempotency_ttl_secs > 0) else {
+        return Ok(next.run(req).await);
+    };
+    let key = key.to_str().ok()
+        .filter(|key| !key.is_empty() && key.len() <= IDEMPOTENCY_KEY_MAX_LENGTH)
+        .ok_or_else(|| AppError::Rejected(ApiErrorCode::InvalidRequest, tr!("Idempotency-Key 须为 1 到 {} 个可见字符。", "Idempotency-Key must be 1 to {} visible characters.", IDEMPOTENCY_KEY_MAX_LENGTH)))?
+        .to_string();
+    // 没有 API 密钥名的调用方（匿名、管理令牌）按客户端 IP 区分，避免彼此重放对方的响应
+    let caller = CALLER.try_with(|caller| match &caller.key_name {
+        Some(name) => format!("key:{}", name),
+        None => {
+            let client = CLIENT_IP.try_with(ToString::to_string).unwrap_or_else(|_| "-".to_string());
+            format!("{}@{}", if caller.admin { "admin" } else { "anon" }, client)
+        }
+    }).unwrap_or_else(|_| "-".to_string());
+    let tenant = req.extensions().get::<TenantName>().map_or("-", |tenant| tenant.0.as_str());
+    let cache_key = format!("{}\n{}\n{}", tenant, caller, key);
+
+    let target = req.extensions().get::<axum::extract::OriginalUri>().map(|uri| uri.0.clone()).unwrap_or_else(|| req.uri().clone());
+    let (parts, body) = req.into_parts();
+    let body = axum::body::to_bytes(body, config.max_body_bytes as usize).await
+        .map_err(|_| AppError::PayloadTooLarge(tr!("请求体超过上限 {} 字节", "Request body exceeds the limit of {} bytes", config.max_body_bytes)))?;
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(format!("{}\n{}\n", parts.method, target).as_bytes());
+    hasher.update(&body);
+    let fingerprint: String = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+
+    let entry = state.idempotency.entry(cache_key.clone())
+        .or_insert_with(|| IdempotencyEntry::InFlight { fingerprint: fingerprint.clone() });
+    if !entry.is_fresh() {
+        let existing = entry.into_value();
+        if existing.fingerprint() != fingerprint {
+            return Err(AppError::Rejected(ApiErrorCode::InvalidRequest, tr!("Idempotency-Key 已用于另一个不同的请求。", "The Idempotency-Key was already used for a different request.").to_string()));
+        }
+        return match existing {
+            IdempotencyEntry::InFlight { .. } => Err(AppError::Conflict(tr!("使用该 Idempotency-Key 的请求仍在处理中，请稍后重试。", "A request with this Idempotency-Key is still in progress; retry later.").to_string())),
+            IdempotencyEntry::Done { status, content_type, body, .. } => {
+                let mut response = (status, body).into_response();
+                if let Some(content_type) = content_type {
+                    response.headers_mut().insert(CONTENT_TYPE, content_type);
+                }
+                response.headers_mut().insert(HeaderName::from_static(IDEMPOTENT_REPLAYED_HEADER), HeaderValue::from_static("true"));
+                Ok(response)
+            }
+        };
+    }
+
+    let mut guard = InFlightGuard { state: state.clone(), key: cache_key.clone(), armed: true };
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    if response.status().is_server_error() {
+        return Ok(response);
+    }
+    let (parts, body) = response.into_parts();
+    let body = axum::body::to_bytes(body, usize::MAX).await
+        .map_err(|e| AppError::FatalError(tr!("读取响应失败: {}", "Failed to read the response: {}", e)))?;
+    state.idempotency.insert(cache_key, IdempotencyEntry::Done {
+        fingerprint,
+        status: parts.status,
+        content_type: parts.headers.get(CONTENT_TYPE).cloned(),
+        body: body.clone(),
+    });
+    guard.armed = false;
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+// --- 客户端 IP ---
+
+/// 请求的客户端 IP（已按 `trusted_proxies` 解析转发头），作为请求扩展提供给处理器与嵌入方。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClientIp(pub IpAddr);
+
+/// 解析单个 IP 或 CIDR（如 "10.0.0.2"、"10.0.0.0/8"、"fd00::/8"），返回网络地址与前缀长度。
+pub(crate) fn parse_ip_net(s: &str) -> Option<(IpAddr, u8)> {
+    let s = s.trim();
+    let (addr, prefix) = match s.split_once('/') {
+        Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix.parse::<u8>().ok()?)),
+        None => (s.parse::<IpAddr>().ok()?, None),
+    };
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = prefix.unwrap_or(max);
+    (prefix <= max).then_some((addr, prefix))
+}
+
+fn ip_in_net(ip: IpAddr, (net, prefix): (IpAddr, u8)) -> bool {
+    // IPv4 映射的 IPv6 地址（双栈监听时常见）按 IPv4 比较
+    match (ip.to_canonical(), net) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+/// 可信代理列表，由 `build_router` 按调用时的配置解析一次。
+pub(crate) type TrustedProxies = Arc<Vec<(IpAddr, u8)>>;
+
+/// 确定客户端 IP：直连对端是可信代理时，取 X-Forwarded-For 中从右往左第一个不可信的地址（其左侧的值可由客户端伪造），
+/// 没有该头时取 X-Real-IP；否则忽略转发头，使用对端地址。宿主应用未提供连接信息时不设置。
+pub(crate) async fn resolve_client_ip(State(proxies): State<TrustedProxies>, mut req: Request, next: Next) -> Response {
+    let Some(peer) = req.extensions().get::<ConnectInfo<SocketAddr>>().map(|info| info.0.ip()) else {
+        return next.run(req).await;
+    };
+    let trusted = |ip: IpAddr| proxies.iter().any(|net| ip_in_net(ip, *net));
+    let mut client = peer.to_canonical();
+    if trusted(client) {
+        let headers = req.headers();
+        let forwarded: Vec<IpAddr> = headers.get_all("x-forwarded-for").iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(|v| v.trim().parse::<IpAddr>().ok())
+            .collect();
+        let real_ip = headers.get("x-real-ip").and_then(|v| v.to_str().ok()).and_then(|v| v.trim().parse::<IpAddr>().ok());
+        if !forwarded.is_empty() {
+            // 整条链都是可信代理时取最左侧的地址
+            client = forwarded.iter().rev().copied().find(|ip| !trusted(*ip)).unwrap_or(forwarded[0]);
+        } else if let Some(ip) = real_ip {
+            client = ip;
+        }
+    }
+    req.extensions_mut().insert(ClientIp(client));
+    CLIENT_IP.scope(client, next.run(req)).await
+}
+
+/// IP 访问控制：在路由之前按当前配置检查客户端 IP。命中 ip_denylist，或配置了 ip_allowlist 而不在其中的请求
+/// 以 403 拒绝（与其他错误响应一样记录日志），并计入 /stats。宿主应用未提供连接信息时不检查。
+pub(crate) async fn enforce_ip_access(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Result<Response, AppError> {
+    let Some(ClientIp(ip)) = req.extensions().get::<ClientIp>().copied() else {
+        return Ok(next.run(req).await);
+    };
+    check_ip_access(&state, &state.current_config(), ip)?;
+    Ok(next.run(req).await)
+}
+
+/// ip_denylist 中的地址、或配置了 ip_allowlist 时不在其中的地址被拒绝（403）
+pub(crate) fn check_ip_access(state: &AppState, config: &ServiceConfig, ip: IpAddr) -> Result<(), AppError> {
+    let listed = |list: &[String]| list.iter().filter_map(|net| parse_ip_net(net)).any(|net| ip_in_net(ip, net));
+    if listed(&config.ip_denylist) || (!config.ip_allowlist.is_empty() && !listed(&config.ip_allowlist)) {
+        state.ip_denied.fetch_add(1, Ordering::Relaxed);
+        return Err(AppError::Forbidden(tr!("客户端地址 {} 不允许访问。", "Access from client address {} is not allowed.", ip)));
+    }
+    Ok(())
+}
+
+// --- 自动封禁 ---
+
+/// 一项生效中的封禁
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Ban {
+    subject: String,                 // "ip:<地址>" 或 "key:<API 密钥名称>"
+    reason: String,                  // 最后一次违规的错误码
+    violations: u32,
+    pub(crate) banned_at: String,
+    expires_at: String,
+    #[serde(skip)]
+    pub(crate) until: Instant,
+}
+
+/// 各主体在滑动窗口内的违规时间与当前封禁
+#[derive(Default)]
+pub(crate) struct BanTracker {
+    pub(crate) violations: HashMap<String, VecDeque<Instant>>,
+    pub(crate) banned: HashMap<String, Ban>,
+}
+
+impl BanTracker {
+    /// 主体当前是否被封禁（顺带移除已到期的封禁）
+    fn is_banned(&mut self, subject: &str) -> bool {
+        match self.banned.get(subject) {
+            Some(ban) if ban.until > Instant::now() => true,
+            Some(_) => {
+                self.banned.remove(subject);
+                false
+            }
+            None => false,
+        }
+    }
+    /// 记录一次违规，达到阈值时封禁并返回新的封禁
+    fn record(&mut self, subject: String, code: ApiErrorCode, config: &AutoBanConfig) -> Option<Ban> {
+        let now = Instant::now();
+        let window = Duration::from_secs(config.window_secs);
+        // 顺带清理窗口外的记录，避免大量一次性来源长期占用内存
+        self.violations.retain(|_, times| times.back().is_some_and(|t| now.duration_since(*t) < window));
+        let times = self.violations.entry(subject.clone()).or_default();
+        times.push_back(now);
+        while times.front().is_some_and(|t| now.duration_since(*t) >= window) {
+            times.pop_front();
+        }
+        if times.len() < config.threshold as usize {
+            return None;
+        }
+        let violations = times.len() as u32;
+        self.violations.remove(&subject);
+        let banned_at = Local::now();
+        let ban = Ban {
+            subject: subject.clone(),
+            reason: code.as_str().to_string(),
+            violations,
+            banned_at: banned_at.to_rfc3339(),
+            expires_at: (banned_at + chrono::Duration::seconds(config.ban_secs as i64)).to_rfc3339(),
+            until: now + Duration::from_secs(config.ban_secs),
+        };
+        self.banned.insert(subject, ban.clone());
+        Some(ban)
+    }
+}
+
+/// 计入自动封禁的错误：认证失败、签名错误与超限的请求
+fn is_violation(code: ApiErrorCode) -> bool {
+    matches!(code, ApiErrorCode::Unauthorized | ApiErrorCode::InvalidSignature | ApiErrorCode::BatchTooLarge | ApiErrorCode::PayloadTooLarge)
+}
+
+/// 自动封禁：拒绝已封禁的客户端 IP 与 API 密钥，并按响应的错误码统计违规。未启用时直接放行。
+/// 出示正确管理令牌的请求不受封禁影响（以便解封）；本机回环地址不按 IP 封禁——它是未配置 admin_token 时的管理入口，
+/// 且反向代理未列入 trusted_proxies 时所有请求都来自该地址。
+pub(crate) async fn enforce_bans(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Result<Response, AppError> {
+    let config = state.current_config();
+    let admin = !config.admin_token.is_empty()
+        && presented_token(req.headers()).is_some_and(|t| constant_time_eq(t.as_bytes(), config.admin_token.as_bytes()));
+    if !config.auto_ban.enabled || admin {
+        return Ok(next.run(req).await);
+    }
+    // 此时尚未识别调用方，按 X-API-Key 直接匹配密钥名称
+    let key = req.headers().get(API_KEY_HEADER).and_then(|presented| {
+        config.api_keys.iter().find(|k| constant_time_eq(presented.as_bytes(), k.key.as_bytes())).map(|k| format!("key:{}", k.name))
+    });
+    let ip = req.extensions().get::<ClientIp>().filter(|ip| !ip.0.is_loopback()).map(|ip| format!("ip:{}", ip.0));
+    let subjects: Vec<String> = ip.into_iter().chain(key).collect();
+    {
+        let mut bans = state.bans.lock().unwrap();
+        if let Some(subject) = subjects.iter().find(|subject| bans.is_banned(subject)) {
+            return Err(AppError::Forbidden(tr!("{} 因多次违规被暂时封禁。", "{} is temporarily banned after repeated violations.", subject)));
+        }
+    }
+    let response = next.run(req).await;
+    if let Some(code) = response.extensions().get::<ApiErrorCode>().copied().filter(|code| is_violation(*code)) {
+        let mut bans = state.bans.lock().unwrap();
+        for subject in subjects {
+            if let Some(ban) = bans.record(subject, code, &config.auto_ban) {
+                eprintln!("{} {}", "WARN".yellow(), tr!("已封禁 {}，{} 秒内 {} 次违规 (最后一次: {})，封禁至 {}", "Banned {} after {2} violations within {1} s (last: {3}) until {4}",
+                    ban.subject, config.auto_ban.window_secs, ban.violations, ban.reason, ban.expires_at));
+            }
+        }
+    }
+    Ok(response)
+}
+
+// --- 按调用方限流与每日配额 ---
+
+/// 限流响应头，同时通过 CORS 暴露给浏览器中的调用方
+pub(crate) const RATE_LIMIT_HEADERS: [&str; 6] = [
+    "x-ratelimit-limit", "x-ratelimit-remaining", "x-ratelimit-reset",
+    "x-quota-limit", "x-quota-remaining", "x-quota-reset",
+];
+
+/// 单个调用方当前窗口与当天的请求数
+pub(crate) struct RateCounter {
+    window: u64,                 // 固定窗口序号（Unix 秒数 / window_secs）
+    requests: u64,
+    day: chrono::NaiveDate,
+    day_requests: u64,
+}
+
+/// 一次计数后的限流状态，用于生成响应头
+struct RateDecision {
+    limit: u64,
+    remaining: u64,
+    reset_secs: u64,
+    quota: Option<(u64, u64, u64)>, // 每日配额: (上限, 剩余, 距次日零点的秒数)
+    exceeded: Option<ApiErrorCode>, // 超出窗口限额或每日配额时为对应错误码，此次请求不计数
+}
+
+impl RateDecision {
+    fn apply(&self, headers: &mut HeaderMap) {
+        let [limit, remaining, reset, quota_limit, quota_remaining, quota_reset] = RATE_LIMIT_HEADERS.map(HeaderName::from_static);
+        headers.insert(limit, HeaderValue::from(self.limit));
+        headers.insert(remaining, HeaderValue::from(self.remaining));
+        headers.insert(reset, HeaderValue::from(self.reset_secs));
+        if let Some((limit, remaining, reset)) = self.quota {
+            headers.insert(quota_limit, HeaderValue::from(limit));
+            headers.insert(quota_remaining, HeaderValue::from(remaining));
+            headers.insert(quota_reset, HeaderValue::from(reset));
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct RateLimiter {
+    pub(crate) counters: HashMap<String, RateCounter>,
+}
+
+impl RateLimiter {
+    /// 为调用方计入一次请求（超限时不计入）并返回计数后的状态
+    fn check(&mut self, subject: &str, config: &RateLimitConfig) -> RateDecision {
+        use chrono::Timelike;
+        let now = Local::now();
+        let secs = now.timestamp().max(0) as u64;
+        let window = secs / config.window_secs;
+        let today = now.date_naive();
+        if self.counters.len() > RATE_LIMIT_PRUNE_THRESHOLD {
+            // 顺带清理窗口与配额均已过期的调用方，避免大量一次性来源长期占用内存
+            self.counters.retain(|_, c| c.window == window || (config.daily_quota > 0 && c.day == today));
+        }
+        let counter = self.counters.entry(subject.to_string())
+            .or_insert(RateCounter { window, requests: 0, day: today, day_requests: 0 });
+        if counter.window != window {
+            counter.window = window;
+            counter.requests = 0;
+        }
+        if counter.day != today {
+            counter.day = today;
+            counter.day_requests = 0;
+        }
+        let exceeded = if counter.requests >= config.requests {
+            Some(ApiErrorCode::RateLimited)
+        } else if config.daily_quota > 0 && counter.day_requests >= config.daily_quota {
+            Some(ApiErrorCode::QuotaExceeded)
+        } else {
+            counter.requests += 1;
+            counter.day_requests += 1;
+            None
+        };
+        RateDecision {
+            limit: config.requests,
+            remaining: config.requests.saturating_sub(counter.requests),
+            reset_secs: (window + 1) * config.window_secs - secs,
+            quota: (config.daily_quota > 0).then(|| (
+                config.daily_quota,
+                config.daily_quota.saturating_sub(counter.day_requests),
+                86_400u64.saturating_sub(now.num_seconds_from_midnight() as u64),
+            )),
+            exceeded,
+        }
+    }
+}
+
+/// 按调用方限流（见 `RateLimitConfig`），位于 `identify_caller` 之内以便按密钥名称计数。
+/// 每个计数的响应（含 429）都带 X-RateLimit-* 头，客户端可据此自行降速。
+pub(crate) async fn enforce_rate_limit(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let config = state.current_config();
+    let caller = CALLER.try_with(|caller| (caller.admin, caller.key_name.clone())).unwrap_or((false, None));
+    let probe = matches!(req.uri().path(), "/health" | "/livez" | "/readyz");
+    if !config.rate_limit.enabled || caller.0 || probe {
+        return next.run(req).await;
+    }
+    let subject = match caller.1 {
+        Some(key) => format!("key:{}", key),
+        None => match req.extensions().get::<ClientIp>() {
+            Some(ip) => format!("ip:{}", ip.0),
+            None => return next.run(req).await,
+        },
+    };
+    let decision = state.rate_limits.lock().unwrap().check(&subject, &config.rate_limit);
+    let mut response = match decision.exceeded {
+        Some(code) => {
+            state.rate_limited.fetch_add(1, Ordering::Relaxed);
+            let (detail, retry_after) = if code == ApiErrorCode::QuotaExceeded {
+                let reset = decision.quota.map(|(_, _, reset)| reset).unwrap_or(0);
+                (tr!("{} 已用完今日配额 ({} 次)。", "{} has used up its daily quota ({} requests).", subject, config.rate_limit.daily_quota), reset)
+            } else {
+                (tr!("{} 请求过于频繁：每 {} 秒最多 {} 次。", "{} is sending too many requests: at most {2} per {1} s.", subject, config.rate_limit.window_secs, config.rate_limit.requests), decision.reset_secs)
+            };
+            let mut response = problem_response(StatusCode::TOO_MANY_REQUESTS, code, detail);
+            response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(retry_after.max(1)));
+            response
+        }
+        None => next.run(req).await,
+    };
+    decision.apply(response.headers_mut());
+    response
+}
+
+/// 客户端可用 X-Deadline-Ms 为本请求的数据库操作设置时限（不超过 max_deadline_ms），使大批量请求无法长期占用工作线程：
+/// 执行中的查询由 SQLite 进度回调中止，仍在排队的任务直接放弃，返回 504。未带该头的请求只受 request_timeout_ms 限制。
+pub(crate) async fn apply_request_deadline(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let Some(value) = req.headers().get(DEADLINE_HEADER) else {
+        return Ok(next.run(req).await);
+    };
+    let ms = value.to_str().ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .filter(|ms| *ms > 0)
+        .ok_or_else(|| AppError::Rejected(ApiErrorCode::InvalidRequest, tr!("X-Deadline-Ms 必须是正整数（毫秒）", "X-Deadline-Ms must be a positive integer (milliseconds)").to_string()))?;
+    let ms = ms.min(state.current_config().max_deadline_ms);
+    Ok(DB_DEADLINE.scope(Instant::now() + Duration::from_millis(ms), next.run(req)).await)
+}
+
+/// 全局请求超时：超过 request_timeout_ms 时丢弃处理器 future 并返回 504，
+/// 其中经由 `run_db` 执行的查询会被中断。NDJSON 流在响应头发出后不受此限制。
+pub(crate) async fn enforce_request_timeout(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let timeout_ms = state.current_config().request_timeout_ms;
+    let path = req.uri().path().to_string();
+    match tokio::time::timeout(Duration::from_millis(timeout_ms), next.run(req)).await {
+        Ok(response) => Ok(response),
+        Err(_) => {
+            eprintln!("{} {}", "WARN".yellow(), tr!("请求 {} 超时 ({} ms)", "Request to {} timed out after {} ms", path, timeout_ms));
+            Err(AppError::Timeout(tr!("请求处理超时 ({} ms)", "Request timed out after {} ms", timeout_ms)))
+        }
+    }
+}
@@ -0,0 +1,413 @@
+//! HTTP 服务：监听（systemd socket activation、PROXY protocol、TLS）与服务启动。
+
+use crate::*;
+
+mod auth;
+mod middleware;
+mod routes;
+
+pub(crate) use self::auth::*;
+pub use self::middleware::*;
+pub use self::routes::*;
+
+// --- systemd 集成 ---
+// 示例单元（socket activation 时由 systemd 持有端口，重启服务期间新连接在套接字队列中等待）：
+//   cyber_lookup.socket:  [Socket] ListenStream=3000
+//   cyber_lookup.service: [Service] Type=notify  ExecStart=/usr/local/bin/cyber_lookup serve  WorkingDirectory=/var/lib/cyber_lookup
+
+/// 向 systemd 发送状态通知 (sd_notify)；不是由 systemd 以 Type=notify 启动（没有 NOTIFY_SOCKET）时什么也不做。
+#[cfg(unix)]
+pub(crate) fn sd_notify(message: &str) {
+    use std::os::unix::net::UnixDatagram;
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let sent = UnixDatagram::unbound().and_then(|socket| match path.as_encoded_bytes().strip_prefix(b"@") {
+        // "@" 开头表示 Linux 抽象命名空间
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(message.as_bytes(), &addr)
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "abstract socket")),
+        None => socket.send_to(message.as_bytes(), &path),
+    });
+    if let Err(e) = sent {
+        eprintln!("{} {}", "WARN".yellow(), tr!("sd_notify 失败: {}", "sd_notify failed: {}", e));
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn sd_notify(_message: &str) {}
+
+/// systemd socket activation 传入的监听套接字（LISTEN_PID 为本进程且 LISTEN_FDS ≥ 1 时为 fd 3）。
+/// 只在首次调用时取得所有权，之后每次启动服务（含 start --supervise 重启）使用它的副本。
+fn activated_listener() -> Option<&'static std::net::TcpListener> {
+    static LISTENER: OnceLock<Option<std::net::TcpListener>> = OnceLock::new();
+    LISTENER.get_or_init(take_activated_listener).as_ref()
+}
+
+#[cfg(unix)]
+fn take_activated_listener() -> Option<std::net::TcpListener> {
+    use std::os::fd::FromRawFd;
+    const SD_LISTEN_FDS_START: i32 = 3;
+    let for_us = env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok()) == Some(process::id());
+    let fds = env::var("LISTEN_FDS").ok().and_then(|n| n.parse::<u32>().ok()).unwrap_or(0);
+    if !for_us || fds == 0 {
+        return None;
+    }
+    if fds > 1 {
+        eprintln!("{} {}", "WARN".yellow(), tr!("systemd 传入了 {} 个套接字，只使用第一个。", "systemd passed {} sockets; only the first is used.", fds));
+    }
+    // SAFETY: 按 sd_listen_fds 约定，fd 3 起为 systemd 传入的监听套接字，且只在这里（经 OnceLock 保证一次）取得所有权
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+    if let Err(e) = listener.set_nonblocking(true) {
+        eprintln!("{} {}", "WARN".yellow(), tr!("systemd 传入的套接字不可用，改为自行监听: {}", "The socket passed by systemd is unusable, binding normally: {}", e));
+        return None;
+    }
+    Some(listener)
+}
+
+#[cfg(not(unix))]
+fn take_activated_listener() -> Option<std::net::TcpListener> {
+    None
+}
+
+// --- PROXY protocol ---
+
+/// 读取并解析连接开头的 PROXY protocol 头（v1 文本或 v2 二进制），只消费头部本身。
+/// 返回其中的源地址；LOCAL 命令（负载均衡器自身的健康检查）或 UNKNOWN 协议族返回 None，调用方使用实际对端地址。
+async fn read_proxy_header<S: tokio::io::AsyncRead + Unpin>(stream: &mut S) -> io::Result<Option<SocketAddr>> {
+    use tokio::io::AsyncReadExt;
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let mut head = [0u8; 5];
+    stream.read_exact(&mut head).await?;
+    if &head == b"PROXY" {
+        // v1: "PROXY TCP4 源地址 目标地址 源端口 目标端口\r\n"，逐字节读取以免吞掉后续的 HTTP 数据
+        let mut line = head.to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= PROXY_V1_MAX_LENGTH {
+                return Err(invalid("PROXY v1 header too long"));
+            }
+            line.push(stream.read_u8().await?);
+        }
+        let line = std::str::from_utf8(&line[..line.len() - 2]).map_err(|_| invalid("PROXY v1 header is not ASCII"))?;
+        let fields: Vec<&str> = line.split(' ').collect();
+        return match fields.as_slice() {
+            ["PROXY", "UNKNOWN", ..] => Ok(None),
+            ["PROXY", "TCP4" | "TCP6", source, _, port, _] => {
+                let ip = source.parse::<IpAddr>().map_err(|_| invalid("invalid PROXY v1 source address"))?;
+                let port = port.parse::<u16>().map_err(|_| invalid("invalid PROXY v1 source port"))?;
+                Ok(Some(SocketAddr::new(ip, port)))
+            }
+            _ => Err(invalid("malformed PROXY v1 header")),
+        };
+    }
+    // v2: 12 字节签名 + 版本/命令 + 协议族 + 2 字节长度 + 地址块
+    let mut fixed = [0u8; 16];
+    fixed[..5].copy_from_slice(&head);
+    stream.read_exact(&mut fixed[5..]).await?;
+    if &fixed[..12] != PROXY_V2_SIGNATURE || fixed[12] >> 4 != 2 {
+        return Err(invalid("missing PROXY protocol header"));
+    }
+    let mut block = vec![0u8; u16::from_be_bytes([fixed[14], fixed[15]]) as usize];
+    stream.read_exact(&mut block).await?;
+    if fixed[12] & 0x0F == 0 {
+        return Ok(None); // LOCAL
+    }
+    let source = match fixed[13] >> 4 {
+        1 if block.len() >= 12 => {
+            let ip = Ipv4Addr::new(block[0], block[1], block[2], block[3]);
+            SocketAddr::new(ip.into(), u16::from_be_bytes([block[8], block[9]]))
+        }
+        2 if block.len() >= 36 => {
+            let octets: [u8; 16] = block[..16].try_into().unwrap_or_default();
+            SocketAddr::new(Ipv6Addr::from(octets).into(), u16::from_be_bytes([block[32], block[33]]))
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(source))
+}
+
+/// 按 http 配置构造连接处理器：HTTP/1.1 与（启用时）HTTP/2 自动识别
+fn http_connection_builder(http: &HttpServerConfig) -> hyper_util::server::conn::auto::Builder<hyper_util::rt::TokioExecutor> {
+    let idle_timeout = (http.idle_timeout_secs > 0).then(|| Duration::from_secs(http.idle_timeout_secs));
+    let mut builder = hyper_util::server::conn::auto::Builder::new(hyper_util::rt::TokioExecutor::new());
+    builder.http1()
+        .timer(hyper_util::rt::TokioTimer::new())
+        .keep_alive(http.keep_alive)
+        .header_read_timeout(idle_timeout);
+    builder.http2()
+        .timer(hyper_util::rt::TokioTimer::new())
+        .max_concurrent_streams(http.http2_max_concurrent_streams)
+        .keep_alive_interval((http.http2_keep_alive_interval_secs > 0).then(|| Duration::from_secs(http.http2_keep_alive_interval_secs)));
+    if let Some(timeout) = idle_timeout {
+        builder.http2().keep_alive_timeout(timeout);
+    }
+    if http.http2 { builder } else { builder.http1_only() }
+}
+
+/// 读取 PEM 证书链与私钥；启用 HTTP/2 时经 ALPN 优先协商 h2
+fn load_tls_acceptor(http: &HttpServerConfig) -> Result<tokio_rustls::TlsAcceptor, String> {
+    use rustls_pki_types::pem::PemObject;
+    let certs = rustls_pki_types::CertificateDer::pem_file_iter(&http.tls_cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("{}: {}", http.tls_cert_path, e))?;
+    let key = rustls_pki_types::PrivateKeyDer::from_pem_file(&http.tls_key_path)
+        .map_err(|e| format!("{}: {}", http.tls_key_path, e))?;
+    let provider = Arc::new(tokio_rustls::rustls::crypto::ring::default_provider());
+    let mut tls = tokio_rustls::rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map_err(|e| e.to_string())?;
+    tls.alpn_protocols = if http.http2 { vec![b"h2".to_vec(), b"http/1.1".to_vec()] } else { vec![b"http/1.1".to_vec()] };
+    Ok(tokio_rustls::TlsAcceptor::from(Arc::new(tls)))
+}
+
+/// HTTP 监听循环：启用 proxy_protocol 时先读取 PROXY 头并以其中的源地址作为连接信息（没有合法 PROXY 头的连接直接关闭），
+/// 配置了证书时完成 TLS 握手，再按 http 配置以 HTTP/1.1 或 HTTP/2 交给路由处理。
+async fn serve_connections(listener: tokio::net::TcpListener, app: Router, config: &ServiceConfig, tls: Option<tokio_rustls::TlsAcceptor>) -> io::Result<()> {
+    let builder = Arc::new(http_connection_builder(&config.http));
+    let proxy_protocol = config.proxy_protocol;
+    loop {
+        let (mut stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                // 文件描述符耗尽等错误是暂时的，稍后重试而不是退出服务
+                eprintln!("{} {}", "WARN".yellow(), tr!("接受连接失败: {}", "Failed to accept connection: {}", e));
+                sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let app = app.clone();
+        let builder = builder.clone();
+        let tls = tls.clone();
+        tokio::spawn(async move {
+            let client = if proxy_protocol {
+                let header = tokio::time::timeout(Duration::from_millis(PROXY_HEADER_TIMEOUT_MS), read_proxy_header(&mut stream)).await;
+                match header {
+                    Ok(Ok(source)) => source.unwrap_or(peer),
+                    Ok(Err(e)) => {
+                        eprintln!("{} {}", "WARN".yellow(), tr!("关闭来自 {} 的连接: PROXY 头无效 ({})", "Closing connection from {}: invalid PROXY header ({})", peer, e));
+                        return;
+                    }
+                    Err(_) => {
+                        eprintln!("{} {}", "WARN".yellow(), tr!("关闭来自 {} 的连接: 等待 PROXY 头超时", "Closing connection from {}: timed out waiting for the PROXY header", peer));
+                        return;
+                    }
+                }
+            } else {
+                peer
+            };
+            let _ = stream.set_nodelay(true);
+            let service = tower::ServiceExt::map_request(app, move |mut req: Request<hyper::body::Incoming>| {
+                req.extensions_mut().insert(ConnectInfo(client));
+                req
+            });
+            let service = hyper_util::service::TowerToHyperService::new(service);
+            // 客户端中途断开或握手失败属于正常情况，不记录
+            match tls {
+                Some(tls) => {
+                    let Ok(Ok(stream)) = tokio::time::timeout(Duration::from_secs(TLS_HANDSHAKE_TIMEOUT_SECS), tls.accept(stream)).await else {
+                        return;
+                    };
+                    let _ = builder.serve_connection(hyper_util::rt::TokioIo::new(stream), service).await;
+                }
+                None => {
+                    let _ = builder.serve_connection(hyper_util::rt::TokioIo::new(stream), service).await;
+                }
+            }
+        });
+    }
+}
+
+pub(crate) async fn try_start_server(state: Arc<AppState>) -> Result<(), AppError> {
+    let config = state.current_config();
+    
+    if let Err(e) = config.validate() {
+        return Err(AppError::FatalError(tr!("配置校验失败: {}", "Config validation failed: {}", e)));
+    }
+    let _log_redirect = redirect_output(&config).unwrap_or_else(|e| {
+        eprintln!("{} {}", "WARN".yellow(), tr!("无法打开日志输出目标，继续输出到终端: {}", "Cannot open the log target, logging to the terminal: {}", e));
+        None
+    });
+    install_error_reporting(&config);
+
+    let bind_addr = config.bind_address.clone();
+
+    // 数据库所在的网络存储可能晚于本服务就绪，按 startup_retry 退避重试后才放弃
+    let retry = &config.startup_retry;
+    let mut attempt = 1;
+    let mut backoff = retry.initial_backoff_ms;
+    while let Err(e) = initialize_storage(&state, &config).await {
+        if attempt >= retry.max_attempts {
+            return Err(e);
+        }
+        println!("{} {}", "WARN".yellow(), tr!("数据库暂不可用 (第 {}/{} 次尝试)，{} ms 后重试...", "Database unavailable (attempt {}/{}), retrying in {} ms...", attempt, retry.max_attempts, backoff));
+        sd_notify(&format!("STATUS=Waiting for the database (attempt {}/{})", attempt, retry.max_attempts));
+        sleep(Duration::from_millis(backoff)).await;
+        backoff = backoff.saturating_mul(2).min(retry.max_backoff_ms);
+        attempt += 1;
+    }
+
+    if config.bloom.enabled && !config.single_sqlite_file() {
+        println!("{} {}", "INFO".yellow(), tr!("布隆过滤器仅用于未分片的 sqlite 后端，已跳过。", "The bloom filter only applies to the unsharded sqlite backend; skipped."));
+    } else if config.bloom.enabled {
+        println!("{} {}", "INFO".yellow(), tr!("正在构建布隆过滤器...", "Building bloom filter..."));
+        let started = Instant::now();
+        let bloom_state = state.clone();
+        match task::spawn_blocking(move || bloom_state.rebuild_bloom()).await {
+            Ok(Ok(())) => println!("{} {}", "OK".green(), tr!("布隆过滤器就绪，用时 {:.2?}。", "Bloom filter ready in {:.2?}.", started.elapsed())),
+            // 构建失败不影响服务，只是所有查询都会访问数据库
+            Ok(Err(e)) => eprintln!("{} {}", "WARN".yellow(), tr!("布隆过滤器构建失败，将直接查询数据库: {}", "Bloom filter build failed, querying the database directly: {}", e)),
+            Err(e) => eprintln!("{} {}", "WARN".yellow(), tr!("布隆过滤器构建任务异常: {}", "Bloom filter build task panicked: {}", e)),
+        }
+    }
+
+    // 预加载常用 ID，须在开始监听之前完成
+    if config.cache.preload_top > 0 && config.cache.enabled && config.single_sqlite_file() {
+        let started = Instant::now();
+        match preload_frequent_ids(&state, &config).await {
+            Ok(loaded) => println!("{} {}", "OK".green(), tr!("已预加载 {} 个常用 ID 到查询缓存，用时 {:.2?}。", "Preloaded {} frequently queried ID(s) into the cache in {:.2?}.", loaded, started.elapsed())),
+            Err(e) => eprintln!("{} {}", "WARN".yellow(), tr!("预加载常用 ID 失败，以空缓存启动: {:?}", "Preloading frequent IDs failed, starting with a cold cache: {:?}", e)),
+        }
+    }
+
+    let addr: SocketAddr = bind_addr.parse()
+        .map_err(|e| AppError::FatalError(tr!("配置错误: 绑定地址格式无效: {}", "Config Error: Invalid bind address format: {}", e)))?;
+    
+    let listener = match activated_listener() {
+        Some(socket) => {
+            let listener = socket.try_clone().and_then(tokio::net::TcpListener::from_std).map_err(AppError::NetworkBindError)?;
+            println!("{} {}", "INFO".yellow(), tr!("使用 systemd 传入的监听套接字，忽略 bind_address。", "Using the listening socket passed by systemd; bind_address is ignored."));
+            listener
+        }
+        None => tokio::net::TcpListener::bind(addr).await.map_err(AppError::NetworkBindError)?,
+    };
+    let addr = listener.local_addr().map_err(AppError::NetworkBindError)?;
+    let tls = if config.http.tls_enabled() {
+        Some(load_tls_acceptor(&config.http).map_err(|e| AppError::FatalError(tr!("配置错误: 无法加载 TLS 证书: {}", "Config Error: Cannot load the TLS certificate: {}", e)))?)
+    } else {
+        None
+    };
+    let scheme = if tls.is_some() { "https" } else { "http" };
+
+    println!("{} {}", "STARTED".green().bold(), tr!("服务启动，监听地址: {}://{}", "Server started, listening on {}://{}", scheme, addr));
+    println!("{} Endpoints: /v1/lookup/:id, /v1/graphql (POST), /v1/batch_lookup (POST/GET), /v1/batch_lookup/stream (POST, NDJSON), /v1/batch_lookup/file (POST, CSV), /v1/batch_delete (POST, ?dry_run=true -> ?confirm=), /v1/mappings (POST), /v1/mappings/:uid (PUT/DELETE, If-Match), /v1/stats, /v1/stats/history, /v1/signing_key, /v1/admin/cache/flush (POST), /v1/admin/overview, /v1/admin/import (POST), /v1/admin/config (GET/PUT), /v1/admin/retention, /v1/admin/access_trail, /v1/admin/bans (GET, DELETE /:subject), /v1/admin/deny_list (GET/POST, DELETE /:id), /v1/admin/log_level (GET/PUT), /v1/admin/merge (GET/POST), /v1/erase/:id (DELETE), /v2/lookup/:id, /v2/batch_lookup (POST), /admin/ui, /health, /livez, /readyz", "INFO".cyan());
+    println!("{} {}", "HINT".yellow(), tr!("提示: 不带 /v1 前缀的旧路径仍可使用，但已弃用（响应带 Deprecation 头）。", "Legacy paths without the /v1 prefix still work but are deprecated (responses carry a Deprecation header)."));
+    if config.anonymous_scopes.is_empty() {
+        println!("{} {}", "HINT".yellow(), tr!("提示: 所有查询与写入接口都要求 X-API-Key。", "All lookup and write endpoints require an X-API-Key."));
+    } else {
+        let scopes: Vec<String> = config.anonymous_scopes.iter()
+            .filter_map(|scope| serde_json::to_value(scope).ok().and_then(|v| v.as_str().map(String::from)))
+            .collect();
+        println!("{} {}", "HINT".yellow(), tr!("提示: 未出示 API 密钥的调用方拥有权限: {}", "Callers without an API key have the scopes: {}", scopes.join(", ")));
+    }
+    if config.jwt.enabled {
+        println!("{} {}", "HINT".yellow(), tr!("提示: 已启用 JWT 认证，可用 Authorization: Bearer <JWT> 代替 X-API-Key。", "JWT authentication is enabled; Authorization: Bearer <JWT> can be used instead of X-API-Key."));
+    }
+    if config.admin_token.is_empty() {
+        println!("{} {}", "HINT".yellow(), tr!("提示: 未配置 admin_token，管理接口与控制台 (/admin/ui) 只允许本机访问。", "admin_token is not set; admin endpoints and the dashboard (/admin/ui) only accept local connections."));
+    }
+    println!("{} {}", "HINT".yellow(), tr!("按 Ctrl+C 停止服务并进入管理模式。", "Press Ctrl+C to stop the server and enter management mode."));
+
+    if !config.tenants.databases.is_empty() {
+        let tenant_state = state.clone();
+        let count = task::spawn_blocking(move || tenant_state.open_tenants()).await
+            .map_err(|e| AppError::FatalError(tr!("后台任务异常终止: {}", "Background task aborted: {}", e)))??;
+        println!("{} {}", "OK".green(), tr!("已加载 {} 个租户: {}（路径前缀 {}{{租户}}/）", "Loaded {} tenant(s): {} (path prefix {}{{tenant}}/)", count, config.tenants.databases.keys().cloned().collect::<Vec<_>>().join(", "), TENANT_PATH_PREFIX));
+    }
+    let app = build_router(state.clone())?;
+    // 声明在监听之前：本函数返回时最后析构，清除监听地址与端口文件
+    let _listen_guard = ListenGuard {
+        state: state.clone(),
+        port_file: Some(config.port_file.clone()).filter(|path| !path.is_empty()),
+    };
+    state.listening.lock().unwrap().http = Some(addr);
+    // gRPC 服务随本函数返回（HTTP 服务停止或被 Ctrl+C 取消）一同停止
+    let _grpc = if config.grpc_bind_address.is_empty() {
+        None
+    } else {
+        Some(start_grpc_server(state.clone(), &config.grpc_bind_address).await?)
+    };
+    let _resp = if config.resp_bind_address.is_empty() {
+        None
+    } else {
+        Some(start_resp_server(state.clone(), &config.resp_bind_address).await?)
+    };
+    let _control = start_control_socket(&state, &config.control_socket);
+    // 所有监听就绪后再写端口文件，读取方看到文件即可连接
+    if !config.port_file.is_empty() {
+        let tmp = format!("{}.tmp", config.port_file);
+        fs::write(&tmp, format!("{}\n", addr.port()))
+            .and_then(|_| fs::rename(&tmp, &config.port_file))
+            .map_err(|e| AppError::FatalError(tr!("写入端口文件 {} 失败: {}", "Failed to write port file {}: {}", config.port_file, e)))?;
+    }
+
+    state.server_running.store(true, Ordering::SeqCst);
+    // 监听已就绪、表结构已初始化，通知 systemd (Type=notify)
+    sd_notify(&format!("READY=1\nSTATUS=Listening on {}", addr));
+    if config.proxy_protocol {
+        println!("{} {}", "INFO".yellow(), tr!("已启用 PROXY protocol，客户端地址取自负载均衡器发送的 PROXY 头。", "PROXY protocol enabled; client addresses are taken from the load balancer's PROXY header."));
+    }
+    let served = serve_connections(listener, app, &config, tls).await;
+    state.server_running.store(false, Ordering::SeqCst);
+    flush_pending_writes(&state).await;
+    served.map_err(AppError::IoError)?;
+        
+    Ok(())
+}
+
+/// 连接存储后端并检查/创建表结构，启动服务前调用
+async fn initialize_storage(state: &Arc<AppState>, config: &ServiceConfig) -> Result<(), AppError> {
+    if !config.single_sqlite_file() {
+        let store = state.store()?;
+        println!("{} {}", "INFO".yellow(), tr!("正在连接存储后端 {} 并检查表结构...", "Connecting to storage backend {} and checking schema...", store.name()));
+        if let Err(e) = store.initialize().await {
+            eprintln!("{} {}", "FAIL".red(), tr!("存储后端初始化失败: {}", "Storage backend initialization failed: {}", e));
+            return Err(e);
+        }
+        println!("{} {}", "OK".green(), tr!("数据库结构健全。", "Database schema OK."));
+    } else {
+        let db_path = &config.db_path;
+        println!("{} {}", "INFO".yellow(), tr!("正在尝试连接数据库: {}", "Connecting to database: {}", db_path));
+        let conn = state.get_db_connection().map_err(|e| {
+            eprintln!("{} {}", "FAIL".red(), tr!("数据库连接失败: {}", "Database connection failed: {}", e));
+            eprintln!("{} {}", "HINT".yellow(), tr!("提示: 请确保 {} 路径下的数据库文件存在且可访问。", "Make sure the database file at {} exists and is accessible.", db_path));
+            AppError::DbError(e)
+        })?;
+
+        println!("{} {}", "INFO".yellow(), tr!("正在检查/创建数据库表结构和索引...", "Checking/creating database schema and indexes..."));
+        match initialize_database(&conn) {
+            Ok(_) => println!("{} {}", "OK".green(), tr!("数据库结构健全。", "Database schema OK.")),
+            Err(e) => {
+                eprintln!("{} {}", "FAIL".red(), tr!("数据库初始化失败: {}", "Database initialization failed: {}", e));
+                return Err(AppError::DbError(e));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 服务停止时写入尚在内存中的访问记录，以及尚未写回的查询次数（供下次启动时预加载）
+pub(crate) async fn flush_pending_writes(state: &Arc<AppState>) {
+    let trail_state = state.clone();
+    if let Ok(Err(e)) = task::spawn_blocking(move || flush_access_trail(&trail_state)).await {
+        eprintln!("{} {}", "WARN".yellow(), tr!("写入访问记录失败: {}", "Failed to write the access trail: {}", e));
+    }
+    let config = state.current_config();
+    if config.cache.preload_top > 0 && config.single_sqlite_file() {
+        let (count_state, keep) = (state.clone(), config.cache.preload_top.saturating_mul(QUERY_FREQUENCY_KEEP_FACTOR));
+        if let Ok(Err(e)) = task::spawn_blocking(move || flush_query_counts(&count_state, keep)).await {
+            eprintln!("{} {}", "WARN".yellow(), tr!("写回查询次数失败: {}", "Failed to record query counts: {}", e));
+        }
+    }
+    if config.analytics.enabled && config.single_sqlite_file() {
+        let analytics_state = state.clone();
+        if let Ok(Err(e)) = task::spawn_blocking(move || flush_analytics(&analytics_state, &config.analytics)).await {
+            eprintln!("{} {}", "WARN".yellow(), tr!("写入查询统计历史失败: {}", "Failed to record query analytics: {}", e));
+        }
+    }
+}
@@ -0,0 +1,1525 @@
+//! HTTP 路由与处理器：查询、写入、管理接口、内容协商与健康检查。
+
+use crate::*;
+
+async fn api_lookup(
+    Path(id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<LookupQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let format = negotiate_format(&headers, query.format.as_deref())?;
+    let config = state.current_config();
+    let missing_code = missing_status_code(query.missing.unwrap_or(config.missing_status))
+        .ok_or_else(|| AppError::Rejected(ApiErrorCode::InvalidRequest, tr!("missing 只能为 404 或 200。", "missing must be 404 or 200.").to_string()))?;
+    let fields = parse_fields(query.fields.as_slice())?;
+    validate_ids([&id])?;
+    let started = Instant::now();
+    // 错误由 AppError 统一记录日志（含错误码与请求 ID）
+    let mut resp = lookup(&state, &id).await?;
+    state.record_query(Some(&id), std::slice::from_ref(&resp), started);
+    mirror_lookup(&state, &config, std::slice::from_ref(&id), std::slice::from_ref(&resp));
+    // 版本按存储中的值计算，须在后处理与脱敏之前；上游的结果不是本地记录，没有版本
+    let etag = resp.uid.as_deref().zip(resp.phone_number.as_deref())
+        .filter(|_| resp.status != "found_upstream")
+        .map(|(uid, phone)| mapping_etag(uid, phone));
+    // 响应体随格式与调用方（是否脱敏）不同，共享缓存须按这些请求头区分
+    let mut cache_headers = HeaderMap::new();
+    if let Some(etag) = etag.as_deref().and_then(|etag| HeaderValue::from_str(etag).ok()) {
+        cache_headers.insert(ETAG, etag);
+        if config.http_cache.max_age_secs > 0 {
+            let visibility = if config.http_cache.public { "public" } else { "private" };
+            if let Ok(value) = HeaderValue::from_str(&format!("{}, max-age={}", visibility, config.http_cache.max_age_secs)) {
+                cache_headers.insert(CACHE_CONTROL, value);
+            }
+            cache_headers.insert(VARY, HeaderValue::from_static("accept, x-api-key, authorization"));
+        }
+    }
+    if let Some(etag) = &etag {
+        let if_none_match = headers.get(IF_NONE_MATCH).and_then(|v| v.to_str().ok());
+        if if_none_match.is_some_and(|tags| if_none_match_satisfied(tags, etag)) {
+            return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
+        }
+    }
+    apply_post_process_hook(&state, std::slice::from_mut(&mut resp))?;
+    mask_results(&config, std::slice::from_mut(&mut resp));
+    let code = if resp.status == "not_found" { missing_code } else { StatusCode::OK };
+    let mut response = render_results(format, code, vec![resp], true, fields.as_deref())?;
+    response.headers_mut().extend(cache_headers);
+    Ok(response)
+}
+
+/// 当前请求是否应返回脱敏手机号：启用 mask_phone_numbers 且调用方没有 unmask 权限。
+/// 请求之外（嵌入调用、命令行）不脱敏。
+pub(crate) fn should_mask(config: &ServiceConfig) -> bool {
+    config.mask_phone_numbers && CALLER.try_with(|caller| !caller.has_scope(ApiScope::Unmask)).unwrap_or(false)
+}
+
+/// 手机号脱敏：11 位及以上保留前 3 位与后 4 位 (138****1234)，较短的号码只保留首尾各四分之一
+pub(crate) fn mask_phone(phone: &str) -> String {
+    let chars: Vec<char> = phone.chars().collect();
+    let (head, tail) = if chars.len() >= 11 { (3, 4) } else { (chars.len() / 4, chars.len() / 4) };
+    chars.iter().enumerate()
+        .map(|(i, &c)| if i < head || i >= chars.len() - tail { c } else { '*' })
+        .collect()
+}
+
+/// 按当前调用方的权限对查询结果脱敏
+pub(crate) fn mask_results(config: &ServiceConfig, results: &mut [LookupResponse]) {
+    redact_phones(results, should_mask(config));
+}
+
+/// 隐去只存了哈希的手机号（哈希模式下对所有调用方），`mask` 为 true 时再对其余号码脱敏
+pub(crate) fn redact_phones(results: &mut [LookupResponse], mask: bool) {
+    for result in results.iter_mut() {
+        if result.phone_number.as_deref().is_some_and(is_hashed_phone) {
+            result.phone_number = None;
+        } else if let Some(phone) = result.phone_number.as_mut().filter(|_| mask) {
+            *phone = mask_phone(phone);
+        }
+    }
+}
+
+/// 隐去连接串中的密码：URL 形式的 `user:password@`，以及 PostgreSQL 键值形式的 `password=...`。
+fn redact_url(url: &str) -> String {
+    if let Some((scheme, rest)) = url.split_once("://") {
+        if let Some((userinfo, host)) = rest.split_once('@') {
+            if let Some((user, _)) = userinfo.split_once(':') {
+                return format!("{}://{}:{}@{}", scheme, user, REDACTED, host);
+            }
+        }
+        return url.to_string();
+    }
+    url.split(' ')
+        .map(|part| if part.starts_with("password=") { format!("password={}", REDACTED) } else { part.to_string() })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// 对外展示的配置：令牌与连接串中的密码替换为 `***`。
+pub(crate) fn redacted_config(config: &ServiceConfig) -> ServiceConfig {
+    let mut config = config.clone();
+    for secret in [&mut config.api_key, &mut config.admin_token, &mut config.error_reporting.dsn, &mut config.storage.sqlcipher_key, &mut config.audit.signing_key, &mut config.jwt.hs256_secret, &mut config.upstream.api_key, &mut config.shadow.api_key] {
+        if !secret.is_empty() {
+            *secret = REDACTED.to_string();
+        }
+    }
+    for api_key in &mut config.api_keys {
+        api_key.key = REDACTED.to_string();
+        if !api_key.signing_secret.is_empty() {
+            api_key.signing_secret = REDACTED.to_string();
+        }
+    }
+    config.storage.postgres_url = redact_url(&config.storage.postgres_url);
+    config.redis.url = redact_url(&config.redis.url);
+    config
+}
+
+/// 隐去后的占位值；PUT /admin/config 收到该值（或与当前值脱敏结果相同的连接串）时保留原值，
+/// 使 GET 返回的配置可以原样修改后提交。
+const REDACTED: &str = "***";
+
+/// 把请求中仍为脱敏占位的密钥还原为当前值。
+fn restore_redacted(new: &mut ServiceConfig, current: &ServiceConfig) {
+    if new.api_key == REDACTED {
+        new.api_key = current.api_key.clone();
+    }
+    if new.admin_token == REDACTED {
+        new.admin_token = current.admin_token.clone();
+    }
+    // API 密钥按名称对应
+    for api_key in &mut new.api_keys {
+        if let Some(existing) = current.api_keys.iter().find(|k| k.name == api_key.name) {
+            if api_key.key == REDACTED {
+                api_key.key = existing.key.clone();
+            }
+            if api_key.signing_secret == REDACTED {
+                api_key.signing_secret = existing.signing_secret.clone();
+            }
+        }
+    }
+    if new.error_reporting.dsn == REDACTED {
+        new.error_reporting.dsn = current.error_reporting.dsn.clone();
+    }
+    if new.storage.sqlcipher_key == REDACTED {
+        new.storage.sqlcipher_key = current.storage.sqlcipher_key.clone();
+    }
+    if new.audit.signing_key == REDACTED {
+        new.audit.signing_key = current.audit.signing_key.clone();
+    }
+    if new.jwt.hs256_secret == REDACTED {
+        new.jwt.hs256_secret = current.jwt.hs256_secret.clone();
+    }
+    if new.upstream.api_key == REDACTED {
+        new.upstream.api_key = current.upstream.api_key.clone();
+    }
+    if new.shadow.api_key == REDACTED {
+        new.shadow.api_key = current.shadow.api_key.clone();
+    }
+    if new.storage.postgres_url == redact_url(&current.storage.postgres_url) {
+        new.storage.postgres_url = current.storage.postgres_url.clone();
+    }
+    if new.redis.url == redact_url(&current.redis.url) {
+        new.redis.url = current.redis.url.clone();
+    }
+}
+
+/// 运行中无法切换的配置项（监听、连接池、中间件与存储后端在启动时构造）。
+/// 返回 `new` 中这些项已被改回 `running` 取值的副本，以及发生变化、需重启才生效的项名。
+pub(crate) fn split_restart_fields(running: &ServiceConfig, new: &ServiceConfig) -> (ServiceConfig, Vec<&'static str>) {
+    let mut applied = new.clone();
+    let mut pending = Vec::new();
+    macro_rules! keep_running {
+        ($($name:literal => $($field:ident).+),* $(,)?) => {$(
+            if serde_json::to_value(&new.$($field).+).ok() != serde_json::to_value(&running.$($field).+).ok() {
+                pending.push($name);
+                applied.$($field).+ = running.$($field).+.clone();
+            }
+        )*};
+    }
+    keep_running!(
+        "db_path" => db_path,
+        "bind_address" => bind_address,
+        "grpc_bind_address" => grpc_bind_address,
+        "resp_bind_address" => resp_bind_address,
+        "control_socket" => control_socket,
+        "deny_list.path" => deny_list.path,
+        "max_in_flight_requests" => max_in_flight_requests,
+        "retry_after_secs" => retry_after_secs,
+        "db_pool_size" => db_pool_size,
+        "trusted_proxies" => trusted_proxies,
+        "proxy_protocol" => proxy_protocol,
+        "http" => http,
+        "cors" => cors,
+        "cache.max_entries" => cache.max_entries,
+        "cache.ttl_secs" => cache.ttl_secs,
+        "upstream.cache_ttl_secs" => upstream.cache_ttl_secs,
+        "write_queue.capacity" => write_queue.capacity,
+        "idempotency_ttl_secs" => idempotency_ttl_secs,
+        "storage" => storage,
+        "redis" => redis,
+        "phone_encryption" => phone_encryption,
+        "error_reporting" => error_reporting,
+        "tenants.databases" => tenants.databases,
+    );
+    (applied, pending)
+}
+
+#[derive(Serialize)]
+struct ConfigUpdateResponse {
+    config: ServiceConfig,             // 保存后的配置（已脱敏）
+    pending_restart: Vec<&'static str>, // 已保存但需重启服务才生效的项
+}
+
+/// 单个 API 密钥自进程启动以来的用量
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct KeyUsage {
+    pub(crate) requests: u64,             // 请求数（所有接口）
+    pub(crate) errors: u64,               // 其中状态码为 4xx / 5xx 的请求数
+    pub(crate) lookups: u64,              // 查询请求数（单条与批量）
+    pub(crate) ids: u64,                  // 查询的 ID 总数
+    pub(crate) found: u64,                // 其中找到的 ID 数
+    pub(crate) max_batch_size: usize,
+    pub(crate) last_seen: Option<String>, // 最近一次请求的时间 (RFC 3339)
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct KeyUsageReport {
+    pub(crate) key: String,
+    pub(crate) since: String,             // 统计起点（进程启动时间）
+    #[serde(flatten)]
+    pub(crate) usage: KeyUsage,
+    pub(crate) hit_rate: f64,             // found / ids
+    pub(crate) avg_batch_size: f64,       // ids / lookups
+}
+
+#[derive(Serialize)]
+struct AdminOverview {
+    version: String,
+    language: Language,
+    storage_backend: &'static str,
+    records: Option<u64>,          // 存储后端不可用时为 None
+    stats: StatsResponse,
+    recent_queries: Vec<RecentQuery>, // 最新的在前
+    config: ServiceConfig,
+}
+
+/// 管理控制台首页（单页应用，数据来自 /v1/admin/overview 等接口）。
+async fn admin_ui() -> impl IntoResponse {
+    ([(CONTENT_TYPE, "text/html; charset=utf-8")], include_str!("../admin_ui.html"))
+}
+
+/// 控制台概览：记录数、缓存统计、最近查询与（脱敏后的）当前配置。
+async fn api_admin_overview(State(state): State<Arc<AppState>>) -> Result<Json<AdminOverview>, AppError> {
+    let config = state.current_config();
+    let store = state.store()?;
+    let records = store.count(None).await.ok();
+    let stats_state = state.clone();
+    let stats = task::spawn_blocking(move || stats_state.stats()).await
+        .map_err(|e| AppError::FatalError(tr!("后台任务异常终止: {}", "Background task aborted: {}", e)))?;
+    let recent_queries = state.recent_queries.lock().unwrap().iter().rev().cloned().collect();
+    Ok(Json(AdminOverview {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        language: config.language,
+        storage_backend: store.name(),
+        records,
+        stats,
+        recent_queries,
+        config: redacted_config(&config),
+    }))
+}
+
+/// 当前生效的配置（密钥已脱敏）。
+async fn api_admin_config(State(state): State<Arc<AppState>>) -> Json<ServiceConfig> {
+    Json(redacted_config(&state.current_config()))
+}
+
+/// 整体替换配置：校验后写入 config.txt，可热更新的项立即生效，其余项在响应的 pending_restart 中列出。
+/// 缺失的字段取默认值；值为 `***` 的密钥保持不变。
+async fn api_admin_config_update(
+    State(state): State<Arc<AppState>>,
+    Json(mut new_config): Json<ServiceConfig>,
+) -> Result<Json<ConfigUpdateResponse>, AppError> {
+    let running = state.current_config();
+    restore_redacted(&mut new_config, &running);
+    new_config.validate().map_err(|e| AppError::Rejected(ApiErrorCode::InvalidRequest, tr!("配置校验失败: {}", "Config validation failed: {}", e)))?;
+
+    let saved = new_config.clone();
+    task::spawn_blocking(move || save_config(&saved)).await
+        .map_err(|e| AppError::FatalError(tr!("后台任务异常终止: {}", "Background task aborted: {}", e)))??;
+    let (applied, pending_restart) = split_restart_fields(&running, &new_config);
+    state.set_config(applied);
+
+    println!("{} {}", "INFO".yellow(), tr!("配置已通过管理接口更新并保存到 {}", "Configuration updated via admin API and saved to {}", DEFAULT_CONFIG_FILE));
+    if !pending_restart.is_empty() {
+        println!("{} {}", "HINT".yellow(), tr!("以下配置项需重启服务后生效: {}", "These settings take effect after a restart: {}", pending_restart.join(", ")));
+    }
+    Ok(Json(ConfigUpdateResponse { config: redacted_config(&new_config), pending_restart }))
+}
+
+/// 上传 CSV、NDJSON 或 xlsx (uid, phone_number，表头可选，按内容识别格式) 导入映射，供控制台使用；查询参数与 `POST /mappings` 相同。
+/// 按 IMPORT_CHUNK_SIZE 行分批写入，每批一个事务：策略为 error 时遇到冲突即停止，之前的批次已提交。
+async fn api_admin_import(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<WriteQuery>,
+    req: Request,
+) -> Result<Json<ImportSummary>, AppError> {
+    let data = read_csv_upload(req).await?;
+    let rules = state.current_config().phone_rules;
+    let mut summary = ImportSummary::default();
+    let mut mappings = Vec::new();
+    for record in import_records(Box::new(io::Cursor::new(data)), None)? {
+        let (line, fields) = match record? {
+            (line, Ok(fields)) => (line, fields),
+            (_, Err(_)) => {
+                summary.rows += 1;
+                summary.invalid += 1;
+                continue;
+            }
+        };
+        if is_import_header(line, &fields) {
+            continue;
+        }
+        summary.rows += 1;
+        match fields.as_slice() {
+            [uid, phone] if valid_field(uid) && valid_field(phone) => match rules.normalize_for_write(phone) {
+                Ok(phone) => mappings.push((uid.clone(), phone)),
+                Err(_) => summary.invalid += 1,
+            },
+            _ => summary.invalid += 1,
+        }
+    }
+
+    let store = state.store()?;
+    for chunk in mappings.chunks(IMPORT_CHUNK_SIZE) {
+        let report = store.insert(chunk.to_vec(), query.on_conflict, query.dry_run).await?;
+        if !query.dry_run {
+            state.invalidate_cache(report.affected_keys);
+            let ids: Vec<&str> = chunk.iter().flat_map(|(uid, phone)| [uid.as_str(), phone.as_str()]).collect();
+            state.bloom_insert(&ids);
+        }
+        summary.writes.merge(&report.summary);
+    }
+    if !query.dry_run {
+        println!("{} {}", "INFO".yellow(), tr!("控制台导入完成: {}", "Dashboard import finished: {}", summary.describe()));
+    }
+    Ok(Json(summary))
+}
+
+#[derive(Serialize)]
+struct BansResponse {
+    bans: Vec<Ban>,
+}
+
+/// 当前生效的封禁，按封禁时间排序
+async fn api_admin_bans(State(state): State<Arc<AppState>>) -> Json<BansResponse> {
+    let mut tracker = state.bans.lock().unwrap();
+    let now = Instant::now();
+    tracker.banned.retain(|_, ban| ban.until > now);
+    let mut bans: Vec<Ban> = tracker.banned.values().cloned().collect();
+    bans.sort_by(|a, b| a.banned_at.cmp(&b.banned_at));
+    Json(BansResponse { bans })
+}
+
+#[derive(Debug, Deserialize)]
+struct LogLevelRequest {
+    level: String,
+    #[serde(default)]
+    persist: bool,                   // 同时写入配置文件的 log_level，否则重启或修改 log_level 后恢复为配置值
+}
+
+#[derive(Serialize)]
+struct LogLevelResponse {
+    level: String,                   // 当前生效的过滤规则
+    configured: String,              // 配置文件中的 log_level
+}
+
+/// 当前生效的日志过滤规则
+async fn api_admin_log_level(State(state): State<Arc<AppState>>) -> Json<LogLevelResponse> {
+    Json(LogLevelResponse { level: log_filter().to_string(), configured: state.current_config().log_level })
+}
+
+/// 运行时调整日志过滤规则，无需重启（重启会丢失正在排查的状态）
+async fn api_admin_log_level_update(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<LogLevelRequest>,
+) -> Result<Json<LogLevelResponse>, AppError> {
+    let filter = LogFilter::parse(&request.level).map_err(|e| AppError::Rejected(ApiErrorCode::InvalidRequest, e))?;
+    if request.persist {
+        let mut config = state.current_config();
+        config.log_level = filter.to_string();
+        let saved = config.clone();
+        task::spawn_blocking(move || save_config(&saved)).await
+            .map_err(|e| AppError::FatalError(tr!("后台任务异常终止: {}", "Background task aborted: {}", e)))??;
+        state.set_config(config);
+    }
+    println!("{} {}", "INFO".yellow(), tr!("日志级别已调整为 {}", "Log level changed to {}", filter));
+    set_log_filter(filter);
+    Ok(api_admin_log_level(State(state)).await)
+}
+
+/// 某个 API 密钥（按名称）自进程启动以来的用量，用于按调用方核算与排查异常的集成
+async fn api_admin_key_usage(State(state): State<Arc<AppState>>, Path(key): Path<String>) -> Result<Json<KeyUsageReport>, AppError> {
+    state.key_usage_report(&state.current_config(), &key)
+        .map(Json)
+        .ok_or_else(|| AppError::Rejected(ApiErrorCode::InvalidRequest, tr!("未知的 API 密钥: {}", "Unknown API key: {}", key)))
+}
+
+/// 手动解除封禁，同时清空该主体的违规计数
+async fn api_admin_unban(State(state): State<Arc<AppState>>, Path(subject): Path<String>) -> Result<impl IntoResponse, AppError> {
+    let removed = {
+        let mut tracker = state.bans.lock().unwrap();
+        tracker.violations.remove(&subject);
+        tracker.banned.remove(&subject).is_some_and(|ban| ban.until > Instant::now())
+    };
+    if !removed {
+        return Err(AppError::Rejected(ApiErrorCode::InvalidRequest, tr!("{} 未被封禁。", "{} is not banned.", subject)));
+    }
+    println!("{} {}", "INFO".yellow(), tr!("已通过 API 解除封禁: {}", "Ban lifted via API: {}", subject));
+    Ok(Json(HealthResponse { status: "ok".to_string(), message: tr!("已解除 {} 的封禁", "Lifted the ban on {}", subject), circuit_breaker: None, checks: Vec::new() }))
+}
+
+/// 防御性：检查批次大小是否超限
+pub(crate) fn check_batch_size(config: &ServiceConfig, len: usize) -> Result<(), AppError> {
+    if len > config.batch_size_limit as usize {
+        return Err(AppError::Rejected(ApiErrorCode::BatchTooLarge, tr!("批次大小 {} 超过上限 {}", "Batch size {} exceeds limit {}", len, config.batch_size_limit)));
+    }
+    Ok(())
+}
+
+/// 防御性：拒绝不可能存在于库中的 ID（超过字段最大长度或含控制字符），避免无意义的查询。
+pub(crate) fn validate_ids<'a>(ids: impl IntoIterator<Item = &'a String>) -> Result<(), AppError> {
+    for id in ids {
+        if is_invalid_id(id) {
+            let shown: String = id.chars().take(32).collect::<String>().escape_debug().to_string();
+            return Err(AppError::Rejected(ApiErrorCode::InvalidIdFormat, tr!("ID '{}' 无效: 最长 {} 字节且不能包含控制字符", "Invalid ID '{}': must be at most {} bytes without control characters", shown, MAX_DATA_LENGTH)));
+        }
+    }
+    Ok(())
+}
+
+/// 超长或含控制字符的 ID；批量查询中逐项报告 INVALID_ID_FORMAT，其余接口整体拒绝
+fn is_invalid_id(id: &str) -> bool {
+    id.len() > MAX_DATA_LENGTH || id.chars().any(char::is_control)
+}
+
+// --- 内容协商 (JSON / CSV / MessagePack / NDJSON) ---
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ResponseFormat {
+    Json,
+    Csv,
+    MsgPack,
+    Ndjson,                      // 仅批量接口支持，单条查询按 JSON 处理
+}
+
+/// 根据 `?format=` 或 Accept 头选择响应格式，未指定时为 JSON。
+fn negotiate_format(headers: &HeaderMap, format: Option<&str>) -> Result<ResponseFormat, AppError> {
+    if let Some(format) = format {
+        return match format.to_lowercase().as_str() {
+            "json" => Ok(ResponseFormat::Json),
+            "csv" => Ok(ResponseFormat::Csv),
+            "msgpack" | "messagepack" => Ok(ResponseFormat::MsgPack),
+            "ndjson" => Ok(ResponseFormat::Ndjson),
+            other => Err(AppError::Rejected(ApiErrorCode::UnsupportedFormat, tr!("不支持的格式 '{}' (可选 json/csv/msgpack/ndjson)", "Unsupported format '{}' (expected json/csv/msgpack/ndjson)", other))),
+        };
+    }
+    let accept = headers.get(ACCEPT).and_then(|v| v.to_str().ok()).unwrap_or("");
+    Ok(if accept.contains(NDJSON_CONTENT_TYPE) {
+        ResponseFormat::Ndjson
+    } else if accept.contains("text/csv") {
+        ResponseFormat::Csv
+    } else if accept.contains("msgpack") {
+        ResponseFormat::MsgPack
+    } else {
+        ResponseFormat::Json
+    })
+}
+
+/// 将查询结果编码为 CSV，列为 status / uid / phone_number / matched_by / query / error（后三列仅批量查询有值）。
+fn results_to_csv(results: &[LookupResponse], fields: Option<&[&'static str]>) -> Result<Vec<u8>, AppError> {
+    let write_err = |e: csv::Error| AppError::FatalError(tr!("写入 CSV 失败: {}", "Failed to write CSV: {}", e));
+    let columns = fields.unwrap_or(LOOKUP_FIELDS);
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(columns).map_err(write_err)?;
+    for r in results {
+        writer.write_record(columns.iter().map(|field| field_value(r, field).unwrap_or("")))
+            .map_err(write_err)?;
+    }
+    writer.into_inner().map_err(|e| AppError::FatalError(tr!("写入 CSV 失败: {}", "Failed to write CSV: {}", e)))
+}
+
+/// 按协商结果序列化：JSON/MessagePack 保持与原 JSON 相同的结构（单条为对象，批量为 `{"results": [...]}`）。
+/// 批量结果中有失败的分块时状态码改为 207，响应体附带 summary。`fields` 为 Some 时只输出所列字段（CSV 只输出所列列）。
+fn render_results(format: ResponseFormat, status: StatusCode, results: Vec<LookupResponse>, single: bool, fields: Option<&[&'static str]>) -> Result<Response, AppError> {
+    let status = if !single && results.iter().any(|r| r.error.is_some()) { StatusCode::MULTI_STATUS } else { status };
+    let msgpack_err = |e: rmp_serde::encode::Error| AppError::FatalError(format!("Failed to encode MessagePack: {}", e));
+    let response = match format {
+        ResponseFormat::Csv => {
+            (status, [(CONTENT_TYPE, CSV_CONTENT_TYPE)], results_to_csv(&results, fields)?).into_response()
+        }
+        ResponseFormat::MsgPack => {
+            let body = if single {
+                rmp_serde::to_vec_named(&SelectedFields { result: &results[0], fields }).map_err(msgpack_err)?
+            } else {
+                rmp_serde::to_vec_named(&BatchResponse::new(&results, fields)).map_err(msgpack_err)?
+            };
+            (status, [(CONTENT_TYPE, MSGPACK_CONTENT_TYPE)], body).into_response()
+        }
+        ResponseFormat::Json | ResponseFormat::Ndjson => {
+            if single {
+                let resp = results.into_iter().next().unwrap_or_else(LookupResponse::not_found);
+                (status, Json(SelectedFields { result: &resp, fields })).into_response()
+            } else {
+                (status, Json(BatchResponse::new(&results, fields))).into_response()
+            }
+        }
+    };
+    Ok(response)
+}
+
+/// 解析一组 ID，结果与输入一一对应：规范化去重后依次经过布隆过滤器、缓存，剩余的交给数据库。
+pub(crate) async fn resolve_batch(state: &Arc<AppState>, config: &ServiceConfig, ids: &[String]) -> Result<Vec<LookupResponse>, AppError> {
+    let started = Instant::now();
+    // 规范化并去重：每个唯一 ID 只处理一次，最后按原始顺序展开
+    let mut unique: Vec<String> = Vec::new();
+    let mut index_of: HashMap<String, usize> = HashMap::new();
+    let mut positions: Vec<usize> = Vec::with_capacity(ids.len());
+    for raw in ids {
+        let id = apply_normalize_hook(state, canonicalize_id(raw))?;
+        let index = match index_of.get(&id) {
+            Some(&i) => i,
+            None => {
+                index_of.insert(id.clone(), unique.len());
+                unique.push(id);
+                unique.len() - 1
+            }
+        };
+        positions.push(index);
+    }
+    log_debug("cyber_lookup::batch", &format!("Batch Request received: {} items, {} unique", ids.len(), unique.len()));
+
+    // 无效 ID 逐项标记为 INVALID_ID_FORMAT；先用布隆过滤器排除一定不存在的 ID，再查缓存，只把剩余的 ID 交给数据库
+    let mut results: Vec<Option<LookupResponse>> = unique.iter()
+        .map(|id| {
+            if is_invalid_id(id) {
+                return Some(LookupResponse::failed(ApiErrorCode::InvalidIdFormat));
+            }
+            if !state.bloom_may_contain(id) {
+                return Some(LookupResponse::not_found());
+            }
+            state.cache_get(id).map(LookupResponse::from_cached_batch)
+        })
+        .collect();
+    let misses: Vec<String> = unique.iter().zip(&results)
+        .filter(|(_, cached)| cached.is_none())
+        .map(|(id, _)| id.clone())
+        .collect();
+    log_debug("cyber_lookup::batch", &format!("Batch cache/bloom: {} answered, {} to database", unique.len() - misses.len(), misses.len()));
+
+    let fetched = if misses.is_empty() {
+        Vec::new()
+    } else {
+        let generation = state.cache_generation();
+        let fetched = state.store()?.batch_lookup(misses.clone()).await?;
+        for (id, resp) in misses.iter().zip(&fetched).filter(|(_, resp)| resp.error.is_none()) {
+            state.cache_put(id, resp, generation);
+        }
+        fetched
+    };
+
+    let mut fetched = fetched.into_iter();
+    let mut resolved: Vec<LookupResponse> = results.iter_mut()
+        .map(|slot| slot.take().or_else(|| fetched.next()).unwrap_or_else(LookupResponse::not_found))
+        .collect();
+    // 未命中的号码按拨号规则换算后再查一次（如 013800000000 -> +8613800000000）
+    let variants: Vec<(usize, String)> = (0..resolved.len())
+        .filter(|&i| resolved[i].status == "not_found")
+        .filter_map(|i| config.phone_rules.lookup_variant(&unique[i]).map(|variant| (i, variant)))
+        .filter(|(_, variant)| state.bloom_may_contain(variant))
+        .collect();
+    if !variants.is_empty() {
+        let fetched = state.store()?.batch_lookup(variants.iter().map(|(_, variant)| variant.clone()).collect()).await?;
+        for ((i, _), resp) in variants.into_iter().zip(fetched) {
+            resolved[i] = resp;
+        }
+    }
+    if config.upstream.enabled {
+        let missing: Vec<usize> = (0..resolved.len()).filter(|&i| resolved[i].status == "not_found").collect();
+        let missing_ids: Vec<String> = missing.iter().map(|&i| unique[i].clone()).collect();
+        for (i, resp) in missing.into_iter().zip(resolve_upstream(state, &missing_ids).await) {
+            resolved[i] = resp;
+        }
+    }
+    apply_deny_list(state, &unique, &mut resolved).await?;
+    // 按输入顺序展开，并标明原始输入与命中的列（uid 与规范化后的输入相同即为按 uid 命中）
+    let results: Vec<LookupResponse> = ids.iter().zip(&positions)
+        .map(|(raw, &i)| {
+            let found = resolved[i].uid.is_some();
+            LookupResponse {
+                matched_by: found.then(|| if resolved[i].uid.as_deref() == Some(unique[i].as_str()) { MatchField::Uid } else { MatchField::Phone }),
+                query: Some(raw.clone()),
+                ..resolved[i].clone()
+            }
+        })
+        .collect();
+    state.record_query(None, &results, started);
+    Ok(results)
+}
+
+/// 以 NDJSON 流式返回批量结果：按窗口逐段解析，每段完成后立即逐行写出，不缓冲整个响应。
+/// 中途出错时写出一行 `{"status":"error",...}` 并结束流（此时状态码已发送，无法再改变）。
+fn stream_batch_ndjson(state: Arc<AppState>, config: ServiceConfig, ids: Vec<String>, fields: Option<Vec<&'static str>>) -> Response {
+    let (tx, rx) = mpsc::channel::<Result<String, io::Error>>(NDJSON_CHANNEL_CAPACITY);
+    let window = (config.batch_chunk_size as usize * config.batch_parallelism as usize).max(1);
+    // 调用方在请求任务中确定，task-local 不会传递给写出任务
+    let mask = should_mask(&config);
+    let context = RequestContext::capture();
+
+    spawn_with_deadline(context.scope(async move {
+        for window_ids in ids.chunks(window) {
+            match resolve_batch(&state, &config, window_ids).await.and_then(|mut results| {
+                apply_post_process_hook(&state, &mut results)?;
+                Ok(results)
+            }) {
+                Ok(mut results) => {
+                    redact_phones(&mut results, mask);
+                    for r in &results {
+                        let line = serde_json::to_string(&SelectedFields { result: r, fields: fields.as_deref() }).unwrap_or_default() + "\n";
+                        // 发送失败说明客户端已断开，停止后续查询
+                        if tx.send(Ok(line)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(e) => {
+                    let line = serde_json::json!({ "status": "error", "code": e.code(), "message": format!("{:?}", e) }).to_string() + "\n";
+                    let _ = tx.send(Ok(line)).await;
+                    return;
+                }
+            }
+        }
+    }));
+
+    ([(CONTENT_TYPE, NDJSON_CONTENT_TYPE)], Body::from_stream(ReceiverStream::new(rx))).into_response()
+}
+
+async fn api_batch_lookup(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<BatchLookupQuery>,
+    headers: HeaderMap,
+    BatchIds(ids, body_fields): BatchIds, 
+) -> Result<Response, AppError> {
+    let config = state.current_config();
+    check_batch_size(&config, ids.len())?;
+    let fields = match query.fields {
+        Some(fields) => parse_fields(&[fields])?,
+        None => parse_fields(&body_fields.unwrap_or_default())?,
+    };
+
+    let format = negotiate_format(&headers, query.format.as_deref())?;
+    if format == ResponseFormat::Ndjson {
+        return Ok(stream_batch_ndjson(state, config, ids, fields));
+    }
+    let mut data = resolve_batch(&state, &config, &ids).await?;
+    mirror_lookup(&state, &config, &ids, &data);
+    apply_post_process_hook(&state, &mut data)?;
+    mask_results(&config, &mut data);
+    render_results(format, StatusCode::OK, data, false, fields.as_deref())
+}
+
+// --- /v2 按主体返回的查询结果 ---
+// v1 每条结果是固定的 uid / phone_number 两个可选字段。v2 以 uid 为主体 (subject)，按标识符类型列出它关联的标识符，
+// 批量查询按主体分组，命中同一主体的多个输入只返回一次。目前的存储模型中 uid 与手机号一一对应，每类各一个。
+
+/// 标识符的类型，/v2 响应中 identifiers 的键
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum IdentifierKind {
+    Uid,
+    Phone,
+}
+
+/// 一个主体及其关联的标识符
+#[derive(Serialize)]
+struct SubjectResult {
+    subject: String,
+    identifiers: BTreeMap<IdentifierKind, Vec<String>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    matched: Vec<String>,            // 批量查询中解析到该主体的输入 ID（按请求顺序）
+}
+
+/// GET /v2/lookup/:id 的响应
+#[derive(Serialize)]
+struct SubjectLookupResponse {
+    status: &'static str,            // found / not_found
+    #[serde(flatten)]
+    subject: Option<SubjectResult>,
+}
+
+/// 批量查询中失败的 ID
+#[derive(Serialize)]
+struct FailedId {
+    id: String,
+    error: String,
+}
+
+/// POST /v2/batch_lookup 的响应
+#[derive(Serialize)]
+struct SubjectBatchResponse {
+    subjects: Vec<SubjectResult>,    // 按各主体首次被命中的请求顺序
+    not_found: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    failed: Vec<FailedId>,
+}
+
+/// 把查询结果按主体分组并脱敏。`results` 与 `ids` 一一对应，须已经过后处理钩子、尚未脱敏。
+fn group_by_subject(config: &ServiceConfig, ids: &[String], results: Vec<LookupResponse>) -> SubjectBatchResponse {
+    let mut response = SubjectBatchResponse { subjects: Vec::new(), not_found: Vec::new(), failed: Vec::new() };
+    let mut index_of: HashMap<String, usize> = HashMap::new();
+    for (id, result) in ids.iter().zip(results) {
+        if let Some(error) = result.error {
+            response.failed.push(FailedId { id: id.clone(), error });
+            continue;
+        }
+        let Some(uid) = result.uid else {
+            response.not_found.push(id.clone());
+            continue;
+        };
+        let index = *index_of.entry(uid.clone()).or_insert_with(|| {
+            let phones = result.phone_number.into_iter().collect();
+            response.subjects.push(SubjectResult {
+                subject: uid.clone(),
+                identifiers: BTreeMap::from([(IdentifierKind::Uid, vec![uid]), (IdentifierKind::Phone, phones)]),
+                matched: Vec::new(),
+            });
+            response.subjects.len() - 1
+        });
+        response.subjects[index].matched.push(id.clone());
+    }
+
+    let mask = should_mask(config);
+    for phones in response.subjects.iter_mut().filter_map(|subject| subject.identifiers.get_mut(&IdentifierKind::Phone)) {
+        // 哈希模式下库中只有号码的哈希，不返回
+        phones.retain(|phone| !is_hashed_phone(phone));
+        if mask {
+            phones.iter_mut().for_each(|phone| *phone = mask_phone(phone));
+        }
+    }
+    response
+}
+
+/// 单个 ID 的 v2 查询：返回命中记录的主体及其标识符。未找到时的状态码同 v1 (missing_status / ?missing=)。
+async fn api_v2_lookup(
+    Path(id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<LookupQuery>,
+) -> Result<Response, AppError> {
+    let config = state.current_config();
+    let missing_code = missing_status_code(query.missing.unwrap_or(config.missing_status))
+        .ok_or_else(|| AppError::Rejected(ApiErrorCode::InvalidRequest, tr!("missing 只能为 404 或 200。", "missing must be 404 or 200.").to_string()))?;
+    validate_ids([&id])?;
+    let started = Instant::now();
+    let mut resp = lookup(&state, &id).await?;
+    state.record_query(Some(&id), std::slice::from_ref(&resp), started);
+    apply_post_process_hook(&state, std::slice::from_mut(&mut resp))?;
+    let grouped = group_by_subject(&config, std::slice::from_ref(&id), vec![resp]);
+    let subject = grouped.subjects.into_iter().next();
+    let (code, status) = if subject.is_some() { (StatusCode::OK, "found") } else { (missing_code, "not_found") };
+    Ok((code, Json(SubjectLookupResponse { status, subject })).into_response())
+}
+
+/// 批量 v2 查询：请求体同 v1 的 /batch_lookup（fields 被忽略）。部分 ID 查询失败时返回 207，失败的 ID 列在 failed 中。
+async fn api_v2_batch_lookup(
+    State(state): State<Arc<AppState>>,
+    BatchIds(ids, _): BatchIds,
+) -> Result<Response, AppError> {
+    let config = state.current_config();
+    check_batch_size(&config, ids.len())?;
+    let mut data = resolve_batch(&state, &config, &ids).await?;
+    mirror_lookup(&state, &config, &ids, &data);
+    apply_post_process_hook(&state, &mut data)?;
+    let grouped = group_by_subject(&config, &ids, data);
+    let code = if grouped.failed.is_empty() { StatusCode::OK } else { StatusCode::MULTI_STATUS };
+    Ok((code, Json(grouped)).into_response())
+}
+
+/// 批量写入映射：`?on_conflict=skip|replace|error`（默认 replace）决定 uid 或手机号已属于其他记录时的处理方式。
+/// 全部写入在一个事务中完成；策略为 error 时遇到冲突整体回滚并返回 409。
+/// `?mode=insert` 为严格插入（忽略 on_conflict）：任一 uid 或手机号已存在时不写入，409 响应列出这些值。
+/// `?dry_run=true` 时事务总是回滚，冲突只计数，响应给出将发生的变化。
+async fn api_write_mappings(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<WriteQuery>,
+    Json(payload): Json<MappingsRequest>,
+) -> Result<Json<WriteResponse>, AppError> {
+    let config = state.current_config();
+    check_batch_size(&config, payload.mappings.len())?;
+    let mut mappings: Vec<(String, String)> = payload.mappings.into_iter().map(|m| (m.uid, m.phone_number)).collect();
+    validate_ids(mappings.iter().flat_map(|(uid, phone)| [uid, phone]))?;
+    if mappings.iter().any(|(uid, phone)| uid.is_empty() || phone.is_empty()) {
+        return Err(AppError::Rejected(ApiErrorCode::InvalidRequest, tr!("uid 与 phone_number 不能为空。", "uid and phone_number must not be empty.").to_string()));
+    }
+    for (_, phone) in mappings.iter_mut() {
+        *phone = config.phone_rules.normalize_for_write(phone).map_err(|e| AppError::Rejected(ApiErrorCode::InvalidRequest, e))?;
+    }
+    check_insert_hook(&state, &mappings)?;
+
+    let (mut strategy, dry_run) = (query.on_conflict, query.dry_run);
+    let ids: Vec<String> = mappings.iter().flat_map(|(uid, phone)| [uid.clone(), phone.clone()]).collect();
+    let store = state.store()?;
+    if query.mode == WriteMode::Insert {
+        let unique: Vec<String> = ids.iter().cloned().collect::<HashSet<_>>().into_iter().collect();
+        let existing: Vec<String> = unique.iter().zip(store.batch_lookup(unique.clone()).await?)
+            .filter(|(_, resp)| resp.status != "not_found")
+            .map(|(id, _)| id.clone())
+            .collect();
+        if !existing.is_empty() {
+            return Err(insert_conflict_error(existing));
+        }
+        // 检查之后被并发写入的记录仍按冲突回滚
+        strategy = ConflictStrategy::Error;
+    }
+    let report = store.insert(mappings, strategy, dry_run).await?;
+    if !dry_run {
+        state.invalidate_cache(report.affected_keys);
+        let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+        state.bloom_insert(&ids);
+    }
+    Ok(Json(WriteResponse { summary: report.summary, dry_run }))
+}
+
+// --- 单条映射的条件修改 (ETag / If-Match) ---
+
+/// 映射的版本标识（强 ETag）：uid 与存储中手机号的 SHA-256 前 16 位十六进制，记录内容变化时随之变化。
+fn mapping_etag(uid: &str, phone: &str) -> String {
+    use sha2::Digest;
+    let digest = sha2::Sha256::digest(format!("{}\n{}", uid, phone).as_bytes());
+    format!("\"{}\"", digest.iter().take(8).map(|b| format!("{:02x}", b)).collect::<String>())
+}
+
+/// 读取 If-Match（必填）
+fn required_if_match(headers: &HeaderMap) -> Result<String, AppError> {
+    headers.get(IF_MATCH).and_then(|v| v.to_str().ok()).map(String::from)
+        .ok_or_else(|| AppError::PreconditionRequired(tr!("修改映射须携带 If-Match 头（GET /lookup/:id 返回的 ETag）。", "Modifying a mapping requires an If-Match header (the ETag returned by GET /lookup/:id).").to_string()))
+}
+
+/// If-Match 是否满足：`*` 要求记录存在，否则须有一项与当前版本强匹配（弱标识 W/ 永不匹配）
+fn if_match_satisfied(if_match: &str, current: Option<&str>) -> bool {
+    let Some(current) = current else {
+        return false;
+    };
+    if_match.trim() == "*" || if_match.split(',').map(str::trim).any(|tag| tag == current)
+}
+
+/// If-None-Match 是否命中当前版本（弱比较：忽略 W/ 前缀），`*` 匹配任意存在的记录
+fn if_none_match_satisfied(if_none_match: &str, current: &str) -> bool {
+    if_none_match.trim() == "*" || if_none_match.split(',').map(|tag| tag.trim().trim_start_matches("W/")).any(|tag| tag == current)
+}
+
+/// uid 当前对应的记录（直接访问存储，不经过缓存）
+async fn current_mapping(store: &Arc<dyn MappingStore>, uid: &str) -> Result<Option<(String, String)>, AppError> {
+    let resp = store.lookup(uid).await?;
+    Ok(match (resp.status.as_str(), resp.uid, resp.phone_number) {
+        ("found_by_uid", Some(uid), Some(phone)) => Some((uid, phone)),
+        _ => None,
+    })
+}
+
+fn check_if_match(if_match: &str, current: Option<&(String, String)>, uid: &str) -> Result<(), AppError> {
+    let etag = current.map(|(uid, phone)| mapping_etag(uid, phone));
+    if if_match_satisfied(if_match, etag.as_deref()) {
+        return Ok(());
+    }
+    Err(AppError::PreconditionFailed(match etag {
+        Some(etag) => tr!("{} 已被修改（当前版本 {}），请重新读取后再提交。", "{} has been modified (current version {}); re-read it before retrying.", uid, etag),
+        None => tr!("{} 不存在或已被删除。", "{} does not exist or has been deleted.", uid),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateMappingRequest {
+    phone_number: String,
+}
+
+/// 修改 uid 对应的手机号：If-Match 须为记录当前的 ETag，否则返回 412（缺少时 428）。
+/// 新号码已属于另一条记录时返回 409，不会像 /mappings 的 replace 策略那样删除对方。成功时响应头带新的 ETag。
+async fn api_update_mapping(
+    State(state): State<Arc<AppState>>,
+    Path(uid): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateMappingRequest>,
+) -> Result<Response, AppError> {
+    let phone = payload.phone_number;
+    validate_ids([&uid, &phone])?;
+    if phone.is_empty() {
+        return Err(AppError::Rejected(ApiErrorCode::InvalidRequest, tr!("uid 与 phone_number 不能为空。", "uid and phone_number must not be empty.").to_string()));
+    }
+    let phone = state.current_config().phone_rules.normalize_for_write(&phone).map_err(|e| AppError::Rejected(ApiErrorCode::InvalidRequest, e))?;
+    check_insert_hook(&state, &[(uid.clone(), phone.clone())])?;
+    let if_match = required_if_match(&headers)?;
+    let store = state.store()?;
+    let _serialized = state.conditional_writes.lock().await;
+    let current = current_mapping(&store, &uid).await?;
+    check_if_match(&if_match, current.as_ref(), &uid)?;
+
+    let owner = store.lookup(&phone).await?;
+    if owner.status == "found_by_phone" && owner.uid.as_deref() != Some(uid.as_str()) {
+        return Err(AppError::Conflict(tr!("手机号已属于另一条记录 ({})。", "The phone number already belongs to another record ({}).", owner.uid.unwrap_or_default())));
+    }
+    let report = store.insert(vec![(uid.clone(), phone.clone())], ConflictStrategy::Replace, false).await?;
+    state.invalidate_cache(report.affected_keys);
+    state.bloom_insert(&[uid.as_str(), phone.as_str()]);
+
+    let etag = current_mapping(&store, &uid).await?.map(|(uid, phone)| mapping_etag(&uid, &phone));
+    let mut response = Json(WriteResponse { summary: report.summary, dry_run: false }).into_response();
+    if let Some(etag) = etag.and_then(|etag| HeaderValue::from_str(&etag).ok()) {
+        response.headers_mut().insert(ETAG, etag);
+    }
+    Ok(response)
+}
+
+/// 删除 uid 对应的记录：If-Match 须为记录当前的 ETag，否则返回 412（缺少时 428）。
+async fn api_delete_mapping(
+    State(state): State<Arc<AppState>>,
+    Path(uid): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<BatchDeleteResponse>, AppError> {
+    validate_ids([&uid])?;
+    let if_match = required_if_match(&headers)?;
+    let store = state.store()?;
+    let _serialized = state.conditional_writes.lock().await;
+    let current = current_mapping(&store, &uid).await?;
+    check_if_match(&if_match, current.as_ref(), &uid)?;
+
+    let report = store.delete(vec![uid.clone()], false).await?;
+    state.invalidate_cache(report.affected_keys);
+    println!("{} {}", "INFO".yellow(), tr!("已删除映射 {} ({} 条记录)", "Deleted mapping {} ({} record(s))", uid, report.deleted));
+    Ok(Json(BatchDeleteResponse { deleted: report.deleted, dry_run: false }))
+}
+
+/// 批量删除：请求体格式与 `/batch_lookup` 相同，所有删除在一个事务中完成。`?dry_run=true` 只返回将被删除的条数。
+async fn api_batch_delete(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<BatchDeleteQuery>,
+    BatchDeleteTarget(target): BatchDeleteTarget,
+) -> Result<Response, AppError> {
+    let config = state.current_config();
+    let store = state.store()?;
+    let (ids, selector) = match (target.uid_prefix, target.ids.is_empty()) {
+        (Some(prefix), true) if !prefix.is_empty() => {
+            validate_ids([&prefix])?;
+            let matched = store.count(Some(prefix.clone())).await?;
+            check_batch_size(&config, matched as usize)?;
+            let uids = Arc::new(Mutex::new(Vec::new()));
+            let sink_uids = uids.clone();
+            store.export(Some(prefix.clone()), Box::new(move |uid, _| {
+                sink_uids.lock().unwrap().push(uid.to_string());
+                Ok(())
+            })).await?;
+            let ids = std::mem::take(&mut *uids.lock().unwrap());
+            (ids, format!("uid_prefix:{}", prefix))
+        }
+        (None, false) => {
+            check_batch_size(&config, target.ids.len())?;
+            validate_ids(&target.ids)?;
+            let selector = format!("ids:{}", target.ids.join("\n"));
+            (target.ids, selector)
+        }
+        _ => return Err(AppError::Rejected(ApiErrorCode::InvalidRequest, tr!("请求体须且只能提供 ids 或非空的 uid_prefix 之一。", "The body must provide exactly one of ids or a non-empty uid_prefix.").to_string())),
+    };
+
+    // 预览与执行都先在回滚的事务中取得将被删除的记录，令牌与这些记录绑定
+    let preview = store.delete(ids.clone(), true).await?;
+    let fingerprint = delete_fingerprint(&selector, &preview.records);
+    if query.dry_run {
+        let expires_at = chrono::Utc::now() + chrono::Duration::seconds(DELETE_CONFIRM_TTL_SECS);
+        let confirmation_token = state.delete_confirmation_token(&fingerprint, expires_at.timestamp());
+        let mut records: Vec<LookupResponse> = preview.records.into_iter()
+            .map(|(uid, phone)| LookupResponse { status: "found".to_string(), uid: Some(uid), phone_number: Some(phone), matched_by: None, query: None, error: None })
+            .collect();
+        mask_results(&config, &mut records);
+        return Ok(Json(BatchDeletePreview {
+            deleted: preview.deleted,
+            dry_run: true,
+            records,
+            confirmation_token,
+            expires_at: expires_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        }).into_response());
+    }
+
+    let token = query.confirm.ok_or_else(|| AppError::PreconditionRequired(tr!(
+        "批量删除需要确认：先以 ?dry_run=true 预览，再以 ?confirm=<confirmation_token> 提交同一请求。",
+        "Batch delete requires confirmation: preview with ?dry_run=true, then resend the same request with ?confirm=<confirmation_token>.").to_string()))?;
+    state.check_delete_confirmation(&token, &fingerprint)?;
+
+    let id_count = ids.len();
+    let report = store.delete(ids, false).await?;
+    state.invalidate_cache(report.affected_keys);
+    println!("{} {}", "INFO".yellow(), tr!("批量删除: {} 个 ID，删除 {} 条记录", "Batch delete: {} ID(s), {} record(s) deleted", id_count, report.deleted));
+    Ok(Json(BatchDeleteResponse { deleted: report.deleted, dry_run: false }).into_response())
+}
+
+/// 批量删除的请求选择条件与将被删除记录（排序后）的摘要输入
+fn delete_fingerprint(selector: &str, records: &[(String, String)]) -> String {
+    let mut rows: Vec<String> = records.iter().map(|(uid, phone)| format!("{}\t{}", uid, phone)).collect();
+    rows.sort();
+    format!("{}\n--\n{}", selector, rows.join("\n"))
+}
+
+/// GDPR 式擦除：删除与 `id`（uid 或手机号）关联的映射并清理各级缓存，在审计日志中写入签名的擦除记录，
+/// 并将该记录作为回执返回。未配置审计签名密钥时拒绝执行，避免留下无法证明的删除。
+async fn api_erase(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Result<Json<AuditRecord>, AppError> {
+    let config = state.current_config();
+    validate_ids([&id])?;
+    let key = config.audit.key()
+        .ok_or_else(|| AppError::ConfigError(tr!("未配置 audit.signing_key，无法签发擦除回执。", "audit.signing_key is not configured; cannot issue an erasure receipt.").to_string()))?;
+
+    let report = state.store()?.delete(vec![id.clone()], false).await?;
+    state.invalidate_cache(report.affected_keys);
+    let record = AuditRecord::signed("erasure", &key, &id, report.deleted);
+    let path = config.audit.path.clone();
+    let written = record.clone();
+    task::spawn_blocking(move || append_audit(&path, &written)).await
+        .map_err(|e| AppError::FatalError(e.to_string()))?
+        .inspect_err(|e| eprintln!("{} {}", "ERR".red(), tr!("擦除已执行但审计记录写入失败 (回执 {}): {}", "Erasure done but writing the audit record failed (receipt {}): {}", record.receipt_id, e)))?;
+    println!("{} {}", "INFO".yellow(), tr!("擦除: 删除 {} 条记录，回执 {}", "Erasure: {} record(s) deleted, receipt {}", report.deleted, record.receipt_id));
+    Ok(Json(record))
+}
+
+/// 读取 CSV 上传内容：支持 `multipart/form-data`（取第一个文件字段）或直接以请求体上传。
+async fn read_csv_upload(req: Request) -> Result<Bytes, AppError> {
+    let is_multipart = req.headers().get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.starts_with("multipart/form-data"))
+        .unwrap_or(false);
+    if !is_multipart {
+        return Bytes::from_request(req, &()).await
+            .map_err(|e| rejection_error(e.status(), tr!("读取请求体失败: {}", "Failed to read request body: {}", e.body_text())));
+    }
+
+    let mut multipart = Multipart::from_request(req, &()).await
+        .map_err(|e| rejection_error(e.status(), tr!("multipart 请求无效: {}", "Invalid multipart request: {}", e.body_text())))?;
+    while let Some(field) = multipart.next_field().await
+        .map_err(|e| rejection_error(e.status(), tr!("multipart 字段无效: {}", "Invalid multipart field: {}", e.body_text())))? {
+        if field.file_name().is_some() || field.name() == Some("file") {
+            return field.bytes().await
+                .map_err(|e| rejection_error(e.status(), tr!("读取上传文件失败: {}", "Failed to read uploaded file: {}", e.body_text())));
+        }
+    }
+    Err(AppError::Rejected(ApiErrorCode::InvalidCsv, tr!("multipart 请求中没有文件字段", "No file field found in multipart request").to_string()))
+}
+
+/// 在 CSV 的表头/首行中定位 ID 列：纯数字视为列序号（不能超出表头宽度），否则按列名匹配。
+fn resolve_csv_column(column: Option<&str>, headers: Option<&csv::StringRecord>) -> Result<usize, AppError> {
+    let column = match column {
+        None => return Ok(0),
+        Some(c) => c.trim(),
+    };
+    if let Ok(index) = column.parse::<usize>() {
+        if let Some(headers) = headers.filter(|h| index >= h.len()) {
+            return Err(AppError::Rejected(ApiErrorCode::InvalidCsv, tr!("列序号 {} 超出表头的 {} 列", "Column index {} is past the {} header column(s)", index, headers.len())));
+        }
+        return Ok(index);
+    }
+    headers
+        .and_then(|h| h.iter().position(|name| name.trim() == column))
+        .ok_or_else(|| AppError::Rejected(ApiErrorCode::InvalidCsv, tr!("CSV 表头中没有列 '{}'", "Column '{}' not found in CSV header", column)))
+}
+
+/// 上传 CSV 批量查询：原样保留每一行，并在末尾追加 status / uid / phone_number 三列。
+async fn api_batch_lookup_file(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<CsvLookupParams>,
+    req: Request,
+) -> Result<Response, AppError> {
+    let config = state.current_config();
+    let data = read_csv_upload(req).await?;
+
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(params.has_header)
+        .flexible(true)
+        .from_reader(data.as_ref());
+    let headers = if params.has_header {
+        Some(reader.headers().map_err(|e| AppError::Rejected(ApiErrorCode::InvalidCsv, tr!("CSV 表头无效: {}", "Invalid CSV header: {}", e)))?.clone())
+    } else {
+        None
+    };
+    let records: Vec<csv::StringRecord> = reader.records().collect::<Result<_, _>>()
+        .map_err(|e| AppError::Rejected(ApiErrorCode::InvalidCsv, tr!("CSV 无效: {}", "Invalid CSV: {}", e)))?;
+    check_batch_size(&config, records.len())?;
+
+    let column = resolve_csv_column(params.column.as_deref(), headers.as_ref())?;
+    let ids: Vec<String> = records.iter().enumerate()
+        .map(|(i, r)| r.get(column).map(String::from).ok_or_else(|| AppError::Rejected(ApiErrorCode::InvalidCsv,
+            tr!("第 {} 行只有 {} 列，没有第 {} 列", "Row {} has only {} column(s), no column {}", i + 1, r.len(), column))))
+        .collect::<Result<_, _>>()?;
+    let mut results = resolve_batch(&state, &config, &ids).await?;
+    apply_post_process_hook(&state, &mut results)?;
+    mask_results(&config, &mut results);
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let write_err = |e: csv::Error| AppError::FatalError(tr!("写入 CSV 失败: {}", "Failed to write CSV: {}", e));
+    if let Some(headers) = &headers {
+        let mut out = headers.clone();
+        out.extend(["status", "uid", "phone_number"]);
+        writer.write_record(&out).map_err(write_err)?;
+    }
+    for (record, result) in records.iter().zip(&results) {
+        let mut out = record.clone();
+        out.push_field(&result.status);
+        out.push_field(result.uid.as_deref().unwrap_or(""));
+        out.push_field(result.phone_number.as_deref().unwrap_or(""));
+        writer.write_record(&out).map_err(write_err)?;
+    }
+    let body = writer.into_inner().map_err(|e| AppError::FatalError(tr!("写入 CSV 失败: {}", "Failed to write CSV: {}", e)))?;
+
+    Ok((
+        [(CONTENT_TYPE, CSV_CONTENT_TYPE), (CONTENT_DISPOSITION, "attachment; filename=\"lookup_results.csv\"")],
+        body,
+    ).into_response())
+}
+
+/// GET 版批量查询，便于浏览器和 curl 调试；上限取 batch_get_limit 与 batch_size_limit 中较小者。
+async fn api_batch_lookup_get(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<BatchQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let config = state.current_config();
+    let format = negotiate_format(&headers, query.format.as_deref())?;
+    let ids: Vec<String> = query.ids.split(',').map(str::trim).filter(|id| !id.is_empty()).map(String::from).collect();
+    let limit = config.batch_get_limit.min(config.batch_size_limit) as usize;
+    if ids.len() > limit {
+        return Err(AppError::Rejected(ApiErrorCode::BatchTooLarge, tr!("GET 批次大小 {} 超过上限 {}；更大的批次请使用 POST /batch_lookup", "GET batch size {} exceeds limit {}; use POST /batch_lookup for larger batches", ids.len(), limit)));
+    }
+    let fields = parse_fields(query.fields.as_slice())?;
+    if format == ResponseFormat::Ndjson {
+        return Ok(stream_batch_ndjson(state, config, ids, fields));
+    }
+    let mut data = resolve_batch(&state, &config, &ids).await?;
+    mirror_lookup(&state, &config, &ids, &data);
+    apply_post_process_hook(&state, &mut data)?;
+    mask_results(&config, &mut data);
+    render_results(format, StatusCode::OK, data, false, fields.as_deref())
+}
+
+async fn api_batch_lookup_stream(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<BatchLookupQuery>,
+    BatchIds(ids, body_fields): BatchIds, 
+) -> Result<Response, AppError> {
+    let config = state.current_config();
+    check_batch_size(&config, ids.len())?;
+    let fields = match query.fields {
+        Some(fields) => parse_fields(&[fields])?,
+        None => parse_fields(&body_fields.unwrap_or_default())?,
+    };
+    Ok(stream_batch_ndjson(state, config, ids, fields))
+}
+
+// --- 尝试启动服务器 / 主循环 / 主入口点 (保持与上个版本一致的逻辑流程) ---
+/// 构造完整的 HTTP 路由（含请求 ID、超时、限流与 CORS 等中间件），可直接 `axum::serve` 或挂载到宿主应用中。
+/// 中间件参数取自调用时的配置。
+/// 查询与写入接口（含 /info 与 /stats），主库与各租户共用；鉴权中间件按主状态的配置校验
+fn data_routes(state: &Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/info", get(api_info))
+        .route("/stats", get(api_stats))
+        .route("/stats/history", get(api_stats_history))
+        .route("/signing_key", get(api_signing_key))
+        .merge(Router::new()
+            .route("/lookup/:id", get(api_lookup))
+            .route("/graphql", post(api_graphql))
+            .merge(Router::new()
+                .route("/batch_lookup", post(api_batch_lookup).get(api_batch_lookup_get))
+                .route("/batch_lookup/stream", post(api_batch_lookup_stream))
+                .route_layer(middleware::from_fn_with_state(state.clone(), verify_signature)))
+            .route_layer(middleware::from_fn_with_state(state.clone(), sign_response))
+            .route_layer(middleware::from_fn_with_state(ApiScope::Read, require_scope)))
+        .merge(Router::new()
+            .route("/batch_lookup/file", post(api_batch_lookup_file))
+            .route_layer(middleware::from_fn_with_state(state.clone(), verify_signature))
+            .route_layer(middleware::from_fn_with_state(ApiScope::Export, require_scope)))
+        .merge(Router::new()
+            .route("/batch_delete", post(api_batch_delete))
+            .route("/mappings", post(api_write_mappings))
+            .route("/mappings/:uid", put(api_update_mapping).delete(api_delete_mapping))
+            .route_layer(middleware::from_fn_with_state(state.clone(), apply_idempotency))
+            .route_layer(middleware::from_fn_with_state(state.clone(), verify_signature))
+            .route_layer(middleware::from_fn_with_state(ApiScope::Write, require_scope)))
+}
+
+/// /v2 查询接口（主库与各租户共用），权限与签名要求同 v1 的查询接口
+fn v2_routes(state: &Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/lookup/:id", get(api_v2_lookup))
+        .merge(Router::new()
+            .route("/batch_lookup", post(api_v2_batch_lookup))
+            .route_layer(middleware::from_fn_with_state(state.clone(), verify_signature)))
+        .route_layer(middleware::from_fn_with_state(state.clone(), sign_response))
+        .route_layer(middleware::from_fn_with_state(ApiScope::Read, require_scope))
+}
+
+/// 请求所属的租户名，由租户路由写入请求扩展，供绑定主状态的中间件区分租户
+#[derive(Debug, Clone)]
+pub(crate) struct TenantName(pub(crate) String);
+
+/// 各租户的路由（已绑定租户状态）与选择租户的请求头
+struct TenantRouting {
+    routers: BTreeMap<String, Router>,
+    header: Option<HeaderName>,
+}
+
+/// 选择了租户的请求交给该租户的路由：`/t/{租户}` 前缀去掉后转发，或按 tenants.header 请求头选择。
+async fn route_tenant(State(routing): State<Arc<TenantRouting>>, mut req: Request, next: Next) -> Response {
+    if routing.routers.is_empty() {
+        return next.run(req).await;
+    }
+    let by_path = req.uri().path().strip_prefix(TENANT_PATH_PREFIX)
+        .and_then(|rest| rest.split_once('/'))
+        .map(|(name, rest)| (name.to_string(), format!("/{}", rest)));
+    // 请求头不影响健康检查、探针与管理接口，它们始终属于本进程
+    let path = req.uri().path();
+    let unversioned = path.strip_prefix(API_V1_PREFIX).unwrap_or(path);
+    let tenant_scoped = !matches!(unversioned, "/health" | "/livez" | "/readyz")
+        && !unversioned.starts_with("/admin/") && !unversioned.starts_with("/erase/");
+    let by_header = routing.header.as_ref()
+        .filter(|_| tenant_scoped)
+        .and_then(|header| req.headers().get(header))
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+    let name = match (by_path, by_header) {
+        (Some((name, rest)), _) => {
+            let path_and_query = match req.uri().query() {
+                Some(query) => format!("{}?{}", rest, query),
+                None => rest,
+            };
+            match path_and_query.parse() {
+                Ok(uri) => *req.uri_mut() = uri,
+                Err(_) => return problem_response(StatusCode::BAD_REQUEST, ApiErrorCode::InvalidRequest, tr!("无效的请求路径。", "Invalid request path.").to_string()),
+            }
+            name
+        }
+        (None, Some(name)) => name,
+        (None, None) => return next.run(req).await,
+    };
+    let Some(router) = routing.routers.get(&name) else {
+        return problem_response(StatusCode::NOT_FOUND, ApiErrorCode::InvalidRequest, tr!("未知的租户: {}", "Unknown tenant: {}", name));
+    };
+    // 外层路由匹配时写入的路径参数会与租户路由的参数叠加，只带上中间件设置的扩展重新构造请求
+    let (parts, body) = req.into_parts();
+    let mut forwarded = Request::new(body);
+    *forwarded.method_mut() = parts.method;
+    *forwarded.uri_mut() = parts.uri;
+    *forwarded.version_mut() = parts.version;
+    *forwarded.headers_mut() = parts.headers;
+    let extensions = forwarded.extensions_mut();
+    if let Some(ip) = parts.extensions.get::<ClientIp>().copied() {
+        extensions.insert(ip);
+    }
+    if let Some(info) = parts.extensions.get::<ConnectInfo<SocketAddr>>().cloned() {
+        extensions.insert(info);
+    }
+    if let Some(uri) = parts.extensions.get::<axum::extract::OriginalUri>().cloned() {
+        extensions.insert(uri);
+    }
+    extensions.insert(TenantName(name));
+    match tower::ServiceExt::oneshot(router.clone(), forwarded).await {
+        Ok(response) => response,
+        Err(never) => match never {},
+    }
+}
+
+pub fn build_router(state: Arc<AppState>) -> Result<Router, AppError> {
+    let config = state.current_config();
+    let trusted_proxies: TrustedProxies = Arc::new(config.trusted_proxies.iter().filter_map(|p| parse_ip_net(p)).collect());
+    let tenant_routing = Arc::new(TenantRouting {
+        routers: state.tenants.get().into_iter().flatten().map(|(name, tenant)| {
+            let data = data_routes(&state);
+            (name.clone(), Router::new().nest(API_V1_PREFIX, data.clone()).nest(API_V2_PREFIX, v2_routes(&state)).merge(data).with_state(tenant.clone()))
+        }).collect(),
+        header: HeaderName::from_bytes(config.tenants.header.as_bytes()).ok(),
+    });
+    let api = data_routes(&state)
+        .merge(Router::new()
+            .route("/admin/cache/flush", post(api_cache_flush))
+            .route("/admin/overview", get(api_admin_overview))
+            .route("/admin/import", post(api_admin_import))
+            .route("/admin/config", get(api_admin_config).put(api_admin_config_update))
+            .route("/admin/retention", get(api_admin_retention))
+            .route("/admin/access_trail", get(api_admin_access_trail))
+            .route("/admin/bans", get(api_admin_bans))
+            .route("/admin/bans/:subject", delete(api_admin_unban))
+            .route("/admin/deny_list", get(api_admin_deny_list).post(api_admin_deny_list_add))
+            .route("/admin/deny_list/:id", delete(api_admin_deny_list_remove))
+            .route("/admin/keys/:id/usage", get(api_admin_key_usage))
+            .route("/admin/log_level", get(api_admin_log_level).put(api_admin_log_level_update))
+            .route("/admin/merge", get(api_admin_merge).post(api_admin_merge_apply))
+            .route("/erase/:id", delete(api_erase))
+            .route_layer(middleware::from_fn_with_state(state.clone(), require_admin)));
+    // 当前版本的接口挂载在 /v1 下，按主体返回全部标识符的查询接口在 /v2 下；旧的无前缀路径保留为兼容别名。
+    // 健康检查与探针、/admin/ui 浏览器入口均不分版本
+    let app = Router::new()
+        .route("/health", get(api_health))
+        .route("/livez", get(api_livez))
+        .route("/readyz", get(api_readyz))
+        .route("/admin/ui", get(admin_ui).route_layer(middleware::from_fn_with_state(state.clone(), require_admin)))
+        .nest(API_V1_PREFIX, api.clone())
+        .nest(API_V2_PREFIX, v2_routes(&state))
+        .merge(api.layer(middleware::from_fn(mark_deprecated)))
+        .layer(middleware::from_fn_with_state(tenant_routing, route_tenant))
+        .layer(middleware::from_fn(track_endpoint))
+        .layer(middleware::from_fn_with_state(state.clone(), enforce_rate_limit))
+        .layer(middleware::from_fn_with_state(state.clone(), identify_caller))
+        .layer(middleware::from_fn_with_state(state.clone(), apply_request_deadline))
+        .layer(middleware::from_fn_with_state(state.clone(), enforce_request_timeout))
+        .layer(middleware::from_fn_with_state(state.clone(), enforce_body_limit))
+        .layer(DefaultBodyLimit::max(config.max_body_bytes as usize))
+        .layer(middleware::from_fn_with_state(state.clone(), enforce_bans))
+        .layer(middleware::from_fn_with_state(state.clone(), enforce_ip_access))
+        .layer(middleware::from_fn_with_state(trusted_proxies, resolve_client_ip))
+        .layer(middleware::from_fn(assign_request_id))
+        .with_state(state.clone());
+    // 负载保护：超过并发上限的请求立即以 503 拒绝，而不是在阻塞线程池中无限排队
+    let retry_after = config.retry_after_secs.to_string();
+    let app = app.layer(
+        ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(move |err: BoxError| {
+                let retry_after = retry_after.clone();
+                async move {
+                    eprintln!("{} {}", "WARN".yellow(), tr!("负载卸载: {}", "Load shed: {}", err));
+                    let detail = tr!("服务器过载，请稍后重试。", "Server is overloaded, please retry later.").to_string();
+                    ([(RETRY_AFTER, retry_after)], problem_response(StatusCode::SERVICE_UNAVAILABLE, ApiErrorCode::Overloaded, detail))
+                }
+            }))
+            .load_shed()
+            // Router::layer 会为每个路由单独实例化中间件，必须使用共享信号量的全局版本
+            .layer(GlobalConcurrencyLimitLayer::new(config.max_in_flight_requests as usize)),
+    );
+    let app = app.layer(middleware::from_fn_with_state(state.clone(), count_responses));
+    let app = if config.cors.enabled {
+        let cors = config.cors.build_layer().map_err(AppError::FatalError)?;
+        println!("{} {}", "INFO".cyan(), tr!("CORS 已启用，允许来源: {}", "CORS enabled, allowed origins: {}", config.cors.allowed_origins.join(", ")));
+        app.layer(cors)
+    } else {
+        app
+    };
+    Ok(app)
+}
+
+/// 综合健康检查（兼容旧的探测配置）：存储可用即为 ok。Kubernetes 应改用 `/livez` 与 `/readyz`。
+/// 启用 health.deep 时还要求每项深度检查通过。
+async fn api_health(State(state): State<Arc<AppState>>) -> (StatusCode, Json<HealthResponse>) {
+    let (mut status, Json(mut health)) = storage_health(&state).await;
+    let config = state.current_config();
+    if !config.health.deep {
+        return (status, Json(health));
+    }
+    health.checks = deep_health_checks(&state, &config).await;
+    if let Some(failed) = health.checks.iter().find(|c| c.status == "error") {
+        if status == StatusCode::OK {
+            status = StatusCode::SERVICE_UNAVAILABLE;
+            health.status = "error".to_string();
+            health.message = tr!("深度检查未通过: {}", "Deep health check failed: {}", failed.name);
+        }
+    }
+    (status, Json(health))
+}
+
+/// 深度检查：存储写读删往返（故障切换期间主库不可写，跳过）与数据目录所在磁盘的可用空间。
+async fn deep_health_checks(state: &Arc<AppState>, config: &ServiceConfig) -> Vec<HealthCheck> {
+    let mut checks = Vec::new();
+
+    let started = Instant::now();
+    let round_trip = if state.read_path().is_some() {
+        None
+    } else {
+        match state.store() {
+            Ok(store) => Some(store.round_trip().await),
+            Err(e) => Some(Err(e)),
+        }
+    };
+    checks.push(HealthCheck {
+        name: "storage_round_trip",
+        status: match &round_trip { None => "skipped", Some(Ok(())) => "ok", Some(Err(_)) => "error" },
+        latency_ms: started.elapsed().as_secs_f64() * 1000.0,
+        message: round_trip.and_then(|r| r.err()).map(|e| e.to_string()),
+    });
+
+    checks.push(disk_space_check(config));
+    checks
+}
+
+/// 数据目录 (db_path 所在目录) 所在磁盘的可用空间不低于 health.min_free_disk_mb。
+pub(crate) fn disk_space_check(config: &ServiceConfig) -> HealthCheck {
+    let started = Instant::now();
+    let data_dir = match FilePath::new(&config.db_path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let (status, message) = match fs2::available_space(&data_dir) {
+        Ok(bytes) => {
+            let free_mb = bytes / (1024 * 1024);
+            let message = tr!("{} 可用 {} MB（下限 {} MB）", "{}: {} MB free (minimum {} MB)", data_dir.display(), free_mb, config.health.min_free_disk_mb);
+            (if free_mb < config.health.min_free_disk_mb { "error" } else { "ok" }, message)
+        }
+        Err(e) => ("error", tr!("无法读取 {} 的磁盘空间: {}", "Failed to read disk space for {}: {}", data_dir.display(), e)),
+    };
+    HealthCheck { name: "disk_space", status, latency_ms: started.elapsed().as_secs_f64() * 1000.0, message: Some(message) }
+}
+
+/// 存储可用性检查（故障切换状态、熔断器与存储探测），`/health` 与 `/readyz` 共用。
+pub(crate) async fn storage_health(state: &Arc<AppState>) -> (StatusCode, Json<HealthResponse>) {
+    let circuit = state.breaker_status();
+    // 已切换到备用库：只要备用库可读就继续提供查询，以 degraded 状态告警
+    if let Some(fallback) = state.read_path() {
+        return match probe_database(&fallback, state.current_config().database_key().as_deref()) {
+            Ok(()) => {
+                let message = tr!("主库不可用，查询由备用库 {} 提供，写入暂不可用", "Primary database unavailable; reads served from fallback {}, writes unavailable", fallback);
+                (StatusCode::OK, Json(HealthResponse { status: "degraded".to_string(), message, circuit_breaker: Some(circuit), checks: Vec::new() }))
+            }
+            Err(e) => {
+                let message = tr!("主库与备用库均不可用: {}", "Both primary and fallback databases are unavailable: {}", e);
+                (StatusCode::SERVICE_UNAVAILABLE, Json(HealthResponse { status: "error".to_string(), message, circuit_breaker: Some(circuit), checks: Vec::new() }))
+            }
+        };
+    }
+    // 熔断期间不再探测数据库，恢复由冷却结束后的试探请求决定
+    if circuit.state == "open" {
+        let message = tr!("数据库熔断中", "Database circuit breaker is open").to_string();
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(HealthResponse { status: "circuit_open".to_string(), message, circuit_breaker: Some(circuit), checks: Vec::new() }));
+    }
+    let probe = if state.current_config().single_sqlite_file() {
+        state.get_db_connection().and_then(|c| c.query_row("SELECT 1", [], |_| Ok(()))).map_err(|e| e.to_string())
+    } else {
+        match state.store() {
+            Ok(store) => store.ping().await.map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    };
+    match probe {
+        Ok(_) => (StatusCode::OK, Json(HealthResponse { status: "ok".to_string(), message: "Ready".to_string(), circuit_breaker: Some(circuit), checks: Vec::new() })),
+        Err(e) => (StatusCode::SERVICE_UNAVAILABLE, Json(HealthResponse { status: "error".to_string(), message: e.to_string(), circuit_breaker: Some(circuit), checks: Vec::new() })),
+    }
+}
+
+/// 存活探针：只确认进程在运行且异步运行时能及时调度任务，不访问数据库，
+/// 数据库的短暂故障不应导致容器被重启。
+async fn api_livez() -> (StatusCode, Json<HealthResponse>) {
+    match tokio::time::timeout(Duration::from_millis(LIVENESS_TIMEOUT_MS), task::spawn(async {})).await {
+        Ok(Ok(())) => (StatusCode::OK, Json(HealthResponse { status: "ok".to_string(), message: "Alive".to_string(), circuit_breaker: None, checks: Vec::new() })),
+        _ => {
+            let message = tr!("运行时在 {} ms 内未能调度任务", "Runtime failed to schedule a task within {} ms", LIVENESS_TIMEOUT_MS);
+            (StatusCode::SERVICE_UNAVAILABLE, Json(HealthResponse { status: "stalled".to_string(), message, circuit_breaker: None, checks: Vec::new() }))
+        }
+    }
+}
+
+/// 就绪探针：在 `/health` 的存储检查之上，还要求表结构已就绪、布隆过滤器不在构建中。
+/// 未就绪只应使实例暂时摘出负载均衡，而不是重启。
+async fn api_readyz(State(state): State<Arc<AppState>>) -> (StatusCode, Json<HealthResponse>) {
+    let (status, Json(mut health)) = api_health(State(state.clone())).await;
+    // 故障切换期间备用库的探测已包含表结构检查
+    if status != StatusCode::OK || state.read_path().is_some() {
+        return (status, Json(health));
+    }
+    let schema = match state.store() {
+        Ok(store) => store.check_schema().await,
+        Err(e) => Err(e),
+    };
+    let not_ready = match schema {
+        Err(e) => Some(("schema_missing", e.to_string())),
+        Ok(()) if state.bloom_building.load(Ordering::SeqCst) => Some(("warming", tr!("布隆过滤器构建中", "Bloom filter is being built").to_string())),
+        Ok(()) => None,
+    };
+    if let Some((reason, message)) = not_ready {
+        health.status = reason.to_string();
+        health.message = message;
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(health));
+    }
+    (StatusCode::OK, Json(health))
+}
+
+async fn api_stats(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse, AppError> {
+    // 估算缓存内存需要遍历全部条目，放到阻塞线程池执行
+    let stats = task::spawn_blocking(move || state.stats()).await
+        .map_err(|e| AppError::FatalError(tr!("后台任务异常终止: {}", "Background task aborted: {}", e)))?;
+    Ok(Json(stats))
+}
+
+async fn api_cache_flush(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse, AppError> {
+    let flushed = task::spawn_blocking(move || state.flush_cache()).await
+        .map_err(|e| AppError::FatalError(tr!("后台任务异常终止: {}", "Background task aborted: {}", e)))??;
+    println!("{} {}", "INFO".yellow(), tr!("缓存已通过 API 清空 ({} 条)", "Cache flushed via API ({} entries)", flushed));
+    Ok(Json(HealthResponse { status: "ok".to_string(), message: tr!("已清空 {} 条缓存", "Flushed {} cache entries", flushed), circuit_breaker: None, checks: Vec::new() }))
+}
+
+/// 构建时间（由 build.rs 写入的 Unix 时间戳），按本地时区格式化
+fn build_time() -> String {
+    env!("CYBER_LOOKUP_BUILD_EPOCH").parse::<i64>().ok()
+        .and_then(|secs| DateTime::from_timestamp(secs, 0))
+        .map(|t| t.with_timezone(&Local).to_rfc3339())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+async fn api_info(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let config = state.current_config();
+    let store = state.store().ok();
+    let records = match &store {
+        Some(store) => store.count(None).await.ok(),
+        None => None,
+    };
+    Json(InfoResponse {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        db_path: config.db_path,
+        bind_address: config.bind_address,
+        listening: state.listening.lock().unwrap().clone(),
+        storage_backend: store.map(|store| store.name()).unwrap_or("unavailable"),
+        git_commit: env!("CYBER_LOOKUP_GIT_COMMIT"),
+        build_time: build_time(),
+        rustc_version: env!("CYBER_LOOKUP_RUSTC_VERSION"),
+        started_at: state.started_at.to_rfc3339(),
+        uptime_secs: state.started.elapsed().as_secs(),
+        records,
+    })
+}
@@ -0,0 +1,7198 @@
+//! UID / 手机号映射查询服务（防御性编程、高交互性，支持 API 密钥 / JWT 与按权限 (scope) 授权）。
+//!
+//! 二进制 `cyber_lookup` 只是 [`run`] 的薄封装；其他服务也可以直接嵌入查询引擎：
+//!
+//! - [`ServiceConfig`]：全部配置项，可由 [`load_config`] 从 `config.txt` 读取，或直接构造 / 反序列化；
+//! - [`AppState`]：连接池、缓存、布隆过滤器与熔断器等运行时状态，由 [`AppState::new`] 创建；
+//! - 存储层：[`MappingStore`] 抽象查询、写入、删除与导出，默认实现为 [`SqliteStore`]，另有按哈希分片的 [`ShardedSqliteStore`]、[`PostgresStore`] 与 [`MemoryStore`]，[`RedisCachedStore`] 可包装任一实现作为多实例共享缓存；也可通过 [`AppState::with_store`] 注入其他实现；
+//!   [`initialize_database`] 建表建索引，[`lookup_one`] 在给定 SQLite 连接上执行单条查询；
+//! - 查询接口：[`lookup`] 与 [`lookup_batch`] 经过缓存与布隆过滤器，行为与 HTTP 接口一致；
+//! - 错误上报：[`ErrorReporter`] 接收意外的数据库 / I/O 错误与 panic，[`SentryReporter`] 发送到 Sentry 兼容的接收端，可通过 [`set_error_reporter`] 替换；
+//! - [`build_router`]：返回完整的 axum `Router`，可整体挂载到宿主应用中（例如 `Router::nest("/lookup-service", ...)`）。
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//!
+//! # async fn demo() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut config = cyber_lookup::ServiceConfig::default();
+//! config.db_path = "data/embedded.db".to_string();
+//! let state = Arc::new(cyber_lookup::AppState::new(config));
+//! let conn = state.get_db_connection()?;
+//! cyber_lookup::initialize_database(&conn)?;
+//!
+//! let result = cyber_lookup::lookup(&state, "13800000000").await?;
+//! println!("{}", result.status);
+//!
+//! let app = axum::Router::new().nest("/mapping", cyber_lookup::build_router(state)?);
+//! # let _ = app;
+//! # Ok(())
+//! # }
+//! ```
+use axum::{
+    routing::{delete, get, post, put},
+    extract::{ConnectInfo, DefaultBodyLimit, FromRequest, MatchedPath, Multipart, Path, Query, Request, State, Json},
+    middleware::{self, Next},
+    error_handling::HandleErrorLayer,
+    BoxError,
+    response::{IntoResponse, Response},
+    http::{StatusCode, HeaderMap, HeaderName, HeaderValue, Method, header::{ACCEPT, AUTHORIZATION, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH, LINK, RETRY_AFTER, VARY, WWW_AUTHENTICATE}}, 
+    body::{Body, Bytes},
+    Router,
+};
+use serde::{Serialize, Deserialize};
+use rusqlite::{Connection, ErrorCode, InterruptHandle, OpenFlags, OptionalExtension, Result as SqlResult, Error as SqlError, types::ToSql};
+use std::sync::{Arc, Mutex, OnceLock, RwLock, Weak};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering};
+use colored::{Colorize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path as FilePath, PathBuf}; 
+use std::fs; 
+use tokio::task;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque}; 
+use std::ops::Deref;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::env;
+use std::process;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+use tokio::sync::mpsc;
+use tokio_stream::{StreamExt, wrappers::ReceiverStream};
+use tower::{ServiceBuilder, limit::GlobalConcurrencyLimitLayer};
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer};
+use chrono::{DateTime, Local};
+use clap::{Parser, Subcommand, ValueEnum};
+use moka::sync::Cache;
+use rustyline::{Editor, Helper, completion::{Completer, Pair}, error::ReadlineError, highlight::Highlighter, hint::Hinter, history::DefaultHistory, validate::Validator};
+use uuid::Uuid;
+use deadpool_postgres::tokio_postgres;
+use redis::AsyncCommands;
+
+// --- 默认配置和常量 ---
+const DEFAULT_DATA_DIR: &str = "data";
+const DEFAULT_CONFIG_FILE: &str = "config.txt";
+const DEFAULT_LOG_FILE: &str = "data/cyber_lookup.log"; // 后台运行且未配置 log_file 时的输出文件
+const DEFAULT_DB_PATH: &str = "data/uid_phone_map.db";
+const DEFAULT_BIND_ADDRESS: &str = "0.0.0.0:3000";
+const MEMORY_DB_PATH: &str = ":memory:"; // db_path 取此值时使用内存存储后端
+const MAX_DATA_LENGTH: usize = 100; // 防御性：数据库字段最大长度
+const DEFAULT_BACKUP_DIR: &str = "data/backups";
+const BACKUP_FILE_PREFIX: &str = "backup-";
+const BACKUP_POLL_SECS: u64 = 60; // 自动备份未启用时的配置轮询间隔
+const ACTIVITY_FLUSH_SECS: u64 = 60; // 映射命中时间写回数据库的间隔，也是保留策略任务的配置轮询间隔
+const SCHEDULER_TICK_SECS: u64 = 1; // 定时任务调度器检查到期任务的间隔（cron 表达式可精确到秒）
+const SCRIPT_RELOAD_SECS: u64 = 2; // 检查脚本钩子文件是否被修改的间隔
+const SCRIPT_HOOK_NAMES: [&str; 3] = ["normalize_id", "validate_insert", "post_process"]; // scripting.dir 下的 <名称>.rhai
+const RETENTION_DELETE_CHUNK: usize = 5000; // 保留策略每个删除事务的最大条数，避免长时间占用写锁
+const ACCESS_TRAIL_FLUSH_SECS: u64 = 5; // 访问记录从内存写入 access_trail.path 的间隔
+const ACCESS_TRAIL_EXPORT_LIMIT: u32 = 100_000; // GET /admin/access_trail 单次返回的最大条数
+const ACCESS_TRAIL_PENDING_LIMIT: usize = 100_000; // 写入失败时内存中最多保留的访问记录条数，超出时丢弃最早的
+const JWKS_POLL_SECS: u64 = 60;        // 未配置 jwks_url 时的配置轮询间隔
+const JWKS_MIN_REFRESH_SECS: u64 = 30; // 遇到未知 kid 时提前刷新 JWKS 的最短间隔，避免伪造令牌触发频繁拉取
+const JWKS_FETCH_TIMEOUT_SECS: u64 = 10;
+const FAILOVER_POLL_SECS: u64 = 60; // 未配置备用库时的配置轮询间隔
+const LIVENESS_TIMEOUT_MS: u64 = 1000; // 存活探针等待运行时调度一个空任务的时限
+const SUPERVISE_MAX_RESTARTS: u32 = 5; // start --supervise 连续重启的上限
+const SUPERVISE_INITIAL_BACKOFF_MS: u64 = 1000;
+const SUPERVISE_MAX_BACKOFF_MS: u64 = 60_000;
+const SUPERVISE_STABLE_SECS: u64 = 300; // 服务稳定运行超过此时长后重启计数清零
+const ALERT_POLL_SECS: u64 = 60; // 未配置告警 webhook 时的配置轮询间隔
+const ALERT_WEBHOOK_TIMEOUT_SECS: u64 = 10; // 单次 webhook 推送的超时
+const ERROR_REPORT_TIMEOUT_SECS: u64 = 10; // 单次错误上报的超时
+const ERROR_REPORT_DEDUP_SECS: u64 = 60; // 相同错误在此时间内只上报一次，避免故障期间刷屏
+const SERVER_PROBE_TIMEOUT_MS: u64 = 300; // 探测服务是否在运行时的连接超时
+const INTEGRITY_MAX_ERRORS: u32 = 100; // integrity_check 最多报告的问题条数
+const DEFAULT_LIST_PAGE_SIZE: u32 = 20; // db-manage 'list' 每页默认条数
+const MAX_LIST_PAGE_SIZE: u32 = 500;
+const MAX_SQL_ROWS: usize = 1000; // db-manage 'sql' 最多显示的行数
+const SEED_BATCH_SIZE: u64 = 10_000; // 生成测试数据时每个事务的记录数
+const MAX_CONFIG_VERSIONS: u32 = 100;  // config_versions 的上限
+const IMPORT_CHUNK_SIZE: usize = 10_000; // 导入时每个事务的记录数
+const XLSX_MAX_ROWS: u64 = 1_048_576;      // Excel 工作表的行数上限（含表头）
+const IMPORT_MAX_WARNINGS: u64 = 10; // 导入时逐条打印的无效行数上限，其余只计数
+const PROGRESS_REDRAW_MS: u64 = 100; // 终端进度条的最短刷新间隔
+const REMOTE_CONNECT_TIMEOUT_SECS: u64 = 10; // 远程导入的连接超时
+const REMOTE_READ_TIMEOUT_SECS: u64 = 60;    // 远程导入单次读取的超时，下载总时长不设上限
+const MAX_SHARDS: u32 = 11;          // 分片数上限：写事务需 ATTACH 全部分片，SQLite 最多附加 10 个库（分片 0 为主库）
+const REDIS_TIMEOUT_MS: u64 = 500;   // Redis 连接与单条命令的超时，超时后直接访问存储后端
+const REDIS_RETRY_SECS: u64 = 5;     // Redis 出错后暂停使用的时长，以及失效订阅断线后的重连间隔
+const BENCH_SAMPLE_SIZE: i64 = 1000; // bench 从库中抽样的记录数，另加约 10% 不存在的 ID
+const SEED_PHONE_PREFIXES: &[&str] = &["130", "131", "132", "135", "136", "137", "138", "139", "150", "151", "152", "158", "159", "176", "177", "180", "181", "186", "188", "189", "199"];
+const BLOOM_MIN_CAPACITY: u64 = 10_000; // 布隆过滤器的最小容量，避免空库时过小
+const BLOOM_GROWTH_FACTOR: u64 = 2;     // 预留给运行期新增记录的容量倍数
+const CACHE_ENTRY_OVERHEAD: usize = 64; // 估算缓存内存时每个条目的固定开销 (字节)
+const QUERY_FREQUENCY_KEEP_FACTOR: u32 = 10; // query_frequency 表保留 preload_top 的这么多倍，其余按次数淘汰
+const SQLITE_MAX_PARAMS: u32 = 32766;  // SQLite 单条语句可绑定参数的上限 (3.32+ 默认值)
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+const NDJSON_CHANNEL_CAPACITY: usize = 1024; // 流式响应的行缓冲，写满时反压查询任务
+const CSV_CONTENT_TYPE: &str = "text/csv; charset=utf-8";
+const MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
+const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+const PROBLEM_TYPE_BASE: &str = "urn:cyber-lookup:problem:";
+const REQUEST_ID_HEADER: &str = "x-request-id";
+const API_KEY_HEADER: &str = "x-api-key";
+const TIMESTAMP_HEADER: &str = "x-timestamp";
+const SIGNATURE_HEADER: &str = "x-signature";
+const RESPONSE_SIGNATURE_HEADER: &str = "x-response-signature";
+const RESPONSE_KEY_ID_HEADER: &str = "x-response-key-id";
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+const IDEMPOTENCY_KEY_MAX_LENGTH: usize = 255;
+const IDEMPOTENCY_MAX_ENTRIES: u64 = 100_000; // 保存的写请求响应条数上限，超出时淘汰最久未用的
+const DELETE_CONFIRM_TTL_SECS: i64 = 300;   // /batch_delete 预览签发的确认令牌有效期
+const WATCH_POLL_SECS: u64 = 5;             // gRPC Watch 在没有本进程写入通知时重新查询的间隔
+const WATCH_CHANNEL_CAPACITY: usize = 64;   // gRPC Watch 待发送结果的缓冲条数
+const GRAPHQL_MAX_DEPTH: usize = 8;         // GraphQL 查询的最大嵌套深度
+const GRAPHQL_MAX_COMPLEXITY: usize = 1000; // GraphQL 查询的最大复杂度（约等于字段数）
+const RESP_MAX_ARG_BYTES: usize = 64 * 1024; // RESP 单个参数（及内联命令一行）的最大字节数，超过时断开连接
+const PHONE_KEY_ENV: &str = "CYBER_LOOKUP_PHONE_KEY";
+const DB_KEY_ENV: &str = "CYBER_LOOKUP_DB_KEY";
+const AUDIT_KEY_ENV: &str = "CYBER_LOOKUP_AUDIT_KEY";
+const JWT_SECRET_ENV: &str = "CYBER_LOOKUP_JWT_SECRET";
+const ENCRYPTED_PHONE_PREFIX: &str = "enc1:"; // 加密后的手机号以此开头，后接 base64 密文
+const HASHED_PHONE_PREFIX: &str = "h1:";       // 哈希模式下的手机号以此开头，后接十六进制 HMAC-SHA256
+const MAX_REQUEST_ID_LENGTH: usize = 128; // 客户端提供的请求 ID 超过该长度时改用新生成的 ID
+const API_V1_PREFIX: &str = "/v1";
+const API_V2_PREFIX: &str = "/v2";
+const TENANT_PATH_PREFIX: &str = "/t/"; // 按路径选择租户: /t/{租户}/lookup/:id
+const RECENT_QUERY_LIMIT: usize = 50; // 管理控制台展示的最近查询条数
+const SLOW_QUERY_LIMIT: usize = 20;   // /stats 中保留的最近慢查询条数
+const DEADLINE_HEADER: &str = "x-deadline-ms";
+const RATE_LIMIT_PRUNE_THRESHOLD: usize = 10_000; // 限流计数表超过该条目数时清理已过期的调用方
+const PROXY_HEADER_TIMEOUT_MS: u64 = 5000; // 等待连接发送 PROXY protocol 头的时限
+const TLS_HANDSHAKE_TIMEOUT_SECS: u64 = 10; // 等待 TLS 握手完成的时限
+const PROXY_V1_MAX_LENGTH: usize = 107;    // v1 头（含 CRLF）的最大长度
+const PROXY_V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+const DEADLINE_CHECK_OPS: i32 = 1000; // SQLite 每执行这么多条虚拟机指令检查一次请求截止时间
+const CONTROL_MAX_REQUEST_BYTES: u64 = 64 * 1024; // 控制套接字单条命令的最大字节数
+const CONTROL_TIMEOUT_SECS: u64 = 60;  // ctl 等待服务应答的时限
+
+// --- 多语言 (zh / en) ---
+
+/// 界面与 API 错误信息使用的语言，由配置项 `language` 决定。
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[default]
+    Zh,
+    En,
+}
+
+/// 当前语言。错误类型与校验逻辑拿不到 AppState，因此使用进程级全局值。
+static LANGUAGE: AtomicU8 = AtomicU8::new(0);
+
+fn set_language(language: Language) {
+    LANGUAGE.store(language as u8, Ordering::Relaxed);
+}
+
+fn language() -> Language {
+    if LANGUAGE.load(Ordering::Relaxed) == Language::En as u8 { Language::En } else { Language::Zh }
+}
+
+/// 按当前语言选择文案：`tr!("中文", "English")` 得到 `&'static str`，
+/// 带参数时 `tr!("共 {} 条", "{} total", n)` 得到 `String`。两种语言的占位符顺序必须一致。
+macro_rules! tr {
+    ($zh:literal, $en:literal $(,)?) => {
+        match language() { Language::Zh => $zh, Language::En => $en }
+    };
+    ($zh:literal, $en:literal, $($arg:expr),+ $(,)?) => {
+        match language() { Language::Zh => format!($zh, $($arg),+), Language::En => format!($en, $($arg),+) }
+    };
+}
+
+// --- 模块 ---
+// 在 tr! 之后声明，子模块才能使用该宏
+
+mod cli;
+mod config;
+mod graphql;
+mod grpc;
+mod http;
+mod resp;
+mod scheduler;
+mod storage;
+
+use cli::*;
+pub use config::*;
+use graphql::*;
+use grpc::*;
+pub use http::*;
+use resp::*;
+use scheduler::*;
+pub use storage::*;
+
+// --- 错误处理 (保持不变) ---
+#[derive(Debug)]
+pub enum AppError {
+    DbError(SqlError),
+    IoError(io::Error),
+    ConfigError(String),
+    NetworkBindError(io::Error),
+    FatalError(String),
+    PayloadTooLarge(String),
+    Timeout(String),
+    CircuitOpen(u64),            // 熔断中，携带距离冷却结束的秒数
+    Rejected(ApiErrorCode, String), // 带有具体错误码的客户端错误 (400)
+    Conflict(String),            // 写入与已有记录冲突且策略为 error (409)
+    Forbidden(String),           // 已识别但不允许的访问 (403)
+    StoreError(String),          // 非 SQLite 存储后端返回的错误
+    StoreUnavailable(String),    // 无法连接非 SQLite 存储后端 (503)
+    Unauthorized,                // 缺少或错误的管理令牌 (401)
+    InvalidSignature(String),    // 缺少、过期、错误或重放的请求签名 (401)
+    PreconditionFailed(String),  // If-Match 与记录当前版本不符 (412)
+    PreconditionRequired(String), // 条件修改缺少 If-Match (428)
+    Denied(String),              // 查询的标识在屏蔽名单中且 respond_with 为 unavailable (451)
+}
+
+impl From<SqlError> for AppError {
+    fn from(err: SqlError) -> Self { AppError::DbError(err) }
+}
+impl From<io::Error> for AppError {
+    fn from(err: io::Error) -> Self { 
+        if err.kind() == io::ErrorKind::AddrInUse || err.kind() == io::ErrorKind::PermissionDenied {
+            return AppError::NetworkBindError(err);
+        }
+        AppError::IoError(err) 
+    }
+}
+impl From<serde_json::Error> for AppError {
+    fn from(err: serde_json::Error) -> Self { AppError::ConfigError(format!("JSON Parse Error: {}", err)) }
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::DbError(e) => write!(f, "{}", tr!("数据库错误: {}", "Database error: {}", e)),
+            AppError::IoError(e) => write!(f, "{}", tr!("I/O 错误: {}", "I/O error: {}", e)),
+            AppError::ConfigError(m) => write!(f, "{}", tr!("配置错误: {}", "Config error: {}", m)),
+            AppError::NetworkBindError(e) => write!(f, "{}", tr!("网络绑定错误: {}", "Bind error: {}", e)),
+            AppError::CircuitOpen(secs) => write!(f, "{}", tr!("数据库熔断中，请在 {} 秒后重试。", "Database circuit breaker is open, retry in {} s.", secs)),
+            AppError::Unauthorized => write!(f, "{}", tr!("未授权的访问。", "Unauthorized access.")),
+            AppError::StoreError(m) => write!(f, "{}", tr!("数据库错误: {}", "Database error: {}", m)),
+            AppError::StoreUnavailable(m) => write!(f, "{}", tr!("数据库不可用: {}", "Database unavailable: {}", m)),
+            AppError::FatalError(m) | AppError::PayloadTooLarge(m) | AppError::Timeout(m) | AppError::Rejected(_, m) | AppError::Conflict(m) | AppError::Forbidden(m) | AppError::InvalidSignature(m)
+            | AppError::PreconditionFailed(m) | AppError::PreconditionRequired(m) | AppError::Denied(m) => write!(f, "{}", m),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl AppError {
+    /// 对外暴露的稳定错误码
+    fn code(&self) -> ApiErrorCode {
+        match self {
+            AppError::DbError(e) if is_unavailable_error(e) => ApiErrorCode::DbUnavailable,
+            AppError::DbError(_) | AppError::StoreError(_) => ApiErrorCode::DbError,
+            AppError::StoreUnavailable(_) => ApiErrorCode::DbUnavailable,
+            AppError::IoError(_) => ApiErrorCode::IoError,
+            AppError::ConfigError(_) => ApiErrorCode::ConfigError,
+            AppError::FatalError(_) => ApiErrorCode::InvalidRequest,
+            AppError::PayloadTooLarge(_) => ApiErrorCode::PayloadTooLarge,
+            AppError::Timeout(_) => ApiErrorCode::Timeout,
+            AppError::CircuitOpen(_) => ApiErrorCode::DbUnavailable,
+            AppError::Rejected(code, _) => *code,
+            AppError::Conflict(_) => ApiErrorCode::Conflict,
+            AppError::Unauthorized => ApiErrorCode::Unauthorized,
+            AppError::Forbidden(_) => ApiErrorCode::Forbidden,
+            AppError::InvalidSignature(_) => ApiErrorCode::InvalidSignature,
+            AppError::PreconditionFailed(_) => ApiErrorCode::PreconditionFailed,
+            AppError::PreconditionRequired(_) => ApiErrorCode::PreconditionRequired,
+            AppError::Denied(_) => ApiErrorCode::Denied,
+            AppError::NetworkBindError(_) => ApiErrorCode::InternalError,
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> axum::response::Response {
+        let code = self.code();
+        let (status, msg) = match self {
+            AppError::Unauthorized => {
+                // 使浏览器弹出登录框（管理控制台）
+                let mut response = problem_response(StatusCode::UNAUTHORIZED, code, tr!("未授权的访问。", "Unauthorized access.").to_string());
+                response.headers_mut().insert(WWW_AUTHENTICATE, HeaderValue::from_static("Basic realm=\"cyber_lookup admin\", charset=\"UTF-8\""));
+                return response;
+            }
+            AppError::Forbidden(m) => (StatusCode::FORBIDDEN, m),
+            AppError::InvalidSignature(m) => (StatusCode::UNAUTHORIZED, m),
+            AppError::PreconditionFailed(m) => (StatusCode::PRECONDITION_FAILED, m),
+            AppError::PreconditionRequired(m) => (StatusCode::PRECONDITION_REQUIRED, m),
+            AppError::Denied(m) => (StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS, m),
+            AppError::DbError(e) if code == ApiErrorCode::DbUnavailable => (StatusCode::SERVICE_UNAVAILABLE, tr!("数据库不可用: {}", "Database unavailable: {}", e)),
+            AppError::DbError(e) => {
+                report_error("DbError", &e.to_string());
+                (StatusCode::INTERNAL_SERVER_ERROR, tr!("数据库错误: {}", "Database error: {}", e))
+            }
+            AppError::StoreError(m) => (StatusCode::INTERNAL_SERVER_ERROR, tr!("数据库错误: {}", "Database error: {}", m)),
+            AppError::StoreUnavailable(m) => (StatusCode::SERVICE_UNAVAILABLE, tr!("数据库不可用: {}", "Database unavailable: {}", m)),
+            AppError::FatalError(m) | AppError::Rejected(_, m) => (StatusCode::BAD_REQUEST, m),
+            AppError::PayloadTooLarge(m) => (StatusCode::PAYLOAD_TOO_LARGE, m),
+            AppError::Conflict(m) => (StatusCode::CONFLICT, m),
+            AppError::Timeout(m) => (StatusCode::GATEWAY_TIMEOUT, m),
+            AppError::CircuitOpen(secs) => {
+                let msg = tr!("数据库熔断中，请在 {} 秒后重试。", "Database circuit breaker is open, retry in {} s.", secs);
+                let mut response = problem_response(StatusCode::SERVICE_UNAVAILABLE, code, msg);
+                response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(secs));
+                return response;
+            }
+            AppError::ConfigError(m) => (StatusCode::INTERNAL_SERVER_ERROR, tr!("配置错误: {}", "Config error: {}", m)),
+            AppError::IoError(e) => {
+                report_error("IoError", &e.to_string());
+                (StatusCode::INTERNAL_SERVER_ERROR, tr!("I/O 错误: {}", "I/O error: {}", e))
+            }
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, tr!("发生未知错误。", "An unknown error occurred.").to_string()),
+        };
+        problem_response(status, code, msg)
+    }
+}
+
+/// 稳定的机器可读错误码，出现在每个错误响应与日志中。只可新增，不可修改已有名称。
+/// 名称只在 `as_str` 中定义，序列化时同样使用它。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ApiErrorCode {
+    InvalidRequest,
+    InvalidIdFormat,
+    BatchTooLarge,
+    UnsupportedFormat,
+    InvalidCsv,
+    PayloadTooLarge,
+    Timeout,
+    Overloaded,
+    Conflict,
+    DbUnavailable,
+    DbError,
+    ConfigError,
+    IoError,
+    Unauthorized,
+    InternalError,
+    Forbidden,
+    InvalidSignature,
+    PreconditionFailed,
+    PreconditionRequired,
+    RateLimited,
+    QuotaExceeded,
+    Denied,
+}
+
+impl ApiErrorCode {
+    fn as_str(self) -> &'static str {
+        match self {
+            ApiErrorCode::InvalidRequest => "INVALID_REQUEST",
+            ApiErrorCode::InvalidIdFormat => "INVALID_ID_FORMAT",
+            ApiErrorCode::BatchTooLarge => "BATCH_TOO_LARGE",
+            ApiErrorCode::UnsupportedFormat => "UNSUPPORTED_FORMAT",
+            ApiErrorCode::InvalidCsv => "INVALID_CSV",
+            ApiErrorCode::PayloadTooLarge => "PAYLOAD_TOO_LARGE",
+            ApiErrorCode::Timeout => "TIMEOUT",
+            ApiErrorCode::Overloaded => "OVERLOADED",
+            ApiErrorCode::Conflict => "CONFLICT",
+            ApiErrorCode::DbUnavailable => "DB_UNAVAILABLE",
+            ApiErrorCode::DbError => "DB_ERROR",
+            ApiErrorCode::ConfigError => "CONFIG_ERROR",
+            ApiErrorCode::IoError => "IO_ERROR",
+            ApiErrorCode::Unauthorized => "UNAUTHORIZED",
+            ApiErrorCode::InternalError => "INTERNAL_ERROR",
+            ApiErrorCode::Forbidden => "FORBIDDEN",
+            ApiErrorCode::InvalidSignature => "INVALID_SIGNATURE",
+            ApiErrorCode::PreconditionFailed => "PRECONDITION_FAILED",
+            ApiErrorCode::PreconditionRequired => "PRECONDITION_REQUIRED",
+            ApiErrorCode::RateLimited => "RATE_LIMITED",
+            ApiErrorCode::QuotaExceeded => "QUOTA_EXCEEDED",
+            ApiErrorCode::Denied => "DENIED",
+        }
+    }
+}
+
+impl Serialize for ApiErrorCode {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+// --- RFC 7807 problem+json 错误响应 ---
+
+/// 错误响应体，`type` 为 `PROBLEM_TYPE_BASE` 加上小写短横线形式的错误码。
+#[derive(Serialize)]
+struct ProblemDetails {
+    #[serde(rename = "type")]
+    problem_type: String,
+    title: String,
+    status: u16,
+    detail: String,
+    code: ApiErrorCode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+tokio::task_local! {
+    /// 当前请求的 ID，由 `assign_request_id` 中间件设置，供错误响应引用。
+    static REQUEST_ID: String;
+    /// 当前请求的数据库截止时间，由 `apply_request_deadline` 中间件根据 X-Deadline-Ms 设置。
+    static DB_DEADLINE: Instant;
+    /// 当前请求的客户端 IP，由 `resolve_client_ip` 中间件设置。
+    static CLIENT_IP: IpAddr;
+    /// 当前请求的调用方，由 `identify_caller` 中间件设置。
+    static CALLER: Caller;
+    /// 当前请求匹配的路由模板（如 /v1/lookup/:id），由 `track_endpoint` 中间件设置，用于慢查询日志。
+    static ENDPOINT: String;
+}
+
+/// 当前任务所属请求的数据库截止时间，未设置时为 None。
+fn current_deadline() -> Option<Instant> {
+    DB_DEADLINE.try_with(|deadline| *deadline).ok()
+}
+
+/// 在新任务中执行 `future`，并沿用当前请求的截止时间（task-local 不会自动传递给新任务）。
+fn spawn_with_deadline<F>(future: F) -> task::JoinHandle<F::Output>
+where
+    F: std::future::Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    match current_deadline() {
+        Some(deadline) => tokio::spawn(DB_DEADLINE.scope(deadline, future)),
+        None => tokio::spawn(future),
+    }
+}
+
+/// 当前请求的调用方上下文（请求 ID、客户端 IP、调用方、路由），在新任务中恢复，使其中的查询记录仍归属到该请求。
+struct RequestContext {
+    request_id: Option<String>,
+    client: Option<IpAddr>,
+    caller: Option<Caller>,
+    endpoint: Option<String>,
+}
+
+impl RequestContext {
+    fn capture() -> Self {
+        RequestContext {
+            request_id: REQUEST_ID.try_with(Clone::clone).ok(),
+            client: CLIENT_IP.try_with(|ip| *ip).ok(),
+            caller: CALLER.try_with(Clone::clone).ok(),
+            endpoint: ENDPOINT.try_with(Clone::clone).ok(),
+        }
+    }
+
+    fn scope<F>(self, future: F) -> std::pin::Pin<Box<dyn std::future::Future<Output = F::Output> + Send>>
+    where
+        F: std::future::Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let mut future: std::pin::Pin<Box<dyn std::future::Future<Output = F::Output> + Send>> = Box::pin(future);
+        if let Some(request_id) = self.request_id {
+            future = Box::pin(REQUEST_ID.scope(request_id, future));
+        }
+        if let Some(client) = self.client {
+            future = Box::pin(CLIENT_IP.scope(client, future));
+        }
+        if let Some(caller) = self.caller {
+            future = Box::pin(CALLER.scope(caller, future));
+        }
+        if let Some(endpoint) = self.endpoint {
+            future = Box::pin(ENDPOINT.scope(endpoint, future));
+        }
+        future
+    }
+}
+
+/// 构造 problem+json 响应并记录日志；在请求上下文中时附带请求 ID。
+/// 服务端错误 (5xx) 总是输出；客户端错误 (4xx) 只在 cyber_lookup::http 的调试级别输出，避免刷屏。
+fn problem_response(status: StatusCode, code: ApiErrorCode, detail: String) -> Response {
+    let request_id = REQUEST_ID.try_with(Clone::clone).ok();
+    let client = CLIENT_IP.try_with(ToString::to_string).unwrap_or_else(|_| "-".to_string());
+    let line = format!("[{}] {} (request {}, client {})", code.as_str(), detail, request_id.as_deref().unwrap_or("-"), client);
+    if status.is_server_error() {
+        eprintln!("{} {}", "ERR".red(), line);
+    } else {
+        log_debug("cyber_lookup::http", &line);
+    }
+    let body = ProblemDetails {
+        problem_type: format!("{}{}", PROBLEM_TYPE_BASE, code.as_str().to_lowercase().replace('_', "-")),
+        title: status.canonical_reason().unwrap_or("Error").to_string(),
+        status: status.as_u16(),
+        detail,
+        code,
+        request_id,
+    };
+    let json = serde_json::to_vec(&body).unwrap_or_default();
+    let mut response = (status, [(CONTENT_TYPE, PROBLEM_CONTENT_TYPE)], json).into_response();
+    // 供外层中间件（自动封禁）识别错误类型
+    response.extensions_mut().insert(code);
+    response
+}
+
+/// 将 axum 提取器的拒绝（请求体格式错误、超限等）转换为 problem+json。
+fn rejection_response(status: StatusCode, detail: String) -> Response {
+    let code = if status == StatusCode::PAYLOAD_TOO_LARGE { ApiErrorCode::PayloadTooLarge } else { ApiErrorCode::InvalidRequest };
+    problem_response(status, code, detail)
+}
+
+/// 同 rejection_response，用于返回 AppError 的处理函数：超限时为 413 PAYLOAD_TOO_LARGE，其余为 400
+fn rejection_error(status: StatusCode, detail: String) -> AppError {
+    if status == StatusCode::PAYLOAD_TOO_LARGE {
+        AppError::PayloadTooLarge(detail)
+    } else {
+        AppError::Rejected(ApiErrorCode::InvalidRequest, detail)
+    }
+}
+
+// --- 内部日志辅助 (利用 log_level) ---
+
+/// 日志级别，越往后越详细
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    const NAMES: [&'static str; 6] = ["off", "error", "warn", "info", "debug", "trace"];
+
+    fn parse(name: &str) -> Option<Self> {
+        const LEVELS: [LogLevel; 6] = [LogLevel::Off, LogLevel::Error, LogLevel::Warn, LogLevel::Info, LogLevel::Debug, LogLevel::Trace];
+        LogLevel::NAMES.iter().position(|n| n.eq_ignore_ascii_case(name.trim())).map(|i| LEVELS[i])
+    }
+
+    fn as_str(self) -> &'static str {
+        LogLevel::NAMES[self as usize]
+    }
+}
+
+/// 日志过滤规则：默认级别加按模块的指令，语法同 RUST_LOG（"debug"、"info,cyber_lookup::db=trace"）。
+/// 模块以 `::` 分隔，最长匹配的指令生效。
+#[derive(Debug, Clone, PartialEq)]
+struct LogFilter {
+    default: LogLevel,
+    directives: Vec<(String, LogLevel)>,
+}
+
+impl LogFilter {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let invalid = |part: &str| tr!("日志级别无效: '{}' (可选: {}，或 模块=级别)", "Invalid log level: '{}' (options: {}, or module=level)", part, LogLevel::NAMES.join("/"));
+        let mut filter = LogFilter { default: LogLevel::Info, directives: Vec::new() };
+        for part in spec.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            match part.split_once('=') {
+                Some((target, level)) if !target.trim().is_empty() => {
+                    let level = LogLevel::parse(level).ok_or_else(|| invalid(part))?;
+                    filter.directives.retain(|(existing, _)| existing != target.trim());
+                    filter.directives.push((target.trim().to_string(), level));
+                }
+                Some(_) => return Err(invalid(part)),
+                None => filter.default = LogLevel::parse(part).ok_or_else(|| invalid(part))?,
+            }
+        }
+        Ok(filter)
+    }
+
+    fn enabled(&self, target: &str, level: LogLevel) -> bool {
+        let matches = |prefix: &str| target == prefix || target.strip_prefix(prefix).is_some_and(|rest| rest.starts_with("::"));
+        let max = self.directives.iter()
+            .filter(|(prefix, _)| matches(prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default, |(_, level)| *level);
+        level != LogLevel::Off && level <= max
+    }
+}
+
+impl std::fmt::Display for LogFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.default.as_str())?;
+        for (target, level) in &self.directives {
+            write!(f, ",{}={}", target, level.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+/// 当前生效的日志过滤规则。启动与修改 log_level 时按配置设置，也可通过 PUT /admin/log_level 或 'loglevel' 命令临时调整。
+static LOG_FILTER: RwLock<LogFilter> = RwLock::new(LogFilter { default: LogLevel::Info, directives: Vec::new() });
+
+fn set_log_filter(filter: LogFilter) {
+    *LOG_FILTER.write().unwrap() = filter;
+}
+
+fn log_filter() -> LogFilter {
+    LOG_FILTER.read().unwrap().clone()
+}
+
+/// 按当前过滤规则输出一条调试日志。`target` 为模块路径，如 "cyber_lookup::db"。
+fn log_at(target: &str, level: LogLevel, message: &str) {
+    if !LOG_FILTER.read().unwrap().enabled(target, level) {
+        return;
+    }
+    let tag = match level {
+        LogLevel::Trace => "TRACE".dimmed(),
+        _ => level.as_str().to_uppercase().blue(),
+    };
+    println!("{} [{}] {}", tag, target, message);
+}
+
+fn log_debug(target: &str, message: &str) {
+    log_at(target, LogLevel::Debug, message);
+}
+
+// --- 日志文件 ---
+
+/// 按大小与时间轮转的日志文件，逐行写入。
+struct RotatingLog {
+    path: PathBuf,
+    policy: LogRotationConfig,
+    file: fs::File,
+    written: u64,
+    period: String,                  // 当前文件所属的时间周期
+}
+
+impl RotatingLog {
+    fn open(path: &str, policy: LogRotationConfig) -> io::Result<Self> {
+        let path = PathBuf::from(path);
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = fs::OpenOptions::new().create(true).append(true).open(&path)?;
+        let metadata = file.metadata()?;
+        // 沿用已有文件时，以其最后修改时间判断所属周期，跨周期重启后首次写入即轮转
+        let modified: DateTime<Local> = metadata.modified().map(DateTime::from).unwrap_or_else(|_| Local::now());
+        let period = Self::period_of(policy.interval, &modified);
+        Ok(RotatingLog { path, policy, file, written: metadata.len(), period })
+    }
+
+    fn period_of(interval: RotationInterval, time: &DateTime<Local>) -> String {
+        match interval {
+            RotationInterval::Never => String::new(),
+            RotationInterval::Hourly => time.format("%Y%m%d%H").to_string(),
+            RotationInterval::Daily => time.format("%Y%m%d").to_string(),
+        }
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        let now = Local::now();
+        let period = Self::period_of(self.policy.interval, &now);
+        let max_bytes = self.policy.max_size_mb * 1024 * 1024;
+        let oversized = max_bytes > 0 && self.written > 0 && self.written + line.len() as u64 > max_bytes;
+        if period != self.period || oversized {
+            self.rotate(&now)?;
+            self.period = period;
+        }
+        self.file.write_all(line)?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self, now: &DateTime<Local>) -> io::Result<()> {
+        let base = self.path.as_os_str().to_string_lossy().to_string();
+        let stamp = now.format("%Y%m%d-%H%M%S");
+        let mut rotated = PathBuf::from(format!("{}.{}", base, stamp));
+        let mut suffix = 1;
+        while rotated.exists() {
+            rotated = PathBuf::from(format!("{}.{}-{}", base, stamp, suffix));
+            suffix += 1;
+        }
+        fs::rename(&self.path, &rotated)?;
+        self.file = fs::OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.written = 0;
+        self.prune();
+        Ok(())
+    }
+
+    /// 只保留最新的 keep 个历史文件（文件名中的时间戳按字典序即时间顺序）
+    fn prune(&self) {
+        let Some(name) = self.path.file_name().and_then(|n| n.to_str()) else { return };
+        let prefix = format!("{}.", name);
+        let dir = match self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            Some(dir) => dir.to_path_buf(),
+            None => PathBuf::from("."),
+        };
+        let Ok(entries) = fs::read_dir(&dir) else { return };
+        let mut rotated: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with(&prefix)))
+            .collect();
+        rotated.sort();
+        let excess = rotated.len().saturating_sub(self.policy.keep as usize);
+        for path in rotated.drain(..excess) {
+            fs::remove_file(path).ok();
+        }
+    }
+}
+
+#[cfg(unix)]
+/// 从行首标签推断 syslog 优先级，返回 (优先级, 标签, 去掉标签后的正文)。无标签的行按 info 处理。
+fn classify_log_line(line: &str) -> (u8, &str, &str) {
+    let (tag, message) = line.split_once(' ').unwrap_or((line, ""));
+    let priority = match tag {
+        "FATAL" | "CORRUPT" => 2,    // crit
+        "ERR" | "ERROR" | "FAIL" => 3,
+        "WARN" => 4,
+        "HINT" | "STARTED" => 5,     // notice
+        "INFO" | "OK" | "FOUND" | "BACKUP" | "RUN" => 6,
+        "DEBUG" => 7,
+        _ if line.starts_with("thread '") => 2, // panic 信息
+        _ => return (6, "", line),
+    };
+    (priority, tag, message)
+}
+
+/// 本机 syslog / journald 的数据报连接
+#[cfg(unix)]
+struct SystemLog {
+    target: LogTarget,
+    path: &'static str,
+    socket: std::os::unix::net::UnixDatagram,
+}
+
+#[cfg(unix)]
+impl SystemLog {
+    const IDENTIFIER: &'static str = "cyber_lookup";
+    const FACILITY_DAEMON: u8 = 3;
+
+    fn connect(target: LogTarget) -> io::Result<Self> {
+        let path = match target {
+            LogTarget::Journald => "/run/systemd/journal/socket",
+            _ => "/dev/log",
+        };
+        let socket = std::os::unix::net::UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(SystemLog { target, path, socket })
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end();
+        if line.is_empty() {
+            return Ok(());
+        }
+        let (priority, tag, message) = classify_log_line(line);
+        let datagram = match self.target {
+            LogTarget::Journald => {
+                // 原生协议：每行 KEY=value；值含换行时用 KEY\n<u64 小端长度><值>\n
+                let mut datagram = Vec::new();
+                let mut field = |key: &str, value: &str| {
+                    if value.contains('\n') {
+                        datagram.extend_from_slice(key.as_bytes());
+                        datagram.push(b'\n');
+                        datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+                    } else {
+                        datagram.extend_from_slice(key.as_bytes());
+                        datagram.push(b'=');
+                    }
+                    datagram.extend_from_slice(value.as_bytes());
+                    datagram.push(b'\n');
+                };
+                field("MESSAGE", message);
+                field("PRIORITY", &priority.to_string());
+                field("SYSLOG_IDENTIFIER", Self::IDENTIFIER);
+                field("SYSLOG_FACILITY", &Self::FACILITY_DAEMON.to_string());
+                if !tag.is_empty() {
+                    field("CYBER_LOOKUP_TAG", tag);
+                }
+                datagram
+            }
+            _ => {
+                // RFC 3164: <PRI>时间 标识[PID]: 消息
+                let pri = Self::FACILITY_DAEMON * 8 + priority;
+                let text = if tag.is_empty() { message.to_string() } else { format!("{} {}", tag, message) };
+                format!("<{}>{} {}[{}]: {}", pri, Local::now().format("%b %e %H:%M:%S"), Self::IDENTIFIER, process::id(), text).into_bytes()
+            }
+        };
+        if self.socket.send(&datagram).is_err() {
+            // syslog 守护进程重启后旧连接失效，重连一次
+            *self = Self::connect(self.target)?;
+            self.socket.send(&datagram)?;
+        }
+        Ok(())
+    }
+}
+
+/// 重定向后服务输出的写入目标
+enum LogSink {
+    File(RotatingLog),
+    #[cfg(unix)]
+    System(SystemLog),
+}
+
+impl LogSink {
+    /// 按配置打开日志目标；未配置 (终端输出) 时返回 None
+    #[cfg(unix)]
+    fn open(config: &ServiceConfig) -> io::Result<Option<Self>> {
+        match config.log_target {
+            LogTarget::Auto if config.log_file.is_empty() => Ok(None),
+            LogTarget::Auto => Ok(Some(LogSink::File(RotatingLog::open(&config.log_file, config.log_rotation.clone())?))),
+            target => Ok(Some(LogSink::System(SystemLog::connect(target)?))),
+        }
+    }
+
+    #[cfg(unix)]
+    fn describe(&self) -> String {
+        match self {
+            LogSink::File(log) => log.path.display().to_string(),
+            LogSink::System(log) => format!("{:?} ({})", log.target, log.path).to_lowercase(),
+        }
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        match self {
+            LogSink::File(log) => log.write_line(line),
+            #[cfg(unix)]
+            LogSink::System(log) => log.write_line(line),
+        }
+    }
+}
+
+/// 逐行把 `reader` 的内容写入日志，直到 EOF。写入失败时输出到 stderr 并停止。
+fn copy_log_lines(sink: &Mutex<LogSink>, reader: impl io::Read) {
+    let mut reader = io::BufReader::new(reader);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match io::BufRead::read_until(&mut reader, b'\n', &mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {
+                if let Err(e) = sink.lock().unwrap().write_line(&line) {
+                    eprintln!("{} {}", "ERR".red(), tr!("写入日志失败: {}", "Failed to write log: {}", e));
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// 服务运行期间把进程的 stdout/stderr 转到日志文件或 syslog / journald，Drop 时恢复为终端。
+/// 通过管道实现：fd 1/2 指向管道写端，后台线程按行写入 LogSink。
+struct OutputRedirect {
+    #[cfg(unix)]
+    saved: [i32; 2],                 // 原 stdout / stderr 的副本
+    #[cfg(unix)]
+    writer: Option<std::thread::JoinHandle<()>>,
+}
+
+/// 配置了 log_file 或 log_target 时开始重定向；未配置或平台不支持时返回 None。
+#[cfg(unix)]
+fn redirect_output(config: &ServiceConfig) -> io::Result<Option<OutputRedirect>> {
+    let Some(sink) = LogSink::open(config)? else {
+        return Ok(None);
+    };
+    println!("{} {}", "INFO".yellow(), tr!("服务输出写入 {}，停止服务后恢复终端输出。", "Server output goes to {} until the server stops.", sink.describe()));
+    let log = Mutex::new(sink);
+    io::stdout().flush()?;
+    io::stderr().flush()?;
+    let mut fds = [0; 2];
+    // SAFETY: 只操作本进程的文件描述符；dup2 之后原 fd 1/2 的副本保存在 saved 中，Drop 时还原
+    let saved = unsafe {
+        if libc::pipe(fds.as_mut_ptr()) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let saved = [libc::dup(1), libc::dup(2)];
+        libc::dup2(fds[1], 1);
+        libc::dup2(fds[1], 2);
+        libc::close(fds[1]);
+        saved
+    };
+    // SAFETY: fds[0] 为刚创建的管道读端，所有权交给 File
+    let reader = unsafe { <fs::File as std::os::fd::FromRawFd>::from_raw_fd(fds[0]) };
+    let writer = std::thread::spawn(move || copy_log_lines(&log, reader));
+    // 日志文件中不写入颜色控制符
+    colored::control::set_override(false);
+    Ok(Some(OutputRedirect { saved, writer: Some(writer) }))
+}
+
+#[cfg(not(unix))]
+fn redirect_output(config: &ServiceConfig) -> io::Result<Option<OutputRedirect>> {
+    // Windows 服务由服务宿主把子进程输出写入轮转日志，控制台运行时保持终端输出
+    if !config.log_file.is_empty() && io::stdout().is_terminal() {
+        println!("{} {}", "WARN".yellow(), tr!("控制台运行时不写入 log_file，仅 Windows 服务模式使用。", "log_file is only used in Windows service mode; console output is unchanged."));
+    }
+    if config.log_target != LogTarget::Auto {
+        println!("{} {}", "WARN".yellow(), tr!("syslog / journald 仅支持 Unix 系统，输出保持不变。", "syslog / journald are only supported on Unix; output is unchanged."));
+    }
+    Ok(None)
+}
+
+impl Drop for OutputRedirect {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        {
+            let _ = io::stdout().flush();
+            let _ = io::stderr().flush();
+            // SAFETY: 还原 redirect_output 保存的 fd；管道写端的最后引用随之关闭，写入线程读到 EOF 后退出
+            unsafe {
+                libc::dup2(self.saved[0], 1);
+                libc::dup2(self.saved[1], 2);
+                libc::close(self.saved[0]);
+                libc::close(self.saved[1]);
+            }
+            if let Some(writer) = self.writer.take() {
+                let _ = writer.join();
+            }
+        }
+        configure_terminal();
+    }
+}
+
+// --- 终端检测 (NO_COLOR / 非 TTY) ---
+
+/// stdin 与 stdout 都连接终端时为 true；否则（管道、重定向、journald）不打印输入提示。
+static INTERACTIVE: AtomicBool = AtomicBool::new(true);
+
+/// 启动时检测终端环境：设置了 NO_COLOR，或 stdout/stderr 任一不是终端时关闭彩色输出
+/// （CLICOLOR_FORCE 可强制开启），避免在日志文件中留下 ANSI 转义序列。
+fn configure_terminal() {
+    let stdout_tty = io::stdout().is_terminal();
+    let stderr_tty = io::stderr().is_terminal();
+    let env_set = |name: &str| env::var_os(name).is_some_and(|v| !v.is_empty() && v != "0");
+    let color = env_set("CLICOLOR_FORCE") || (!env_set("NO_COLOR") && stdout_tty && stderr_tty);
+    colored::control::set_override(color);
+    INTERACTIVE.store(io::stdin().is_terminal() && stdout_tty, Ordering::Relaxed);
+}
+
+fn is_interactive() -> bool {
+    INTERACTIVE.load(Ordering::Relaxed)
+}
+
+/// 管理命令的输出模式：`--json` 或 db-manage 中的 `output json` 打开后，结果以单行 JSON 输出到 stdout。
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+fn json_output() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
+fn print_json(value: serde_json::Value) {
+    println!("{}", value);
+}
+
+/// 输出管理命令的数据库错误：JSON 模式下写到 stdout，否则按原样式写到 stderr。
+fn report_db_error(message: String, err: &SqlError) {
+    if json_output() {
+        print_json(serde_json::json!({ "status": "error", "message": message, "error": err.to_string() }));
+    } else {
+        eprintln!("{} {}: {}", "DB ERR".red(), message, err);
+    }
+}
+
+// --- 防御性输入辅助函数 (新增/强化) ---
+
+// --- 行编辑 (rustyline：历史、Ctrl+R 搜索、命令补全) ---
+
+/// 主管理菜单与 db-manage 的命令名，用于 Tab 补全。
+const MANAGE_COMMANDS: &[&str] = &["start", "config", "db-manage", "run", "tasks", "loglevel", "info", "exit"];
+const DB_COMMANDS: &[&str] = &[
+    "insert", "lookup", "delete", "delete-file", "count", "clear", "backup", "restore", "vacuum", "analyze",
+    "integrity", "check", "merge", "key-usage", "cache-clear", "output", "list", "update", "sql", "stats", "seed", "import", "export", "run",
+    "begin", "commit", "rollback", "undo", "back", "exit",
+];
+
+/// 只补全第一个词（命令名）；读取普通输入值时命令列表为空，不做补全。
+#[derive(Default)]
+struct CommandHelper {
+    commands: &'static [&'static str],
+}
+
+impl Completer for CommandHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &rustyline::Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let prefix = &line[..pos];
+        if prefix.contains(char::is_whitespace) {
+            return Ok((pos, Vec::new()));
+        }
+        let candidates = self.commands.iter()
+            .filter(|c| c.starts_with(prefix))
+            .map(|c| Pair { display: c.to_string(), replacement: c.to_string() })
+            .collect();
+        Ok((0, candidates))
+    }
+}
+
+impl Hinter for CommandHelper {
+    type Hint = String;
+}
+impl Highlighter for CommandHelper {}
+impl Validator for CommandHelper {}
+impl Helper for CommandHelper {}
+
+/// 全局共享的行编辑器，两个交互 shell 共用同一份历史。历史只保存在内存中，
+/// 且只记录命令名（不含参数），不记录 UID/手机号等输入值，避免把敏感数据写到磁盘。
+static LINE_EDITOR: Mutex<Option<Editor<CommandHelper, DefaultHistory>>> = Mutex::new(None);
+
+/// 读取一行输入并返回清理后的字符串，包括 I/O 错误处理。
+fn read_line(prompt: &str) -> Result<String, io::Error> {
+    read_with_editor(prompt, &[])
+}
+
+/// 读取一条 shell 命令：支持 Tab 补全 `commands` 中的命令名，并把命令名（不含参数）记入历史。
+fn read_command(prompt: &str, commands: &'static [&'static str]) -> Result<String, io::Error> {
+    read_with_editor(prompt, commands)
+}
+
+fn read_with_editor(prompt: &str, commands: &'static [&'static str]) -> Result<String, io::Error> {
+    if let Some(line) = next_script_line() {
+        return line;
+    }
+    if !is_interactive() {
+        return read_stdin_line(prompt);
+    }
+    let mut guard = LINE_EDITOR.lock().unwrap();
+    if guard.is_none() {
+        match Editor::new() {
+            Ok(mut editor) => {
+                editor.set_helper(Some(CommandHelper::default()));
+                *guard = Some(editor);
+            }
+            // 终端不支持行编辑时退回到普通读取
+            Err(_) => return read_stdin_line(prompt),
+        }
+    }
+    let editor = guard.as_mut().expect("line editor initialized above");
+    if let Some(helper) = editor.helper_mut() {
+        helper.commands = commands;
+    }
+    match editor.readline(&prompt.green().to_string()) {
+        Ok(line) => {
+            let line = line.trim().to_string();
+            // 参数可能是 UID / 手机号（如 `lookup <ID>`），历史只保留命令名
+            if let Some(command) = line.split_whitespace().next().filter(|_| !commands.is_empty()) {
+                let _ = editor.add_history_entry(command);
+            }
+            Ok(line)
+        }
+        // Ctrl+C 只取消当前输入
+        Err(ReadlineError::Interrupted) => Ok(String::new()),
+        Err(ReadlineError::Eof) => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "EOF")),
+        Err(ReadlineError::Io(e)) => Err(e),
+        Err(e) => Err(io::Error::other(e)),
+    }
+}
+
+/// 不经行编辑器直接从 stdin 读取一行（非交互环境或终端不支持时）。
+fn read_stdin_line(prompt: &str) -> Result<String, io::Error> {
+    if is_interactive() {
+        print!("{}", prompt.green());
+        io::stdout().flush()?;
+    }
+    let mut input = String::new();
+    // 防御性：处理 I/O 读取错误；EOF 作为错误返回，避免调用方在关闭的 stdin 上空转
+    match io::stdin().read_line(&mut input) {
+        Ok(0) => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "EOF")),
+        Ok(_) => Ok(input.trim().to_string()),
+        Err(e) => Err(e),
+    }
+}
+
+/// 读取一个可选的字符串输入，如果用户输入为空，则返回 None。
+fn read_optional_string(prompt: &str, current_value: &str) -> Result<Option<String>, io::Error> {
+    let input = read_line(&tr!("{} (当前: {}, 回车跳过): ", "{} (current: {}, Enter to skip): ", prompt, current_value))?;
+    if input.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(input))
+    }
+}
+
+/// 读取一个 U32 输入，并处理解析错误和边界条件（如不能为 0）。
+fn read_u32(prompt: &str, current_value: u32) -> Result<Option<u32>, String> {
+    // 使用 read_line 保证 I/O 错误已经被处理
+    let input = match read_line(&tr!("{} (当前: {}, 回车跳过): ", "{} (current: {}, Enter to skip): ", prompt, current_value)) {
+        Ok(s) => s,
+        Err(e) => return Err(tr!("读取输入失败: {}", "Failed to read input: {}", e)),
+    };
+
+    if input.is_empty() {
+        Ok(None)
+    } else {
+        match input.parse::<u32>() {
+            Ok(size) => {
+                // 防御性：检查是否为 0
+                if size == 0 {
+                    Err(tr!("输入值必须大于 0。", "Value must be greater than 0.").to_string())
+                } else {
+                    Ok(Some(size))
+                }
+            },
+            // 防御性：处理解析失败
+            Err(_) => Err(tr!("输入 '{}' 无效，请输入一个正整数。", "Invalid input '{}', please enter a positive integer.", input)),
+        }
+    }
+}
+
+// --- 应用状态结构体 / 配置管理 / 数据库初始化 (保持不变) ---
+// --- 布隆过滤器 ---
+
+/// 简单的布隆过滤器：`contains` 返回 false 时 ID 一定不存在；返回 true 时仍需查库确认。
+/// 不支持删除，删除记录后对应位保持置位，只会多一次查库而不会产生漏判。
+struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    /// 按预期元素数量和目标误判率计算位数组大小与哈希函数个数。
+    fn with_capacity(expected_items: u64, false_positive_rate: f64) -> Self {
+        let n = expected_items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = ((-n * false_positive_rate.ln()) / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / n) * ln2).round().clamp(1.0, 16.0) as u32;
+        BloomFilter {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+        }
+    }
+
+    /// 双重哈希：第 i 个位置为 h1 + i * h2。
+    fn positions(&self, item: &str) -> impl Iterator<Item = u64> + '_ {
+        let hash_with = |seed: u8| {
+            let mut hasher = DefaultHasher::new();
+            seed.hash(&mut hasher);
+            item.hash(&mut hasher);
+            hasher.finish()
+        };
+        let (h1, h2) = (hash_with(0), hash_with(1) | 1);
+        (0..self.num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits)
+    }
+
+    fn insert(&mut self, item: &str) {
+        let positions: Vec<u64> = self.positions(item).collect();
+        for pos in positions {
+            self.bits[(pos / 64) as usize] |= 1 << (pos % 64);
+        }
+    }
+
+    fn contains(&self, item: &str) -> bool {
+        self.positions(item).all(|pos| self.bits[(pos / 64) as usize] & (1 << (pos % 64)) != 0)
+    }
+
+    fn memory_bytes(&self) -> usize {
+        self.bits.len() * std::mem::size_of::<u64>()
+    }
+}
+
+/// 扫描全表构建布隆过滤器，uid 与手机号都会加入。
+fn build_bloom(conn: &Connection, false_positive_rate: f64) -> SqlResult<BloomFilter> {
+    let rows: i64 = conn.query_row("SELECT COUNT(*) FROM user_mapping", [], |row| row.get(0))?;
+    let capacity = (rows as u64 * 2).max(BLOOM_MIN_CAPACITY) * BLOOM_GROWTH_FACTOR;
+    let mut bloom = BloomFilter::with_capacity(capacity, false_positive_rate);
+
+    let mut stmt = conn.prepare("SELECT uid, phone_dec(phone_number) FROM user_mapping")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        bloom.insert(&row.get::<_, String>(0)?);
+        bloom.insert(&row.get::<_, String>(1)?);
+    }
+    Ok(bloom)
+}
+
+// --- 连接池 ---
+
+/// 从池中借出的连接，Drop 时自动归还。池被重置后借出的旧连接不会再放回。
+struct PooledConnection<'a> {
+    conn: Option<Connection>,
+    state: &'a AppState,
+    generation: u64,
+}
+
+impl Deref for PooledConnection<'_> {
+    type Target = Connection;
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().expect("pooled connection already released")
+    }
+}
+
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.state.release_connection(conn, self.generation);
+        }
+    }
+}
+
+// --- 数据库熔断 ---
+
+/// 熔断器状态。`open_until` 过期后进入半开状态：只放行一个试探请求，其结果决定关闭还是重新熔断，
+/// 试探完成前其余请求仍被拒绝。
+#[derive(Default)]
+struct CircuitBreaker {
+    failures: VecDeque<Instant>, // 窗口内的失败时间点
+    open_until: Option<Instant>,
+    probe_in_flight: bool,       // 半开状态下已放行的试探请求尚未完成
+    trips: u64,                  // 累计熔断次数
+}
+
+impl CircuitBreaker {
+    /// 熔断期间返回剩余冷却秒数（向上取整），否则放行。
+    fn check(&self, now: Instant) -> Result<(), u64> {
+        match self.open_until {
+            Some(until) if until > now => {
+                let remaining = until - now;
+                Err(remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0))
+            }
+            _ => Ok(()),
+        }
+    }
+    /// 决定是否放行一次数据库访问：返回 Ok(true) 表示这是半开状态下的试探请求；
+    /// 试探进行中时其余请求以 Err(1) 拒绝（1 秒后重试）。
+    fn admit(&mut self, now: Instant) -> Result<bool, u64> {
+        self.check(now)?;
+        if self.open_until.is_none() {
+            return Ok(false);
+        }
+        if self.probe_in_flight {
+            return Err(1);
+        }
+        self.probe_in_flight = true;
+        Ok(true)
+    }
+    fn record_success(&mut self) {
+        self.failures.clear();
+        self.open_until = None;
+        self.probe_in_flight = false;
+    }
+    /// 记录一次失败，返回是否因此触发熔断。
+    fn record_failure(&mut self, config: &CircuitBreakerConfig, now: Instant) -> bool {
+        let half_open = self.open_until.is_some_and(|until| until <= now);
+        let window = Duration::from_secs(config.window_secs);
+        while self.failures.front().is_some_and(|t| now.duration_since(*t) > window) {
+            self.failures.pop_front();
+        }
+        self.failures.push_back(now);
+        if half_open || self.failures.len() >= config.failure_threshold as usize {
+            self.failures.clear();
+            self.probe_in_flight = false;
+            self.open_until = Some(now + Duration::from_secs(config.cooldown_secs));
+            self.trips += 1;
+            return true;
+        }
+        false
+    }
+    fn status(&self, now: Instant) -> CircuitStatus {
+        let (state, retry_after_secs) = match (self.open_until, self.check(now)) {
+            (_, Err(secs)) => ("open", Some(secs)),
+            (Some(_), Ok(())) => ("half_open", None),
+            (None, Ok(())) => ("closed", None),
+        };
+        CircuitStatus { state: state.to_string(), recent_failures: self.failures.len(), trips: self.trips, retry_after_secs }
+    }
+}
+
+/// 半开状态下放行的试探请求。结果未被记录（如被中断或请求被取消）就被丢弃时释放试探名额，
+/// 让下一个请求重新试探。
+struct BreakerProbe<'a>(&'a AppState);
+
+impl Drop for BreakerProbe<'_> {
+    fn drop(&mut self) {
+        self.0.breaker.lock().unwrap().probe_in_flight = false;
+    }
+}
+
+// --- 可取消的数据库任务 ---
+
+/// 持有正在执行查询的连接的中断句柄。查询结束后句柄会被清除，因此归还到池中的连接不会被误中断。
+type InterruptSlot = Arc<Mutex<Option<InterruptHandle>>>;
+
+/// 若 future 在查询完成前被丢弃（如请求超时），Drop 时中断仍在执行的 SQLite 语句。
+struct InterruptOnDrop(InterruptSlot);
+
+impl Drop for InterruptOnDrop {
+    fn drop(&mut self) {
+        if let Some(handle) = self.0.lock().unwrap().take() {
+            handle.interrupt();
+        }
+    }
+}
+
+// --- 数据库工作线程 ---
+
+/// 任务主体。连接打开失败时收到错误，由任务自行回报给等待方。
+type DbTask = Box<dyn FnOnce(SqlResult<&Connection>) + Send>;
+
+/// 在工作线程上执行的数据库任务，`path` 为 None 时使用配置中的 db_path。
+struct DbJob {
+    path: Option<String>,
+    run: DbTask,
+}
+
+/// 启动 `count` 个数据库工作线程，每个线程持有自己的连接，连接池重置（配置变更、恢复备份）后在下一个任务前重新打开。
+/// 线程只持有 `AppState` 的弱引用：状态释放后任务队列随之关闭，线程退出。
+fn spawn_db_workers(state: &Arc<AppState>, count: usize) -> crossbeam_channel::Sender<DbJob> {
+    let (sender, receiver) = crossbeam_channel::unbounded::<DbJob>();
+    for index in 0..count.max(1) {
+        let (state, receiver) = (Arc::downgrade(state), receiver.clone());
+        std::thread::Builder::new()
+            .name(format!("db-worker-{}", index))
+            .spawn(move || db_worker_loop(state, receiver))
+            .expect("failed to spawn database worker thread");
+    }
+    sender
+}
+
+fn db_worker_loop(state: Weak<AppState>, jobs: crossbeam_channel::Receiver<DbJob>) {
+    // 按文件路径缓存连接（分片模式下一个线程会访问多个文件），连接池重置后全部重新打开
+    let mut conns: HashMap<String, Connection> = HashMap::new();
+    let mut opened_generation = 0;
+    while let Ok(job) = jobs.recv() {
+        let Some(state) = state.upgrade() else { break };
+        let generation = state.pool_generation.load(Ordering::SeqCst);
+        if generation != opened_generation {
+            conns.clear();
+            opened_generation = generation;
+        }
+        let path = job.path.unwrap_or_else(|| state.config.lock().unwrap().db_path.clone());
+        if !conns.contains_key(&path) {
+            match state.open_connection(&path) {
+                Ok(c) => {
+                    conns.insert(path.clone(), c);
+                }
+                Err(e) => {
+                    (job.run)(Err(e));
+                    continue;
+                }
+            }
+        }
+        drop(state);
+        let current = &conns[&path];
+        if let Err(panic) = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| (job.run)(Ok(current)))) {
+            let message = panic.downcast_ref::<&str>().map(|m| m.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            eprintln!("{} {}", "ERR".red(), tr!("数据库任务崩溃: {}", "Database task panicked: {}", message));
+            // 连接可能停留在未完成的事务中，丢弃后重新打开
+            conns.remove(&path);
+        }
+    }
+}
+
+/// 在数据库工作线程上对 db_path 执行 `op`，见 [`run_db_at`]。
+async fn run_db<T, F>(state: Arc<AppState>, op: F) -> Result<T, AppError>
+where
+    T: Send + 'static,
+    F: FnMut(&Connection) -> SqlResult<T> + Send + 'static,
+{
+    run_db_at(state, None, op).await
+}
+
+/// 在数据库工作线程上执行 `op`（含锁竞争重试），并等待结果。`path` 为 None 时使用配置中的 db_path。
+/// 调用方放弃等待时，查询会被中断而不是继续占用工作线程；尚未开始的任务会被直接跳过。
+/// 熔断打开时直接返回 `CircuitOpen`，不访问数据库。
+async fn run_db_at<T, F>(state: Arc<AppState>, path: Option<String>, mut op: F) -> Result<T, AppError>
+where
+    T: Send + 'static,
+    F: FnMut(&Connection) -> SqlResult<T> + Send + 'static,
+{
+    // 熔断器只针对主库：故障切换期间读取备用库的任务既不受其限制，也不计入失败次数
+    let guarded = path.is_none() || path != state.read_path();
+    let _probe = if guarded { state.breaker_check()? } else { None };
+    let slot: InterruptSlot = Arc::new(Mutex::new(None));
+    let _guard = InterruptOnDrop(slot.clone());
+    let retry = state.current_config().db_retry;
+    let deadline = current_deadline();
+    let (reply, response) = tokio::sync::oneshot::channel();
+    let run = Box::new(move |conn: SqlResult<&Connection>| {
+        if reply.is_closed() {
+            return;
+        }
+        let result = conn.and_then(|conn| {
+            // 排队期间已超过请求的截止时间则不再执行；执行中超时由进度回调中止语句
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                return Err(SqlError::SqliteFailure(rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_INTERRUPT), None));
+            }
+            if let Some(deadline) = deadline {
+                conn.progress_handler(DEADLINE_CHECK_OPS, Some(move || Instant::now() >= deadline));
+            }
+            *slot.lock().unwrap() = Some(conn.get_interrupt_handle());
+            let result = with_retry(&retry, || op(conn));
+            *slot.lock().unwrap() = None;
+            if deadline.is_some() {
+                conn.progress_handler(0, None::<fn() -> bool>);
+            }
+            result
+        });
+        // 先释放 `op` 捕获的资源（如导出回调持有的写入器），等待方收到结果后即可独占使用
+        drop(op);
+        let _ = reply.send(result);
+    });
+    state.db_workers().send(DbJob { path, run })
+        .map_err(|_| AppError::FatalError(tr!("数据库工作线程已退出", "Database worker threads have exited").to_string()))?;
+    let deadline_exceeded = || AppError::Timeout(tr!("数据库操作超过请求的截止时间 (X-Deadline-Ms)", "Database work exceeded the request deadline (X-Deadline-Ms)").to_string());
+    // 工作线程全忙时也不超过截止时间等待；放弃后排队中的任务会被跳过
+    let response = match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline.into(), response).await.map_err(|_| deadline_exceeded())?,
+        None => response.await,
+    };
+    let result = response
+        .map_err(|_| AppError::FatalError(tr!("数据库任务异常终止，详见服务日志", "Database task aborted, see the server log").to_string()))?;
+    if guarded {
+        state.breaker_record(&result);
+    }
+    match result {
+        Err(e) if e.sqlite_error_code() == Some(ErrorCode::OperationInterrupted) && deadline.is_some_and(|d| Instant::now() >= d) => Err(deadline_exceeded()),
+        result => Ok(result?),
+    }
+}
+
+/// 缓存条目：命中时保存 (uid, phone_number)，未命中时为 None（负缓存）。
+type CachedMapping = Option<(String, String)>;
+
+pub struct AppState {
+    config: Mutex<ServiceConfig>, 
+    server_running: AtomicBool,  // 本进程内 HTTP 服务是否正在监听
+    cache: Cache<String, CachedMapping>,
+    cache_generation: AtomicU64, // 每次缓存失效前递增；查询期间发生过失效时丢弃其结果，不写入缓存
+    bloom: RwLock<Option<BloomFilter>>, // None 表示尚未构建，此时所有查询都访问数据库
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    bloom_negatives: AtomicU64,  // 被布隆过滤器直接判定为不存在的查询数
+    bloom_building: AtomicBool,  // 布隆过滤器正在（重新）构建，/readyz 报告未就绪
+    pool: Mutex<Vec<Connection>>,
+    pool_generation: AtomicU64,  // 每次重置连接池时递增
+    breaker: Mutex<CircuitBreaker>,
+    store: OnceLock<Arc<dyn MappingStore>>, // 首次使用时按配置创建，或由 `with_store` 注入
+    db_workers: OnceLock<crossbeam_channel::Sender<DbJob>>, // 首次异步查询时启动
+    failover_active: AtomicBool, // 主库连续健康检查失败，查询已切换到备用库
+    recent_queries: Mutex<VecDeque<RecentQuery>>, // 最近的查询，供管理控制台展示
+    started_at: DateTime<Local>, // 进程启动时间 (/info)
+    started: Instant,            // 计算运行时长，不受系统时钟调整影响
+    responses_total: AtomicU64,  // 已完成的 HTTP 响应数，供告警计算错误率
+    responses_failed: AtomicU64, // 其中状态码为 5xx 的响应数
+    slow_queries: Arc<SlowQueryLog>, // 与包装存储后端的 TimedStore 共享
+    lookup_activity: Mutex<HashSet<String>>, // 上次写回后被查询命中的 uid（启用保留策略时）
+    query_counts: Mutex<HashMap<String, u64>>, // 上次写回后各 ID 被查到的次数（启用 cache.preload_top 时）
+    analytics: Mutex<AnalyticsBuffer>, // 上次写入 analytics_daily 后的按天增量（启用 analytics 时）
+    retention_report: Mutex<Option<RetentionReport>>, // 最近一次保留策略执行的报告
+    task_runs: Mutex<HashMap<String, TaskRun>>, // 定时任务名称 -> 最近一次执行状态
+    scripts: RwLock<Option<Arc<ScriptHooks>>>, // 已加载的 Rhai 脚本钩子，未启用时为 None
+    upstream_client: reqwest::Client,
+    upstream_cache: Cache<String, CachedMapping>, // 未写回时上游的查询结果
+    upstream_queries: AtomicU64, // 转发给上游的 ID 数
+    upstream_found: AtomicU64,   // 其中上游找到的数量
+    upstream_errors: AtomicU64,  // 上游请求失败的次数
+    shadow: ShadowCounters,
+    write_queue: OnceLock<tokio::sync::mpsc::Sender<QueuedWrite>>, // 首次经队列写入时创建
+    write_queue_stats: WriteQueueCounters,
+    access_trail: Mutex<Vec<AccessEntry>>, // 尚未写入文件的访问记录
+    jwt_keys: RwLock<JwtKeyCache>, // JWT 验签公钥 (PEM 文件与 JWKS)
+    response_signer: RwLock<Option<Arc<ResponseSigner>>>, // 已读取的响应签名私钥
+    seen_signatures: Mutex<HashMap<String, i64>>, // 重放窗口内已接受的请求签名 -> 其时间戳
+    ip_denied: AtomicU64,        // 被 ip_allowlist / ip_denylist 拒绝的请求数
+    bans: Mutex<BanTracker>,     // 自动封禁的违规计数与当前封禁
+    rate_limits: Mutex<RateLimiter>, // 各调用方当前窗口与当天的请求数
+    rate_limited: AtomicU64,     // 因限流或每日配额被拒绝的请求数
+    idempotency: Cache<String, IdempotencyEntry>, // 调用方 + Idempotency-Key -> 写请求的指纹与响应
+    conditional_writes: tokio::sync::Mutex<()>, // 串行化带 If-Match 的修改，使版本检查与写入之间不被其他条件修改插入
+    changes: tokio::sync::watch::Sender<u64>, // 每次写入后递增，通知 gRPC Watch 重新查询
+    delete_confirm_key: String,  // 本进程签发批量删除确认令牌的随机密钥，重启后旧令牌失效
+    listening: Mutex<ListenAddresses>, // 服务运行期间各监听的实际地址（端口为 0 时由系统分配）
+    tenants: OnceLock<BTreeMap<String, Arc<AppState>>>, // 租户名 -> 租户的状态（独立的存储、缓存与统计），首次启动服务时创建
+    key_usage: Arc<Mutex<HashMap<String, KeyUsage>>>, // API 密钥名称 -> 自进程启动以来的用量，与各租户共享
+    last_write: Mutex<Option<Instant>>, // 最近一次写入提交的时间，后台 WAL 检查点据此等待空闲
+    wal_checkpoints: Mutex<WalCheckpointStatus>, // 后台 WAL 检查点的执行情况 (/stats)
+    deny_list: RwLock<Option<Arc<HashSet<String>>>>, // 已加载的屏蔽名单，首次查询时读取，名单修改后清空
+}
+impl AppState {
+    pub fn new(config: ServiceConfig) -> Self {
+        let slow_queries = Arc::new(SlowQueryLog::new(config.slow_query_ms));
+        let cache = Cache::builder()
+            .max_capacity(config.cache.max_entries)
+            .time_to_live(Duration::from_secs(config.cache.ttl_secs))
+            .build();
+        let idempotency = Cache::builder()
+            .max_capacity(IDEMPOTENCY_MAX_ENTRIES)
+            .time_to_live(Duration::from_secs(config.idempotency_ttl_secs.max(1)))
+            .build();
+        let upstream_cache = Cache::builder()
+            .max_capacity(config.cache.max_entries)
+            .time_to_live(Duration::from_secs(config.upstream.cache_ttl_secs.max(1)))
+            .build();
+        AppState {
+            config: Mutex::new(config),
+            server_running: AtomicBool::new(false),
+            cache,
+            cache_generation: AtomicU64::new(0),
+            bloom: RwLock::new(None),
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
+            bloom_negatives: AtomicU64::new(0),
+            bloom_building: AtomicBool::new(false),
+            pool: Mutex::new(Vec::new()),
+            pool_generation: AtomicU64::new(0),
+            breaker: Mutex::new(CircuitBreaker::default()),
+            store: OnceLock::new(),
+            db_workers: OnceLock::new(),
+            failover_active: AtomicBool::new(false),
+            recent_queries: Mutex::new(VecDeque::with_capacity(RECENT_QUERY_LIMIT)),
+            started_at: Local::now(),
+            started: Instant::now(),
+            responses_total: AtomicU64::new(0),
+            responses_failed: AtomicU64::new(0),
+            slow_queries,
+            lookup_activity: Mutex::new(HashSet::new()),
+            query_counts: Mutex::new(HashMap::new()),
+            analytics: Mutex::new(AnalyticsBuffer::default()),
+            retention_report: Mutex::new(None),
+            task_runs: Mutex::new(HashMap::new()),
+            scripts: RwLock::new(None),
+            upstream_client: reqwest::Client::new(),
+            upstream_cache,
+            upstream_queries: AtomicU64::new(0),
+            upstream_found: AtomicU64::new(0),
+            upstream_errors: AtomicU64::new(0),
+            shadow: ShadowCounters::default(),
+            write_queue: OnceLock::new(),
+            write_queue_stats: WriteQueueCounters::default(),
+            access_trail: Mutex::new(Vec::new()),
+            jwt_keys: RwLock::new(JwtKeyCache::default()),
+            response_signer: RwLock::new(None),
+            seen_signatures: Mutex::new(HashMap::new()),
+            ip_denied: AtomicU64::new(0),
+            bans: Mutex::new(BanTracker::default()),
+            rate_limits: Mutex::new(RateLimiter::default()),
+            rate_limited: AtomicU64::new(0),
+            idempotency,
+            conditional_writes: tokio::sync::Mutex::new(()),
+            changes: tokio::sync::watch::Sender::new(0),
+            delete_confirm_key: rand::random::<[u8; 32]>().iter().map(|b| format!("{:02x}", b)).collect(),
+            listening: Mutex::new(ListenAddresses::default()),
+            tenants: OnceLock::new(),
+            key_usage: Arc::new(Mutex::new(HashMap::new())),
+            last_write: Mutex::new(None),
+            wal_checkpoints: Mutex::new(WalCheckpointStatus::default()),
+            deny_list: RwLock::new(None),
+        }
+    }
+    /// 签发批量删除确认令牌：`<过期时间戳>.<HMAC(过期时间戳, 请求指纹)>`
+    fn delete_confirmation_token(&self, fingerprint: &str, expires_at: i64) -> String {
+        format!("{}.{}", expires_at, hmac_hex(&self.delete_confirm_key, format!("{}\n{}", expires_at, fingerprint).as_bytes()))
+    }
+    /// 校验确认令牌：过期或与当前将被删除的记录不符（预览后数据已变化、或令牌属于其他请求）时拒绝
+    fn check_delete_confirmation(&self, token: &str, fingerprint: &str) -> Result<(), AppError> {
+        let expires_at = token.split_once('.').and_then(|(ts, _)| ts.parse::<i64>().ok());
+        let Some(expires_at) = expires_at.filter(|&ts| self.delete_confirmation_token(fingerprint, ts) == token) else {
+            return Err(AppError::PreconditionFailed(tr!(
+                "确认令牌无效：预览之后将被删除的记录已变化，或令牌不属于此请求，请重新预览。",
+                "Invalid confirmation token: the records to delete changed since the preview, or the token belongs to another request; preview again.").to_string()));
+        };
+        if chrono::Utc::now().timestamp() > expires_at {
+            return Err(AppError::PreconditionFailed(tr!("确认令牌已过期，请重新预览。", "The confirmation token has expired; preview again.").to_string()));
+        }
+        Ok(())
+    }
+    /// 使用给定的存储后端（如测试用的替身实现）代替 `storage.backend` 配置。
+    pub fn with_store(config: ServiceConfig, store: Arc<dyn MappingStore>) -> Self {
+        let state = Self::new(config);
+        let _ = state.store.set(Arc::new(TimedStore { inner: store, log: state.slow_queries.clone() }));
+        state
+    }
+    /// 当前的存储后端。首次调用时按 `storage` 配置创建（连接按需建立），之后修改该配置需重启才生效。
+    pub fn store(self: &Arc<Self>) -> Result<Arc<dyn MappingStore>, AppError> {
+        if let Some(store) = self.store.get() {
+            return Ok(store.clone());
+        }
+        let config = self.current_config();
+        let store: Arc<dyn MappingStore> = match config.storage_backend() {
+            StorageBackend::Sqlite if config.storage.shards > 1 => Arc::new(ShardedSqliteStore::new(Arc::downgrade(self), &config.db_path, config.storage.shards)),
+            StorageBackend::Sqlite => Arc::new(SqliteStore { state: Arc::downgrade(self) }),
+            StorageBackend::Postgres => Arc::new(PostgresStore::new(&config.storage.postgres_url, config.db_pool_size as usize)?),
+            StorageBackend::Memory => Arc::new(MemoryStore::new()),
+        };
+        let store: Arc<dyn MappingStore> = if config.redis.enabled {
+            Arc::new(RedisCachedStore::new(store, config.redis.clone())?)
+        } else {
+            store
+        };
+        let store = Arc::new(TimedStore { inner: store, log: self.slow_queries.clone() });
+        // 并发初始化时以先写入者为准
+        Ok(self.store.get_or_init(|| store).clone())
+    }
+    /// 记录一次查询（单条时带上 ID），只保留最近 RECENT_QUERY_LIMIT 条。
+    fn record_query(&self, id: Option<&str>, results: &[LookupResponse], started: Instant) {
+        let entry = RecentQuery {
+            time: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            client: CLIENT_IP.try_with(ToString::to_string).ok(),
+            key: CALLER.try_with(|caller| caller.key_name.clone()).ok().flatten(),
+            id: id.map(String::from),
+            ids: results.len(),
+            found: results.iter().filter(|r| r.uid.is_some()).count(),
+            elapsed_ms: started.elapsed().as_secs_f64() * 1000.0,
+        };
+        if let Some(key) = &entry.key {
+            let mut usage = self.key_usage.lock().unwrap();
+            let usage = usage.entry(key.clone()).or_default();
+            usage.lookups += 1;
+            usage.ids += entry.ids as u64;
+            usage.found += entry.found as u64;
+            usage.max_batch_size = usage.max_batch_size.max(entry.ids);
+        }
+        let mut recent = self.recent_queries.lock().unwrap();
+        if recent.len() >= RECENT_QUERY_LIMIT {
+            recent.pop_front();
+        }
+        recent.push_back(entry);
+        drop(recent);
+        let (tracks_lookups, counts_queries, trail, analytics) = {
+            let config = self.config.lock().unwrap();
+            (config.retention.tracks_lookups(), config.cache.preload_top > 0, config.access_trail.enabled, config.analytics.enabled.then_some(config.analytics.top_n))
+        };
+        if let Some(top_n) = analytics {
+            let mut buffer = self.analytics.lock().unwrap();
+            let day = buffer.days.entry(Local::now().format("%Y-%m-%d").to_string()).or_default();
+            day.lookups += 1;
+            day.ids += results.len() as u64;
+            day.found += results.iter().filter(|r| r.uid.is_some()).count() as u64;
+            day.not_found += results.iter().filter(|r| r.status == "not_found").count() as u64;
+            if top_n > 0 {
+                for result in results.iter().filter(|r| r.uid.is_some()) {
+                    if let Some(queried) = id.or(result.query.as_deref()) {
+                        *day.counts.entry(canonicalize_id(queried)).or_default() += 1;
+                    }
+                }
+            }
+        }
+        if tracks_lookups {
+            let mut activity = self.lookup_activity.lock().unwrap();
+            activity.extend(results.iter().filter_map(|r| r.uid.clone()));
+        }
+        if counts_queries {
+            // 只统计找到的 ID，避免无效输入挤占排名；批量查询以结果中的原始输入计
+            let mut counts = self.query_counts.lock().unwrap();
+            for result in results.iter().filter(|r| r.uid.is_some()) {
+                if let Some(queried) = id.or(result.query.as_deref()) {
+                    *counts.entry(canonicalize_id(queried)).or_default() += 1;
+                }
+            }
+        }
+        if trail {
+            let now = Local::now().timestamp();
+            let key = CALLER.try_with(|caller| caller.key_name.clone()).ok().flatten();
+            let client = CLIENT_IP.try_with(ToString::to_string).ok();
+            let request_id = REQUEST_ID.try_with(Clone::clone).ok();
+            let endpoint = ENDPOINT.try_with(Clone::clone).ok();
+            let mut pending = self.access_trail.lock().unwrap();
+            for result in results.iter().filter(|r| r.uid.is_some()) {
+                pending.push(AccessEntry {
+                    time: now,
+                    uid: result.uid.clone().unwrap_or_default(),
+                    phone_number: result.phone_number.clone(),
+                    api_key: key.clone(),
+                    client: client.clone(),
+                    request_id: request_id.clone(),
+                    endpoint: endpoint.clone(),
+                });
+            }
+        }
+    }
+    /// 记录持有 API 密钥的调用方的一次请求（所有接口，含被拒绝的请求）
+    fn record_key_request(&self, key: &str, status: StatusCode) {
+        let mut usage = self.key_usage.lock().unwrap();
+        let usage = usage.entry(key.to_string()).or_default();
+        usage.requests += 1;
+        if status.is_client_error() || status.is_server_error() {
+            usage.errors += 1;
+        }
+        usage.last_seen = Some(Local::now().to_rfc3339());
+    }
+    /// 某个 API 密钥的用量报告；密钥未配置且没有任何用量时为 None
+    fn key_usage_report(&self, config: &ServiceConfig, key: &str) -> Option<KeyUsageReport> {
+        let usage = self.key_usage.lock().unwrap().get(key).cloned();
+        if usage.is_none() && !config.api_keys.iter().any(|k| k.name == key) {
+            return None;
+        }
+        let usage = usage.unwrap_or_default();
+        let ratio = |a: u64, b: u64| if b == 0 { 0.0 } else { a as f64 / b as f64 };
+        Some(KeyUsageReport {
+            key: key.to_string(),
+            since: self.started_at.to_rfc3339(),
+            hit_rate: ratio(usage.found, usage.ids),
+            avg_batch_size: ratio(usage.ids, usage.lookups),
+            usage,
+        })
+    }
+    /// 故障切换期间查询应读取的备用库路径；未切换时为 None（读取 db_path）。
+    fn read_path(&self) -> Option<String> {
+        if !self.failover_active.load(Ordering::SeqCst) {
+            return None;
+        }
+        let path = self.config.lock().unwrap().failover.fallback_db_path.clone();
+        (!path.is_empty()).then_some(path)
+    }
+    /// 数据库工作线程的任务队列，首次调用时按 `db_pool_size` 启动线程。
+    fn db_workers(self: &Arc<Self>) -> &crossbeam_channel::Sender<DbJob> {
+        self.db_workers.get_or_init(|| spawn_db_workers(self, self.config.lock().unwrap().db_pool_size as usize))
+    }
+    /// 熔断打开时拒绝访问数据库；半开状态下只放行一个试探请求，返回其守卫。禁用熔断时总是放行。
+    fn breaker_check(&self) -> Result<Option<BreakerProbe<'_>>, AppError> {
+        if !self.config.lock().unwrap().circuit_breaker.enabled {
+            return Ok(None);
+        }
+        let probe = self.breaker.lock().unwrap().admit(Instant::now()).map_err(AppError::CircuitOpen)?;
+        Ok(probe.then_some(BreakerProbe(self)))
+    }
+    /// 根据数据库操作的结果更新熔断器。被主动中断的查询（请求超时）不计为失败。
+    fn breaker_record<T>(&self, result: &SqlResult<T>) {
+        let config = self.config.lock().unwrap().circuit_breaker.clone();
+        if !config.enabled {
+            return;
+        }
+        let mut breaker = self.breaker.lock().unwrap();
+        match result {
+            Ok(_) => breaker.record_success(),
+            Err(e) if e.sqlite_error_code() == Some(ErrorCode::OperationInterrupted) => {}
+            Err(e) => {
+                if breaker.record_failure(&config, Instant::now()) {
+                    eprintln!("{} {}", "WARN".yellow(), tr!("数据库连续出错，熔断 {} 秒: {}", "Repeated database errors, circuit open for {} s: {}", config.cooldown_secs, e));
+                }
+            }
+        }
+    }
+    fn breaker_status(&self) -> CircuitStatus {
+        self.breaker.lock().unwrap().status(Instant::now())
+    }
+    /// 布隆过滤器判定：返回 false 表示 ID 一定不存在。未启用或未构建时总是返回 true。
+    fn bloom_may_contain(&self, id: &str) -> bool {
+        if !self.config.lock().unwrap().bloom.enabled {
+            return true;
+        }
+        let may_contain = match &*self.bloom.read().unwrap() {
+            Some(bloom) => bloom.contains(id),
+            None => true,
+        };
+        if !may_contain {
+            self.bloom_negatives.fetch_add(1, Ordering::Relaxed);
+        }
+        may_contain
+    }
+    /// 新增记录后同步更新布隆过滤器；即使当前被禁用也要记录，避免重新启用后漏判。
+    fn bloom_insert(&self, ids: &[&str]) {
+        if let Some(bloom) = self.bloom.write().unwrap().as_mut() {
+            ids.iter().for_each(|id| bloom.insert(id));
+        }
+    }
+    /// 从 SQLite 数据库重新构建布隆过滤器（禁用或使用其他存储后端时清空）。
+    fn rebuild_bloom(&self) -> SqlResult<()> {
+        let (bloom_config, single_file, hashed) = {
+            let config = self.config.lock().unwrap();
+            let hashed = config.phone_encryption.enabled && config.phone_encryption.mode == PhoneProtection::Hash;
+            (config.bloom.clone(), config.single_sqlite_file(), hashed)
+        };
+        // 共享的外部存储可能被其他实例写入，本地过滤器无法同步，会把新记录误判为不存在；分片数据不在 db_path 中；
+        // 哈希模式下库中只有手机号的哈希，过滤器无法判断原始号码
+        if !bloom_config.enabled || !single_file || hashed {
+            *self.bloom.write().unwrap() = None;
+            return Ok(());
+        }
+        // 构建期间先置空，防止旧过滤器对新数据漏判
+        *self.bloom.write().unwrap() = None;
+        self.bloom_building.store(true, Ordering::SeqCst);
+        let bloom = self.with_db(|conn| build_bloom(conn, bloom_config.false_positive_rate));
+        self.bloom_building.store(false, Ordering::SeqCst);
+        *self.bloom.write().unwrap() = Some(bloom?);
+        Ok(())
+    }
+    fn cache_enabled(&self) -> bool {
+        self.config.lock().unwrap().cache.enabled
+    }
+    fn cache_get(&self, id: &str) -> Option<CachedMapping> {
+        if !self.cache_enabled() {
+            return None;
+        }
+        let cached = self.cache.get(id);
+        let counter = if cached.is_some() { &self.cache_hits } else { &self.cache_misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        cached
+    }
+    /// 清空整个查询缓存（不记为写入）
+    fn clear_cache(&self) {
+        self.cache_generation.fetch_add(1, Ordering::SeqCst);
+        self.cache.invalidate_all();
+    }
+    /// 清空查询缓存并重建布隆过滤器，用于数据被带外修改（如直接替换数据库文件）之后。
+    fn flush_cache(&self) -> SqlResult<u64> {
+        self.cache.run_pending_tasks();
+        let flushed = self.cache.entry_count();
+        self.clear_cache();
+        self.rebuild_bloom()?;
+        Ok(flushed)
+    }
+    fn stats(&self) -> StatsResponse {
+        let config = self.current_config();
+        self.cache.run_pending_tasks();
+        let hits = self.cache_hits.load(Ordering::Relaxed);
+        let misses = self.cache_misses.load(Ordering::Relaxed);
+        // 粗略估算：键值字符串长度 + 每条目固定开销
+        let cache_bytes: usize = self.cache.iter()
+            .map(|(key, value)| {
+                let value_len = value.as_ref().map(|(u, p)| u.len() + p.len()).unwrap_or(0);
+                key.len() + value_len + CACHE_ENTRY_OVERHEAD
+            })
+            .sum();
+        let bloom = self.bloom.read().unwrap();
+        StatsResponse {
+            cache: CacheStats {
+                enabled: config.cache.enabled,
+                entries: self.cache.entry_count(),
+                max_entries: config.cache.max_entries,
+                hits,
+                misses,
+                hit_rate: if hits + misses == 0 { 0.0 } else { hits as f64 / (hits + misses) as f64 },
+                estimated_memory_bytes: cache_bytes as u64,
+            },
+            bloom: BloomStats {
+                enabled: config.bloom.enabled,
+                built: bloom.is_some(),
+                memory_bytes: bloom.as_ref().map(|b| b.memory_bytes() as u64).unwrap_or(0),
+                negatives: self.bloom_negatives.load(Ordering::Relaxed),
+            },
+            slow_queries: SlowQueryStats {
+                threshold_ms: config.slow_query_ms,
+                total: self.slow_queries.total.load(Ordering::Relaxed),
+                recent: self.slow_queries.recent.lock().unwrap().iter().cloned().collect(),
+            },
+            ip_access: IpAccessStats {
+                allowlist_entries: config.ip_allowlist.len(),
+                denylist_entries: config.ip_denylist.len(),
+                denied: self.ip_denied.load(Ordering::Relaxed),
+            },
+            rate_limit: RateLimitStats {
+                enabled: config.rate_limit.enabled,
+                tracked: self.rate_limits.lock().unwrap().counters.len(),
+                rejected: self.rate_limited.load(Ordering::Relaxed),
+            },
+            upstream: UpstreamStats {
+                enabled: config.upstream.enabled,
+                queries: self.upstream_queries.load(Ordering::Relaxed),
+                found: self.upstream_found.load(Ordering::Relaxed),
+                errors: self.upstream_errors.load(Ordering::Relaxed),
+            },
+            shadow: ShadowStats {
+                enabled: config.shadow.enabled,
+                percent: config.shadow.percent,
+                mirrored: self.shadow.mirrored.load(Ordering::Relaxed),
+                matched: self.shadow.matched.load(Ordering::Relaxed),
+                diverged: self.shadow.diverged.load(Ordering::Relaxed),
+                errors: self.shadow.errors.load(Ordering::Relaxed),
+                dropped: self.shadow.dropped.load(Ordering::Relaxed),
+            },
+            write_queue: WriteQueueStats {
+                enabled: config.write_queue.enabled,
+                groups: self.write_queue_stats.groups.load(Ordering::Relaxed),
+                writes: self.write_queue_stats.writes.load(Ordering::Relaxed),
+                rows: self.write_queue_stats.rows.load(Ordering::Relaxed),
+                fallbacks: self.write_queue_stats.fallbacks.load(Ordering::Relaxed),
+            },
+            scheduled_tasks: self.task_statuses(&config),
+            wal: self.wal_stats(&config),
+        }
+    }
+    fn wal_stats(&self, config: &ServiceConfig) -> WalStats {
+        let enabled = config.storage_backend() == StorageBackend::Sqlite && config.pragmas.journal_mode.eq_ignore_ascii_case("wal");
+        let checkpoints = self.wal_checkpoints.lock().unwrap().clone();
+        WalStats {
+            enabled,
+            bytes: if enabled { sqlite_files(config).iter().map(|path| file_size(&format!("{}-wal", path))).sum() } else { 0 },
+            checkpoint_secs: config.pragmas.wal_checkpoint_secs,
+            checkpoints: checkpoints.runs,
+            failures: checkpoints.failures,
+            last_checkpoint: checkpoints.last_at,
+            last_result: checkpoints.last_result,
+        }
+    }
+    /// 最近 `idle` 时间内没有写入
+    fn write_idle(&self, idle: Duration) -> bool {
+        self.last_write.lock().unwrap().is_none_or(|at| at.elapsed() >= idle)
+    }
+    /// 按配置顺序列出定时任务及其最近一次执行状态
+    fn task_statuses(&self, config: &ServiceConfig) -> Vec<TaskStatus> {
+        let runs = self.task_runs.lock().unwrap();
+        config.scheduler.tasks.iter()
+            .map(|scheduled| {
+                let next_run = config.scheduler.enabled
+                    .then(|| parse_schedule(&scheduled.schedule).ok()?.find_next_occurrence(&Local::now(), false).ok())
+                    .flatten()
+                    .map(|t| t.to_rfc3339());
+                TaskStatus {
+                    name: scheduled.name.clone(),
+                    task: scheduled.task,
+                    schedule: scheduled.schedule.clone(),
+                    next_run,
+                    last_run: runs.get(&scheduled.name).cloned(),
+                }
+            })
+            .collect()
+    }
+    /// 当前的缓存代数，须在读取存储之前取得并传给 `cache_put`
+    fn cache_generation(&self) -> u64 {
+        self.cache_generation.load(Ordering::SeqCst)
+    }
+    /// 写入查询结果。`generation` 之后发生过失效时，结果可能早于那次写入，不予缓存。
+    fn cache_put(&self, id: &str, resp: &LookupResponse, generation: u64) {
+        if !self.cache_enabled() || self.cache_generation() != generation {
+            return;
+        }
+        self.cache.insert(id.to_string(), resp.cache_entry());
+        // 失效可能恰好发生在上面的检查与插入之间：失效先递增代数，这里插入后再核对一次
+        if self.cache_generation() != generation {
+            self.cache.invalidate(id);
+        }
+    }
+    /// 写操作提交后使相关缓存失效。`keys` 应在写入前用 `affected_keys` 收集；收集失败时整体清空以保证正确性。
+    fn invalidate_cache(&self, keys: Option<Vec<String>>) {
+        self.cache_generation.fetch_add(1, Ordering::SeqCst);
+        match keys {
+            Some(keys) => keys.iter().for_each(|k| self.cache.invalidate(k)),
+            None => self.cache.invalidate_all(),
+        }
+        *self.last_write.lock().unwrap() = Some(Instant::now());
+        self.changes.send_modify(|generation| *generation += 1);
+    }
+    /// 按当前配置打开一个新的数据库连接并应用 PRAGMA。
+    pub fn get_db_connection(&self) -> SqlResult<Connection> {
+        let path = self.config.lock().unwrap().db_path.clone();
+        self.open_connection(&path)
+    }
+    /// 按当前的 PRAGMA 配置打开指定数据库文件（如分片文件）。
+    fn open_connection(&self, path: &str) -> SqlResult<Connection> {
+        let (pragmas, encryption, key) = {
+            let config = self.config.lock().unwrap();
+            (config.pragmas.clone(), config.phone_encryption.clone(), config.database_key())
+        };
+        let conn = Connection::open(path)?;
+        apply_database_key(&conn, key.as_deref())?;
+        apply_pragmas(&conn, &pragmas)?;
+        let cipher = PhoneCipher::load(&encryption).map_err(|e| SqlError::UserFunctionError(e.into()))?;
+        register_phone_functions(&conn, cipher.map(Arc::new))?;
+        Ok(conn)
+    }
+    /// 从连接池借出一个连接，池空时新建。
+    fn pooled_connection(&self) -> SqlResult<PooledConnection<'_>> {
+        let generation = self.pool_generation.load(Ordering::SeqCst);
+        let idle = self.pool.lock().unwrap().pop();
+        let conn = match idle {
+            Some(conn) => conn,
+            None => self.get_db_connection()?,
+        };
+        Ok(PooledConnection { conn: Some(conn), state: self, generation })
+    }
+    fn release_connection(&self, conn: Connection, generation: u64) {
+        let max_idle = self.config.lock().unwrap().db_pool_size as usize;
+        let mut pool = self.pool.lock().unwrap();
+        if generation == self.pool_generation.load(Ordering::SeqCst) && pool.len() < max_idle {
+            pool.push(conn);
+        }
+    }
+    /// 丢弃所有空闲连接，已借出的连接归还时也会被丢弃。切换 db_path 或替换数据库文件后必须调用。
+    fn reset_pool(&self) {
+        self.pool_generation.fetch_add(1, Ordering::SeqCst);
+        self.pool.lock().unwrap().clear();
+    }
+    /// 借出池化连接并执行 `op`，遇到锁竞争时按配置的退避策略整体重试（含建立连接）。
+    fn with_db<T>(&self, mut op: impl FnMut(&Connection) -> SqlResult<T>) -> SqlResult<T> {
+        let retry = self.config.lock().unwrap().db_retry.clone();
+        with_retry(&retry, || self.pooled_connection().and_then(|conn| op(&conn)))
+    }
+    pub fn current_config(&self) -> ServiceConfig {
+        self.config.lock().unwrap().clone()
+    }
+    fn set_config(&self, new_config: ServiceConfig) {
+        set_language(new_config.language);
+        // 只在 log_level 被修改时覆盖运行时调整过的过滤规则
+        if new_config.log_level != self.config.lock().unwrap().log_level {
+            if let Ok(filter) = LogFilter::parse(&new_config.log_level) {
+                set_log_filter(filter);
+            }
+        }
+        self.slow_queries.threshold_ms.store(new_config.slow_query_ms, Ordering::Relaxed);
+        // 租户列表只在启动时读取，其余配置随主配置一同更新
+        if let Some(tenants) = self.tenants.get() {
+            for (name, tenant) in tenants {
+                if let Some(path) = new_config.tenants.databases.get(name) {
+                    tenant.set_config(new_config.tenant_config(path));
+                }
+            }
+        }
+        *self.config.lock().unwrap() = new_config;
+        // db_path 或 pragma 可能已变化，旧连接不可再复用
+        self.reset_pool();
+    }
+    /// 按 tenants 配置创建各租户的状态并初始化其数据库；已创建过时不做任何事
+    fn open_tenants(&self) -> Result<usize, AppError> {
+        if let Some(tenants) = self.tenants.get() {
+            return Ok(tenants.len());
+        }
+        let config = self.current_config();
+        let mut tenants = BTreeMap::new();
+        for (name, path) in &config.tenants.databases {
+            // 密钥用量按调用方统计，不区分租户
+            let tenant = AppState { key_usage: self.key_usage.clone(), ..AppState::new(config.tenant_config(path)) };
+            initialize_database(&tenant.get_db_connection()?)?;
+            if config.bloom.enabled {
+                if let Err(e) = tenant.rebuild_bloom() {
+                    eprintln!("{} {}", "WARN".yellow(), tr!("租户 {} 的布隆过滤器构建失败，将直接查询数据库: {}", "Bloom filter build failed for tenant {}, querying the database directly: {}", name, e));
+                }
+            }
+            tenants.insert(name.clone(), Arc::new(tenant));
+        }
+        let count = tenants.len();
+        let _ = self.tenants.set(tenants);
+        Ok(count)
+    }
+}
+pub fn load_config() -> Result<ServiceConfig, AppError> {
+    let path = FilePath::new(DEFAULT_CONFIG_FILE);
+    let config = if !path.exists() {
+        let default_config = ServiceConfig::default();
+        save_config(&default_config)?;
+        println!("{} {}", "INFO".yellow(), tr!("已创建配置文件: {}", "Config file created at: {}", DEFAULT_CONFIG_FILE));
+        default_config
+    } else {
+        let content = fs::read_to_string(path)?;
+        serde_json::from_str(&content).map_err(AppError::from)?
+    };
+
+    // 先切换语言，使校验错误以配置的语言输出
+    set_language(config.language);
+
+    // 防御性：加载后立即校验
+    if let Err(e) = config.validate() {
+        return Err(AppError::FatalError(tr!("配置校验失败: {}", "Config validation failed: {}", e)));
+    }
+    if let Ok(filter) = LogFilter::parse(&config.log_level) {
+        set_log_filter(filter);
+    }
+    
+    Ok(config)
+}
+fn save_config(config: &ServiceConfig) -> Result<(), AppError> {
+    // 防御性：写入前再次校验
+    if let Err(e) = config.validate() {
+        return Err(AppError::FatalError(tr!("配置校验失败，未保存: {}", "Config validation failed, not saved: {}", e)));
+    }
+
+    let content = serde_json::to_string_pretty(config).map_err(AppError::from)?;
+    // 内容没有变化时不改写文件，也不产生新的旧版本
+    if fs::read_to_string(DEFAULT_CONFIG_FILE).is_ok_and(|current| current == content) {
+        return Ok(());
+    }
+    // 先完整写入临时文件再改名替换，写到一半崩溃时原文件不受影响
+    let tmp = format!("{}.tmp", DEFAULT_CONFIG_FILE);
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    drop(file);
+    rotate_config_versions(config.config_versions)?;
+    fs::rename(&tmp, DEFAULT_CONFIG_FILE).map_err(AppError::from)
+}
+
+/// 第 n 份旧配置的文件名，1 为最近一份
+fn config_version_path(n: u32) -> String {
+    format!("{}.{}", DEFAULT_CONFIG_FILE, n)
+}
+
+/// 把当前的配置文件复制为 config.txt.1，已有的旧版本依次后移，超过 keep 份的删除
+fn rotate_config_versions(keep: u32) -> io::Result<()> {
+    if keep == 0 || !FilePath::new(DEFAULT_CONFIG_FILE).exists() {
+        return Ok(());
+    }
+    fs::remove_file(config_version_path(keep)).ok();
+    for n in (1..keep).rev() {
+        let from = config_version_path(n);
+        if FilePath::new(&from).exists() {
+            fs::rename(&from, config_version_path(n + 1))?;
+        }
+    }
+    fs::copy(DEFAULT_CONFIG_FILE, config_version_path(1))?;
+    Ok(())
+}
+
+/// `config rollback [N]`：恢复第 N 份旧配置（默认 1，即最近一次保存之前的配置）并保存。
+/// 被替换的配置同样作为旧版本保留，回滚本身也可以再回滚。返回恢复的版本号与配置。
+fn rollback_config(arg: &str) -> Result<(u32, ServiceConfig), String> {
+    let n = match arg.trim() {
+        "" => 1,
+        n => n.parse::<u32>().ok().filter(|n| *n > 0)
+            .ok_or_else(|| tr!("用法: config rollback [N]（N 从 1 开始，1 为最近一份旧配置）", "Usage: config rollback [N] (N starts at 1, the most recent previous config)").to_string())?,
+    };
+    let path = config_version_path(n);
+    let content = fs::read_to_string(&path).map_err(|e| tr!("无法读取旧配置 {}: {}", "Cannot read the previous config {}: {}", path, e))?;
+    let value: serde_json::Value = serde_json::from_str(&content).map_err(|e| tr!("{} 不是有效的 JSON: {}", "{} is not valid JSON: {}", path, e))?;
+    let config = parse_config_value(&value).map_err(|e| tr!("{} 未通过校验: {}", "{} failed validation: {}", path, e))?;
+    save_config(&config).map_err(|e| tr!("保存配置失败: {:?}", "Failed to save the configuration: {:?}", e))?;
+    Ok((n, config))
+}
+/// 为 SQLCipher 加密的数据库设置口令，必须是连接上的第一条语句。分片经 ATTACH 时沿用主库口令。
+/// 未以 sqlcipher 特性编译时不做任何事（配置了口令的情况已在 validate 中拒绝）。
+fn apply_database_key(conn: &Connection, key: Option<&str>) -> SqlResult<()> {
+    #[cfg(feature = "sqlcipher")]
+    if let Some(key) = key {
+        conn.pragma_update(None, "key", key)?;
+    }
+    #[cfg(not(feature = "sqlcipher"))]
+    let _ = (conn, key);
+    Ok(())
+}
+
+/// 在新建立的连接上应用配置的 PRAGMA（值已在 validate 中校验过）。
+fn apply_pragmas(conn: &Connection, pragmas: &PragmaConfig) -> SqlResult<()> {
+    conn.busy_timeout(Duration::from_millis(pragmas.busy_timeout_ms))?;
+    conn.pragma_update(None, "journal_mode", pragmas.journal_mode.to_lowercase())?;
+    conn.pragma_update(None, "synchronous", pragmas.synchronous.to_lowercase())?;
+    conn.pragma_update(None, "cache_size", pragmas.cache_size)?;
+    conn.pragma_update(None, "mmap_size", pragmas.mmap_size)?;
+    Ok(())
+}
+
+/// 是否为可重试的锁竞争错误 (SQLITE_BUSY / SQLITE_LOCKED)。
+fn is_busy_error(err: &SqlError) -> bool {
+    matches!(err, SqlError::SqliteFailure(e, _) if matches!(e.code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked))
+}
+
+/// 数据库暂时不可用（打不开、锁竞争重试耗尽）的错误，对外报告为 503 而不是 500。
+fn is_unavailable_error(err: &SqlError) -> bool {
+    is_busy_error(err) || matches!(err, SqlError::SqliteFailure(e, _) if e.code == ErrorCode::CannotOpen)
+}
+
+/// 以有界指数退避重试 `op`。只有锁竞争错误会重试，其余错误立即返回。
+/// 使用阻塞式 sleep，只能在 spawn_blocking 或 CLI 线程中调用。
+fn with_retry<T>(retry: &RetryConfig, mut op: impl FnMut() -> SqlResult<T>) -> SqlResult<T> {
+    let mut attempt = 1;
+    let mut backoff = retry.initial_backoff_ms;
+    loop {
+        match op() {
+            Err(e) if is_busy_error(&e) && attempt < retry.max_attempts => {
+                std::thread::sleep(Duration::from_millis(backoff));
+                backoff = (backoff * 2).min(retry.max_backoff_ms);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// 收集一次写操作会影响的缓存键：输入的 ID 本身，以及与之关联的现有记录的 uid 和手机号。
+/// 现有记录的手机号只存了哈希时无法得知其缓存键，返回 None（需清空整个缓存）。
+fn affected_keys(conn: &Connection, ids: &[&str]) -> SqlResult<Option<Vec<String>>> {
+    let mut keys: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+    let mut stmt = conn.prepare("SELECT uid, phone_dec(phone_number) FROM user_mapping WHERE uid = ?1 OR phone_number = phone_enc(?1)")?;
+    for id in ids {
+        let rows = stmt.query_map([id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+        for row in rows {
+            let (uid, phone) = row?;
+            if is_hashed_phone(&phone) {
+                return Ok(None);
+            }
+            keys.push(uid);
+            keys.push(phone);
+        }
+    }
+    Ok(Some(keys))
+}
+
+/// 读取 ID 列表文件：每行一个 uid 或手机号，忽略空行与 `#` 开头的注释行。
+fn read_id_file(path: &str) -> io::Result<Vec<String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+/// 列出尚未创建的表结构对象：user_mapping 表及其索引，分片文件还需有 uid_home 列。
+fn missing_schema_objects(conn: &Connection, sharded: bool) -> SqlResult<Vec<String>> {
+    let mut missing = Vec::new();
+    for name in ["user_mapping", "idx_uid", "idx_phone"] {
+        let exists: bool = conn.query_row("SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = ?1)", [name], |row| row.get(0))?;
+        if !exists {
+            missing.push(name.to_string());
+        }
+    }
+    if sharded && missing.is_empty() {
+        let migrated: bool = conn.query_row("SELECT EXISTS (SELECT 1 FROM pragma_table_info('user_mapping') WHERE name = 'uid_home')", [], |row| row.get(0))?;
+        if !migrated {
+            missing.push("user_mapping.uid_home".to_string());
+        }
+    }
+    Ok(missing)
+}
+
+/// 深度健康检查的写读删往返。每次使用随机键，并发的检查互不干扰；读不回时 query_row 返回错误。
+fn sqlite_round_trip(conn: &Connection) -> SqlResult<()> {
+    conn.execute("CREATE TABLE IF NOT EXISTS health_check (key TEXT PRIMARY KEY)", ())?;
+    let key = Uuid::new_v4().to_string();
+    conn.execute("INSERT INTO health_check (key) VALUES (?1)", [&key])?;
+    conn.query_row("SELECT 1 FROM health_check WHERE key = ?1", [&key], |_| Ok(()))?;
+    conn.execute("DELETE FROM health_check WHERE key = ?1", [&key])?;
+    Ok(())
+}
+
+fn schema_result(missing: Vec<String>) -> Result<(), AppError> {
+    if missing.is_empty() {
+        return Ok(());
+    }
+    Err(AppError::FatalError(tr!("表结构未就绪，缺少: {}", "Schema is not ready, missing: {}", missing.join(", "))))
+}
+
+/// 映射表的建表与索引语句，SQL 转储导出时原样写出
+const MAPPING_SCHEMA: [&str; 3] = [
+    "CREATE TABLE IF NOT EXISTS user_mapping (
+            uid TEXT NOT NULL,
+            phone_number TEXT NOT NULL,
+            UNIQUE(uid, phone_number)
+        )",
+    "CREATE UNIQUE INDEX IF NOT EXISTS idx_uid ON user_mapping (uid)",
+    "CREATE UNIQUE INDEX IF NOT EXISTS idx_phone ON user_mapping (phone_number)",
+];
+
+pub fn initialize_database(conn: &Connection) -> SqlResult<()> {
+    for statement in MAPPING_SCHEMA {
+        conn.execute(statement, ())?;
+    }
+    Ok(())
+}
+
+// --- 进度显示 ---
+
+/// 进度的计数单位
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ProgressUnit {
+    Rows,
+    Bytes,
+}
+
+/// 长时间任务的进度提示（速率与预计剩余时间），输出到 stderr：终端上原地刷新，否则每完成 10% 输出一行。
+/// `position / total` 决定百分比与 ETA，`count` 决定速率；二者单位可以不同，例如导入时按已读字节估算进度、按行数计算速率。
+/// `total` 为 0 表示总量未知（如没有 Content-Length 的下载），此时只显示数量与速率。
+struct Progress {
+    label: String,
+    unit: ProgressUnit,
+    total: u64,
+    position: u64,
+    count: u64,
+    started: Instant,
+    last_draw: Option<Instant>,
+    last_decile: u64,
+    tty: bool,
+}
+
+impl Progress {
+    fn new(label: &str, unit: ProgressUnit, total: u64) -> Self {
+        Progress {
+            label: label.to_string(),
+            unit,
+            total,
+            position: 0,
+            count: 0,
+            started: Instant::now(),
+            last_draw: None,
+            last_decile: 0,
+            tty: io::stderr().is_terminal(),
+        }
+    }
+    fn advance(&mut self, n: u64) {
+        self.set(self.position + n, self.count + n);
+    }
+    fn set(&mut self, position: u64, count: u64) {
+        self.position = position.min(self.total);
+        self.count = count;
+        let percent = (self.position * 100).checked_div(self.total).unwrap_or(100);
+        if self.tty {
+            // 限制刷新频率，逐行调用时也不会拖慢任务
+            let finished = self.total > 0 && self.position >= self.total;
+            if self.last_draw.is_some_and(|t| t.elapsed() < Duration::from_millis(PROGRESS_REDRAW_MS)) && !finished {
+                return;
+            }
+            self.last_draw = Some(Instant::now());
+            eprint!("\r{}   ", self.render(percent));
+            io::stderr().flush().ok();
+        } else if self.total > 0 && percent / 10 > self.last_decile {
+            self.last_decile = percent / 10;
+            eprintln!("{}", self.render(percent));
+        }
+    }
+    fn render(&self, percent: u64) -> String {
+        let elapsed = self.started.elapsed().as_secs_f64().max(0.001);
+        let rate = self.count as f64 / elapsed;
+        let (count, rate) = match self.unit {
+            ProgressUnit::Rows => (tr!("{} 行", "{} rows", self.count), tr!("{:.0} 行/秒", "{:.0} rows/s", rate)),
+            ProgressUnit::Bytes => (format_bytes(self.count), format!("{}/s", format_bytes(rate as u64))),
+        };
+        if self.total == 0 {
+            return format!("{} {} ({})", self.label, count, rate);
+        }
+        let eta = if self.position == 0 || self.position >= self.total {
+            "-".to_string()
+        } else {
+            format!("{:.0?}", Duration::from_secs_f64(elapsed * (self.total - self.position) as f64 / self.position as f64))
+        };
+        tr!("{} {:>3}% {} ({}, 剩余约 {})", "{} {:>3}% {} ({}, ETA {})", self.label, percent, count, rate, eta)
+    }
+    fn finish(&self) {
+        if self.tty && self.last_draw.is_some() {
+            eprintln!();
+        }
+    }
+}
+
+// --- 测试数据生成 (seed) ---
+
+/// 随机生成一对 10 位数字 UID 与 11 位大陆手机号（号段取自常见前缀，不对应真实用户）。
+fn random_mapping(rng: &mut impl rand::Rng) -> (String, String) {
+    let uid = rng.gen_range(1_000_000_000u64..10_000_000_000).to_string();
+    let prefix = SEED_PHONE_PREFIXES[rng.gen_range(0..SEED_PHONE_PREFIXES.len())];
+    let phone = format!("{}{:08}", prefix, rng.gen_range(0..100_000_000u32));
+    (uid, phone)
+}
+
+/// 以每批 `batch_size` 条的事务写入 `count` 条随机记录，与已有数据冲突的组合会重新生成。返回写入条数。
+fn seed_database(state: &AppState, count: u64, batch_size: u64) -> SqlResult<u64> {
+    let mut conn = state.get_db_connection()?;
+    initialize_database(&conn)?;
+    let retry = state.current_config().db_retry;
+    let mut rng = rand::thread_rng();
+    let mut progress = Progress::new(tr!("生成测试数据", "Seeding"), ProgressUnit::Rows, count);
+    let mut inserted = 0;
+    while inserted < count {
+        let target = batch_size.max(1).min(count - inserted);
+        let batch: Vec<(String, String)> = (0..target).map(|_| random_mapping(&mut rng)).collect();
+        let written = with_retry(&retry, || {
+            let tx = conn.transaction()?;
+            let mut written = 0;
+            {
+                let mut stmt = tx.prepare("INSERT OR IGNORE INTO user_mapping (uid, phone_number) VALUES (?1, phone_enc(?2))")?;
+                for (uid, phone) in &batch {
+                    written += stmt.execute([uid, phone])? as u64;
+                }
+            }
+            tx.commit()?;
+            Ok(written)
+        })?;
+        let ids: Vec<&str> = batch.iter().flat_map(|(uid, phone)| [uid.as_str(), phone.as_str()]).collect();
+        state.bloom_insert(&ids);
+        inserted += written;
+        progress.advance(written);
+    }
+    progress.finish();
+    // 新记录可能命中此前缓存的 not_found
+    state.clear_cache();
+    Ok(inserted)
+}
+
+// --- 导入 / 导出 (CSV、NDJSON、xlsx、SQL 转储) ---
+
+/// 导入 / 导出的数据格式。导入时默认按内容识别，导出时默认按文件扩展名选择；SQL 转储只能导出。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DataFormat {
+    Csv,         // uid,phone_number，表头可选
+    #[value(alias = "jsonl")]
+    Ndjson,      // 每行一个 {"uid": ..., "phone_number": ...}
+    Xlsx,        // 第一个工作表的前两列，表头可选
+    Sql,         // 可由 sqlite3 命令行直接执行的 SQL 转储
+}
+
+impl DataFormat {
+    fn parse(s: &str) -> Option<DataFormat> {
+        DataFormat::from_str(s.trim(), true).ok()
+    }
+    /// 按导出文件的扩展名（忽略 .gz 后缀）选择格式，无法识别时为 CSV
+    fn from_path(path: &str) -> DataFormat {
+        let lower = path.to_ascii_lowercase();
+        let name = lower.strip_suffix(".gz").unwrap_or(&lower);
+        match FilePath::new(name).extension().and_then(|e| e.to_str()) {
+            Some("ndjson" | "jsonl") => DataFormat::Ndjson,
+            Some("xlsx") => DataFormat::Xlsx,
+            Some("sql") => DataFormat::Sql,
+            _ => DataFormat::Csv,
+        }
+    }
+    /// 按导入内容的开头识别格式：zip 魔数为 xlsx，以 `{` 开头为 NDJSON，以 SQL 注释或语句开头为 SQL 转储（随后被拒绝），其余按 CSV 处理
+    fn sniff(head: &[u8]) -> DataFormat {
+        let head = head.strip_prefix(b"\xef\xbb\xbf".as_slice()).unwrap_or(head);
+        let text = head.trim_ascii_start();
+        let starts_with = |prefix: &str| text.get(..prefix.len()).is_some_and(|t| t.eq_ignore_ascii_case(prefix.as_bytes()));
+        if head.starts_with(b"PK\x03\x04") {
+            DataFormat::Xlsx
+        } else if text.starts_with(b"{") {
+            DataFormat::Ndjson
+        } else if ["--", "PRAGMA ", "BEGIN", "CREATE ", "INSERT "].into_iter().any(starts_with) {
+            DataFormat::Sql
+        } else {
+            DataFormat::Csv
+        }
+    }
+}
+
+/// 一次导入的结果统计
+#[derive(Debug, Default, Serialize)]
+struct ImportSummary {
+    rows: u64,      // 读取的数据行（不含表头）
+    invalid: u64,   // 列数不对、为空、过长或含控制字符的行
+    #[serde(flatten)]
+    writes: WriteSummary,
+}
+
+impl ImportSummary {
+    fn describe(&self) -> String {
+        tr!("读取 {} 行：新增 {}，覆盖 {}，跳过 {}，未变 {}，冲突 {}，无效 {}",
+            "{} rows read: {} inserted, {} replaced, {} skipped, {} unchanged, {} conflicts, {} invalid",
+            self.rows, self.writes.inserted, self.writes.replaced, self.writes.skipped, self.writes.unchanged, self.writes.conflicts, self.invalid)
+    }
+}
+
+/// 统计经过的原始字节数，用于在解压之前估算导入进度
+struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R: io::Read> io::Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+fn is_remote_source(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+/// 已打开的导入源
+struct ImportSource {
+    reader: Box<dyn io::Read>,
+    total_bytes: u64,          // 原始数据总字节数，未知时为 0
+    consumed: Arc<AtomicU64>,  // 已读的原始（解压前）字节数
+}
+
+/// 打开导入源：本地文件或 http(s) URL（边下载边导入，不落盘），gzip 内容按魔数自动解压。
+fn open_import_source(source: &str) -> Result<ImportSource, AppError> {
+    let (raw, total): (Box<dyn io::Read>, u64) = if is_remote_source(source) {
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(Duration::from_secs(REMOTE_CONNECT_TIMEOUT_SECS))
+            .timeout_read(Duration::from_secs(REMOTE_READ_TIMEOUT_SECS))
+            .build();
+        let response = agent.get(source).call()
+            .map_err(|e| AppError::IoError(io::Error::other(tr!("下载失败: {}", "Download failed: {}", e))))?;
+        let total = response.header("Content-Length").and_then(|v| v.parse().ok()).unwrap_or(0);
+        (Box::new(response.into_reader()), total)
+    } else {
+        let file = fs::File::open(source)?;
+        let total = file.metadata()?.len();
+        (Box::new(file), total)
+    };
+    let count = Arc::new(AtomicU64::new(0));
+    let mut reader = io::BufReader::new(CountingReader { inner: raw, count: count.clone() });
+    let is_gzip = io::BufRead::fill_buf(&mut reader)?.starts_with(&[0x1f, 0x8b]);
+    let reader: Box<dyn io::Read> = if is_gzip {
+        Box::new(flate2::bufread::MultiGzDecoder::new(reader))
+    } else {
+        Box::new(reader)
+    };
+    Ok(ImportSource { reader, total_bytes: total, consumed: count })
+}
+
+/// 导入字段是否合法：非空、不超过 MAX_DATA_LENGTH 且不含控制字符（与 `validate_ids` 一致）。
+fn valid_field(value: &str) -> bool {
+    !value.is_empty() && value.len() <= MAX_DATA_LENGTH && !value.chars().any(char::is_control)
+}
+
+/// 导入源中的一条记录：行号与字段；无法解析的行为错误描述
+type ImportRecord = (u64, Result<Vec<String>, String>);
+
+/// 首行首个字段为 uid（忽略大小写与 UTF-8 BOM）时视为表头
+fn is_import_header(line: u64, fields: &[String]) -> bool {
+    line == 1 && fields.first().is_some_and(|f| f.trim_start_matches('\u{feff}').eq_ignore_ascii_case("uid"))
+}
+
+/// 按格式逐条读取导入记录，`format` 为空时按内容识别。CSV 与 NDJSON 边读边解析；
+/// xlsx 是 zip 包，需要随机访问，整个读入内存后读取第一个工作表。
+fn import_records(input: Box<dyn io::Read>, format: Option<DataFormat>) -> Result<Box<dyn Iterator<Item = Result<ImportRecord, AppError>>>, AppError> {
+    let mut input = io::BufReader::new(input);
+    let format = match format {
+        Some(format) => format,
+        None => DataFormat::sniff(io::BufRead::fill_buf(&mut input)?),
+    };
+    Ok(match format {
+        DataFormat::Csv => {
+            let reader = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .flexible(true)
+                .trim(csv::Trim::All)
+                .comment(Some(b'#'))
+                .from_reader(input);
+            Box::new(reader.into_records().map(|record| match record {
+                Ok(record) => Ok((record.position().map(|p| p.line()).unwrap_or(0), Ok(record.iter().map(str::to_string).collect()))),
+                Err(e) if matches!(e.kind(), csv::ErrorKind::Io(_)) => Err(AppError::IoError(e.into())),
+                Err(e) => Ok((e.position().map(|p| p.line()).unwrap_or(0), Err(e.to_string()))),
+            }))
+        }
+        DataFormat::Ndjson => Box::new(io::BufRead::lines(input).enumerate()
+            .filter(|(_, line)| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+            .map(|(index, line)| Ok((index as u64 + 1, ndjson_fields(&line?))))),
+        DataFormat::Xlsx => {
+            use calamine::Reader;
+            let invalid = |e: &dyn std::fmt::Display| AppError::Rejected(ApiErrorCode::UnsupportedFormat, tr!("无法读取 xlsx 文件: {}", "Unable to read xlsx file: {}", e));
+            let mut data = Vec::new();
+            io::Read::read_to_end(&mut input, &mut data)?;
+            let mut workbook: calamine::Xlsx<_> = calamine::open_workbook_from_rs(io::Cursor::new(data)).map_err(|e| invalid(&e))?;
+            let range = workbook.worksheet_range_at(0)
+                .ok_or_else(|| invalid(&tr!("没有工作表", "no worksheet")))?
+                .map_err(|e| invalid(&e))?;
+            let first_row = range.start().map(|(row, _)| row as u64).unwrap_or(0);
+            let rows: Vec<_> = range.rows().enumerate().filter_map(|(index, cells)| {
+                let mut fields: Vec<String> = cells.iter().map(|cell| cell.to_string().trim().to_string()).collect();
+                // 区域按最宽的一行计算，去掉末尾的空单元格
+                while fields.last().is_some_and(String::is_empty) {
+                    fields.pop();
+                }
+                (!fields.is_empty()).then(|| Ok((first_row + index as u64 + 1, Ok(fields))))
+            }).collect();
+            Box::new(rows.into_iter())
+        }
+        DataFormat::Sql => return Err(AppError::Rejected(ApiErrorCode::UnsupportedFormat, tr!("SQL 转储只能导出，请用 sqlite3 执行。", "SQL dumps are export-only; load them with sqlite3.").to_string())),
+    })
+}
+
+/// NDJSON 的一行：含 uid 与 phone_number 的对象，值可为字符串或整数，其余字段忽略
+fn ndjson_fields(line: &str) -> Result<Vec<String>, String> {
+    let value: serde_json::Value = serde_json::from_str(line.trim_start_matches('\u{feff}')).map_err(|e| e.to_string())?;
+    let field = |name: &str| match value.get(name) {
+        Some(serde_json::Value::String(s)) => Some(s.trim().to_string()),
+        Some(serde_json::Value::Number(n)) if n.is_u64() || n.is_i64() => Some(n.to_string()),
+        _ => None,
+    };
+    match (field("uid"), field("phone_number")) {
+        (Some(uid), Some(phone)) => Ok(vec![uid, phone]),
+        _ => Err(tr!("应为含 uid 与 phone_number 字段的 JSON 对象", "expected a JSON object with uid and phone_number").to_string()),
+    }
+}
+
+/// 导入 uid 与手机号两列的数据（`source` 为文件路径或 URL，格式见 `DataFormat`，首行表头可选），每 IMPORT_CHUNK_SIZE 行一个事务，冲突按 `strategy` 处理。
+/// 无效行跳过并计数，不会中断导入；策略为 error 时遇到冲突即停止，冲突所在批次回滚，之前的批次已提交。
+/// `dry_run` 时整个导入包在一个最终回滚的事务中，冲突只计数不中止，统计结果与正式导入一致。
+fn import_mappings(state: &AppState, source: &str, format: Option<DataFormat>, strategy: ConflictStrategy, dry_run: bool) -> Result<ImportSummary, AppError> {
+    let ImportSource { reader: input, total_bytes, consumed } = open_import_source(source)?;
+    let records = import_records(input, format)?;
+    let mut conn = state.get_db_connection()?;
+    initialize_database(&conn)?;
+    let config = state.current_config();
+    let retry = config.db_retry;
+    if dry_run {
+        // 出错提前返回时连接被丢弃，未提交的事务随之回滚
+        conn.execute_batch("BEGIN")?;
+    }
+
+    let mut summary = ImportSummary::default();
+    let mut progress = Progress::new(tr!("导入", "Importing"), ProgressUnit::Rows, total_bytes);
+    let mut chunk: Vec<(u64, String, String)> = Vec::with_capacity(IMPORT_CHUNK_SIZE);
+    for record in records {
+        let (line, fields) = match record? {
+            (line, Ok(fields)) => (line, fields),
+            (line, Err(e)) => {
+                summary.rows += 1;
+                summary.invalid += 1;
+                if summary.invalid <= IMPORT_MAX_WARNINGS {
+                    eprintln!("{} {}", "WARN".yellow(), tr!("跳过无法解析的第 {} 行: {}", "Skipping unparsable line {}: {}", line, e));
+                }
+                continue;
+            }
+        };
+        if is_import_header(line, &fields) {
+            continue;
+        }
+        summary.rows += 1;
+        let mapping = match fields.as_slice() {
+            [uid, phone] if valid_field(uid) && valid_field(phone) => config.phone_rules.normalize_for_write(phone).ok().map(|phone| (uid.clone(), phone)),
+            _ => None,
+        };
+        match mapping {
+            Some((uid, phone)) => chunk.push((line, uid, phone)),
+            None => {
+                summary.invalid += 1;
+                if summary.invalid <= IMPORT_MAX_WARNINGS {
+                    eprintln!("{} {}", "WARN".yellow(), tr!("第 {} 行无效，已跳过: {:?}", "Line {} is invalid, skipped: {:?}", line, fields));
+                }
+            }
+        }
+        if chunk.len() >= IMPORT_CHUNK_SIZE {
+            let written = write_import_chunk(state, &mut conn, &retry, &chunk, strategy, dry_run);
+            if written.is_err() {
+                progress.finish();
+            }
+            summary.writes.merge(&written?);
+            chunk.clear();
+            progress.set(consumed.load(Ordering::Relaxed), summary.writes.total());
+        }
+    }
+    let written = if chunk.is_empty() { Ok(WriteSummary::default()) } else { write_import_chunk(state, &mut conn, &retry, &chunk, strategy, dry_run) };
+    if let Ok(w) = &written {
+        summary.writes.merge(w);
+        progress.set(total_bytes, summary.writes.total());
+    }
+    progress.finish();
+    written?;
+    if dry_run {
+        conn.execute_batch("ROLLBACK")?;
+        return Ok(summary);
+    }
+    if summary.invalid > IMPORT_MAX_WARNINGS {
+        eprintln!("{} {}", "WARN".yellow(), tr!("另有 {} 行无效，未逐条列出。", "{} more invalid rows not listed.", summary.invalid - IMPORT_MAX_WARNINGS));
+    }
+    // 覆盖写入可能改变已缓存的映射
+    state.clear_cache();
+    Ok(summary)
+}
+
+/// 在一个保存点中写入一批导入记录 (行号, uid, 手机号)，返回各类结果计数。
+/// 保存点在顶层等同于事务；dry-run 时嵌套在外层事务中，释放后仍会随外层一起回滚。
+fn write_import_chunk(state: &AppState, conn: &mut Connection, retry: &RetryConfig, chunk: &[(u64, String, String)], strategy: ConflictStrategy, dry_run: bool) -> Result<WriteSummary, AppError> {
+    let result = with_retry(retry, || {
+        let sp = conn.savepoint()?;
+        let mut summary = WriteSummary::default();
+        for (line, uid, phone) in chunk {
+            match write_mapping(&sp, uid, phone, strategy)? {
+                // 提前返回时保存点被丢弃，整批回滚
+                WriteOutcome::Conflict if !dry_run => return Ok(Err((*line, uid, phone))),
+                outcome => summary.record(outcome),
+            }
+        }
+        sp.commit()?;
+        Ok(Ok(summary))
+    })?;
+    match result {
+        Ok(summary) if dry_run => Ok(summary),
+        Ok(summary) => {
+            let ids: Vec<&str> = chunk.iter().flat_map(|(_, uid, phone)| [uid.as_str(), phone.as_str()]).collect();
+            state.bloom_insert(&ids);
+            Ok(summary)
+        }
+        Err((line, uid, phone)) => Err(AppError::Conflict(tr!("第 {} 行 ({}, {}) 与已有记录冲突，该批次已回滚。", "Line {} ({}, {}) conflicts with an existing record; its batch was rolled back.", line, uid, phone))),
+    }
+}
+
+/// 导出选项。表中尚无修改时间列，暂不支持按修改时间过滤。
+#[derive(Debug, Default)]
+struct ExportOptions {
+    uid_prefix: Option<String>,   // 只导出 uid 以此开头的记录
+    gzip: bool,                   // 以 gzip 压缩输出；文件名以 .gz 结尾时自动启用
+    format: Option<DataFormat>,   // 为空时按文件扩展名选择
+}
+
+/// 解析 db-manage 的 `export <文件> [--format 格式] [--uid-prefix 前缀] [--gzip]`。
+fn parse_export_args(arg: &str) -> Result<(String, ExportOptions), String> {
+    let usage = || tr!("用法: export <文件> [--format csv|ndjson|xlsx|sql] [--uid-prefix 前缀] [--gzip]", "Usage: export <file> [--format csv|ndjson|xlsx|sql] [--uid-prefix PREFIX] [--gzip]").to_string();
+    let mut path = None;
+    let mut options = ExportOptions::default();
+    let mut tokens = arg.split_whitespace();
+    while let Some(token) = tokens.next() {
+        match token {
+            "--gzip" => options.gzip = true,
+            "--uid-prefix" => options.uid_prefix = Some(tokens.next().ok_or_else(usage)?.to_string()),
+            "--format" => options.format = Some(tokens.next().and_then(DataFormat::parse).ok_or_else(usage)?),
+            _ if path.is_none() && !token.starts_with("--") => path = Some(token.to_string()),
+            _ => return Err(usage()),
+        }
+    }
+    path.map(|p| (p, options)).ok_or_else(usage)
+}
+
+/// 从 db-manage 的参数中取出 `--format <格式>`，返回其余参数与格式。
+fn take_format_arg(arg: &str) -> Result<(String, Option<DataFormat>), String> {
+    let Some((before, after)) = arg.split_once("--format") else {
+        return Ok((arg.to_string(), None));
+    };
+    let after = after.trim_start();
+    let (value, rest) = after.split_once(char::is_whitespace).unwrap_or((after, ""));
+    let format = DataFormat::parse(value)
+        .ok_or_else(|| tr!("未知格式 '{}' (可选 csv/ndjson/xlsx/sql)", "Unknown format '{}' (expected csv/ndjson/xlsx/sql)", value))?;
+    Ok((format!("{} {}", before.trim_end(), rest), Some(format)))
+}
+
+/// 导出目标文件，按需以 gzip 压缩
+enum ExportOutput {
+    Plain(io::BufWriter<fs::File>),
+    Gzip(flate2::write::GzEncoder<io::BufWriter<fs::File>>),
+}
+
+impl io::Write for ExportOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ExportOutput::Plain(w) => w.write(buf),
+            ExportOutput::Gzip(w) => w.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ExportOutput::Plain(w) => w.flush(),
+            ExportOutput::Gzip(w) => w.flush(),
+        }
+    }
+}
+
+impl ExportOutput {
+    fn create(path: &str, gzip: bool) -> io::Result<ExportOutput> {
+        let file = io::BufWriter::new(fs::File::create(path)?);
+        Ok(if gzip {
+            ExportOutput::Gzip(flate2::write::GzEncoder::new(file, flate2::Compression::default()))
+        } else {
+            ExportOutput::Plain(file)
+        })
+    }
+    /// 必须显式 finish 才会写入 gzip 尾部，drop 时的错误会被忽略
+    fn finish(self) -> io::Result<()> {
+        match self {
+            ExportOutput::Plain(mut w) => w.flush(),
+            ExportOutput::Gzip(w) => w.finish()?.flush(),
+        }
+    }
+}
+
+/// NDJSON 导出的一行
+#[derive(Serialize)]
+struct ExportRow<'a> {
+    uid: &'a str,
+    phone_number: &'a str,
+}
+
+/// 按格式写出记录的导出文件
+enum ExportWriter {
+    Csv(Box<csv::Writer<ExportOutput>>),
+    Ndjson(ExportOutput),
+    Sql(ExportOutput),
+    // 常量内存模式：已写完的行暂存在临时文件中，保存时才生成 xlsx
+    Xlsx { workbook: Box<rust_xlsxwriter::Workbook>, path: String, row: u32 },
+}
+
+impl ExportWriter {
+    fn create(path: &str, format: DataFormat, gzip: bool) -> Result<ExportWriter, AppError> {
+        Ok(match format {
+            // 首行快照注释只有一个字段，writer 需允许各行字段数不同
+            DataFormat::Csv => ExportWriter::Csv(Box::new(csv::WriterBuilder::new().flexible(true).from_writer(ExportOutput::create(path, gzip)?))),
+            DataFormat::Ndjson => ExportWriter::Ndjson(ExportOutput::create(path, gzip)?),
+            DataFormat::Sql => ExportWriter::Sql(ExportOutput::create(path, gzip)?),
+            DataFormat::Xlsx if gzip => return Err(AppError::Rejected(ApiErrorCode::UnsupportedFormat,
+                tr!("xlsx 文件本身已经压缩，不能再以 gzip 输出。", "xlsx files are already compressed and cannot be gzipped.").to_string())),
+            DataFormat::Xlsx => {
+                let mut workbook = rust_xlsxwriter::Workbook::new();
+                workbook.add_worksheet_with_constant_memory();
+                ExportWriter::Xlsx { workbook: Box::new(workbook), path: path.to_string(), row: 0 }
+            }
+        })
+    }
+
+    /// 在全部记录之前写出文件头：CSV 为快照注释与表头，SQL 为注释、建表语句与 BEGIN，xlsx 为表头；NDJSON 没有文件头。
+    fn begin(&mut self, snapshot_at: &str, total: u64) -> io::Result<()> {
+        match self {
+            ExportWriter::Csv(writer) => {
+                writer.write_record([format!("# snapshot_at={} rows={}", snapshot_at, total)])?;
+                writer.write_record(["uid", "phone_number"])?;
+            }
+            ExportWriter::Ndjson(_) => {}
+            ExportWriter::Sql(output) => {
+                writeln!(output, "-- cyber_lookup export snapshot_at={} rows={}", snapshot_at, total)?;
+                writeln!(output, "PRAGMA foreign_keys=OFF;")?;
+                writeln!(output, "BEGIN TRANSACTION;")?;
+                for statement in MAPPING_SCHEMA {
+                    writeln!(output, "{};", statement)?;
+                }
+            }
+            ExportWriter::Xlsx { .. } if total >= XLSX_MAX_ROWS => {
+                return Err(io::Error::other(tr!("{} 条记录超出 xlsx 工作表的行数上限 ({} 行)，请按 uid 前缀分批导出或改用其他格式。",
+                    "{} records exceed the xlsx worksheet limit of {} rows; export in parts by uid prefix or use another format.", total, XLSX_MAX_ROWS - 1)));
+            }
+            ExportWriter::Xlsx { .. } => self.row("uid", "phone_number")?,
+        }
+        Ok(())
+    }
+
+    fn row(&mut self, uid: &str, phone: &str) -> io::Result<()> {
+        match self {
+            ExportWriter::Csv(writer) => writer.write_record([uid, phone])?,
+            ExportWriter::Ndjson(output) => {
+                serde_json::to_writer(&mut *output, &ExportRow { uid, phone_number: phone })?;
+                output.write_all(b"\n")?;
+            }
+            ExportWriter::Sql(output) => writeln!(output, "INSERT INTO user_mapping (uid, phone_number) VALUES ('{}', '{}');", uid.replace('\'', "''"), phone.replace('\'', "''"))?,
+            ExportWriter::Xlsx { workbook, row, .. } => {
+                let sheet = workbook.worksheet_from_index(0).map_err(io::Error::other)?;
+                // 以文本写入，保留开头的 0 与超过 15 位的数字
+                sheet.write_string(*row, 0, uid).map_err(io::Error::other)?;
+                sheet.write_string(*row, 1, phone).map_err(io::Error::other)?;
+                *row += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), AppError> {
+        match self {
+            ExportWriter::Csv(writer) => writer.into_inner().map_err(|e| AppError::IoError(e.into_error()))?.finish()?,
+            ExportWriter::Ndjson(output) => output.finish()?,
+            ExportWriter::Sql(mut output) => {
+                writeln!(output, "COMMIT;")?;
+                output.finish()?
+            }
+            ExportWriter::Xlsx { mut workbook, path, .. } => workbook.save(&path).map_err(|e| AppError::IoError(io::Error::other(e)))?,
+        }
+        Ok(())
+    }
+}
+
+/// 按写入顺序导出记录，返回导出条数。格式取自 `options.format`，未指定时按文件扩展名选择。
+/// 记录经由存储后端读取，取自同一时间点的快照；CSV 第一行为注释 `# snapshot_at=<快照时间> rows=<条数>`（导入时忽略），之后是表头。
+/// SQL 转储中的手机号与其他格式一样是导出时的明文（或哈希）值，可用 `sqlite3 新库 < 转储文件` 重建映射表。
+fn export_mappings(state: &Arc<AppState>, path: &str, options: &ExportOptions) -> Result<u64, AppError> {
+    let format = options.format.unwrap_or_else(|| DataFormat::from_path(path));
+    let writer = ExportWriter::create(path, format, options.gzip || path.ends_with(".gz"))?;
+    let store = state.store()?;
+
+    // 回调在存储后端的工作线程中执行，writer 与进度通过共享状态传入，导出结束后取回
+    let shared = Arc::new(Mutex::new((writer, Progress::new(tr!("导出", "Exporting"), ProgressUnit::Rows, 0))));
+    let (hook_shared, sink_shared) = (shared.clone(), shared.clone());
+    let on_snapshot: SnapshotHook = Box::new(move |total| {
+        let mut guard = hook_shared.lock().unwrap();
+        guard.0.begin(&Local::now().to_rfc3339(), total)?;
+        guard.1.total = total;
+        Ok(())
+    });
+    let exported = block_on(store.export_snapshot(options.uid_prefix.clone(), on_snapshot, Box::new(move |uid, phone| {
+        let mut guard = sink_shared.lock().unwrap();
+        guard.0.row(uid, phone)?;
+        guard.1.advance(1);
+        Ok(())
+    })))?;
+    let (writer, progress) = Arc::try_unwrap(shared)
+        .map_err(|_| AppError::FatalError(tr!("导出回调仍被占用", "Export callback is still in use").to_string()))?
+        .into_inner().unwrap();
+    progress.finish();
+    writer.finish()?;
+    Ok(exported)
+}
+
+// --- 数据质量检查 (check) ---
+
+/// 手机号允许的位数（不含开头的 +），E.164 最长 15 位
+const CHECK_PHONE_DIGITS: std::ops::RangeInclusive<usize> = 5..=15;
+/// 摘要中展示的问题样例数
+const CHECK_SAMPLE_LIMIT: usize = 10;
+
+/// 数据质量问题的类别
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum IssueKind {
+    MalformedPhone,  // 手机号不是（可带 + 的）5~15 位数字；哈希模式下的值不检查
+    SuspiciousUid,   // uid 为空、首尾有空白或含控制字符：查询时 ID 会被修剪，这样的记录无法按 uid 命中
+    Oversized,       // 字段超过 MAX_DATA_LENGTH（绕过校验直接写入库文件的数据）
+    DuplicateUid,    // 修剪空白后与另一条记录的 uid 相同
+    DuplicatePhone,  // 修剪空白后与另一条记录的手机号相同
+    CrossMapping,    // 手机号与另一条记录的 uid 相同：查询按 uid 优先，按该号码只会命中另一条记录
+}
+
+impl IssueKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            IssueKind::MalformedPhone => "malformed_phone",
+            IssueKind::SuspiciousUid => "suspicious_uid",
+            IssueKind::Oversized => "oversized",
+            IssueKind::DuplicateUid => "duplicate_uid",
+            IssueKind::DuplicatePhone => "duplicate_phone",
+            IssueKind::CrossMapping => "cross_mapping",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct QualityIssue {
+    kind: IssueKind,
+    uid: String,
+    phone_number: String,
+    detail: String,
+}
+
+#[derive(Debug, Serialize)]
+struct QualityReport {
+    scanned: u64,
+    total_issues: u64,
+    by_kind: BTreeMap<IssueKind, u64>,
+    samples: Vec<QualityIssue>,
+    detail_file: Option<String>,
+}
+
+/// 扫描过程中的状态：逐行检查的问题，以及做重复与交叉检查用的 uid / 手机号索引（均为修剪后的值）
+#[derive(Default)]
+struct QualityScan {
+    scanned: u64,
+    issues: Vec<QualityIssue>,
+    uids: HashMap<String, String>,    // uid -> 原始 uid
+    phones: HashMap<String, String>,  // 手机号 -> 所属记录的 uid
+}
+
+impl QualityScan {
+    fn inspect(&mut self, uid: &str, phone: &str) {
+        self.scanned += 1;
+        let mut push = |kind, detail: String| self.issues.push(QualityIssue { kind, uid: uid.to_string(), phone_number: phone.to_string(), detail });
+        if uid.len() > MAX_DATA_LENGTH || phone.len() > MAX_DATA_LENGTH {
+            push(IssueKind::Oversized, tr!("uid {} 字节，手机号 {} 字节，上限 {}", "uid is {} bytes, phone is {} bytes, limit {}", uid.len(), phone.len(), MAX_DATA_LENGTH));
+        }
+        if uid.is_empty() || uid != uid.trim() || uid.chars().any(char::is_control) {
+            push(IssueKind::SuspiciousUid, tr!("uid 为空、首尾有空白或含控制字符", "uid is empty, padded with whitespace or contains control characters").to_string());
+        }
+        if !is_hashed_phone(phone) && !is_well_formed_phone(phone) {
+            push(IssueKind::MalformedPhone, tr!("手机号应为可带 + 的 {}~{} 位数字", "phone should be {}-{} digits with an optional +", CHECK_PHONE_DIGITS.start(), CHECK_PHONE_DIGITS.end()));
+        }
+        let canonical_uid = canonicalize_id(uid);
+        match self.uids.get(&canonical_uid) {
+            Some(other) => push(IssueKind::DuplicateUid, tr!("与 uid '{}' 的记录重复", "duplicates the record with uid '{}'", other)),
+            None => { self.uids.insert(canonical_uid.clone(), uid.to_string()); }
+        }
+        let canonical_phone = canonicalize_id(phone);
+        match self.phones.get(&canonical_phone) {
+            Some(other) => push(IssueKind::DuplicatePhone, tr!("与 uid '{}' 的记录手机号重复", "same phone as the record with uid '{}'", other)),
+            None => { self.phones.insert(canonical_phone, canonical_uid); }
+        }
+    }
+
+    /// 逐行检查结束后做交叉检查，返回全部问题
+    fn finish(mut self) -> (u64, Vec<QualityIssue>) {
+        let mut cross: Vec<QualityIssue> = self.phones.iter()
+            .filter(|(phone, owner)| *phone != *owner)
+            .filter_map(|(phone, owner)| self.uids.get(phone).map(|other| QualityIssue {
+                kind: IssueKind::CrossMapping,
+                uid: self.uids.get(owner).cloned().unwrap_or_else(|| owner.clone()),
+                phone_number: phone.clone(),
+                detail: tr!("手机号与 uid '{}' 的记录冲突，按此号码查询将命中后者", "phone equals the uid of record '{}'; looking it up returns that record instead", other),
+            }))
+            .collect();
+        cross.sort_by(|a, b| a.uid.cmp(&b.uid));
+        self.issues.extend(cross);
+        (self.scanned, self.issues)
+    }
+}
+
+fn is_well_formed_phone(phone: &str) -> bool {
+    let digits = phone.strip_prefix('+').unwrap_or(phone);
+    CHECK_PHONE_DIGITS.contains(&digits.len()) && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// 经由存储后端扫描全部记录，`detail` 非空时将每个问题以 NDJSON 写入该文件。
+fn run_quality_check(state: &Arc<AppState>, detail: Option<&str>) -> Result<QualityReport, AppError> {
+    let store = state.store()?;
+    let total = block_on(store.count(None))?;
+    let shared = Arc::new(Mutex::new((QualityScan::default(), Progress::new(tr!("检查", "Checking"), ProgressUnit::Rows, total))));
+    let sink_shared = shared.clone();
+    block_on(store.export(None, Box::new(move |uid, phone| {
+        let mut guard = sink_shared.lock().unwrap();
+        guard.0.inspect(uid, phone);
+        guard.1.advance(1);
+        Ok(())
+    })))?;
+    let (scan, progress) = Arc::try_unwrap(shared)
+        .map_err(|_| AppError::FatalError(tr!("检查回调仍被占用", "Check callback is still in use").to_string()))?
+        .into_inner().unwrap();
+    progress.finish();
+    let (scanned, issues) = scan.finish();
+
+    if let Some(path) = detail {
+        let mut writer = io::BufWriter::new(fs::File::create(path)?);
+        for issue in &issues {
+            serde_json::to_writer(&mut writer, issue).map_err(io::Error::from)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+    }
+    let mut by_kind = BTreeMap::new();
+    for issue in &issues {
+        *by_kind.entry(issue.kind).or_insert(0) += 1;
+    }
+    Ok(QualityReport {
+        scanned,
+        total_issues: issues.len() as u64,
+        by_kind,
+        samples: issues.into_iter().take(CHECK_SAMPLE_LIMIT).collect(),
+        detail_file: detail.map(String::from),
+    })
+}
+
+fn print_quality_report(report: &QualityReport) {
+    if report.total_issues == 0 {
+        println!("{} {}", "OK".green(), tr!("已检查 {} 条记录，未发现问题。", "Checked {} records, no issues found.", report.scanned));
+        return;
+    }
+    println!("{} {}", "WARN".yellow(), tr!("已检查 {} 条记录，发现 {} 个问题:", "Checked {} records, found {} issue(s):", report.scanned, report.total_issues));
+    let rows: Vec<Vec<String>> = report.by_kind.iter()
+        .map(|(kind, count)| vec![kind.as_str().to_string(), count.to_string()])
+        .collect();
+    print_table(&[tr!("类别", "kind"), tr!("数量", "count")], &rows);
+    println!("{} {}", "INFO".yellow(), tr!("样例 (最多 {} 条):", "Samples (at most {}):", CHECK_SAMPLE_LIMIT));
+    for issue in &report.samples {
+        println!("  - [{}] {} / {}: {}", issue.kind.as_str(), issue.uid, issue.phone_number, issue.detail);
+    }
+    match &report.detail_file {
+        Some(path) => println!("{} {}", "INFO".yellow(), tr!("完整明细已写入 {}", "Full details written to {}", path)),
+        None => println!("{} {}", "HINT".yellow(), tr!("可指定明细文件以 NDJSON 输出全部问题。", "Pass a detail file to write every issue as NDJSON.")),
+    }
+}
+
+// --- 重复记录合并 (merge) ---
+
+/// 合并涉及的一条记录
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MergeRecord {
+    pub uid: String,
+    pub phone_number: String,
+}
+
+/// 一组相互冲突的记录：修剪空白后 uid 或手机号相同（可经由组内其他记录传递）。
+/// 建议的规范记录取组内最早写入的记录并修剪空白，合并时组内全部记录替换为这一条。
+#[derive(Debug, Clone, Serialize)]
+pub struct MergeGroup {
+    pub records: Vec<MergeRecord>,   // 按写入顺序
+    pub canonical: MergeRecord,
+}
+
+/// 一次合并的结果
+#[derive(Debug, Default)]
+pub struct MergeReport {
+    pub groups: usize,
+    pub merged: usize,                       // 被替换的记录条数
+    pub affected_keys: Option<Vec<String>>,
+}
+
+/// GET / POST /admin/merge 与 db-manage 'merge' 的输出
+#[derive(Debug, Serialize)]
+struct MergePlan {
+    scanned: u64,
+    groups: Vec<MergeGroup>,
+    applied: bool,
+    merged: usize,
+}
+
+/// 扫描状态：全部记录与按修剪后的 uid / 手机号建立的索引，冲突的记录以并查集归为一组
+#[derive(Default)]
+struct MergeScan {
+    records: Vec<MergeRecord>,
+    parent: Vec<usize>,
+    uids: HashMap<String, usize>,
+    phones: HashMap<String, usize>,
+}
+
+impl MergeScan {
+    fn root(&mut self, mut index: usize) -> usize {
+        while self.parent[index] != index {
+            self.parent[index] = self.parent[self.parent[index]];
+            index = self.parent[index];
+        }
+        index
+    }
+
+    /// 合并两组，以较早写入的记录为根
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.root(a), self.root(b));
+        self.parent[a.max(b)] = a.min(b);
+    }
+
+    fn add(&mut self, uid: &str, phone: &str) {
+        let index = self.records.len();
+        self.records.push(MergeRecord { uid: uid.to_string(), phone_number: phone.to_string() });
+        self.parent.push(index);
+        match self.uids.get(&canonicalize_id(uid)).copied() {
+            Some(other) => self.union(index, other),
+            None => { self.uids.insert(canonicalize_id(uid), index); }
+        }
+        match self.phones.get(&canonicalize_id(phone)).copied() {
+            Some(other) => self.union(index, other),
+            None => { self.phones.insert(canonicalize_id(phone), index); }
+        }
+    }
+
+    /// 含两条及以上记录的组，按最早记录的写入顺序排列
+    fn finish(mut self) -> Vec<MergeGroup> {
+        let mut members: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for index in 0..self.records.len() {
+            let root = self.root(index);
+            if root != index || members.contains_key(&root) {
+                members.entry(root).or_default().push(index);
+            }
+        }
+        members.into_iter().map(|(root, rest)| {
+            let first = &self.records[root];
+            let canonical = MergeRecord { uid: canonicalize_id(&first.uid), phone_number: canonicalize_id(&first.phone_number) };
+            let records = std::iter::once(root).chain(rest).map(|i| self.records[i].clone()).collect();
+            MergeGroup { records, canonical }
+        }).collect()
+    }
+}
+
+/// 经由存储后端扫描全部记录，返回 (扫描条数, 建议的合并)。
+fn find_merge_groups(state: &Arc<AppState>) -> Result<(u64, Vec<MergeGroup>), AppError> {
+    let store = state.store()?;
+    let scan = Arc::new(Mutex::new(MergeScan::default()));
+    let sink_scan = scan.clone();
+    let scanned = block_on(store.export(None, Box::new(move |uid, phone| {
+        sink_scan.lock().unwrap().add(uid, phone);
+        Ok(())
+    })))?;
+    let scan = Arc::try_unwrap(scan)
+        .map_err(|_| AppError::FatalError(tr!("合并扫描回调仍被占用", "Merge scan callback is still in use").to_string()))?
+        .into_inner().unwrap();
+    Ok((scanned, scan.finish()))
+}
+
+/// 重新扫描并合并全部重复记录。写入后清理缓存并把规范记录加入布隆过滤器。
+fn apply_merge(state: &Arc<AppState>) -> Result<MergePlan, AppError> {
+    let (scanned, groups) = find_merge_groups(state)?;
+    if groups.is_empty() {
+        return Ok(MergePlan { scanned, groups, applied: false, merged: 0 });
+    }
+    let report = block_on(state.store()?.merge(groups.clone()))?;
+    state.invalidate_cache(report.affected_keys);
+    for group in &groups {
+        state.bloom_insert(&[&group.canonical.uid, &group.canonical.phone_number]);
+    }
+    log_debug("cyber_lookup::db", &format!("merged {} group(s), {} record(s)", report.groups, report.merged));
+    Ok(MergePlan { scanned, groups, applied: true, merged: report.merged })
+}
+
+/// 在调用方的事务中执行合并：逐组删除原记录、写入 merge_history 与规范记录。
+/// 原记录已不存在时返回内层错误，调用方不提交即整体回滚。
+fn merge_groups(conn: &Connection, groups: &[MergeGroup]) -> SqlResult<Result<usize, AppError>> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS merge_history (
+            merge_id TEXT NOT NULL,
+            merged_at TEXT NOT NULL,
+            canonical_uid TEXT NOT NULL,
+            canonical_phone TEXT NOT NULL,
+            uid TEXT NOT NULL,
+            phone_number TEXT NOT NULL
+        )",
+        (),
+    )?;
+    let merged_at = Local::now().to_rfc3339();
+    let mut remove = conn.prepare("DELETE FROM user_mapping WHERE uid = ?1 AND phone_number = phone_enc(?2)")?;
+    let mut history = conn.prepare("INSERT INTO merge_history (merge_id, merged_at, canonical_uid, canonical_phone, uid, phone_number) VALUES (?1, ?2, ?3, phone_enc(?4), ?5, phone_enc(?6))")?;
+    let mut insert = conn.prepare("INSERT INTO user_mapping (uid, phone_number) VALUES (?1, phone_enc(?2))")?;
+    let mut merged = 0;
+    for group in groups {
+        let merge_id = Uuid::new_v4().to_string();
+        for record in &group.records {
+            if remove.execute([&record.uid, &record.phone_number])? != 1 {
+                return Ok(Err(AppError::Conflict(tr!("记录 ({}, {}) 已被修改或删除，未合并任何记录，请重新扫描。", "Record ({}, {}) was changed or deleted; nothing was merged, scan again.", record.uid, record.phone_number))));
+            }
+            history.execute([&merge_id, &merged_at, &group.canonical.uid, &group.canonical.phone_number, &record.uid, &record.phone_number])?;
+            merged += 1;
+        }
+        insert.execute([&group.canonical.uid, &group.canonical.phone_number])?;
+    }
+    Ok(Ok(merged))
+}
+
+/// 合并后需要失效的缓存键：组内各记录与规范记录的 uid 和手机号
+fn merge_affected_keys(groups: &[MergeGroup]) -> Option<Vec<String>> {
+    let mut keys: Vec<String> = groups.iter()
+        .flat_map(|group| group.records.iter().chain(std::iter::once(&group.canonical)))
+        .flat_map(|record| [record.uid.clone(), record.phone_number.clone()])
+        .collect();
+    keys.sort();
+    keys.dedup();
+    Some(keys)
+}
+
+fn print_merge_plan(plan: &MergePlan) {
+    if plan.groups.is_empty() {
+        println!("{} {}", "OK".green(), tr!("已扫描 {} 条记录，没有需要合并的重复记录。", "Scanned {} records, no duplicates to merge.", plan.scanned));
+        return;
+    }
+    let rows: Vec<Vec<String>> = plan.groups.iter().flat_map(|group| {
+        let canonical = format!("{} / {}", group.canonical.uid, group.canonical.phone_number);
+        group.records.iter().enumerate().map(move |(i, record)| vec![
+            format!("{:?}", record.uid),
+            format!("{:?}", record.phone_number),
+            if i == 0 { canonical.clone() } else { String::new() },
+        ])
+    }).collect();
+    print_table(&[tr!("uid", "uid"), tr!("手机号", "phone"), tr!("合并为", "merge into")], &rows);
+    println!("{} {}", "WARN".yellow(), tr!("已扫描 {} 条记录，{} 组重复记录待合并。", "Scanned {} records, {} duplicate group(s) to merge.", plan.scanned, plan.groups.len()));
+}
+
+/// 列出建议的合并（不做修改）
+async fn api_admin_merge(State(state): State<Arc<AppState>>) -> Result<Json<MergePlan>, AppError> {
+    task::spawn_blocking(move || {
+        let (scanned, groups) = find_merge_groups(&state)?;
+        Ok(Json(MergePlan { scanned, groups, applied: false, merged: 0 }))
+    }).await.map_err(|e| AppError::FatalError(tr!("后台任务异常终止: {}", "Background task aborted: {}", e)))?
+}
+
+/// 重新扫描并在一个事务中合并全部重复记录
+async fn api_admin_merge_apply(State(state): State<Arc<AppState>>) -> Result<Json<MergePlan>, AppError> {
+    let plan = task::spawn_blocking(move || apply_merge(&state)).await
+        .map_err(|e| AppError::FatalError(tr!("后台任务异常终止: {}", "Background task aborted: {}", e)))??;
+    if plan.applied {
+        println!("{} {}", "INFO".yellow(), tr!("已通过 API 合并 {} 组重复记录 ({} 条)", "Merged {} duplicate group(s) via API ({} records)", plan.groups.len(), plan.merged));
+    }
+    Ok(Json(plan))
+}
+
+// --- 压测 (bench) ---
+
+/// 压测结果，延迟单位为毫秒
+#[derive(Serialize)]
+struct BenchReport {
+    target: String,
+    requests: u64,
+    errors: u64,
+    concurrency: u64,
+    batch: usize,
+    elapsed_ms: u64,
+    requests_per_sec: f64,
+    ids_per_sec: f64,
+    p50_ms: f64,
+    p95_ms: f64,
+    p99_ms: f64,
+    max_ms: f64,
+}
+
+/// 从库中随机抽取查询用的 ID（uid 与手机号各半），并混入少量不存在的 ID 以覆盖未命中路径。
+fn sample_bench_ids(conn: &Connection) -> SqlResult<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT uid, phone_dec(phone_number) FROM user_mapping ORDER BY RANDOM() LIMIT ?1")?;
+    let mut ids = Vec::new();
+    for (i, row) in stmt.query_map([BENCH_SAMPLE_SIZE], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?.enumerate() {
+        let (uid, phone) = row?;
+        ids.push(if i % 2 == 0 { uid } else { phone });
+        if i % 10 == 0 {
+            ids.push(format!("bench-miss-{}", i));
+        }
+    }
+    Ok(ids)
+}
+
+/// 已排序延迟序列的分位数（最近秩法）。
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    sorted[((p / 100.0) * (sorted.len() - 1) as f64).round() as usize]
+}
+
+/// 以 `concurrency` 个并发任务共发出 `requests` 个请求。`url` 为空时经连接池直接查询数据库，
+/// 不经过 HTTP、缓存与布隆过滤器，便于单独评估连接池与 pragma 配置。
+async fn run_bench(state: Arc<AppState>, requests: u64, concurrency: u64, batch: usize, url: Option<String>) -> Result<BenchReport, String> {
+    let ids = run_db(state.clone(), sample_bench_ids).await.map_err(|e| format!("{:?}", e))?;
+    if ids.is_empty() {
+        return Err(tr!("数据库为空，请先用 seed 生成测试数据。", "The database is empty; generate test data with seed first.").to_string());
+    }
+    let ids = Arc::new(ids);
+    let batch = batch.max(1);
+    let url = url.map(|u| u.trim_end_matches('/').to_string());
+    let client = reqwest::Client::new();
+    let store = state.store().map_err(|e| e.to_string())?;
+    let issued = Arc::new(AtomicU64::new(0));
+
+    let started = Instant::now();
+    let mut workers = Vec::new();
+    for _ in 0..concurrency.max(1) {
+        let (store, ids, url, client, issued) = (store.clone(), ids.clone(), url.clone(), client.clone(), issued.clone());
+        workers.push(tokio::spawn(async move {
+            let mut latencies = Vec::new();
+            let mut errors = 0u64;
+            loop {
+                let n = issued.fetch_add(1, Ordering::Relaxed);
+                if n >= requests {
+                    break;
+                }
+                let start = n as usize * batch;
+                let chunk: Vec<String> = (0..batch).map(|i| ids[(start + i) % ids.len()].clone()).collect();
+                let sent = Instant::now();
+                let ok = match (&url, batch) {
+                    // 404 是单条查询未命中时的正常响应
+                    (Some(url), 1) => client.get(format!("{}{}/lookup/{}", url, API_V1_PREFIX, chunk[0])).send().await
+                        .map(|r| r.status().is_success() || r.status() == StatusCode::NOT_FOUND)
+                        .unwrap_or(false),
+                    (Some(url), _) => client.post(format!("{}{}/batch_lookup", url, API_V1_PREFIX)).json(&serde_json::json!({ "ids": chunk })).send().await
+                        .map(|r| r.status().is_success())
+                        .unwrap_or(false),
+                    (None, 1) => store.lookup(&chunk[0]).await.is_ok(),
+                    (None, _) => store.batch_lookup(chunk).await.is_ok(),
+                };
+                latencies.push(sent.elapsed());
+                if !ok {
+                    errors += 1;
+                }
+            }
+            (latencies, errors)
+        }));
+    }
+
+    let mut latencies = Vec::with_capacity(requests as usize);
+    let mut errors = 0;
+    for worker in workers {
+        let (l, e) = worker.await.map_err(|e| e.to_string())?;
+        latencies.extend(l);
+        errors += e;
+    }
+    let elapsed = started.elapsed();
+    latencies.sort();
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    let secs = elapsed.as_secs_f64().max(0.001);
+    Ok(BenchReport {
+        target: url.unwrap_or_else(|| "db".to_string()),
+        requests: latencies.len() as u64,
+        errors,
+        concurrency,
+        batch,
+        elapsed_ms: elapsed.as_millis() as u64,
+        requests_per_sec: latencies.len() as f64 / secs,
+        ids_per_sec: (latencies.len() * batch) as f64 / secs,
+        p50_ms: ms(percentile(&latencies, 50.0)),
+        p95_ms: ms(percentile(&latencies, 95.0)),
+        p99_ms: ms(percentile(&latencies, 99.0)),
+        max_ms: ms(latencies.last().copied().unwrap_or_default()),
+    })
+}
+
+// --- 数据库备份与轮换 ---
+
+/// 使用 `VACUUM INTO` 生成一份一致性快照，文件名包含时间戳，返回备份文件路径。
+/// `show_progress` 时按目标文件相对源库的大小显示进度（VACUUM 本身不提供进度）。
+fn backup_database(conn: &Connection, dest_dir: &str, show_progress: bool) -> Result<PathBuf, AppError> {
+    fs::create_dir_all(dest_dir)?;
+    // 文件名精确到毫秒；同一毫秒内的多次备份追加 _1、_2 …（VACUUM INTO 拒绝覆盖已存在的文件），字典序仍为时间序
+    let stamp = Local::now().format("%Y%m%d-%H%M%S-%3f").to_string();
+    let mut dest = FilePath::new(dest_dir).join(format!("{}{}.db", BACKUP_FILE_PREFIX, stamp));
+    let mut attempt = 0;
+    while dest.exists() {
+        attempt += 1;
+        dest = FilePath::new(dest_dir).join(format!("{}{}_{}.db", BACKUP_FILE_PREFIX, stamp, attempt));
+    }
+    if !show_progress {
+        conn.execute("VACUUM INTO ?1", [dest.to_string_lossy()])?;
+        return Ok(dest);
+    }
+    let total: i64 = conn.query_row("SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()", [], |row| row.get(0))?;
+    let done = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            let mut progress = Progress::new(tr!("备份", "Backing up"), ProgressUnit::Bytes, total as u64);
+            while !done.load(Ordering::Relaxed) {
+                let size = fs::metadata(&dest).map(|m| m.len()).unwrap_or(0);
+                progress.set(size, size);
+                std::thread::sleep(Duration::from_millis(PROGRESS_REDRAW_MS));
+            }
+            progress.finish();
+        });
+        let result = conn.execute("VACUUM INTO ?1", [dest.to_string_lossy()]);
+        done.store(true, Ordering::Relaxed);
+        result
+    })?;
+    Ok(dest)
+}
+
+/// 列出目录中的备份文件，按时间从旧到新排序（文件名内含时间戳，字典序即时间序）。
+fn list_backups(dest_dir: &str) -> io::Result<Vec<PathBuf>> {
+    let mut backups: Vec<PathBuf> = fs::read_dir(dest_dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|n| n.to_str())
+                .map(|n| n.starts_with(BACKUP_FILE_PREFIX) && n.ends_with(".db"))
+                .unwrap_or(false)
+        })
+        .collect();
+    backups.sort();
+    Ok(backups)
+}
+
+/// 只保留最新的 `keep` 份备份，返回被删除的文件。
+fn prune_backups(dest_dir: &str, keep: usize) -> io::Result<Vec<PathBuf>> {
+    let mut backups = list_backups(dest_dir)?;
+    let excess = backups.len().saturating_sub(keep);
+    let removed: Vec<PathBuf> = backups.drain(..excess).collect();
+    for path in &removed {
+        fs::remove_file(path)?;
+    }
+    Ok(removed)
+}
+
+/// 执行一次完整的备份流程：快照 + 轮换。手动 `backup` 命令（显示进度）与定时任务共用。
+fn run_backup_once(state: &AppState, backup: &BackupConfig, show_progress: bool) -> Result<(PathBuf, Vec<PathBuf>), AppError> {
+    let conn = state.get_db_connection()?;
+    let dest = backup_database(&conn, &backup.dest_dir, show_progress)?;
+    let removed = prune_backups(&backup.dest_dir, backup.keep as usize)?;
+    Ok((dest, removed))
+}
+
+// --- 数据库维护 (vacuum / analyze / integrity_check) ---
+
+/// 将字节数格式化为便于阅读的单位。
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 { format!("{} {}", bytes, UNITS[0]) } else { format!("{:.2} {}", value, UNITS[unit]) }
+}
+
+/// 读取文件大小，文件不存在或不可访问时返回 0。
+fn file_size(path: &str) -> u64 {
+    fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+/// 执行 `PRAGMA integrity_check`，返回发现的问题列表（为空表示数据库完好）。
+fn check_integrity(conn: &Connection) -> SqlResult<Vec<String>> {
+    let mut stmt = conn.prepare(&format!("PRAGMA integrity_check({})", INTEGRITY_MAX_ERRORS))?;
+    let rows: Vec<String> = stmt.query_map([], |row| row.get(0))?.collect::<SqlResult<_>>()?;
+    Ok(rows.into_iter().filter(|r| r != "ok").collect())
+}
+
+/// db-manage 'stats' 展示的数据库概况
+#[derive(Serialize)]
+struct DbStats {
+    rows: i64,
+    distinct_uids: i64,
+    distinct_phones: i64,
+    db_bytes: u64,
+    wal_bytes: u64,
+    objects: Option<Vec<ObjectSize>>,    // dbstat 虚表不可用时为 None
+    last_backup: Option<String>,         // 最近一次备份的文件名与修改时间
+}
+
+/// 单个表或索引占用的空间
+#[derive(Serialize)]
+struct ObjectSize {
+    name: String,
+    bytes: u64,
+}
+
+/// 收集行数、去重计数、文件大小、表/索引大小以及最近备份时间。
+fn collect_db_stats(conn: &Connection, config: &ServiceConfig) -> SqlResult<DbStats> {
+    let (rows, distinct_uids, distinct_phones) = conn.query_row(
+        "SELECT COUNT(*), COUNT(DISTINCT uid), COUNT(DISTINCT phone_number) FROM user_mapping",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+    let objects = conn.prepare("SELECT name, SUM(pgsize) FROM dbstat GROUP BY name ORDER BY name")
+        .and_then(|mut stmt| stmt.query_map([], |row| Ok(ObjectSize { name: row.get(0)?, bytes: row.get::<_, i64>(1)? as u64 }))?.collect::<SqlResult<Vec<_>>>())
+        .ok();
+    let last_backup = list_backups(&config.backup.dest_dir).ok()
+        .and_then(|backups| backups.last().cloned())
+        .map(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            match fs::metadata(&path).and_then(|m| m.modified()) {
+                Ok(time) => format!("{} ({})", chrono::DateTime::<chrono::Local>::from(time).format("%Y-%m-%d %H:%M:%S"), name),
+                Err(_) => name,
+            }
+        });
+    Ok(DbStats {
+        rows,
+        distinct_uids,
+        distinct_phones,
+        db_bytes: file_size(&config.db_path),
+        wal_bytes: file_size(&format!("{}-wal", config.db_path)),
+        objects,
+        last_backup,
+    })
+}
+
+// --- 从备份恢复 ---
+
+/// 判断 HTTP 服务是否在运行：先看本进程标志，再探测绑定地址，以覆盖另一个进程中运行的实例。
+fn is_server_running(state: &AppState) -> bool {
+    if state.server_running.load(Ordering::SeqCst) {
+        return true;
+    }
+    let mut addr = match state.current_config().bind_address.parse::<SocketAddr>() {
+        Ok(a) => a,
+        Err(_) => return false,
+    };
+    // 监听 0.0.0.0 / :: 时改为探测本机回环地址
+    if addr.ip().is_unspecified() {
+        match addr {
+            SocketAddr::V4(_) => addr.set_ip(Ipv4Addr::LOCALHOST.into()),
+            SocketAddr::V6(_) => addr.set_ip(Ipv6Addr::LOCALHOST.into()),
+        }
+    }
+    TcpStream::connect_timeout(&addr, Duration::from_millis(SERVER_PROBE_TIMEOUT_MS)).is_ok()
+}
+
+/// 解析用户给出的备份路径：优先按原路径查找，找不到时再到备份目录中查找同名文件。
+fn resolve_backup_path(input: &str, dest_dir: &str) -> PathBuf {
+    let direct = PathBuf::from(input);
+    if direct.exists() {
+        return direct;
+    }
+    FilePath::new(dest_dir).join(input)
+}
+
+/// 校验备份文件：必须是可打开的 SQLite 数据库、通过 quick_check，并包含预期的 user_mapping 表结构。
+/// 返回备份中的记录数。SQLCipher 加密库的备份 (VACUUM INTO) 沿用同一口令，需传入 `key`。
+fn verify_backup(path: &FilePath, key: Option<&str>) -> Result<i64, AppError> {
+    if !path.is_file() {
+        return Err(AppError::FatalError(tr!("备份文件不存在: {}", "Backup file does not exist: {}", path.display())));
+    }
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    apply_database_key(&conn, key)?;
+
+    let check: String = conn.query_row("PRAGMA quick_check", [], |row| row.get(0))?;
+    if check != "ok" {
+        return Err(AppError::FatalError(tr!("备份文件完整性检查失败: {}", "Backup integrity check failed: {}", check)));
+    }
+
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info('user_mapping')")?;
+    let columns: Vec<String> = stmt.query_map([], |row| row.get(0))?.collect::<SqlResult<_>>()?;
+    for required in ["uid", "phone_number"] {
+        if !columns.iter().any(|c| c == required) {
+            return Err(AppError::FatalError(tr!("备份文件缺少 user_mapping.{} 列，不是有效的映射库备份。", "Backup is missing column user_mapping.{} and is not a valid mapping database.", required)));
+        }
+    }
+
+    let count = conn.query_row("SELECT COUNT(*) FROM user_mapping", [], |row| row.get(0))?;
+    Ok(count)
+}
+
+/// 用备份替换当前数据库。替换前会校验备份并为当前库留一份安全副本，返回该副本路径（当前库不存在时为 None）。
+fn restore_database(state: &AppState, backup_path: &FilePath) -> Result<Option<PathBuf>, AppError> {
+    if is_server_running(state) {
+        return Err(AppError::FatalError(tr!("HTTP 服务正在运行，拒绝恢复。请先停止服务。", "The HTTP server is running; refusing to restore. Stop the server first.").to_string()));
+    }
+    verify_backup(backup_path, state.current_config().database_key().as_deref())?;
+
+    let db_path = state.current_config().db_path;
+    let db_file = FilePath::new(&db_path);
+
+    // 安全副本：连同 WAL 一起复制，保证副本本身可用
+    let safety_copy = if db_file.exists() {
+        let copy = PathBuf::from(format!("{}.pre-restore-{}", db_path, Local::now().format("%Y%m%d-%H%M%S")));
+        fs::copy(db_file, &copy)?;
+        let wal = PathBuf::from(format!("{}-wal", db_path));
+        if wal.exists() {
+            fs::copy(&wal, format!("{}-wal", copy.display()))?;
+        }
+        Some(copy)
+    } else {
+        None
+    };
+
+    // 先复制到临时文件再原子替换，避免复制中途失败留下半个数据库
+    let tmp = PathBuf::from(format!("{}.restore-tmp", db_path));
+    fs::copy(backup_path, &tmp)?;
+    fs::rename(&tmp, db_file)?;
+
+    // 旧库遗留的 WAL/SHM 不属于新文件，必须清除
+    for suffix in ["-wal", "-shm"] {
+        let stale = PathBuf::from(format!("{}{}", db_path, suffix));
+        if stale.exists() {
+            fs::remove_file(stale)?;
+        }
+    }
+    state.reset_pool();
+    state.clear_cache();
+    if let Err(e) = state.rebuild_bloom() {
+        eprintln!("{} {}", "WARN".yellow(), tr!("恢复后重建布隆过滤器失败，已禁用过滤: {}", "Failed to rebuild the bloom filter after restore, filtering disabled: {}", e));
+    }
+    Ok(safety_copy)
+}
+
+/// 后台定时备份任务：每轮重新读取配置，因此通过 `config` 修改后无需重启即可生效。
+async fn run_backup_scheduler(state: Arc<AppState>) {
+    loop {
+        let backup = state.current_config().backup;
+        if !backup.enabled {
+            sleep(Duration::from_secs(BACKUP_POLL_SECS)).await;
+            continue;
+        }
+        sleep(Duration::from_secs(backup.interval_secs)).await;
+
+        let task_state = state.clone();
+        let result = task::spawn_blocking(move || run_backup_once(&task_state, &backup, false)).await;
+        match result {
+            Ok(Ok((dest, removed))) => {
+                println!("{} {}", "BACKUP".cyan(), tr!("自动备份完成: {}", "Scheduled backup complete: {}", dest.display()));
+                for path in removed {
+                    println!("{} {}", "BACKUP".cyan(), tr!("已清理旧备份: {}", "Pruned old backup: {}", path.display()));
+                }
+            }
+            Ok(Err(e)) => eprintln!("{} {}", "ERR".red(), tr!("自动备份失败: {:?}", "Scheduled backup failed: {:?}", e)),
+            Err(e) => eprintln!("{} {}", "ERR".red(), tr!("自动备份任务异常: {}", "Scheduled backup task panicked: {}", e)),
+        }
+    }
+}
+
+// --- 数据保留策略 ---
+
+/// 一次保留策略执行的报告
+#[derive(Debug, Clone, Serialize)]
+pub struct RetentionReport {
+    time: String,
+    dry_run: bool,
+    rules: Vec<RetentionRuleReport>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct RetentionRuleReport {
+    target: RetentionTarget,
+    max_age_days: u32,
+    removed: u64,                    // dry_run 时为将被删除的条数
+    skipped: Option<String>,         // 未执行的原因
+}
+
+/// 记录映射最近命中时间的表与触发器：写入（含 INSERT OR REPLACE 与 UPDATE）也算一次"使用"，
+/// 避免新导入的记录被当作长期未查询而删除。tracking_since 为开始统计的时间，之前的记录以此作为最近命中时间。
+fn ensure_activity_tracking(conn: &Connection) -> SqlResult<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS mapping_activity (uid TEXT PRIMARY KEY, last_lookup INTEGER NOT NULL) WITHOUT ROWID;
+         CREATE TABLE IF NOT EXISTS retention_meta (key TEXT PRIMARY KEY, value INTEGER NOT NULL);
+         INSERT OR IGNORE INTO retention_meta (key, value) VALUES ('tracking_since', unixepoch());
+         CREATE TRIGGER IF NOT EXISTS mapping_activity_insert AFTER INSERT ON user_mapping BEGIN
+             INSERT OR REPLACE INTO mapping_activity (uid, last_lookup) VALUES (NEW.uid, unixepoch());
+         END;
+         CREATE TRIGGER IF NOT EXISTS mapping_activity_update AFTER UPDATE OF uid, phone_number ON user_mapping BEGIN
+             INSERT OR REPLACE INTO mapping_activity (uid, last_lookup) VALUES (NEW.uid, unixepoch());
+         END;",
+    )
+}
+
+/// 把内存中记录的命中 uid 写回 mapping_activity
+fn flush_lookup_activity(state: &AppState) -> SqlResult<usize> {
+    let uids: Vec<String> = std::mem::take(&mut *state.lookup_activity.lock().unwrap()).into_iter().collect();
+    let conn = state.get_db_connection()?;
+    ensure_activity_tracking(&conn)?;
+    if uids.is_empty() {
+        return Ok(0);
+    }
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt = tx.prepare("INSERT OR REPLACE INTO mapping_activity (uid, last_lookup) VALUES (?1, unixepoch())")?;
+        for uid in &uids {
+            stmt.execute([uid])?;
+        }
+    }
+    tx.commit()?;
+    Ok(uids.len())
+}
+
+/// 按规则执行一次保留策略（阻塞，需在 spawn_blocking 或 CLI 线程中调用）。删除后清空进程内缓存。
+fn apply_retention(state: &AppState, retention: &RetentionConfig) -> Result<RetentionReport, AppError> {
+    let config = state.current_config();
+    let mut rules = Vec::new();
+    for rule in &retention.rules {
+        let mut report = RetentionRuleReport { target: rule.target, max_age_days: rule.max_age_days, removed: 0, skipped: None };
+        match rule.target {
+            RetentionTarget::UnusedMappings if !config.single_sqlite_file() => {
+                report.skipped = Some(tr!("仅支持单文件 sqlite 存储后端", "only supported on the single-file sqlite backend").to_string());
+            }
+            RetentionTarget::UnusedMappings => {
+                let conn = state.get_db_connection()?;
+                ensure_activity_tracking(&conn)?;
+                let cutoff = Local::now().timestamp() - rule.max_age_days as i64 * 24 * 60 * 60;
+                // 没有命中记录的映射视为在开始统计时最后一次使用
+                let expired = "SELECT m.rowid FROM user_mapping m LEFT JOIN mapping_activity a ON a.uid = m.uid
+                    WHERE COALESCE(a.last_lookup, (SELECT value FROM retention_meta WHERE key = 'tracking_since')) < ?1";
+                if retention.dry_run {
+                    report.removed = conn.query_row(&format!("SELECT COUNT(*) FROM ({})", expired), [cutoff], |row| row.get(0))?;
+                } else {
+                    let delete = format!("DELETE FROM user_mapping WHERE rowid IN ({} LIMIT {})", expired, RETENTION_DELETE_CHUNK);
+                    loop {
+                        let deleted = with_retry(&config.db_retry, || conn.execute(&delete, [cutoff]))?;
+                        report.removed += deleted as u64;
+                        if deleted < RETENTION_DELETE_CHUNK {
+                            break;
+                        }
+                    }
+                    conn.execute("DELETE FROM mapping_activity WHERE uid NOT IN (SELECT uid FROM user_mapping)", [])?;
+                }
+            }
+            RetentionTarget::AccessTrail => {
+                let conn = open_access_trail(&config)?;
+                let cutoff = Local::now().timestamp() - rule.max_age_days as i64 * 24 * 60 * 60;
+                if retention.dry_run {
+                    report.removed = conn.query_row("SELECT COUNT(*) FROM access_trail WHERE time < ?1", [cutoff], |row| row.get(0))?;
+                } else {
+                    let delete = format!("DELETE FROM access_trail WHERE id IN (SELECT id FROM access_trail WHERE time < ?1 LIMIT {})", RETENTION_DELETE_CHUNK);
+                    loop {
+                        let deleted = with_retry(&config.db_retry, || conn.execute(&delete, [cutoff]))?;
+                        report.removed += deleted as u64;
+                        if deleted < RETENTION_DELETE_CHUNK {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        rules.push(report);
+    }
+    if !retention.dry_run && rules.iter().any(|rule| rule.removed > 0) {
+        state.invalidate_cache(None);
+    }
+    let report = RetentionReport { time: Local::now().to_rfc3339(), dry_run: retention.dry_run, rules };
+    *state.retention_report.lock().unwrap() = Some(report.clone());
+    Ok(report)
+}
+
+fn print_retention_report(report: &RetentionReport) {
+    for rule in &report.rules {
+        let target = serde_json::to_value(rule.target).ok().and_then(|v| v.as_str().map(String::from)).unwrap_or_default();
+        match &rule.skipped {
+            Some(reason) => println!("{} {}", "RETENTION".cyan(), tr!("{} (>{} 天): 跳过，{}", "{} (>{} days): skipped, {}", target, rule.max_age_days, reason)),
+            None if report.dry_run => println!("{} {}", "RETENTION".cyan(), tr!("{} (>{} 天): 将删除 {} 条 (dry run)", "{} (>{} days): {} record(s) would be removed (dry run)", target, rule.max_age_days, rule.removed)),
+            None => println!("{} {}", "RETENTION".cyan(), tr!("{} (>{} 天): 已删除 {} 条", "{} (>{} days): {} record(s) removed", target, rule.max_age_days, rule.removed)),
+        }
+    }
+}
+
+/// 后台保留策略任务：每 ACTIVITY_FLUSH_SECS 写回命中时间，每 interval_secs 执行一次规则。每轮重新读取配置。
+async fn run_retention_scheduler(state: Arc<AppState>) {
+    let mut last_run: Option<Instant> = None;
+    loop {
+        sleep(Duration::from_secs(ACTIVITY_FLUSH_SECS)).await;
+        let retention = state.current_config().retention;
+        if !retention.enabled {
+            state.lookup_activity.lock().unwrap().clear();
+            last_run = None;
+            continue;
+        }
+        if retention.tracks_lookups() && state.current_config().single_sqlite_file() {
+            let task_state = state.clone();
+            match task::spawn_blocking(move || flush_lookup_activity(&task_state)).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => eprintln!("{} {}", "WARN".yellow(), tr!("写回映射命中时间失败: {}", "Failed to record mapping lookup times: {}", e)),
+                Err(e) => eprintln!("{} {}", "ERR".red(), tr!("保留策略任务异常: {}", "Retention task panicked: {}", e)),
+            }
+        }
+        if last_run.is_some_and(|at| at.elapsed() < Duration::from_secs(retention.interval_secs)) {
+            continue;
+        }
+        last_run = Some(Instant::now());
+        let task_state = state.clone();
+        match task::spawn_blocking(move || apply_retention(&task_state, &retention)).await {
+            Ok(Ok(report)) => print_retention_report(&report),
+            Ok(Err(e)) => eprintln!("{} {}", "ERR".red(), tr!("保留策略执行失败: {:?}", "Retention run failed: {:?}", e)),
+            Err(e) => eprintln!("{} {}", "ERR".red(), tr!("保留策略任务异常: {}", "Retention task panicked: {}", e)),
+        }
+    }
+}
+
+/// 最近一次保留策略执行的报告（尚未执行时为 null）
+async fn api_admin_retention(State(state): State<Arc<AppState>>) -> Json<Option<RetentionReport>> {
+    Json(state.retention_report.lock().unwrap().clone())
+}
+
+// --- 常用 ID 预加载 ---
+
+/// 把内存中的查询次数累加到 query_frequency，并只保留次数最多的 `keep` 个 ID
+fn flush_query_counts(state: &AppState, keep: u32) -> SqlResult<usize> {
+    let counts = std::mem::take(&mut *state.query_counts.lock().unwrap());
+    if counts.is_empty() {
+        return Ok(0);
+    }
+    let conn = state.get_db_connection()?;
+    conn.execute_batch("CREATE TABLE IF NOT EXISTS query_frequency (id TEXT PRIMARY KEY, hits INTEGER NOT NULL, last_query INTEGER NOT NULL) WITHOUT ROWID;")?;
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt = tx.prepare(
+            "INSERT INTO query_frequency (id, hits, last_query) VALUES (?1, ?2, unixepoch())
+             ON CONFLICT(id) DO UPDATE SET hits = hits + excluded.hits, last_query = excluded.last_query")?;
+        for (id, hits) in &counts {
+            stmt.execute(rusqlite::params![id, *hits as i64])?;
+        }
+        tx.execute("DELETE FROM query_frequency WHERE id NOT IN (SELECT id FROM query_frequency ORDER BY hits DESC, last_query DESC LIMIT ?1)", [keep])?;
+    }
+    tx.commit()?;
+    Ok(counts.len())
+}
+
+/// 查询次数最多的 `limit` 个 ID；表尚不存在时为空
+fn top_queried_ids(conn: &Connection, limit: u32) -> SqlResult<Vec<String>> {
+    let exists: bool = conn.query_row("SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'query_frequency')", [], |row| row.get(0))?;
+    if !exists {
+        return Ok(Vec::new());
+    }
+    let mut stmt = conn.prepare("SELECT id FROM query_frequency ORDER BY hits DESC, last_query DESC LIMIT ?1")?;
+    let ids = stmt.query_map([limit], |row| row.get(0))?.collect();
+    ids
+}
+
+/// 按块查询给定的 ID 并写入查询缓存，返回写入的条数
+async fn preload_ids(state: &Arc<AppState>, ids: &[String], chunk_size: usize) -> Result<usize, AppError> {
+    let store = state.store()?;
+    let mut loaded = 0;
+    for chunk in ids.chunks(chunk_size.max(1)) {
+        let generation = state.cache_generation();
+        let results = store.batch_lookup(chunk.to_vec()).await?;
+        for (id, resp) in chunk.iter().zip(&results) {
+            state.cache_put(id, resp, generation);
+            loaded += 1;
+        }
+    }
+    Ok(loaded)
+}
+
+/// 启动时预加载最常查询的 ID，使重启后的第一波请求不必全部访问数据库
+async fn preload_frequent_ids(state: &Arc<AppState>, config: &ServiceConfig) -> Result<usize, AppError> {
+    let (task_state, limit) = (state.clone(), config.cache.preload_top);
+    let ids = task::spawn_blocking(move || top_queried_ids(&task_state.get_db_connection()?, limit)).await
+        .map_err(|e| AppError::FatalError(e.to_string()))??;
+    preload_ids(state, &ids, config.batch_chunk_size as usize).await
+}
+
+/// 后台写回查询次数：每 ACTIVITY_FLUSH_SECS 一次，每轮重新读取配置
+async fn run_query_frequency_writer(state: Arc<AppState>) {
+    loop {
+        sleep(Duration::from_secs(ACTIVITY_FLUSH_SECS)).await;
+        let config = state.current_config();
+        if config.cache.preload_top == 0 || !config.single_sqlite_file() {
+            state.query_counts.lock().unwrap().clear();
+            continue;
+        }
+        let task_state = state.clone();
+        let keep = config.cache.preload_top.saturating_mul(QUERY_FREQUENCY_KEEP_FACTOR);
+        match task::spawn_blocking(move || flush_query_counts(&task_state, keep)).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => eprintln!("{} {}", "WARN".yellow(), tr!("写回查询次数失败: {}", "Failed to record query counts: {}", e)),
+            Err(e) => eprintln!("{} {}", "ERR".red(), tr!("查询次数写回任务异常: {}", "Query count task panicked: {}", e)),
+        }
+    }
+}
+
+// --- 查询统计历史 (analytics) ---
+
+/// 尚未写入数据库的统计增量
+#[derive(Default)]
+struct AnalyticsBuffer {
+    days: BTreeMap<String, DailyCounts>, // 日期 (YYYY-MM-DD) -> 增量，跨零点时分属两天
+    cache_seen: (u64, u64),              // 上次写入时缓存命中/未命中的累计值
+}
+
+#[derive(Default)]
+struct DailyCounts {
+    lookups: u64,                    // 查询请求数（批量查询计一次）
+    ids: u64,                        // 查询的 ID 数
+    found: u64,
+    not_found: u64,
+    cache_hits: u64,
+    cache_misses: u64,
+    counts: HashMap<String, u64>,    // 找到的 ID -> 次数
+}
+
+impl DailyCounts {
+    fn merge(&mut self, other: DailyCounts) {
+        self.lookups += other.lookups;
+        self.ids += other.ids;
+        self.found += other.found;
+        self.not_found += other.not_found;
+        self.cache_hits += other.cache_hits;
+        self.cache_misses += other.cache_misses;
+        for (id, hits) in other.counts {
+            *self.counts.entry(id).or_default() += hits;
+        }
+    }
+    /// 只保留次数最多的 `keep` 个 ID，返回丢弃的个数
+    fn truncate_counts(&mut self, keep: usize) -> usize {
+        let excess = self.counts.len().saturating_sub(keep);
+        if excess > 0 {
+            let mut counts: Vec<(String, u64)> = self.counts.drain().collect();
+            counts.sort_unstable_by_key(|(_, hits)| std::cmp::Reverse(*hits));
+            counts.truncate(keep);
+            self.counts = counts.into_iter().collect();
+        }
+        excess
+    }
+}
+
+/// 一天的统计，`top` 为当天查询次数最多的 ID
+#[derive(Debug, Serialize)]
+struct AnalyticsDay {
+    date: String,
+    lookups: u64,
+    ids: u64,
+    found: u64,
+    not_found: u64,
+    cache_hits: u64,
+    cache_misses: u64,
+    top: Vec<TopId>,
+}
+
+#[derive(Debug, Serialize)]
+struct TopId {
+    id: String,
+    hits: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct AnalyticsHistory {
+    enabled: bool,
+    days: Vec<AnalyticsDay>,         // 按日期升序；当天最近 flush_secs 内的查询尚未计入
+}
+
+/// `/stats/history` 的查询参数
+#[derive(Debug, Deserialize)]
+struct HistoryQuery {
+    days: Option<u32>,
+}
+
+const ANALYTICS_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS analytics_daily (
+        date TEXT PRIMARY KEY,
+        lookups INTEGER NOT NULL,
+        ids INTEGER NOT NULL,
+        found INTEGER NOT NULL,
+        not_found INTEGER NOT NULL,
+        cache_hits INTEGER NOT NULL,
+        cache_misses INTEGER NOT NULL
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS analytics_daily_ids (
+        date TEXT NOT NULL,
+        id TEXT NOT NULL,
+        hits INTEGER NOT NULL,
+        PRIMARY KEY (date, id)
+    ) WITHOUT ROWID;";
+
+/// 把内存中的增量累加到 analytics_daily，每天只保留次数最多的 top_n * QUERY_FREQUENCY_KEEP_FACTOR 个 ID，
+/// 并清理超过 keep_days 的历史。缓存计数不区分日期，记在写入当天。返回写入的天数。
+/// 写入失败时增量并回内存下次重试；积压按同样的规则裁剪（超过 keep_days 的日期、每天次数靠后的 ID），内存不会无限增长。
+fn flush_analytics(state: &AppState, config: &AnalyticsConfig) -> SqlResult<usize> {
+    let days = {
+        let mut buffer = state.analytics.lock().unwrap();
+        let seen = (state.cache_hits.load(Ordering::Relaxed), state.cache_misses.load(Ordering::Relaxed));
+        let (hits, misses) = (seen.0.saturating_sub(buffer.cache_seen.0), seen.1.saturating_sub(buffer.cache_seen.1));
+        buffer.cache_seen = seen;
+        let mut days = std::mem::take(&mut buffer.days);
+        if hits + misses > 0 {
+            let today = days.entry(Local::now().format("%Y-%m-%d").to_string()).or_default();
+            today.cache_hits += hits;
+            today.cache_misses += misses;
+        }
+        days
+    };
+    if days.is_empty() {
+        return Ok(0);
+    }
+    let keep = config.top_n.saturating_mul(QUERY_FREQUENCY_KEEP_FACTOR);
+    let cutoff = (Local::now() - chrono::Duration::days(i64::from(config.keep_days))).format("%Y-%m-%d").to_string();
+    match write_analytics(state, &days, keep, &cutoff) {
+        Ok(()) => Ok(days.len()),
+        Err(e) => {
+            let mut buffer = state.analytics.lock().unwrap();
+            let newer = std::mem::replace(&mut buffer.days, days);
+            for (date, day) in newer {
+                buffer.days.entry(date).or_default().merge(day);
+            }
+            buffer.days.retain(|date, _| *date >= cutoff);
+            let dropped: usize = buffer.days.values_mut().map(|day| day.truncate_counts(keep as usize)).sum();
+            if dropped > 0 {
+                eprintln!("{} {}", "WARN".yellow(), tr!("查询统计积压过多，已丢弃 {} 个次数靠后的 ID", "Query analytics backlog too large; dropped {} least-queried IDs", dropped));
+            }
+            Err(e)
+        }
+    }
+}
+
+/// 在一个事务中把 `days` 累加到统计表
+fn write_analytics(state: &AppState, days: &BTreeMap<String, DailyCounts>, keep: u32, cutoff: &str) -> SqlResult<()> {
+    let conn = state.get_db_connection()?;
+    conn.execute_batch(ANALYTICS_SCHEMA)?;
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut totals = tx.prepare(
+            "INSERT INTO analytics_daily (date, lookups, ids, found, not_found, cache_hits, cache_misses) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT(date) DO UPDATE SET lookups = lookups + excluded.lookups, ids = ids + excluded.ids, found = found + excluded.found,
+                 not_found = not_found + excluded.not_found, cache_hits = cache_hits + excluded.cache_hits, cache_misses = cache_misses + excluded.cache_misses")?;
+        let mut ids = tx.prepare(
+            "INSERT INTO analytics_daily_ids (date, id, hits) VALUES (?1, ?2, ?3)
+             ON CONFLICT(date, id) DO UPDATE SET hits = hits + excluded.hits")?;
+        for (date, day) in days {
+            totals.execute(rusqlite::params![date, day.lookups as i64, day.ids as i64, day.found as i64, day.not_found as i64, day.cache_hits as i64, day.cache_misses as i64])?;
+            for (id, hits) in &day.counts {
+                ids.execute(rusqlite::params![date, id, *hits as i64])?;
+            }
+            tx.execute("DELETE FROM analytics_daily_ids WHERE date = ?1 AND id NOT IN (SELECT id FROM analytics_daily_ids WHERE date = ?1 ORDER BY hits DESC LIMIT ?2)", rusqlite::params![date, keep])?;
+        }
+        tx.execute("DELETE FROM analytics_daily WHERE date < ?1", [cutoff])?;
+        tx.execute("DELETE FROM analytics_daily_ids WHERE date < ?1", [cutoff])?;
+    }
+    tx.commit()
+}
+
+/// 最近 `days` 天（含当天）的统计；表尚不存在时为空
+fn analytics_history(conn: &Connection, days: u32, top_n: u32) -> SqlResult<Vec<AnalyticsDay>> {
+    let exists: bool = conn.query_row("SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'analytics_daily')", [], |row| row.get(0))?;
+    if !exists {
+        return Ok(Vec::new());
+    }
+    let since = (Local::now() - chrono::Duration::days(i64::from(days.saturating_sub(1)))).format("%Y-%m-%d").to_string();
+    let mut stmt = conn.prepare("SELECT date, lookups, ids, found, not_found, cache_hits, cache_misses FROM analytics_daily WHERE date >= ?1 ORDER BY date")?;
+    let mut history = stmt.query_map([&since], |row| Ok(AnalyticsDay {
+        date: row.get(0)?,
+        lookups: row.get::<_, i64>(1)? as u64,
+        ids: row.get::<_, i64>(2)? as u64,
+        found: row.get::<_, i64>(3)? as u64,
+        not_found: row.get::<_, i64>(4)? as u64,
+        cache_hits: row.get::<_, i64>(5)? as u64,
+        cache_misses: row.get::<_, i64>(6)? as u64,
+        top: Vec::new(),
+    }))?.collect::<SqlResult<Vec<_>>>()?;
+    let mut top = conn.prepare("SELECT id, hits FROM analytics_daily_ids WHERE date = ?1 ORDER BY hits DESC, id LIMIT ?2")?;
+    for day in &mut history {
+        day.top = top.query_map(rusqlite::params![day.date, top_n], |row| Ok(TopId { id: row.get(0)?, hits: row.get::<_, i64>(1)? as u64 }))?
+            .collect::<SqlResult<_>>()?;
+    }
+    Ok(history)
+}
+
+/// 后台写入统计历史：每 analytics.flush_secs 一次，每轮重新读取配置
+async fn run_analytics_writer(state: Arc<AppState>) {
+    loop {
+        let config = state.current_config();
+        sleep(Duration::from_secs(config.analytics.flush_secs.max(1))).await;
+        let config = state.current_config();
+        if !config.analytics.enabled || !config.single_sqlite_file() {
+            state.analytics.lock().unwrap().days.clear();
+            continue;
+        }
+        let task_state = state.clone();
+        match task::spawn_blocking(move || flush_analytics(&task_state, &config.analytics)).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => eprintln!("{} {}", "WARN".yellow(), tr!("写入查询统计历史失败: {}", "Failed to record query analytics: {}", e)),
+            Err(e) => eprintln!("{} {}", "ERR".red(), tr!("查询统计写入任务异常: {}", "Analytics task panicked: {}", e)),
+        }
+    }
+}
+
+/// 最近 `?days=`（默认 30）天的查询统计，按日期升序
+async fn api_stats_history(State(state): State<Arc<AppState>>, Query(query): Query<HistoryQuery>) -> Result<Json<AnalyticsHistory>, AppError> {
+    let config = state.current_config();
+    let days = query.days.unwrap_or(30);
+    if days == 0 || days > config.analytics.keep_days.max(1) {
+        return Err(AppError::Rejected(ApiErrorCode::InvalidRequest, tr!("days 须在 1~{} 之间。", "days must be between 1 and {}.", config.analytics.keep_days.max(1))));
+    }
+    let top_n = config.analytics.top_n;
+    let history = task::spawn_blocking(move || analytics_history(&state.get_db_connection()?, days, top_n)).await
+        .map_err(|e| AppError::FatalError(tr!("后台任务异常终止: {}", "Background task aborted: {}", e)))??;
+    Ok(Json(AnalyticsHistory { enabled: config.analytics.enabled, days: history }))
+}
+
+// --- 个人信息访问记录 ---
+
+/// 一条访问记录：某个调用方在某时刻查到了哪条映射
+#[derive(Debug, Clone, Serialize)]
+struct AccessEntry {
+    time: i64,                       // Unix 时间戳（秒）
+    uid: String,
+    phone_number: Option<String>,
+    api_key: Option<String>,         // API 密钥名称；管理员与匿名调用方为空
+    client: Option<String>,
+    request_id: Option<String>,
+    endpoint: Option<String>,
+}
+
+/// 打开访问记录库（不存在时创建）。配置了 SQLCipher 口令时同样加密；
+/// 手机号与主库一样经 phone_enc / phone_dec 按 phone_encryption 保存。
+fn open_access_trail(config: &ServiceConfig) -> SqlResult<Connection> {
+    if let Some(dir) = FilePath::new(&config.access_trail.path).parent().filter(|d| !d.as_os_str().is_empty()) {
+        let _ = fs::create_dir_all(dir);
+    }
+    let conn = Connection::open(&config.access_trail.path)?;
+    apply_database_key(&conn, config.database_key().as_deref())?;
+    conn.busy_timeout(Duration::from_millis(config.pragmas.busy_timeout_ms))?;
+    let cipher = PhoneCipher::load(&config.phone_encryption).map_err(|e| SqlError::UserFunctionError(e.into()))?;
+    register_phone_functions(&conn, cipher.map(Arc::new))?;
+    conn.pragma_update(None, "journal_mode", "wal")?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS access_trail (
+             id INTEGER PRIMARY KEY,
+             time INTEGER NOT NULL,
+             uid TEXT NOT NULL,
+             phone_number TEXT,
+             api_key TEXT,
+             client TEXT,
+             request_id TEXT,
+             endpoint TEXT
+         );
+         CREATE INDEX IF NOT EXISTS idx_trail_time ON access_trail (time);
+         CREATE INDEX IF NOT EXISTS idx_trail_uid ON access_trail (uid);
+         CREATE INDEX IF NOT EXISTS idx_trail_phone ON access_trail (phone_number);",
+    )?;
+    Ok(conn)
+}
+
+/// 把内存中的访问记录写入文件；失败时放回队列，下次重试。
+/// 队列超过 ACCESS_TRAIL_PENDING_LIMIT 时丢弃最早的记录并告警，避免库长时间不可写时内存无限增长。
+fn flush_access_trail(state: &AppState) -> SqlResult<usize> {
+    let entries = std::mem::take(&mut *state.access_trail.lock().unwrap());
+    if entries.is_empty() {
+        return Ok(0);
+    }
+    let write = |entries: &[AccessEntry]| -> SqlResult<()> {
+        let conn = open_access_trail(&state.current_config())?;
+        let tx = conn.unchecked_transaction()?;
+        {
+            let mut stmt = tx.prepare("INSERT INTO access_trail (time, uid, phone_number, api_key, client, request_id, endpoint) VALUES (?1, ?2, phone_enc(?3), ?4, ?5, ?6, ?7)")?;
+            for e in entries {
+                stmt.execute(rusqlite::params![e.time, e.uid, e.phone_number, e.api_key, e.client, e.request_id, e.endpoint])?;
+            }
+        }
+        tx.commit()
+    };
+    match write(&entries) {
+        Ok(()) => Ok(entries.len()),
+        Err(e) => {
+            let mut pending = state.access_trail.lock().unwrap();
+            let newer = std::mem::replace(&mut *pending, entries);
+            pending.extend(newer);
+            let excess = pending.len().saturating_sub(ACCESS_TRAIL_PENDING_LIMIT);
+            if excess > 0 {
+                pending.drain(..excess);
+                eprintln!("{} {}", "WARN".yellow(), tr!("访问记录积压超过 {} 条，已丢弃最早的 {} 条", "Access trail backlog exceeds {} entries; dropped the oldest {}", ACCESS_TRAIL_PENDING_LIMIT, excess));
+            }
+            Err(e)
+        }
+    }
+}
+
+/// 后台写入访问记录
+async fn run_access_trail_writer(state: Arc<AppState>) {
+    loop {
+        sleep(Duration::from_secs(ACCESS_TRAIL_FLUSH_SECS)).await;
+        let task_state = state.clone();
+        match task::spawn_blocking(move || flush_access_trail(&task_state)).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => eprintln!("{} {}", "WARN".yellow(), tr!("写入访问记录失败，稍后重试: {}", "Failed to write the access trail, will retry: {}", e)),
+            Err(e) => eprintln!("{} {}", "ERR".red(), tr!("访问记录任务异常: {}", "Access trail task panicked: {}", e)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AccessTrailQuery {
+    id: Option<String>,              // 按 uid 或手机号过滤
+    since: Option<String>,           // RFC 3339 时间或 YYYY-MM-DD
+    until: Option<String>,
+    limit: Option<u32>,
+    format: Option<String>,          // json（默认）或 csv
+}
+
+#[derive(Serialize)]
+struct AccessTrailResponse {
+    entries: Vec<AccessEntry>,       // 最新的在前
+}
+
+/// 解析 RFC 3339 时间或 YYYY-MM-DD（本地时区零点），返回 Unix 时间戳
+fn parse_time_filter(value: &str) -> Result<i64, AppError> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.timestamp());
+    }
+    chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .and_then(|time| time.and_local_timezone(Local).earliest())
+        .map(|time| time.timestamp())
+        .ok_or_else(|| AppError::Rejected(ApiErrorCode::InvalidRequest, tr!("无效的时间: {}（应为 RFC 3339 或 YYYY-MM-DD）", "Invalid time: {} (expected RFC 3339 or YYYY-MM-DD)", value)))
+}
+
+/// 导出访问记录（最新的在前），回答"谁在什么时候查过这个号码"。尚在内存中的记录会先写入文件。
+async fn api_admin_access_trail(State(state): State<Arc<AppState>>, Query(query): Query<AccessTrailQuery>) -> Result<Response, AppError> {
+    let since = query.since.as_deref().map(parse_time_filter).transpose()?;
+    let until = query.until.as_deref().map(parse_time_filter).transpose()?;
+    let limit = query.limit.unwrap_or(ACCESS_TRAIL_EXPORT_LIMIT).min(ACCESS_TRAIL_EXPORT_LIMIT);
+    let csv = match query.format.as_deref() {
+        None | Some("json") => false,
+        Some("csv") => true,
+        Some(other) => return Err(AppError::Rejected(ApiErrorCode::UnsupportedFormat, tr!("不支持的格式: {}", "Unsupported format: {}", other))),
+    };
+    let task_state = state.clone();
+    let entries = task::spawn_blocking(move || -> SqlResult<Vec<AccessEntry>> {
+        flush_access_trail(&task_state)?;
+        let conn = open_access_trail(&task_state.current_config())?;
+        let mut stmt = conn.prepare(
+            "SELECT time, uid, phone_dec(phone_number), api_key, client, request_id, endpoint FROM access_trail
+             WHERE (?1 IS NULL OR uid = ?1 OR phone_number IN (?1, phone_enc(?1))) AND (?2 IS NULL OR time >= ?2) AND (?3 IS NULL OR time < ?3)
+             ORDER BY id DESC LIMIT ?4")?;
+        let rows = stmt.query_map(rusqlite::params![query.id, since, until, limit], |row| Ok(AccessEntry {
+            time: row.get(0)?,
+            uid: row.get(1)?,
+            phone_number: row.get(2)?,
+            api_key: row.get(3)?,
+            client: row.get(4)?,
+            request_id: row.get(5)?,
+            endpoint: row.get(6)?,
+        }))?;
+        rows.collect()
+    }).await.map_err(|e| AppError::FatalError(e.to_string()))??;
+
+    if !csv {
+        return Ok(Json(AccessTrailResponse { entries }).into_response());
+    }
+    let write_err = |e: csv::Error| AppError::FatalError(tr!("写入 CSV 失败: {}", "Failed to write CSV: {}", e));
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(["time", "uid", "phone_number", "api_key", "client", "request_id", "endpoint"]).map_err(write_err)?;
+    for e in &entries {
+        let time = DateTime::from_timestamp(e.time, 0).map(|t| t.with_timezone(&Local).to_rfc3339()).unwrap_or_default();
+        writer.write_record([time.as_str(), &e.uid, e.phone_number.as_deref().unwrap_or(""), e.api_key.as_deref().unwrap_or(""),
+            e.client.as_deref().unwrap_or(""), e.request_id.as_deref().unwrap_or(""), e.endpoint.as_deref().unwrap_or("")]).map_err(write_err)?;
+    }
+    let body = writer.into_inner().map_err(|e| AppError::FatalError(tr!("写入 CSV 失败: {}", "Failed to write CSV: {}", e)))?;
+    Ok(([(CONTENT_TYPE, CSV_CONTENT_TYPE)], body).into_response())
+}
+
+// --- 主库健康检查与故障切换 ---
+
+/// 以只读、不创建文件的方式打开数据库并读取映射表，判断主库是否可用（卷未挂载时不会误建一个空库）。
+fn probe_database(path: &str, key: Option<&str>) -> SqlResult<()> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)?;
+    apply_database_key(&conn, key)?;
+    conn.query_row("SELECT COUNT(*) FROM (SELECT 1 FROM user_mapping LIMIT 1)", [], |_| Ok(()))
+}
+
+/// 配置了备用库时定期探测主库：连续失败达到阈值后把查询切换到备用库，主库恢复后切回（未配置时仅轮询配置）。
+async fn run_failover_monitor(state: Arc<AppState>) {
+    let mut failures = 0;
+    loop {
+        let config = state.current_config();
+        let failover = config.failover.clone();
+        if failover.fallback_db_path.is_empty() || !config.single_sqlite_file() {
+            failures = 0;
+            state.failover_active.store(false, Ordering::SeqCst);
+            sleep(Duration::from_secs(FAILOVER_POLL_SECS)).await;
+            continue;
+        }
+        sleep(Duration::from_secs(failover.check_interval_secs)).await;
+
+        let db_path = config.db_path.clone();
+        let key = config.database_key();
+        let probe = task::spawn_blocking(move || probe_database(&db_path, key.as_deref())).await;
+        match probe {
+            Ok(Ok(())) => {
+                failures = 0;
+                if state.failover_active.swap(false, Ordering::SeqCst) {
+                    // 备用库可能落后于主库，切回后丢弃期间缓存的结果
+                    state.clear_cache();
+                    println!("{} {}", "OK".green(), tr!("主库已恢复，查询切回 {}", "Primary database recovered, reads switched back to {}", config.db_path));
+                }
+            }
+            Ok(Err(e)) => {
+                failures += 1;
+                if failures >= failover.failure_threshold && !state.failover_active.swap(true, Ordering::SeqCst) {
+                    eprintln!("{} {}", "WARN".yellow(), tr!("主库连续 {} 次健康检查失败 ({})，查询切换到备用库 {}", "Primary database failed {} consecutive health checks ({}); reads switched to fallback {}", failures, e, failover.fallback_db_path));
+                }
+            }
+            Err(e) => eprintln!("{} {}", "ERR".red(), tr!("主库健康检查任务异常: {}", "Primary health check task panicked: {}", e)),
+        }
+    }
+}
+
+/// 推送到告警 webhook 的 JSON
+#[derive(Serialize)]
+struct AlertPayload<'a> {
+    service: &'static str,
+    instance: &'a str,       // bind_address，区分多个实例
+    alert: &'static str,     // database / disk_space / error_rate
+    state: &'static str,     // firing / resolved
+    message: &'a str,
+    time: String,
+}
+
+async fn send_alert(client: &reqwest::Client, config: &ServiceConfig, alert: &'static str, alert_state: &'static str, message: &str) {
+    let payload = AlertPayload {
+        service: "cyber_lookup",
+        instance: &config.bind_address,
+        alert,
+        state: alert_state,
+        message,
+        time: Local::now().to_rfc3339(),
+    };
+    let sent = client.post(&config.alerts.webhook_url).json(&payload).send().await.and_then(|r| r.error_for_status());
+    match sent {
+        Ok(_) => println!("{} {}", "WARN".yellow(), tr!("已发送告警 [{} {}]: {}", "Alert sent [{} {}]: {}", alert, alert_state, message)),
+        Err(e) => eprintln!("{} {}", "ERR".red(), tr!("告警推送失败 [{} {}]: {}", "Failed to deliver alert [{} {}]: {}", alert, alert_state, e)),
+    }
+}
+
+/// 配置了 alerts.webhook_url 时定期检查健康状况并推送告警（未配置时仅轮询配置）。
+async fn run_alert_monitor(state: Arc<AppState>) {
+    let client = match reqwest::Client::builder().timeout(Duration::from_secs(ALERT_WEBHOOK_TIMEOUT_SECS)).build() {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("{} {}", "ERR".red(), tr!("告警 HTTP 客户端创建失败，告警已禁用: {}", "Failed to create alert HTTP client, alerts disabled: {}", e));
+            return;
+        }
+    };
+    let mut last_sent: HashMap<&'static str, Instant> = HashMap::new();
+    let mut firing: HashSet<&'static str> = HashSet::new();
+    let mut last_counts = (state.responses_total.load(Ordering::Relaxed), state.responses_failed.load(Ordering::Relaxed));
+    loop {
+        let config = state.current_config();
+        if config.alerts.webhook_url.is_empty() {
+            firing.clear();
+            sleep(Duration::from_secs(ALERT_POLL_SECS)).await;
+            continue;
+        }
+        sleep(Duration::from_secs(config.alerts.check_interval_secs)).await;
+
+        // 故障切换到备用库也视为数据库告警：写入不可用
+        let (status, Json(health)) = storage_health(&state).await;
+        let database = (status != StatusCode::OK || state.read_path().is_some()).then_some(health.message);
+
+        let disk = disk_space_check(&config);
+        let disk = (disk.status == "error").then(|| disk.message.unwrap_or_default());
+
+        let counts = (state.responses_total.load(Ordering::Relaxed), state.responses_failed.load(Ordering::Relaxed));
+        let (total, failed) = (counts.0 - last_counts.0, counts.1 - last_counts.1);
+        last_counts = counts;
+        let rate = if total == 0 { 0.0 } else { failed as f64 / total as f64 };
+        let error_rate = (total >= config.alerts.min_requests && rate > config.alerts.error_rate_threshold)
+            .then(|| tr!("最近 {} 秒内 {}/{} 个响应为 5xx ({:.1}%)", "{1}/{2} responses in the last {0}s were 5xx ({3:.1}%)", config.alerts.check_interval_secs, failed, total, rate * 100.0));
+
+        for (alert, problem) in [("database", database), ("disk_space", disk), ("error_rate", error_rate)] {
+            match problem {
+                Some(message) => {
+                    firing.insert(alert);
+                    let due = last_sent.get(alert).is_none_or(|t| t.elapsed() >= Duration::from_secs(config.alerts.cooldown_secs));
+                    if due {
+                        last_sent.insert(alert, Instant::now());
+                        send_alert(&client, &config, alert, "firing", &message).await;
+                    }
+                }
+                None if firing.remove(alert) => {
+                    send_alert(&client, &config, alert, "resolved", tr!("已恢复", "Recovered")).await;
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+// --- API 响应/请求模型 / 核心业务逻辑 (保持不变) ---
+/// 单条查询结果。`status` 为 found_by_uid / found_by_phone（单条）、found（批量）、found_upstream（上游回源）或 not_found。
+/// 批量查询的结果与输入逐位置对应，并带有原始输入 `query` 与命中的列 `matched_by`（未找到时省略）。
+/// 批量查询中所在分块查询失败的结果状态为 error，`error` 为错误码（如 DB_ERROR），其余分块照常返回。
+#[derive(Debug, Serialize, Clone)]
+pub struct LookupResponse {
+    pub status: String, pub uid: Option<String>, pub phone_number: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matched_by: Option<MatchField>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+/// 批量查询的输入命中的列
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchField {
+    Uid,
+    Phone,
+}
+impl MatchField {
+    fn as_str(self) -> &'static str {
+        match self {
+            MatchField::Uid => "uid",
+            MatchField::Phone => "phone",
+        }
+    }
+}
+impl LookupResponse {
+    fn not_found() -> Self {
+        LookupResponse { status: "not_found".to_string(), uid: None, phone_number: None, matched_by: None, query: None, error: None }
+    }
+    fn failed(code: ApiErrorCode) -> Self {
+        LookupResponse { status: "error".to_string(), error: Some(code.as_str().to_string()), ..Self::not_found() }
+    }
+    /// 由缓存条目还原单条查询结果，状态与 `lookup_one` 一致（uid 优先）。
+    fn from_cached_single(id: &str, cached: CachedMapping) -> Self {
+        match cached {
+            Some((uid, phone)) => {
+                let status = if uid == id { "found_by_uid" } else { "found_by_phone" };
+                LookupResponse { status: status.to_string(), uid: Some(uid), phone_number: Some(phone), matched_by: None, query: None, error: None }
+            }
+            None => Self::not_found(),
+        }
+    }
+    /// 由缓存条目还原批量查询结果，状态与 `batch_lookup` 一致。
+    fn from_cached_batch(cached: CachedMapping) -> Self {
+        match cached {
+            Some((uid, phone)) => LookupResponse { status: "found".to_string(), uid: Some(uid), phone_number: Some(phone), matched_by: None, query: None, error: None },
+            None => Self::not_found(),
+        }
+    }
+    fn cache_entry(&self) -> CachedMapping {
+        match (&self.uid, &self.phone_number) {
+            (Some(uid), Some(phone)) => Some((uid.clone(), phone.clone())),
+            _ => None,
+        }
+    }
+}
+#[derive(Debug, Deserialize)] 
+struct BatchRequest {
+    ids: Vec<String>, 
+    fields: Option<Vec<String>>,
+}
+/// 批量请求体：`application/json` 的 `{"ids": [...], "fields": [...]}`（fields 可选），或 `text/plain` 每行一个 ID（忽略空行）。
+struct BatchIds(Vec<String>, Option<Vec<String>>);
+
+#[axum::async_trait]
+impl<S: Send + Sync> FromRequest<S> for BatchIds {
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_plain_text = req.headers().get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.starts_with("text/plain"))
+            .unwrap_or(false);
+        if is_plain_text {
+            let body = String::from_request(req, state).await
+                .map_err(|e| rejection_response(e.status(), e.body_text()))?;
+            let ids = body.lines().map(str::trim).filter(|line| !line.is_empty()).map(String::from).collect();
+            Ok(BatchIds(ids, None))
+        } else {
+            let Json(payload) = Json::<BatchRequest>::from_request(req, state).await
+                .map_err(|e| rejection_response(e.status(), e.body_text()))?;
+            Ok(BatchIds(payload.ids, payload.fields))
+        }
+    }
+}
+/// `GET /batch_lookup?ids=a,b,c` 的查询参数
+#[derive(Debug, Deserialize)]
+struct BatchQuery {
+    #[serde(default)]
+    ids: String,
+    format: Option<String>,
+    fields: Option<String>,
+}
+/// 批量查询的查询参数：`?format=` 优先级高于 Accept 头，`?fields=` 优先于请求体中的 fields
+#[derive(Debug, Deserialize)]
+struct BatchLookupQuery {
+    format: Option<String>,
+    fields: Option<String>,
+}
+/// `/lookup/:id` 的查询参数：`?missing=404|200` 覆盖 missing_status，`?fields=uid,status` 只返回所列字段
+#[derive(Debug, Deserialize)]
+struct LookupQuery {
+    format: Option<String>,
+    missing: Option<u16>,
+    fields: Option<String>,
+}
+
+// --- 结果字段选择 (?fields=) ---
+
+/// 查询结果中可选择的字段，也是未选择时 CSV 的列顺序
+const LOOKUP_FIELDS: &[&str] = &["status", "uid", "phone_number", "matched_by", "query", "error"];
+
+/// 解析逗号分隔的字段列表，按 LOOKUP_FIELDS 的顺序返回；None 表示返回全部字段。
+fn parse_fields<S: AsRef<str>>(fields: &[S]) -> Result<Option<Vec<&'static str>>, AppError> {
+    let requested: Vec<&str> = fields.iter().flat_map(|f| f.as_ref().split(',')).map(str::trim).filter(|f| !f.is_empty()).collect();
+    if fields.is_empty() {
+        return Ok(None);
+    }
+    if let Some(unknown) = requested.iter().find(|f| !LOOKUP_FIELDS.contains(f)) {
+        return Err(AppError::Rejected(ApiErrorCode::InvalidRequest, tr!("未知字段 '{}' (可选: {})", "Unknown field '{}' (options: {})", unknown, LOOKUP_FIELDS.join(", "))));
+    }
+    if requested.is_empty() {
+        return Err(AppError::Rejected(ApiErrorCode::InvalidRequest, tr!("fields 不能为空 (可选: {})", "fields must not be empty (options: {})", LOOKUP_FIELDS.join(", "))));
+    }
+    Ok(Some(LOOKUP_FIELDS.iter().copied().filter(|f| requested.contains(f)).collect()))
+}
+
+/// 结果中某个字段的文本值
+fn field_value<'a>(result: &'a LookupResponse, field: &str) -> Option<&'a str> {
+    match field {
+        "status" => Some(&result.status),
+        "uid" => result.uid.as_deref(),
+        "phone_number" => result.phone_number.as_deref(),
+        "matched_by" => result.matched_by.map(MatchField::as_str),
+        "query" => result.query.as_deref(),
+        "error" => result.error.as_deref(),
+        _ => None,
+    }
+}
+
+/// 按字段选择序列化一条结果：uid / phone_number 与完整结果一样总是输出（可为 null），其余字段为空时省略
+struct SelectedFields<'a> {
+    result: &'a LookupResponse,
+    fields: Option<&'a [&'static str]>,
+}
+
+impl Serialize for SelectedFields<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let Some(fields) = self.fields else {
+            return self.result.serialize(serializer);
+        };
+        let entries: Vec<(&str, Option<&str>)> = fields.iter()
+            .map(|&field| (field, field_value(self.result, field)))
+            .filter(|(field, value)| value.is_some() || matches!(*field, "uid" | "phone_number"))
+            .collect();
+        let mut map = serializer.serialize_map(Some(entries.len()))?;
+        for (field, value) in entries {
+            map.serialize_entry(field, &value)?;
+        }
+        map.end()
+    }
+}
+/// 未找到时允许的状态码；批量接口总是 200，逐条以 status: not_found 标明
+fn missing_status_code(code: u16) -> Option<StatusCode> {
+    match code {
+        404 => Some(StatusCode::NOT_FOUND),
+        200 => Some(StatusCode::OK),
+        _ => None,
+    }
+}
+/// `/batch_lookup/file` 的查询参数
+#[derive(Debug, Deserialize)]
+struct CsvLookupParams {
+    column: Option<String>,      // ID 所在列：列名或从 0 开始的序号，默认第一列
+    #[serde(default = "default_true")]
+    has_header: bool,
+}
+fn default_true() -> bool { true }
+#[derive(Serialize)]
+struct BatchResponse<'a> {
+    results: Vec<SelectedFields<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<BatchSummary>,   // 仅在部分结果失败（207）时给出
+}
+/// 部分失败的批量查询的结果统计
+#[derive(Debug, Serialize)]
+struct BatchSummary {
+    total: usize,
+    found: usize,
+    not_found: usize,
+    failed: usize,
+}
+impl<'a> BatchResponse<'a> {
+    fn new(results: &'a [LookupResponse], fields: Option<&'a [&'static str]>) -> Self {
+        let failed = results.iter().filter(|r| r.error.is_some()).count();
+        let summary = (failed > 0).then(|| {
+            let found = results.iter().filter(|r| r.uid.is_some()).count();
+            BatchSummary { total: results.len(), found, not_found: results.len() - found - failed, failed }
+        });
+        BatchResponse { results: results.iter().map(|result| SelectedFields { result, fields }).collect(), summary }
+    }
+}
+/// `POST /mappings` 的请求体
+#[derive(Debug, Deserialize)]
+struct MappingsRequest {
+    mappings: Vec<MappingInput>,
+}
+#[derive(Debug, Deserialize)]
+struct MappingInput {
+    uid: String,
+    phone_number: String,
+}
+/// `POST /mappings` 的查询参数
+#[derive(Debug, Deserialize)]
+struct WriteQuery {
+    #[serde(default)]
+    mode: WriteMode,
+    #[serde(default)]
+    on_conflict: ConflictStrategy,
+    #[serde(default)]
+    dry_run: bool,
+}
+#[derive(Serialize)]
+struct WriteResponse {
+    #[serde(flatten)]
+    summary: WriteSummary,
+    dry_run: bool,
+}
+/// `/batch_delete` 的查询参数：先以 `dry_run=true` 预览，再带上预览返回的 `confirm` 令牌执行
+#[derive(Debug, Deserialize)]
+struct BatchDeleteQuery {
+    #[serde(default)]
+    dry_run: bool,
+    confirm: Option<String>,
+}
+#[derive(Debug, Deserialize)]
+struct BatchDeleteRequest {
+    #[serde(default)]
+    ids: Vec<String>,
+    uid_prefix: Option<String>,  // 与 ids 二选一：删除 uid 以此开头的全部记录
+}
+/// `/batch_delete` 的请求体：`application/json` 的 `{"ids": [...]}` 或 `{"uid_prefix": "..."}`，
+/// 或 `text/plain` 每行一个 ID。
+struct BatchDeleteTarget(BatchDeleteRequest);
+
+#[axum::async_trait]
+impl<S: Send + Sync> FromRequest<S> for BatchDeleteTarget {
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_json = req.headers().get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.starts_with("application/json"))
+            .unwrap_or(false);
+        if is_json {
+            let Json(payload) = Json::<BatchDeleteRequest>::from_request(req, state).await
+                .map_err(|e| rejection_response(e.status(), e.body_text()))?;
+            Ok(BatchDeleteTarget(payload))
+        } else {
+            let BatchIds(ids, _) = BatchIds::from_request(req, state).await?;
+            Ok(BatchDeleteTarget(BatchDeleteRequest { ids, uid_prefix: None }))
+        }
+    }
+}
+#[derive(Serialize)]
+struct BatchDeleteResponse {
+    deleted: usize,              // dry_run 时为将被删除的条数
+    dry_run: bool,
+}
+/// `/batch_delete?dry_run=true` 的预览：将被删除的记录与执行所需的确认令牌
+#[derive(Serialize)]
+struct BatchDeletePreview {
+    deleted: usize,
+    dry_run: bool,
+    records: Vec<LookupResponse>,
+    confirmation_token: String,  // 在 expires_at 之前以 ?confirm=<令牌> 重新提交同一请求即执行删除
+    expires_at: String,
+}
+/// 管理控制台中的一条最近查询
+#[derive(Debug, Clone, Serialize)]
+struct RecentQuery {
+    time: String,
+    client: Option<String>,
+    key: Option<String>,  // 调用方出示的 API 密钥名称
+    id: Option<String>,   // 单条查询的 ID，批量查询为 None
+    ids: usize,
+    found: usize,
+    elapsed_ms: f64,
+}
+#[derive(Serialize)]
+struct InfoResponse {
+    version: String, db_path: String, bind_address: String, storage_backend: &'static str,
+    listening: ListenAddresses,      // 实际监听地址，服务未运行时为 null
+    git_commit: &'static str,        // 构建时的提交，工作区有未提交修改时带 -dirty 后缀
+    build_time: String,
+    rustc_version: &'static str,
+    started_at: String,
+    uptime_secs: u64,
+    records: Option<u64>,            // 存储后端不可用时为 None
+}
+/// 各监听的实际地址
+#[derive(Debug, Clone, Default, Serialize)]
+struct ListenAddresses {
+    http: Option<SocketAddr>,
+    grpc: Option<SocketAddr>,
+    resp: Option<SocketAddr>,
+}
+
+/// 服务停止（含被 Ctrl+C 取消）时清除记录的监听地址并删除端口文件
+struct ListenGuard {
+    state: Arc<AppState>,
+    port_file: Option<String>,
+}
+
+impl Drop for ListenGuard {
+    fn drop(&mut self) {
+        *self.state.listening.lock().unwrap() = ListenAddresses::default();
+        if let Some(path) = &self.port_file {
+            fs::remove_file(path).ok();
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct HealthResponse {
+    status: String, message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    circuit_breaker: Option<CircuitStatus>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    checks: Vec<HealthCheck>, // 仅在启用 health.deep 时填写
+}
+/// 深度健康检查中的一项
+#[derive(Serialize)]
+struct HealthCheck {
+    name: &'static str,
+    status: &'static str,     // ok / error / skipped
+    latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+#[derive(Serialize)]
+struct CircuitStatus {
+    state: String,               // closed / open / half_open
+    recent_failures: usize,
+    trips: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after_secs: Option<u64>,
+}
+#[derive(Serialize)]
+struct StatsResponse {
+    cache: CacheStats,
+    bloom: BloomStats,
+    slow_queries: SlowQueryStats,
+    ip_access: IpAccessStats,
+    rate_limit: RateLimitStats,
+    upstream: UpstreamStats,
+    shadow: ShadowStats,
+    write_queue: WriteQueueStats,
+    scheduled_tasks: Vec<TaskStatus>,
+    wal: WalStats,
+}
+#[derive(Serialize)]
+struct WalStats {
+    enabled: bool,                   // sqlite 后端且 journal_mode 为 wal
+    bytes: u64,                      // 当前 WAL 文件大小（分片时为合计）
+    checkpoint_secs: u64,
+    checkpoints: u64,                // 后台检查点的执行次数
+    failures: u64,
+    last_checkpoint: Option<String>,
+    last_result: Option<String>,
+}
+/// 后台 WAL 检查点的累计执行情况
+#[derive(Debug, Clone, Default)]
+struct WalCheckpointStatus {
+    runs: u64,
+    failures: u64,
+    last_at: Option<String>,
+    last_result: Option<String>,
+}
+#[derive(Serialize)]
+struct UpstreamStats {
+    enabled: bool, queries: u64, found: u64, errors: u64,
+}
+/// 流量镜像的累计计数，单位均为 ID
+#[derive(Serialize)]
+struct ShadowStats {
+    enabled: bool, percent: f64, mirrored: u64, matched: u64, diverged: u64, errors: u64, dropped: u64,
+}
+#[derive(Serialize)]
+struct WriteQueueStats {
+    enabled: bool, groups: u64, writes: u64, rows: u64, fallbacks: u64,
+}
+#[derive(Serialize)]
+struct CacheStats {
+    enabled: bool, entries: u64, max_entries: u64, hits: u64, misses: u64, hit_rate: f64, estimated_memory_bytes: u64,
+}
+#[derive(Serialize)]
+struct BloomStats {
+    enabled: bool, built: bool, memory_bytes: u64, negatives: u64,
+}
+#[derive(Serialize)]
+struct IpAccessStats {
+    allowlist_entries: usize, denylist_entries: usize, denied: u64,
+}
+#[derive(Serialize)]
+struct RateLimitStats {
+    enabled: bool,
+    tracked: usize,              // 当前计数中的调用方数
+    rejected: u64,               // 因限流或每日配额被拒绝的请求数
+}
+#[derive(Serialize)]
+struct SlowQueryStats {
+    threshold_ms: u64,
+    total: u64,
+    recent: Vec<SlowQuery>,      // 最近 SLOW_QUERY_LIMIT 条，按时间先后
+}
+/// 一次超过 slow_query_ms 的存储操作
+#[derive(Debug, Clone, Serialize)]
+struct SlowQuery {
+    time: String,
+    operation: &'static str,
+    endpoint: Option<String>,    // 来自 HTTP 请求时为路由模板，后台任务与命令行操作为 None
+    #[serde(skip_serializing_if = "Option::is_none")]
+    batch_size: Option<usize>,
+    elapsed_ms: f64,
+}
+
+/// 需要已注册 phone_enc / phone_dec 的连接（由 [`AppState::get_db_connection`] 打开）。
+pub fn lookup_one(conn: &Connection, id: &str) -> SqlResult<LookupResponse> {
+    // 只把"无结果"视为未命中，其余错误（如 BUSY）向上传递以便重试
+    let mut stmt = conn.prepare("SELECT phone_dec(phone_number) FROM user_mapping WHERE uid = ?1")?;
+    if let Some(phone) = stmt.query_row([id], |row| row.get(0)).optional()? {
+        return Ok(LookupResponse { status: "found_by_uid".to_string(), uid: Some(id.to_string()), phone_number: Some(phone), matched_by: None, query: None, error: None });
+    }
+    // 返回库中的值而不是 `id`：哈希模式下由响应层据此隐去手机号
+    let mut stmt = conn.prepare("SELECT uid, phone_dec(phone_number) FROM user_mapping WHERE phone_number = phone_enc(?1)")?;
+    if let Some((uid, phone)) = stmt.query_row([id], |row| Ok((row.get(0)?, row.get(1)?))).optional()? {
+        return Ok(LookupResponse { status: "found_by_phone".to_string(), uid: Some(uid), phone_number: Some(phone), matched_by: None, query: None, error: None });
+    }
+    Ok(LookupResponse::not_found())
+}
+
+/// 被丢弃时中止对应的异步任务，使其中的 `run_db` 中断查询。
+struct AbortOnDrop<T>(task::JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// 批量查询：按 `chunk_size` 拆分成多条 SQL 以避开 SQLite 的绑定参数上限，
+/// 分块轮流分配给最多 `parallelism` 个任务并发执行（各自占用一个数据库工作线程及其连接），结果按输入顺序合并。
+/// `path` 为 None 时查询 db_path，否则查询指定的（分片）文件。
+async fn batch_lookup(state: Arc<AppState>, path: Option<String>, ids: Vec<String>, chunk_size: usize, parallelism: usize) -> Result<Vec<LookupResponse>, AppError> {
+    let chunks: Vec<Vec<String>> = ids.chunks(chunk_size.max(1)).map(<[String]>::to_vec).collect();
+    let workers = parallelism.clamp(1, chunks.len().max(1));
+    let mut groups: Vec<Vec<(usize, Vec<String>)>> = (0..workers).map(|_| Vec::new()).collect();
+    for (index, chunk) in chunks.into_iter().enumerate() {
+        groups[index % workers].push((index, chunk));
+    }
+
+    // 各分组立即开始执行；请求被放弃时，未完成分组的查询会随 future 一起被中断。
+    // 分块各自重试，仍失败的分块结果标记为 error，其余分块照常返回；整组失败或超过截止时间时按原错误返回（计入熔断）
+    let retry = state.current_config().db_retry;
+    let handles: Vec<_> = groups.into_iter().map(|group| {
+        let retry = retry.clone();
+        spawn_with_deadline(run_db_at(state.clone(), path.clone(), move |conn| {
+            let mut parts = Vec::with_capacity(group.len());
+            let mut failures = Vec::new();
+            for (index, chunk) in &group {
+                match with_retry(&retry, || batch_lookup_chunk(conn, chunk)) {
+                    Ok(results) => parts.push((*index, results)),
+                    Err(e) if e.sqlite_error_code() == Some(ErrorCode::OperationInterrupted) => return Err(e),
+                    Err(e) => failures.push((*index, chunk.len(), e)),
+                }
+            }
+            if parts.is_empty() {
+                if let Some((_, _, e)) = failures.pop() {
+                    return Err(e);
+                }
+            }
+            for (index, len, e) in failures {
+                eprintln!("{} {}", "WARN".yellow(), tr!("批量查询的一个分块 ({} 个 ID) 查询失败，已标记为 error: {}", "A batch chunk ({} IDs) failed and was marked as error: {}", len, e));
+                parts.push((index, vec![LookupResponse::failed(AppError::from(e).code()); len]));
+            }
+            Ok(parts)
+        }))
+    }).map(AbortOnDrop).collect();
+
+    let mut ordered: Vec<(usize, Vec<LookupResponse>)> = Vec::new();
+    for mut handle in handles {
+        let part = (&mut handle.0).await.map_err(|e| AppError::FatalError(tr!("查询任务异常终止: {}", "Query task aborted: {}", e)))??;
+        ordered.extend(part);
+    }
+    ordered.sort_by_key(|(index, _)| *index);
+    Ok(ordered.into_iter().flat_map(|(_, results)| results).collect())
+}
+
+/// 单个分块的查询：一次 SQL 同时匹配 uid 与 phone_number。
+/// 使用编号参数让两个 IN 列表共享同一组绑定，调用方应先去重以减少参数数量。
+fn batch_lookup_chunk(conn: &Connection, ids: &[String]) -> SqlResult<Vec<LookupResponse>> {
+    let placeholders: String = (1..=ids.len()).map(|i| format!("?{}", i)).collect::<Vec<String>>().join(",");
+    let encrypted: String = (1..=ids.len()).map(|i| format!("phone_enc(?{})", i)).collect::<Vec<String>>().join(",");
+    let sql = format!("SELECT uid, phone_dec(phone_number) FROM user_mapping WHERE uid IN ({}) OR phone_number IN ({})", placeholders, encrypted);
+    let params: Vec<&dyn ToSql> = ids.iter().map(|id| id as &dyn ToSql).collect();
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(&*params, |row| {Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))})?;
+    
+    let mut map = HashMap::new();
+    for row in rows {
+        let (u, p) = row?;
+        let resp = LookupResponse { status: "found".to_string(), uid: Some(u.clone()), phone_number: Some(p.clone()), matched_by: None, query: None, error: None };
+        map.insert(u.clone(), resp.clone());
+        map.insert(p, resp);
+    }
+    // 哈希模式下结果中只有号码的哈希，按手机号命中的 ID 需先换算成哈希再匹配
+    let hashed = map.keys().any(|key| is_hashed_phone(key));
+    let mut hash_stmt = conn.prepare_cached("SELECT phone_enc(?1)")?;
+    ids.iter().map(|id| {
+        let found = match map.get(id) {
+            Some(resp) => Some(resp),
+            None if hashed => map.get(&hash_stmt.query_row([id], |row| row.get::<_, String>(0))?),
+            None => None,
+        };
+        Ok(found.cloned().unwrap_or_else(LookupResponse::not_found))
+    }).collect()
+}
+
+/// 批量请求中的 ID 规范化：去除首尾空白。
+fn canonicalize_id(id: &str) -> String {
+    id.trim().to_string()
+}
+
+// --- 错误上报 ---
+
+/// 一次上报的错误及其发生时的请求上下文（在请求之外发生时为 None）
+#[derive(Debug, Clone)]
+pub struct ErrorEvent {
+    pub kind: &'static str,            // DbError / IoError / panic
+    pub message: String,
+    pub location: Option<String>,      // panic 发生的源码位置
+    pub request_id: Option<String>,
+    pub endpoint: Option<String>,      // 路由模板，不含路径中的 ID
+    pub client: Option<String>,
+}
+
+/// 错误上报接口。配置了 error_reporting.dsn 时服务启动时安装 [`SentryReporter`]，
+/// 嵌入方也可以通过 [`set_error_reporter`] 接入其他监控系统。`report` 在请求处理或 panic 钩子中同步调用，不应阻塞。
+pub trait ErrorReporter: Send + Sync {
+    fn report(&self, event: ErrorEvent);
+}
+
+static ERROR_REPORTER: RwLock<Option<Arc<dyn ErrorReporter>>> = RwLock::new(None);
+
+/// 设置（或以 None 清除）全局错误上报器，并安装 panic 钩子。
+pub fn set_error_reporter(reporter: Option<Arc<dyn ErrorReporter>>) {
+    *ERROR_REPORTER.write().unwrap() = reporter;
+    install_panic_hook();
+}
+
+/// 在原有 panic 钩子（输出到 stderr）之外上报 panic，只安装一次
+fn install_panic_hook() {
+    static INSTALLED: OnceLock<()> = OnceLock::new();
+    INSTALLED.get_or_init(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            previous(info);
+            let message = info.payload().downcast_ref::<&str>().map(|m| m.to_string())
+                .or_else(|| info.payload().downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "panic".to_string());
+            let location = info.location().map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column()));
+            dispatch_error(ErrorEvent { location, ..error_event("panic", message) });
+        }));
+    });
+}
+
+/// 以当前任务的请求上下文构造事件
+fn error_event(kind: &'static str, message: String) -> ErrorEvent {
+    ErrorEvent {
+        kind,
+        message,
+        location: None,
+        request_id: REQUEST_ID.try_with(Clone::clone).ok(),
+        endpoint: ENDPOINT.try_with(Clone::clone).ok(),
+        client: CLIENT_IP.try_with(ToString::to_string).ok(),
+    }
+}
+
+fn dispatch_error(event: ErrorEvent) {
+    // panic 钩子中不能因锁中毒再次 panic
+    let reporter = match ERROR_REPORTER.read() {
+        Ok(reporter) => reporter.clone(),
+        Err(_) => return,
+    };
+    if let Some(reporter) = reporter {
+        reporter.report(event);
+    }
+}
+
+/// 上报一个意外错误（未配置上报器时什么也不做）
+fn report_error(kind: &'static str, message: &str) {
+    dispatch_error(error_event(kind, message.to_string()));
+}
+
+/// 按 Sentry store 协议 (sentry_version=7) 异步发送事件，兼容 Sentry 及 GlitchTip 等实现。
+/// 相同的错误在 ERROR_REPORT_DEDUP_SECS 内只发送一次；不在 tokio 运行时中（如主线程 panic）时丢弃事件。
+pub struct SentryReporter {
+    client: reqwest::Client,
+    store_url: String,
+    auth: String,
+    environment: String,
+    recent: Mutex<HashMap<String, Instant>>,
+}
+
+/// 解析 DSN `<scheme>://<public_key>@<host>[/<path>]/<project_id>`，返回 store 接口地址与公钥
+fn parse_sentry_dsn(dsn: &str) -> Result<(String, String), String> {
+    let dsn = reqwest::Url::parse(dsn).map_err(|e| e.to_string())?;
+    if !matches!(dsn.scheme(), "http" | "https") {
+        return Err(tr!("只支持 http / https", "only http and https are supported").to_string());
+    }
+    if dsn.username().is_empty() {
+        return Err(tr!("缺少公钥", "missing public key").to_string());
+    }
+    let host = dsn.host_str().ok_or_else(|| tr!("缺少主机名", "missing host").to_string())?;
+    let path = dsn.path().trim_end_matches('/');
+    let (prefix, project) = path.rsplit_once('/').unwrap_or(("", path));
+    if project.is_empty() {
+        return Err(tr!("缺少项目 ID", "missing project id").to_string());
+    }
+    let port = dsn.port().map(|p| format!(":{}", p)).unwrap_or_default();
+    Ok((format!("{}://{}{}{}/api/{}/store/", dsn.scheme(), host, port, prefix, project), dsn.username().to_string()))
+}
+
+impl SentryReporter {
+    pub fn new(config: &ErrorReportingConfig) -> Result<Self, String> {
+        let (store_url, key) = parse_sentry_dsn(&config.dsn)?;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(ERROR_REPORT_TIMEOUT_SECS))
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(SentryReporter {
+            client,
+            store_url,
+            auth: format!("Sentry sentry_version=7, sentry_client=cyber_lookup/{}, sentry_key={}", env!("CARGO_PKG_VERSION"), key),
+            environment: config.environment.clone(),
+            recent: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// 是否在去重窗口内已发送过相同的错误
+    fn is_duplicate(&self, event: &ErrorEvent) -> bool {
+        let key = format!("{}|{}|{}", event.kind, event.location.as_deref().unwrap_or(""), event.message);
+        let now = Instant::now();
+        let window = Duration::from_secs(ERROR_REPORT_DEDUP_SECS);
+        let Ok(mut recent) = self.recent.lock() else { return true };
+        recent.retain(|_, sent| now.duration_since(*sent) < window);
+        recent.insert(key, now).is_some()
+    }
+}
+
+impl ErrorReporter for SentryReporter {
+    fn report(&self, event: ErrorEvent) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else { return };
+        if self.is_duplicate(&event) {
+            return;
+        }
+        let mut tags = serde_json::Map::new();
+        for (key, value) in [("endpoint", &event.endpoint), ("request_id", &event.request_id)] {
+            if let Some(value) = value {
+                tags.insert(key.to_string(), serde_json::Value::from(value.as_str()));
+            }
+        }
+        let body = serde_json::json!({
+            "event_id": Uuid::new_v4().simple().to_string(),
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "platform": "other",
+            "level": if event.kind == "panic" { "fatal" } else { "error" },
+            "logger": "cyber_lookup",
+            "release": format!("cyber_lookup@{}", env!("CARGO_PKG_VERSION")),
+            "environment": self.environment,
+            "exception": { "values": [{ "type": event.kind, "value": event.message }] },
+            "culprit": event.location,
+            "tags": tags,
+            "user": event.client.as_ref().map(|ip| serde_json::json!({ "ip_address": ip })),
+            "extra": { "git_commit": env!("CYBER_LOOKUP_GIT_COMMIT") },
+        });
+        let request = self.client.post(&self.store_url).header("X-Sentry-Auth", &self.auth).json(&body);
+        runtime.spawn(async move {
+            if let Err(e) = request.send().await.and_then(|r| r.error_for_status()) {
+                eprintln!("{} {}", "WARN".yellow(), tr!("错误上报失败: {}", "Failed to report error: {}", e));
+            }
+        });
+    }
+}
+
+/// 配置了 error_reporting.dsn 且嵌入方未设置其他上报器时，安装 Sentry 上报器
+fn install_error_reporting(config: &ServiceConfig) {
+    if config.error_reporting.dsn.is_empty() || ERROR_REPORTER.read().unwrap().is_some() {
+        return;
+    }
+    match SentryReporter::new(&config.error_reporting) {
+        Ok(reporter) => {
+            println!("{} {}", "INFO".yellow(), tr!("错误上报已启用: {}", "Error reporting enabled: {}", reporter.store_url));
+            set_error_reporter(Some(Arc::new(reporter)));
+        }
+        Err(e) => eprintln!("{} {}", "WARN".yellow(), tr!("error_reporting.dsn 无效，错误上报未启用: {}", "Invalid error_reporting.dsn, error reporting disabled: {}", e)),
+    }
+}
+
+// --- 手机号字段加密 ---
+
+/// 手机号的确定性加密：AES-256-GCM-SIV，固定 nonce。相同号码总是得到相同密文，
+/// 因此 `phone_number = phone_enc(?)` 仍能使用唯一索引；代价是密文会暴露哪些记录的号码相同（本表中号码本就唯一）。
+/// 哈希模式下以同一密钥为盐计算 HMAC-SHA256，无法还原号码；已有的密文仍可解密，便于从加密模式迁移。
+pub struct PhoneCipher {
+    aead: aes_gcm_siv::Aes256GcmSiv,
+    mac: hmac::Hmac<sha2::Sha256>,
+    mode: PhoneProtection,
+}
+
+impl PhoneCipher {
+    const NONCE: [u8; 12] = [0; 12];
+
+    /// 按配置加载密钥：环境变量 CYBER_LOOKUP_PHONE_KEY 优先，其次 key_file。未启用时返回 None。
+    pub fn load(config: &PhoneEncryptionConfig) -> Result<Option<Self>, String> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        let text = match env::var(PHONE_KEY_ENV) {
+            Ok(key) if !key.trim().is_empty() => key,
+            _ if config.key_file.is_empty() => return Err(tr!("未设置 {} 且未配置 key_file", "{} is not set and key_file is not configured", PHONE_KEY_ENV)),
+            _ => fs::read_to_string(&config.key_file).map_err(|e| tr!("读取 {} 失败: {}", "Failed to read {}: {}", config.key_file, e))?,
+        };
+        Self::from_key_text(text.trim(), config.mode).map(Some)
+    }
+
+    /// 解析 64 位十六进制或 base64 编码的 32 字节密钥
+    fn from_key_text(text: &str, mode: PhoneProtection) -> Result<Self, String> {
+        use aes_gcm_siv::KeyInit;
+        use base64::Engine;
+        let key = if text.len() == 64 && text.bytes().all(|b| b.is_ascii_hexdigit()) {
+            (0..32).map(|i| u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).unwrap_or(0)).collect()
+        } else {
+            base64::engine::general_purpose::STANDARD.decode(text).map_err(|e| e.to_string())?
+        };
+        let aead = aes_gcm_siv::Aes256GcmSiv::new_from_slice(&key)
+            .map_err(|_| tr!("密钥必须是 32 字节 (实际 {} 字节)", "the key must be 32 bytes (got {})", key.len()))?;
+        let mac = <hmac::Hmac<sha2::Sha256> as hmac::Mac>::new_from_slice(&key).map_err(|e| e.to_string())?;
+        Ok(PhoneCipher { aead, mac, mode })
+    }
+
+    /// 已是目标形式的值原样返回，因此迁移可以重复执行
+    fn encrypt(&self, phone: &str) -> String {
+        use aes_gcm_siv::aead::Aead;
+        use base64::Engine;
+        use hmac::Mac;
+        if self.mode == PhoneProtection::Hash {
+            if is_hashed_phone(phone) {
+                return phone.to_string();
+            }
+            let digest = self.mac.clone().chain_update(phone.as_bytes()).finalize().into_bytes();
+            let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+            return format!("{}{}", HASHED_PHONE_PREFIX, hex);
+        }
+        if phone.starts_with(ENCRYPTED_PHONE_PREFIX) {
+            return phone.to_string();
+        }
+        let nonce = aes_gcm_siv::Nonce::from_slice(&Self::NONCE);
+        // 加密只会因明文超长 (> 64 GiB) 失败
+        let sealed = self.aead.encrypt(nonce, phone.as_bytes()).unwrap_or_default();
+        format!("{}{}", ENCRYPTED_PHONE_PREFIX, base64::engine::general_purpose::STANDARD_NO_PAD.encode(sealed))
+    }
+
+    /// 尚未迁移的明文与无法还原的哈希原样返回
+    fn decrypt(&self, value: &str) -> Result<String, String> {
+        use aes_gcm_siv::aead::Aead;
+        use base64::Engine;
+        let Some(encoded) = value.strip_prefix(ENCRYPTED_PHONE_PREFIX) else {
+            return Ok(value.to_string());
+        };
+        let sealed = base64::engine::general_purpose::STANDARD_NO_PAD.decode(encoded).map_err(|e| e.to_string())?;
+        let plain = self.aead.decrypt(aes_gcm_siv::Nonce::from_slice(&Self::NONCE), sealed.as_ref())
+            .map_err(|_| tr!("手机号解密失败，密钥与加密时不一致？", "Failed to decrypt a phone number; was it encrypted with a different key?").to_string())?;
+        String::from_utf8(plain).map_err(|e| e.to_string())
+    }
+}
+
+/// 库中保存的是手机号哈希而非号码本身（哈希模式），这样的值不得出现在响应中
+fn is_hashed_phone(value: &str) -> bool {
+    value.starts_with(HASHED_PHONE_PREFIX)
+}
+
+/// 在连接上注册 SQL 函数 phone_enc / phone_dec。未启用加密时两者原样返回（遇到密文时 phone_dec 报错），
+/// 所有读写 phone_number 的语句都经过这两个函数，因此无需区分两种模式。
+fn register_phone_functions(conn: &Connection, cipher: Option<Arc<PhoneCipher>>) -> SqlResult<()> {
+    use rusqlite::functions::FunctionFlags;
+    let flags = || FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+    let encrypt_with = cipher.clone();
+    conn.create_scalar_function("phone_enc", 1, flags(), move |ctx| {
+        let value: Option<String> = ctx.get(0)?;
+        Ok(match (&encrypt_with, value) {
+            (Some(cipher), Some(phone)) => Some(cipher.encrypt(&phone)),
+            (_, value) => value,
+        })
+    })?;
+    conn.create_scalar_function("phone_dec", 1, flags(), move |ctx| {
+        let value: Option<String> = ctx.get(0)?;
+        match (&cipher, value) {
+            (Some(cipher), Some(stored)) => cipher.decrypt(&stored).map(Some).map_err(|e| SqlError::UserFunctionError(e.into())),
+            (None, Some(stored)) if stored.starts_with(ENCRYPTED_PHONE_PREFIX) => Err(SqlError::UserFunctionError(
+                tr!("数据库中的手机号已加密，请启用 phone_encryption 并提供密钥。", "Phone numbers in the database are encrypted; enable phone_encryption and provide the key.").into())),
+            (_, value) => Ok(value),
+        }
+    })
+}
+
+/// 生成随机密钥并以十六进制写入 `path`（不覆盖已有文件，Unix 上权限为 0600）
+fn generate_phone_key(path: &str) -> Result<(), AppError> {
+    if let Some(dir) = FilePath::new(path).parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)
+        .map_err(|e| AppError::FatalError(tr!("无法创建密钥文件 {}: {}", "Cannot create key file {}: {}", path, e)))?;
+    let key: [u8; 32] = rand::random();
+    let hex: String = key.iter().map(|b| format!("{:02x}", b)).collect();
+    writeln!(file, "{}", hex)?;
+    Ok(())
+}
+
+/// 把现有数据库（及全部分片文件）中的手机号转换为 phone_encryption.mode 的形式（明文或密文 → 密文/哈希），
+/// 返回转换的条数。已是目标形式的记录保持不变，可重复执行。
+/// 完成后执行 VACUUM，清除空闲页中残留的明文。
+fn encrypt_phones(state: &AppState) -> Result<u64, AppError> {
+    if is_server_running(state) {
+        return Err(AppError::FatalError(tr!("HTTP 服务正在运行，拒绝迁移。请先停止服务。", "The HTTP server is running; refusing to migrate. Stop the server first.").to_string()));
+    }
+    let config = state.current_config();
+    if !config.phone_encryption.enabled {
+        return Err(AppError::ConfigError(tr!("请先在配置中启用 phone_encryption 并设置密钥。", "Enable phone_encryption and configure the key first.").to_string()));
+    }
+    let paths: Vec<String> = if config.storage.shards > 1 {
+        (0..config.storage.shards as usize).map(|index| shard_path(&config.db_path, index, config.storage.shards as usize)).collect()
+    } else {
+        vec![config.db_path.clone()]
+    };
+    let mut encrypted = 0;
+    for path in &paths {
+        let conn = state.open_connection(path)?;
+        let tx = conn.unchecked_transaction()?;
+        let prefix = match config.phone_encryption.mode {
+            PhoneProtection::Encrypt => ENCRYPTED_PHONE_PREFIX,
+            PhoneProtection::Hash => HASHED_PHONE_PREFIX,
+        };
+        let pattern = format!("{}%", prefix);
+        let changed = tx.execute("UPDATE user_mapping SET phone_number = phone_enc(phone_dec(phone_number)) WHERE phone_number NOT LIKE ?1", [&pattern])?;
+        tx.commit()?;
+        if changed > 0 {
+            conn.execute_batch("VACUUM; PRAGMA wal_checkpoint(TRUNCATE);")?;
+        }
+        println!("{} {}", "INFO".yellow(), tr!("{}: 转换 {} 条", "{}: converted {} row(s)", path, changed));
+        encrypted += changed as u64;
+    }
+    Ok(encrypted)
+}
+
+// --- 审计日志 ---
+
+/// 一条签名的审计记录，同时作为回执返回给请求方。`subject_digest` 为标识的 HMAC-SHA256（不记录标识本身），
+/// 持有签名密钥的合规人员可据此确认某个标识是否被擦除。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub receipt_id: String,
+    pub event: String,
+    pub time: String,
+    pub subject_digest: String,
+    pub records: usize,                  // 删除的映射条数（denied_lookup 为 0）
+    pub requested_by: String,            // API 密钥名称、admin 或 anonymous
+    pub client: Option<String>,
+    pub request_id: Option<String>,
+    pub signature: String,               // 对 signature 置空后的 JSON 计算的 HMAC-SHA256
+}
+
+impl AuditRecord {
+    /// 以当前请求上下文生成并签名一条记录
+    fn signed(event: &str, key: &str, subject: &str, records: usize) -> Self {
+        let requested_by = caller_name();
+        let mut record = AuditRecord {
+            receipt_id: Uuid::new_v4().to_string(),
+            event: event.to_string(),
+            time: Local::now().to_rfc3339(),
+            subject_digest: hmac_hex(key, subject.as_bytes()),
+            records,
+            requested_by,
+            client: CLIENT_IP.try_with(ToString::to_string).ok(),
+            request_id: REQUEST_ID.try_with(Clone::clone).ok(),
+            signature: String::new(),
+        };
+        record.signature = record.expected_signature(key);
+        record
+    }
+
+    fn expected_signature(&self, key: &str) -> String {
+        let unsigned = AuditRecord { signature: String::new(), ..self.clone() };
+        hmac_hex(key, serde_json::to_string(&unsigned).unwrap_or_default().as_bytes())
+    }
+}
+
+/// 当前请求的调用方：API 密钥名称、admin 或 anonymous
+fn caller_name() -> String {
+    CALLER.try_with(|caller| match &caller.key_name {
+        Some(name) => name.clone(),
+        None if caller.admin => "admin".to_string(),
+        None => "anonymous".to_string(),
+    }).unwrap_or_else(|_| "anonymous".to_string())
+}
+
+fn hmac_hex(key: &str, data: &[u8]) -> String {
+    use hmac::Mac;
+    // HMAC 接受任意长度的密钥
+    let mac = <hmac::Hmac<sha2::Sha256> as Mac>::new_from_slice(key.as_bytes()).expect("HMAC accepts any key length");
+    mac.chain_update(data).finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// 串行化审计日志的追加写入
+static AUDIT_LOCK: Mutex<()> = Mutex::new(());
+
+/// 追加一条审计记录并落盘（fsync），返回前记录已持久化
+fn append_audit(path: &str, record: &AuditRecord) -> io::Result<()> {
+    let _guard = AUDIT_LOCK.lock().unwrap();
+    if let Some(dir) = FilePath::new(path).parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(record).map_err(io::Error::other)?)?;
+    file.sync_data()
+}
+
+/// 校验审计日志中每条记录的签名，返回 (记录数, 签名不符或无法解析的行号)
+fn verify_audit_log(path: &str, key: &str) -> io::Result<(usize, Vec<usize>)> {
+    let content = fs::read_to_string(path)?;
+    let mut total = 0;
+    let mut invalid = Vec::new();
+    for (index, line) in content.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        total += 1;
+        match serde_json::from_str::<AuditRecord>(line) {
+            Ok(record) if constant_time_eq(record.signature.as_bytes(), record.expected_signature(key).as_bytes()) => {}
+            _ => invalid.push(index + 1),
+        }
+    }
+    Ok((total, invalid))
+}
+
+// --- 屏蔽名单 ---
+
+const DENY_LIST_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS denied_identifiers (
+    id TEXT PRIMARY KEY,
+    reason TEXT NOT NULL DEFAULT '',
+    added_at TEXT NOT NULL,
+    added_by TEXT NOT NULL
+)";
+
+#[derive(Debug, Serialize)]
+struct DeniedIdentifier {
+    id: String,
+    reason: String,
+    added_at: String,
+    added_by: String,                // API 密钥名称、admin 或 anonymous
+}
+
+/// 打开屏蔽名单文件并确保表存在
+fn open_deny_list(path: &str) -> SqlResult<Connection> {
+    if let Some(dir) = FilePath::new(path).parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).ok();
+    }
+    let conn = Connection::open(path)?;
+    conn.busy_timeout(Duration::from_secs(5))?;
+    conn.execute(DENY_LIST_SCHEMA, ())?;
+    Ok(conn)
+}
+
+/// 读取屏蔽名单中的全部标识；文件不存在时为空，不创建文件
+fn load_deny_list(path: &str) -> SqlResult<HashSet<String>> {
+    if !FilePath::new(path).exists() {
+        return Ok(HashSet::new());
+    }
+    let conn = open_deny_list(path)?;
+    let mut stmt = conn.prepare("SELECT id FROM denied_identifiers")?;
+    let ids = stmt.query_map((), |row| row.get::<_, String>(0))?.collect();
+    ids
+}
+
+impl AppState {
+    /// 当前的屏蔽名单，首次使用时从 deny_list.path 加载。加载失败时返回错误，查询随之失败而不是放过受保护的标识。
+    fn deny_list(&self) -> Result<Arc<HashSet<String>>, AppError> {
+        if let Some(list) = self.deny_list.read().unwrap().as_ref() {
+            return Ok(list.clone());
+        }
+        let path = self.current_config().deny_list.path;
+        let list = Arc::new(load_deny_list(&path)
+            .map_err(|e| AppError::StoreUnavailable(tr!("无法读取屏蔽名单 {}: {}", "Cannot read the deny list {}: {}", path, e)))?);
+        *self.deny_list.write().unwrap() = Some(list.clone());
+        Ok(list)
+    }
+
+    /// 名单修改后丢弃本进程（含各租户）已加载的名单，下次查询时重新读取
+    fn reload_deny_list(&self) {
+        *self.deny_list.write().unwrap() = None;
+        for tenant in self.tenants.get().into_iter().flat_map(|tenants| tenants.values()) {
+            *tenant.deny_list.write().unwrap() = None;
+        }
+    }
+}
+
+/// 把命中屏蔽名单的结果（查询的 ID 或结果中的 uid / 手机号在名单中）按 deny_list.respond_with 替换为 not_found
+/// 或 DENIED 错误，并为每次命中写一条审计记录。返回命中的条数。
+async fn apply_deny_list(state: &Arc<AppState>, ids: &[String], results: &mut [LookupResponse]) -> Result<usize, AppError> {
+    let list = state.deny_list()?;
+    if list.is_empty() {
+        return Ok(0);
+    }
+    let config = state.current_config();
+    let mut denied = Vec::new();
+    for (id, resp) in ids.iter().zip(results.iter_mut()) {
+        let hit = [Some(id.as_str()), resp.uid.as_deref(), resp.phone_number.as_deref()].into_iter().flatten().find(|value| list.contains(*value));
+        if let Some(hit) = hit {
+            denied.push(hit.to_string());
+            *resp = match config.deny_list.respond_with {
+                DeniedResponse::NotFound => LookupResponse::not_found(),
+                DeniedResponse::Unavailable => LookupResponse::failed(ApiErrorCode::Denied),
+            };
+        }
+    }
+    let count = denied.len();
+    if count > 0 {
+        audit_denied_lookups(&config, denied).await;
+    }
+    Ok(count)
+}
+
+/// 为命中屏蔽名单的查询写入审计记录（只记录标识的摘要）。未配置审计签名密钥时只输出警告；写入失败不影响响应。
+async fn audit_denied_lookups(config: &ServiceConfig, subjects: Vec<String>) {
+    let Some(key) = config.audit.key() else {
+        eprintln!("{} {}", "WARN".yellow(), tr!("{} 次查询命中屏蔽名单；未配置 audit.signing_key，未写入审计日志。", "{} lookup(s) hit the deny list; audit.signing_key is not configured, so nothing was written to the audit log.", subjects.len()));
+        return;
+    };
+    // 记录须在请求任务中生成，才能带上调用方、客户端与请求 ID
+    let records: Vec<AuditRecord> = subjects.iter().map(|subject| AuditRecord::signed("denied_lookup", &key, subject, 0)).collect();
+    let path = config.audit.path.clone();
+    match task::spawn_blocking(move || records.iter().try_for_each(|record| append_audit(&path, record))).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => eprintln!("{} {}", "ERR".red(), tr!("写入屏蔽名单命中的审计记录失败: {}", "Failed to write the audit record for a deny list hit: {}", e)),
+        Err(e) => eprintln!("{} {}", "ERR".red(), tr!("后台任务异常终止: {}", "Background task aborted: {}", e)),
+    }
+}
+
+/// `POST /admin/deny_list` 的请求体
+#[derive(Deserialize)]
+struct DenyListAddRequest {
+    ids: Vec<String>,
+    #[serde(default)]
+    reason: String,
+}
+
+#[derive(Serialize)]
+struct DenyListResponse {
+    entries: Vec<DeniedIdentifier>,
+}
+
+#[derive(Serialize)]
+struct DenyListChange {
+    changed: usize,                  // 新加入或移除的条数
+}
+
+/// 屏蔽名单的全部条目，按加入时间排序
+async fn api_admin_deny_list(State(state): State<Arc<AppState>>) -> Result<Json<DenyListResponse>, AppError> {
+    let path = state.current_config().deny_list.path;
+    let entries = task::spawn_blocking(move || -> SqlResult<Vec<DeniedIdentifier>> {
+        if !FilePath::new(&path).exists() {
+            return Ok(Vec::new());
+        }
+        let conn = open_deny_list(&path)?;
+        let mut stmt = conn.prepare("SELECT id, reason, added_at, added_by FROM denied_identifiers ORDER BY added_at, id")?;
+        let entries = stmt.query_map((), |row| Ok(DeniedIdentifier { id: row.get(0)?, reason: row.get(1)?, added_at: row.get(2)?, added_by: row.get(3)? }))?.collect();
+        entries
+    }).await.map_err(|e| AppError::FatalError(e.to_string()))??;
+    Ok(Json(DenyListResponse { entries }))
+}
+
+/// 把一组标识加入屏蔽名单（已在名单中的忽略），即时生效
+async fn api_admin_deny_list_add(State(state): State<Arc<AppState>>, Json(request): Json<DenyListAddRequest>) -> Result<Json<DenyListChange>, AppError> {
+    let ids: Vec<String> = request.ids.iter().map(|id| canonicalize_id(id)).filter(|id| !id.is_empty()).collect();
+    if ids.is_empty() {
+        return Err(AppError::Rejected(ApiErrorCode::InvalidRequest, tr!("ids 不能为空。", "ids must not be empty.").to_string()));
+    }
+    validate_ids(&ids)?;
+    let path = state.current_config().deny_list.path;
+    let (reason, added_by, added_at) = (request.reason, caller_name(), Local::now().to_rfc3339());
+    let changed = task::spawn_blocking(move || -> SqlResult<usize> {
+        let mut conn = open_deny_list(&path)?;
+        let tx = conn.transaction()?;
+        let mut added = 0;
+        {
+            let mut stmt = tx.prepare("INSERT OR IGNORE INTO denied_identifiers (id, reason, added_at, added_by) VALUES (?1, ?2, ?3, ?4)")?;
+            for id in &ids {
+                added += stmt.execute([id, &reason, &added_at, &added_by])?;
+            }
+        }
+        tx.commit()?;
+        Ok(added)
+    }).await.map_err(|e| AppError::FatalError(e.to_string()))??;
+    state.reload_deny_list();
+    println!("{} {}", "INFO".yellow(), tr!("已通过 API 向屏蔽名单加入 {} 个标识", "Added {} identifier(s) to the deny list via API", changed));
+    Ok(Json(DenyListChange { changed }))
+}
+
+/// 从屏蔽名单中移除一个标识
+async fn api_admin_deny_list_remove(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Result<Json<DenyListChange>, AppError> {
+    let path = state.current_config().deny_list.path;
+    let target = canonicalize_id(&id);
+    let changed = task::spawn_blocking(move || -> SqlResult<usize> {
+        if !FilePath::new(&path).exists() {
+            return Ok(0);
+        }
+        open_deny_list(&path)?.execute("DELETE FROM denied_identifiers WHERE id = ?1", [&target])
+    }).await.map_err(|e| AppError::FatalError(e.to_string()))??;
+    if changed == 0 {
+        return Err(AppError::Rejected(ApiErrorCode::InvalidRequest, tr!("{} 不在屏蔽名单中。", "{} is not on the deny list.", id)));
+    }
+    state.reload_deny_list();
+    println!("{} {}", "INFO".yellow(), tr!("已通过 API 从屏蔽名单移除 {}", "Removed {} from the deny list via API", id));
+    Ok(Json(DenyListChange { changed }))
+}
+
+// --- 慢查询记录 ---
+
+/// 慢查询阈值与统计，由 [`TimedStore`] 写入，`/stats` 读取
+struct SlowQueryLog {
+    threshold_ms: AtomicU64,         // 随配置更新，0 表示关闭
+    total: AtomicU64,
+    recent: Mutex<VecDeque<SlowQuery>>,
+}
+
+impl SlowQueryLog {
+    fn new(threshold_ms: u64) -> Self {
+        SlowQueryLog { threshold_ms: AtomicU64::new(threshold_ms), total: AtomicU64::new(0), recent: Mutex::new(VecDeque::with_capacity(SLOW_QUERY_LIMIT)) }
+    }
+
+    fn observe(&self, operation: &'static str, batch_size: Option<usize>, started: Instant) {
+        let threshold_ms = self.threshold_ms.load(Ordering::Relaxed);
+        let elapsed = started.elapsed();
+        log_at("cyber_lookup::db", LogLevel::Trace, &format!("{} took {:.2} ms (batch size {})", operation, elapsed.as_secs_f64() * 1000.0,
+            batch_size.map(|n| n.to_string()).unwrap_or_else(|| "-".to_string())));
+        if threshold_ms == 0 || elapsed < Duration::from_millis(threshold_ms) {
+            return;
+        }
+        let entry = SlowQuery {
+            time: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            operation,
+            endpoint: ENDPOINT.try_with(Clone::clone).ok(),
+            batch_size,
+            elapsed_ms: elapsed.as_secs_f64() * 1000.0,
+        };
+        let request_id = REQUEST_ID.try_with(Clone::clone).ok();
+        eprintln!("{} {}", "WARN".yellow(), tr!(
+            "慢查询: {} 用时 {:.1} ms (接口 {}, 批量 {}, 请求 {})",
+            "Slow query: {} took {:.1} ms (endpoint {}, batch size {}, request {})",
+            operation, entry.elapsed_ms, entry.endpoint.as_deref().unwrap_or("-"),
+            batch_size.map(|n| n.to_string()).unwrap_or_else(|| "-".to_string()), request_id.as_deref().unwrap_or("-")));
+        self.total.fetch_add(1, Ordering::Relaxed);
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() >= SLOW_QUERY_LIMIT {
+            recent.pop_front();
+        }
+        recent.push_back(entry);
+    }
+}
+
+/// 包装最终使用的存储后端，对查询、写入、删除、计数与导出计时（含 Redis 缓存层），超过阈值的记入 [`SlowQueryLog`]。
+/// 探活与建表等管理操作直接转发。
+struct TimedStore {
+    inner: Arc<dyn MappingStore>,
+    log: Arc<SlowQueryLog>,
+}
+
+#[axum::async_trait]
+impl MappingStore for TimedStore {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    async fn initialize(&self) -> Result<(), AppError> {
+        self.inner.initialize().await
+    }
+
+    async fn ping(&self) -> Result<(), AppError> {
+        self.inner.ping().await
+    }
+
+    async fn check_schema(&self) -> Result<(), AppError> {
+        self.inner.check_schema().await
+    }
+
+    async fn round_trip(&self) -> Result<(), AppError> {
+        self.inner.round_trip().await
+    }
+
+    async fn lookup(&self, id: &str) -> Result<LookupResponse, AppError> {
+        let started = Instant::now();
+        let result = self.inner.lookup(id).await;
+        self.log.observe("lookup", None, started);
+        result
+    }
+
+    async fn batch_lookup(&self, ids: Vec<String>) -> Result<Vec<LookupResponse>, AppError> {
+        let (started, batch_size) = (Instant::now(), ids.len());
+        let result = self.inner.batch_lookup(ids).await;
+        self.log.observe("batch_lookup", Some(batch_size), started);
+        result
+    }
+
+    async fn insert(&self, mappings: Vec<(String, String)>, strategy: ConflictStrategy, dry_run: bool) -> Result<WriteReport, AppError> {
+        let (started, batch_size) = (Instant::now(), mappings.len());
+        let result = self.inner.insert(mappings, strategy, dry_run).await;
+        self.log.observe("insert", Some(batch_size), started);
+        result
+    }
+
+    async fn delete(&self, ids: Vec<String>, dry_run: bool) -> Result<DeleteReport, AppError> {
+        let (started, batch_size) = (Instant::now(), ids.len());
+        let result = self.inner.delete(ids, dry_run).await;
+        self.log.observe("delete", Some(batch_size), started);
+        result
+    }
+
+    async fn count(&self, uid_prefix: Option<String>) -> Result<u64, AppError> {
+        let started = Instant::now();
+        let result = self.inner.count(uid_prefix).await;
+        self.log.observe("count", None, started);
+        result
+    }
+
+    async fn export(&self, uid_prefix: Option<String>, sink: ExportSink) -> Result<u64, AppError> {
+        let started = Instant::now();
+        let result = self.inner.export(uid_prefix, sink).await;
+        self.log.observe("export", result.as_ref().ok().map(|&n| n as usize), started);
+        result
+    }
+
+    async fn export_snapshot(&self, uid_prefix: Option<String>, on_snapshot: SnapshotHook, sink: ExportSink) -> Result<u64, AppError> {
+        let started = Instant::now();
+        let result = self.inner.export_snapshot(uid_prefix, on_snapshot, sink).await;
+        self.log.observe("export", result.as_ref().ok().map(|&n| n as usize), started);
+        result
+    }
+
+    async fn merge(&self, groups: Vec<MergeGroup>) -> Result<MergeReport, AppError> {
+        let started = Instant::now();
+        let result = self.inner.merge(groups).await;
+        self.log.observe("merge", result.as_ref().ok().map(|r| r.merged), started);
+        result
+    }
+}
+
+// --- API 路由处理器 (保持不变) ---
+/// 查询单个 uid 或手机号：依次经过布隆过滤器、缓存与数据库，与 `GET /lookup/:id` 一致。
+pub async fn lookup(state: &Arc<AppState>, id: &str) -> Result<LookupResponse, AppError> {
+    let id = apply_normalize_hook(state, id.to_string())?;
+    let mut resp = lookup_unfiltered(state, &id).await?;
+    if apply_deny_list(state, std::slice::from_ref(&id), std::slice::from_mut(&mut resp)).await? > 0
+        && state.current_config().deny_list.respond_with == DeniedResponse::Unavailable {
+        return Err(AppError::Denied(tr!("{} 受保护，不提供查询。", "{} is protected and cannot be looked up.", id)));
+    }
+    Ok(resp)
+}
+
+/// 本地（含号码变体）与上游查询，尚未经过屏蔽名单
+async fn lookup_unfiltered(state: &Arc<AppState>, id: &str) -> Result<LookupResponse, AppError> {
+    let resp = lookup_local(state, id).await?;
+    if resp.status != "not_found" {
+        return Ok(resp);
+    }
+    if let Some(variant) = state.current_config().phone_rules.lookup_variant(id) {
+        let resp = lookup_local(state, &variant).await?;
+        if resp.status != "not_found" {
+            return Ok(resp);
+        }
+    }
+    Ok(resolve_upstream(state, std::slice::from_ref(&id.to_string())).await.pop().unwrap_or(resp))
+}
+
+async fn lookup_local(state: &Arc<AppState>, id: &str) -> Result<LookupResponse, AppError> {
+    // 布隆过滤器判定一定不存在时直接返回，不访问缓存和数据库
+    if !state.bloom_may_contain(id) {
+        return Ok(LookupResponse::not_found());
+    }
+    if let Some(cached) = state.cache_get(id) {
+        return Ok(LookupResponse::from_cached_single(id, cached));
+    }
+    let generation = state.cache_generation();
+    let resp = state.store()?.lookup(id).await?;
+    state.cache_put(id, &resp, generation);
+    Ok(resp)
+}
+
+// --- 上游回源查询 ---
+
+/// 本地未找到的 ID 交给上游查询，结果与输入一一对应（未启用、未找到或上游出错时为 not_found）。
+/// 启用 write_back 时把找到的映射写入本地存储，否则按 cache_ttl_secs 缓存在内存中。
+async fn resolve_upstream(state: &Arc<AppState>, ids: &[String]) -> Vec<LookupResponse> {
+    let config = state.current_config();
+    let upstream = &config.upstream;
+    if !upstream.enabled || ids.is_empty() {
+        return ids.iter().map(|_| LookupResponse::not_found()).collect();
+    }
+    let mut answers: Vec<Option<CachedMapping>> = ids.iter().map(|id| state.upstream_cache.get(id)).collect();
+    let pending: Vec<String> = ids.iter().zip(&answers).filter(|(_, cached)| cached.is_none()).map(|(id, _)| id.clone()).collect();
+    if !pending.is_empty() {
+        state.upstream_queries.fetch_add(pending.len() as u64, Ordering::Relaxed);
+        let fetched = match upstream.mode {
+            UpstreamMode::CyberLookup => fetch_upstream_batches(state, &config, &pending).await,
+            UpstreamMode::Http => fetch_upstream_each(state, upstream, &pending).await,
+        };
+        let mut found = Vec::new();
+        for (id, answer) in pending.iter().zip(fetched) {
+            // 出错的 ID 不缓存，下次重试
+            let Some(answer) = answer else { continue };
+            if let Some(pair) = &answer {
+                found.push(pair.clone());
+            }
+            if !upstream.write_back && upstream.cache_ttl_secs > 0 {
+                state.upstream_cache.insert(id.clone(), answer.clone());
+            }
+            let index = ids.iter().position(|candidate| candidate == id).unwrap_or_default();
+            answers[index] = Some(answer);
+        }
+        state.upstream_found.fetch_add(found.len() as u64, Ordering::Relaxed);
+        if upstream.write_back && !found.is_empty() {
+            write_back_upstream(state, found).await;
+        }
+    }
+    answers.into_iter()
+        .map(|answer| match answer.flatten() {
+            Some((uid, phone)) => LookupResponse { status: "found_upstream".to_string(), uid: Some(uid), phone_number: Some(phone), matched_by: None, query: None, error: None },
+            None => LookupResponse::not_found(),
+        })
+        .collect()
+}
+
+fn upstream_request(upstream: &UpstreamConfig, builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    let builder = builder.timeout(Duration::from_millis(upstream.timeout_ms));
+    if upstream.api_key.is_empty() {
+        builder
+    } else {
+        builder.header(API_KEY_HEADER, &upstream.api_key)
+    }
+}
+
+fn upstream_failed(state: &AppState, error: impl std::fmt::Display) {
+    state.upstream_errors.fetch_add(1, Ordering::Relaxed);
+    eprintln!("{} {}", "WARN".yellow(), tr!("上游查询失败，按未找到处理: {}", "Upstream lookup failed, treating as not found: {}", error));
+}
+
+/// cyber_lookup 模式：按 batch_chunk_size 分批查询上游的 /batch_lookup。外层 None 表示该 ID 查询出错。
+async fn fetch_upstream_batches(state: &AppState, config: &ServiceConfig, ids: &[String]) -> Vec<Option<CachedMapping>> {
+    #[derive(Deserialize)]
+    struct UpstreamBatch {
+        results: Vec<UpstreamResult>,
+    }
+    #[derive(Deserialize)]
+    struct UpstreamResult {
+        status: String,
+        uid: Option<String>,
+        phone_number: Option<String>,
+    }
+    let url = format!("{}/batch_lookup", config.upstream.url.trim_end_matches('/'));
+    let mut answers = Vec::with_capacity(ids.len());
+    for chunk in ids.chunks(config.batch_chunk_size.max(1) as usize) {
+        let request = upstream_request(&config.upstream, state.upstream_client.post(&url).json(&serde_json::json!({ "ids": chunk })));
+        let batch = async {
+            let batch: UpstreamBatch = request.send().await?.error_for_status()?.json().await?;
+            Ok::<_, reqwest::Error>(batch)
+        }.await;
+        match batch {
+            Ok(batch) if batch.results.len() == chunk.len() => answers.extend(batch.results.into_iter().map(|r| {
+                Some(match (r.status.as_str(), r.uid, r.phone_number) {
+                    ("not_found", _, _) => None,
+                    (_, Some(uid), Some(phone)) => Some((uid, phone)),
+                    _ => None,
+                })
+            })),
+            Ok(batch) => {
+                upstream_failed(state, tr!("返回 {} 条结果，请求了 {} 个 ID", "{} result(s) returned for {} ID(s)", batch.results.len(), chunk.len()));
+                answers.extend(chunk.iter().map(|_| None));
+            }
+            Err(e) => {
+                upstream_failed(state, e);
+                answers.extend(chunk.iter().map(|_| None));
+            }
+        }
+    }
+    answers
+}
+
+/// http 模式：以 batch_parallelism 的并发逐个 GET。外层 None 表示该 ID 查询出错。
+async fn fetch_upstream_each(state: &Arc<AppState>, upstream: &UpstreamConfig, ids: &[String]) -> Vec<Option<CachedMapping>> {
+    #[derive(Deserialize)]
+    struct UpstreamMapping {
+        uid: String,
+        phone_number: String,
+    }
+    let parallelism = Arc::new(tokio::sync::Semaphore::new(state.current_config().batch_parallelism.max(1) as usize));
+    let mut tasks = task::JoinSet::new();
+    for (index, id) in ids.iter().enumerate() {
+        let url = upstream.url.replace("{id}", &utf8_percent_encode(id));
+        let request = upstream_request(upstream, state.upstream_client.get(url));
+        let parallelism = parallelism.clone();
+        tasks.spawn(async move {
+            let _permit = parallelism.acquire_owned().await;
+            let answer = async {
+                let response = request.send().await?;
+                if response.status() == StatusCode::NOT_FOUND {
+                    return Ok(None);
+                }
+                let mapping: UpstreamMapping = response.error_for_status()?.json().await?;
+                Ok::<_, reqwest::Error>(Some((mapping.uid, mapping.phone_number)))
+            }.await;
+            (index, answer)
+        });
+    }
+    let mut answers = vec![None; ids.len()];
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok((index, Ok(answer))) => answers[index] = Some(answer),
+            Ok((_, Err(e))) => upstream_failed(state, e),
+            Err(e) => upstream_failed(state, e),
+        }
+    }
+    answers
+}
+
+// --- 流量镜像 (shadow) ---
+
+/// 镜像请求的累计计数与当前并发数
+#[derive(Default)]
+struct ShadowCounters {
+    mirrored: AtomicU64,             // 已转发并得到结果的 ID 数
+    matched: AtomicU64,
+    diverged: AtomicU64,
+    errors: AtomicU64,               // 镜像请求失败或结果条数不符的 ID 数
+    dropped: AtomicU64,              // 超过 max_in_flight 而未转发的 ID 数
+    in_flight: AtomicU64,
+}
+
+/// 按 shadow.percent 抽样，把这次查询的 ID 异步转发给镜像实例，与本地结果（后处理与脱敏之前）比较。
+/// 只比较是否找到及 uid / 手机号，单条查询与批量查询的状态名差异不算不一致。
+fn mirror_lookup(state: &Arc<AppState>, config: &ServiceConfig, ids: &[String], primary: &[LookupResponse]) {
+    let shadow = &config.shadow;
+    if !shadow.enabled || ids.is_empty() || rand::random::<f64>() * 100.0 >= shadow.percent {
+        return;
+    }
+    let counters = &state.shadow;
+    if counters.in_flight.fetch_add(1, Ordering::Relaxed) >= shadow.max_in_flight {
+        counters.in_flight.fetch_sub(1, Ordering::Relaxed);
+        counters.dropped.fetch_add(ids.len() as u64, Ordering::Relaxed);
+        return;
+    }
+    let (state, shadow, ids) = (state.clone(), shadow.clone(), ids.to_vec());
+    let primary: Vec<CachedMapping> = primary.iter().map(LookupResponse::cache_entry).collect();
+    tokio::spawn(async move {
+        let result = fetch_shadow(&state, &shadow, &ids).await;
+        let counters = &state.shadow;
+        counters.in_flight.fetch_sub(1, Ordering::Relaxed);
+        match result {
+            Ok(answers) if answers.len() == ids.len() => {
+                counters.mirrored.fetch_add(ids.len() as u64, Ordering::Relaxed);
+                for ((id, mine), theirs) in ids.iter().zip(&primary).zip(&answers) {
+                    if mine == theirs {
+                        counters.matched.fetch_add(1, Ordering::Relaxed);
+                    } else {
+                        counters.diverged.fetch_add(1, Ordering::Relaxed);
+                        log_debug("cyber_lookup::shadow", &format!("divergence for {}: primary {:?}, shadow {:?}", id, mine, theirs));
+                    }
+                }
+            }
+            Ok(answers) => {
+                counters.errors.fetch_add(ids.len() as u64, Ordering::Relaxed);
+                log_debug("cyber_lookup::shadow", &format!("{} result(s) returned for {} id(s)", answers.len(), ids.len()));
+            }
+            Err(e) => {
+                counters.errors.fetch_add(ids.len() as u64, Ordering::Relaxed);
+                log_debug("cyber_lookup::shadow", &format!("mirror request failed: {}", e));
+            }
+        }
+    });
+}
+
+/// 以一次 POST {url}/batch_lookup 查询镜像实例，结果与 `ids` 一一对应
+async fn fetch_shadow(state: &AppState, shadow: &ShadowConfig, ids: &[String]) -> Result<Vec<CachedMapping>, reqwest::Error> {
+    #[derive(Deserialize)]
+    struct ShadowBatch {
+        results: Vec<ShadowResult>,
+    }
+    #[derive(Deserialize)]
+    struct ShadowResult {
+        status: String,
+        uid: Option<String>,
+        phone_number: Option<String>,
+    }
+    let url = format!("{}/batch_lookup", shadow.url.trim_end_matches('/'));
+    let mut request = state.upstream_client.post(url)
+        .timeout(Duration::from_millis(shadow.timeout_ms))
+        .json(&serde_json::json!({ "ids": ids }));
+    if !shadow.api_key.is_empty() {
+        request = request.header(API_KEY_HEADER, &shadow.api_key);
+    }
+    let batch: ShadowBatch = request.send().await?.error_for_status()?.json().await?;
+    Ok(batch.results.into_iter().map(|r| match (r.status.as_str(), r.uid, r.phone_number) {
+        ("not_found", _, _) => None,
+        (_, Some(uid), Some(phone)) => Some((uid, phone)),
+        _ => None,
+    }).collect())
+}
+
+/// URL 路径段中的 ID 按 UTF-8 百分号编码（保留非保留字符）
+fn utf8_percent_encode(id: &str) -> String {
+    id.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// write_back：把上游找到的映射写入本地存储，已有的记录保持不变
+async fn write_back_upstream(state: &Arc<AppState>, found: Vec<(String, String)>) {
+    let rules = state.current_config().phone_rules;
+    let found: Vec<(String, String)> = found.into_iter()
+        .filter_map(|(uid, phone)| rules.normalize_for_write(&phone).ok().map(|phone| (uid, phone)))
+        .collect();
+    let ids: Vec<String> = found.iter().flat_map(|(uid, phone)| [uid.clone(), phone.clone()]).collect();
+    let written = async {
+        validate_ids(&ids)?;
+        state.store()?.insert(found, ConflictStrategy::Skip, false).await
+    }.await;
+    match written {
+        Ok(report) => {
+            state.invalidate_cache(report.affected_keys);
+            let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+            state.bloom_insert(&ids);
+        }
+        Err(e) => eprintln!("{} {}", "WARN".yellow(), tr!("上游结果写回本地失败: {}", "Failed to write upstream results back locally: {}", e)),
+    }
+}
+
+/// 批量查询，结果与输入一一对应（重复 ID 只查询一次），与 `POST /batch_lookup` 一致。
+pub async fn lookup_batch(state: &Arc<AppState>, ids: &[String]) -> Result<Vec<LookupResponse>, AppError> {
+    let config = state.current_config();
+    check_batch_size(&config, ids.len())?;
+    resolve_batch(state, &config, ids).await
+}
+
+// --- 脚本钩子 (Rhai) ---
+
+/// 一组已编译的脚本钩子，对应文件不存在的钩子为 None。
+/// 脚本以最后一个表达式的值作为结果，只读取输入变量，无法访问文件或网络。
+struct ScriptHooks {
+    engine: rhai::Engine,
+    normalize_id: Option<rhai::AST>,    // 输入 `id`，返回规范化后的 ID 字符串；返回 () 表示不修改
+    validate_insert: Option<rhai::AST>, // 输入 `uid`、`phone`，返回 true / () 接受，false 或拒绝原因字符串拒绝
+    post_process: Option<rhai::AST>,    // 输入 `result` (#{status, uid, phone_number})，返回修改后的 map；返回 () 表示不修改
+}
+
+/// 脚本钩子文件的修改时间与引擎限制，变化时重新加载
+type ScriptFingerprint = (String, u64, Vec<Option<std::time::SystemTime>>);
+
+fn script_fingerprint(config: &ScriptingConfig) -> ScriptFingerprint {
+    let mtimes = SCRIPT_HOOK_NAMES.iter()
+        .map(|name| fs::metadata(FilePath::new(&config.dir).join(format!("{}.rhai", name))).and_then(|m| m.modified()).ok())
+        .collect();
+    (config.dir.clone(), config.max_operations, mtimes)
+}
+
+impl ScriptHooks {
+    fn load(config: &ScriptingConfig) -> Result<Self, String> {
+        let mut engine = rhai::Engine::new();
+        engine.set_max_operations(config.max_operations);
+        engine.set_max_call_levels(32);
+        engine.set_max_expr_depths(64, 32);
+        engine.set_max_string_size(MAX_DATA_LENGTH * 16);
+        engine.on_print(|text| println!("{} {}", "SCRIPT".cyan(), text));
+        engine.on_debug(|text, _, _| println!("{} {}", "SCRIPT".cyan(), text));
+        let mut compiled = Vec::new();
+        for name in SCRIPT_HOOK_NAMES {
+            let path = FilePath::new(&config.dir).join(format!("{}.rhai", name));
+            if !path.exists() {
+                compiled.push(None);
+                continue;
+            }
+            let source = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            compiled.push(Some(engine.compile(source).map_err(|e| format!("{}: {}", path.display(), e))?));
+        }
+        let mut compiled = compiled.into_iter();
+        Ok(ScriptHooks {
+            normalize_id: compiled.next().flatten(),
+            validate_insert: compiled.next().flatten(),
+            post_process: compiled.next().flatten(),
+            engine,
+        })
+    }
+
+    fn loaded(&self) -> Vec<&'static str> {
+        [&self.normalize_id, &self.validate_insert, &self.post_process].iter().zip(SCRIPT_HOOK_NAMES)
+            .filter(|(ast, _)| ast.is_some())
+            .map(|(_, name)| name)
+            .collect()
+    }
+
+    fn eval(&self, hook: &str, ast: &rhai::AST, scope: &mut rhai::Scope) -> Result<rhai::Dynamic, AppError> {
+        self.engine.eval_ast_with_scope::<rhai::Dynamic>(scope, ast)
+            .map_err(|e| AppError::ConfigError(tr!("脚本钩子 {} 执行失败: {}", "Script hook {} failed: {}", hook, e)))
+    }
+}
+
+/// 按配置（重新）加载脚本钩子；文件与配置未变化时不做任何事
+fn reload_script_hooks(state: &AppState, last: &mut Option<ScriptFingerprint>) {
+    let config = state.current_config().scripting;
+    if !config.enabled {
+        *state.scripts.write().unwrap() = None;
+        *last = None;
+        return;
+    }
+    let fingerprint = script_fingerprint(&config);
+    if last.as_ref() == Some(&fingerprint) {
+        return;
+    }
+    *last = Some(fingerprint);
+    match ScriptHooks::load(&config) {
+        Ok(hooks) => {
+            let loaded = hooks.loaded();
+            println!("{} {}", "INFO".yellow(), tr!("已加载脚本钩子 ({}): {}", "Script hooks loaded from {}: {}", config.dir, if loaded.is_empty() { "-".to_string() } else { loaded.join(", ") }));
+            *state.scripts.write().unwrap() = Some(Arc::new(hooks));
+        }
+        Err(e) => eprintln!("{} {}", "ERR".red(), tr!("脚本钩子编译失败，继续使用之前的版本: {}", "Failed to compile script hooks, keeping the previous version: {}", e)),
+    }
+}
+
+/// 后台检查脚本钩子文件，修改后重新加载（修改 scripting 配置同样无需重启）
+async fn run_script_reloader(state: Arc<AppState>, mut last: Option<ScriptFingerprint>) {
+    loop {
+        sleep(Duration::from_secs(SCRIPT_RELOAD_SECS)).await;
+        reload_script_hooks(&state, &mut last);
+    }
+}
+
+/// normalize_id 钩子：在内置规范化（去除首尾空白）之后执行，结果同样须为合法 ID
+fn apply_normalize_hook(state: &AppState, id: String) -> Result<String, AppError> {
+    let hooks = state.scripts.read().unwrap().clone();
+    let Some((hooks, ast)) = hooks.as_ref().and_then(|hooks| Some((hooks, hooks.normalize_id.as_ref()?))) else {
+        return Ok(id);
+    };
+    let mut scope = rhai::Scope::new();
+    scope.push("id", id.clone());
+    let result = hooks.eval("normalize_id", ast, &mut scope)?;
+    if result.is_unit() {
+        return Ok(id);
+    }
+    let normalized = result.into_string()
+        .map_err(|kind| AppError::ConfigError(tr!("脚本钩子 normalize_id 应返回字符串，实际为 {}", "Script hook normalize_id must return a string, got {}", kind)))?;
+    validate_ids([&normalized])?;
+    Ok(normalized)
+}
+
+/// validate_insert 钩子：逐条检查待写入的映射，任一条被拒绝时整批返回 400
+fn check_insert_hook(state: &AppState, mappings: &[(String, String)]) -> Result<(), AppError> {
+    let hooks = state.scripts.read().unwrap().clone();
+    let Some((hooks, ast)) = hooks.as_ref().and_then(|hooks| Some((hooks, hooks.validate_insert.as_ref()?))) else {
+        return Ok(());
+    };
+    for (uid, phone) in mappings {
+        let mut scope = rhai::Scope::new();
+        scope.push("uid", uid.clone());
+        scope.push("phone", phone.clone());
+        let result = hooks.eval("validate_insert", ast, &mut scope)?;
+        let reason = if result.is_unit() || result.as_bool() == Ok(true) {
+            continue;
+        } else if result.as_bool() == Ok(false) {
+            tr!("被 validate_insert 脚本拒绝", "rejected by the validate_insert script").to_string()
+        } else {
+            result.into_string()
+                .map_err(|kind| AppError::ConfigError(tr!("脚本钩子 validate_insert 应返回布尔值或字符串，实际为 {}", "Script hook validate_insert must return a bool or a string, got {}", kind)))?
+        };
+        return Err(AppError::Rejected(ApiErrorCode::InvalidRequest, tr!("映射 {} 无效: {}", "Mapping {} is invalid: {}", uid, reason)));
+    }
+    Ok(())
+}
+
+/// post_process 钩子：在脱敏之前修改返回给调用方的查询结果（缓存中保存的仍是原始结果）。
+/// GraphQL 接口返回类型化字段，不经过此钩子。
+fn apply_post_process_hook(state: &AppState, results: &mut [LookupResponse]) -> Result<(), AppError> {
+    let hooks = state.scripts.read().unwrap().clone();
+    let Some((hooks, ast)) = hooks.as_ref().and_then(|hooks| Some((hooks, hooks.post_process.as_ref()?))) else {
+        return Ok(());
+    };
+    let invalid = |what: &str| AppError::ConfigError(tr!("脚本钩子 post_process 返回的 {} 无效", "Script hook post_process returned an invalid {}", what));
+    let optional = |value: Option<String>| value.map(rhai::Dynamic::from).unwrap_or(rhai::Dynamic::UNIT);
+    for result in results.iter_mut() {
+        let mut map = rhai::Map::new();
+        map.insert("status".into(), result.status.clone().into());
+        map.insert("uid".into(), optional(result.uid.clone()));
+        map.insert("phone_number".into(), optional(result.phone_number.clone()));
+        let mut scope = rhai::Scope::new();
+        scope.push("result", map);
+        let processed = hooks.eval("post_process", ast, &mut scope)?;
+        if processed.is_unit() {
+            continue;
+        }
+        let mut map = processed.try_cast::<rhai::Map>().ok_or_else(|| invalid("map"))?;
+        let mut field = |name: &str| -> Result<Option<String>, AppError> {
+            match map.remove(name) {
+                Some(value) if value.is_unit() => Ok(None),
+                Some(value) => value.into_string().map(Some).map_err(|_| invalid(name)),
+                None => Ok(None),
+            }
+        };
+        // matched_by / query 描述的是输入，不交给脚本修改
+        *result = LookupResponse {
+            status: field("status")?.ok_or_else(|| invalid("status"))?,
+            uid: field("uid")?,
+            phone_number: field("phone_number")?,
+            ..result.clone()
+        };
+    }
+    Ok(())
+}
+
+fn report_start_error(error: AppError) {
+    match error {
+        AppError::NetworkBindError(e) => {
+            eprintln!("{} {}", "FAIL".red(), tr!("启动失败 (端口冲突或权限不足): {}", "Start failed (port in use or permission denied): {}", e));
+            eprintln!("{} {}", "HINT".yellow(), tr!("请使用 'config' 修改 bind_address。", "Use 'config' to change bind_address."));
+        }
+        AppError::DbError(_) => {}
+        AppError::FatalError(m) => eprintln!("{} {}", "FAIL".red(), tr!("启动失败 (致命配置错误): {}", "Start failed (fatal configuration error): {}", m)),
+        e => {
+            eprintln!("{} {}", "FAIL".red(), tr!("发生未知错误: {:?}", "Unexpected error: {:?}", e));
+        }
+    }
+}
+
+/// `start --supervise`：服务出错退出或 panic 时按指数退避自动重启，连续重启 SUPERVISE_MAX_RESTARTS 次后放弃并回到管理模式；
+/// 稳定运行超过 SUPERVISE_STABLE_SECS 后重启计数清零。release 构建使用 panic = "abort"，panic 会直接结束进程，不在此恢复。
+async fn supervise_server(state: Arc<AppState>) {
+    let mut restarts = 0;
+    let mut backoff = Duration::from_millis(SUPERVISE_INITIAL_BACKOFF_MS);
+    loop {
+        println!("{}", tr!("尝试启动服务 (监督模式)...", "Starting server (supervised)...").yellow());
+        let started = Instant::now();
+        match tokio::spawn(try_start_server(state.clone())).await {
+            Ok(Ok(())) => {
+                println!("{}", tr!("服务已停止。", "Server stopped.").red());
+                return;
+            }
+            Ok(Err(e)) => report_start_error(e),
+            Err(e) => {
+                state.server_running.store(false, Ordering::SeqCst);
+                eprintln!("{} {}", "FAIL".red(), tr!("服务任务异常终止: {}", "Server task panicked: {}", e));
+            }
+        }
+        if started.elapsed() >= Duration::from_secs(SUPERVISE_STABLE_SECS) {
+            restarts = 0;
+            backoff = Duration::from_millis(SUPERVISE_INITIAL_BACKOFF_MS);
+        }
+        if restarts >= SUPERVISE_MAX_RESTARTS {
+            eprintln!("{} {}", "FAIL".red(), tr!("服务已连续重启 {} 次仍失败，停止监督。", "Server failed after {} consecutive restarts; giving up.", restarts));
+            return;
+        }
+        restarts += 1;
+        eprintln!("{} {}", "WARN".yellow(), tr!("{:.1?} 后重启服务 (第 {}/{} 次)", "Restarting server in {:.1?} (attempt {}/{})", backoff, restarts, SUPERVISE_MAX_RESTARTS));
+        sleep(backoff).await;
+        backoff = (backoff * 2).min(Duration::from_millis(SUPERVISE_MAX_BACKOFF_MS));
+    }
+}
+
+/// 启动服务运行期间的后台任务。未启用的功能只轮询配置，启用后无需重启。
+fn spawn_background_tasks(state: &Arc<AppState>) {
+    // 后台定时备份
+    tokio::spawn(run_backup_scheduler(state.clone()));
+    // 数据保留策略与映射命中时间记录
+    tokio::spawn(run_retention_scheduler(state.clone()));
+    // cron 定时任务
+    tokio::spawn(run_task_scheduler(state.clone()));
+    // WAL 检查点
+    tokio::spawn(run_wal_checkpointer(state.clone()));
+    // Rhai 脚本钩子：启动前同步加载一次，之后监视文件修改
+    let mut scripts = None;
+    reload_script_hooks(state, &mut scripts);
+    tokio::spawn(run_script_reloader(state.clone(), scripts));
+    // 个人信息访问记录
+    tokio::spawn(run_access_trail_writer(state.clone()));
+    // 启动预加载所用的查询次数
+    tokio::spawn(run_query_frequency_writer(state.clone()));
+    tokio::spawn(run_analytics_writer(state.clone()));
+    // 多实例部署时同步其他实例的写入 (Redis)
+    tokio::spawn(run_cache_invalidation_listener(state.clone()));
+    // 主库健康检查与备用库切换
+    tokio::spawn(run_failover_monitor(state.clone()));
+    tokio::spawn(run_alert_monitor(state.clone()));
+    // JWT 认证的 JWKS 公钥刷新
+    tokio::spawn(run_jwks_refresher(state.clone()));
+}
+
+/// 等待 Ctrl+C 或 SIGTERM
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = term.recv() => {}
+                }
+            }
+            Err(_) => { tokio::signal::ctrl_c().await.ok(); }
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.ok();
+}
+
+/// PID 文件中记录的进程是否仍在运行
+fn pid_file_alive(path: &str) -> bool {
+    let Some(pid) = fs::read_to_string(path).ok().and_then(|s| s.trim().parse::<u32>().ok()) else {
+        return false;
+    };
+    #[cfg(unix)]
+    {
+        // 信号 0 只检查进程是否存在；EPERM 说明进程存在但属于其他用户
+        // SAFETY: kill 不涉及内存安全，pid 来自文件内容
+        let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+        result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = pid;
+        false
+    }
+}
+
+/// `serve`：不进入管理模式地运行 HTTP 服务，直到出错或收到 Ctrl+C / SIGTERM。
+async fn serve(state: &Arc<AppState>, pid_file: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(path) = pid_file {
+        if pid_file_alive(path) {
+            return Err(tr!("PID 文件 {} 中的进程仍在运行，拒绝重复启动。", "The process recorded in PID file {} is still running; refusing to start another.", path).into());
+        }
+        fs::write(path, format!("{}\n", process::id()))
+            .map_err(|e| tr!("写入 PID 文件 {} 失败: {}", "Failed to write PID file {}: {}", path, e))?;
+    }
+    spawn_background_tasks(state);
+    let result = tokio::select! {
+        result = try_start_server(state.clone()) => result,
+        _ = shutdown_signal() => {
+            sd_notify("STOPPING=1");
+            println!("{} {}", "INFO".yellow(), tr!("收到停止信号，服务退出。", "Shutdown signal received, exiting."));
+            // 服务任务随 select 一起被丢弃，由这里代为写入
+            flush_pending_writes(state).await;
+            Ok(())
+        }
+    };
+    if let Some(path) = pid_file {
+        fs::remove_file(path).ok();
+    }
+    result.map_err(|e| tr!("服务异常退出: {}", "Server exited with an error: {}", e).into())
+}
+
+// --- 集成测试模式 (--test-mode) ---
+
+/// 测试模式写入的固定数据 (uid, 手机号)，下游的集成测试可直接依赖
+const TEST_FIXTURES: [(&str, &str); 5] = [
+    ("1000000001", "13800000001"),
+    ("1000000002", "13800000002"),
+    ("1000000003", "13900000003"),
+    ("1000000004", "15000000004"),
+    ("1000000005", "18600000005"),
+];
+
+/// 测试模式就绪后输出的 `READY` 行
+#[derive(Serialize)]
+struct TestModeReady {
+    base_url: String,
+    pid: u32,
+    data_dir: String,            // 临时工作目录，退出时删除
+    fixtures: Vec<ExportRow<'static>>,
+}
+
+/// `--test-mode`：在新建的临时目录中运行，结束后（含启动失败）删除该目录
+async fn run_test_mode() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = env::temp_dir().join(format!("cyber_lookup-test-{}", Uuid::new_v4().simple()));
+    fs::create_dir_all(dir.join(DEFAULT_DATA_DIR))?;
+    env::set_current_dir(&dir)?;
+    let result = serve_test_mode(&dir).await;
+    // 先离开该目录（Windows 上无法删除当前目录）
+    env::set_current_dir(env::temp_dir()).ok();
+    fs::remove_dir_all(&dir).ok();
+    result
+}
+
+async fn serve_test_mode(dir: &FilePath) -> Result<(), Box<dyn std::error::Error>> {
+    let config = ServiceConfig {
+        bind_address: "127.0.0.1:0".to_string(),
+        // 与本机正在运行的实例互不干扰
+        control_socket: String::new(),
+        ..ServiceConfig::default()
+    };
+    let state = Arc::new(AppState::new(config));
+    {
+        let mut conn = state.get_db_connection()?;
+        initialize_database(&conn)?;
+        let tx = conn.transaction()?;
+        for (uid, phone) in TEST_FIXTURES {
+            write_mapping(&tx, uid, phone, ConflictStrategy::Replace)?;
+        }
+        tx.commit()?;
+    }
+    spawn_background_tasks(&state);
+    // 所有监听就绪后输出 READY 行，之后一直等待，由服务结束或停止信号结束 select
+    let announce = async {
+        while !state.server_running.load(Ordering::SeqCst) {
+            sleep(Duration::from_millis(20)).await;
+        }
+        let addr = state.listening.lock().unwrap().http;
+        if let Some(addr) = addr {
+            let ready = TestModeReady {
+                base_url: format!("http://{}", addr),
+                pid: process::id(),
+                data_dir: dir.display().to_string(),
+                fixtures: TEST_FIXTURES.iter().map(|(uid, phone)| ExportRow { uid, phone_number: phone }).collect(),
+            };
+            println!("READY {}", serde_json::to_string(&ready).unwrap_or_default());
+            io::stdout().flush().ok();
+        }
+        std::future::pending::<()>().await
+    };
+    tokio::select! {
+        result = try_start_server(state.clone()) => result.map_err(|e| tr!("服务异常退出: {}", "Server exited with an error: {}", e))?,
+        _ = announce => {}
+        _ = shutdown_signal() => {
+            println!("{} {}", "INFO".yellow(), tr!("收到停止信号，测试模式退出并删除 {}", "Shutdown signal received; leaving test mode and removing {}", dir.display()));
+        }
+    }
+    Ok(())
+}
+
+/// 以追加方式打开后台运行时的输出文件 (log_file，为空时使用 DEFAULT_LOG_FILE)，返回路径与文件。
+#[cfg(any(unix, windows))]
+fn open_log_file(config: &ServiceConfig) -> Result<(String, fs::File), Box<dyn std::error::Error>> {
+    let log_path = if config.log_file.is_empty() { DEFAULT_LOG_FILE.to_string() } else { config.log_file.clone() };
+    if let Some(dir) = FilePath::new(&log_path).parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    let log = fs::OpenOptions::new().create(true).append(true).open(&log_path)
+        .map_err(|e| tr!("打开日志文件 {} 失败: {}", "Failed to open log file {}: {}", log_path, e))?;
+    Ok((log_path, log))
+}
+
+/// `serve --daemon`：以相同参数（去掉 --daemon）在新会话中重新启动自身，标准输入置空、输出追加到日志文件，
+/// 当前进程随即退出。子进程继承工作目录，因此 config.txt 与相对路径保持不变。
+/// 不使用 fork：此时 tokio 多线程运行时已启动，fork 出的子进程中只剩调用线程。
+#[cfg(unix)]
+fn daemonize(config: &ServiceConfig, pid_file: Option<&str>) -> Result<String, Box<dyn std::error::Error>> {
+    use std::os::unix::process::CommandExt;
+    if let Some(path) = pid_file {
+        if pid_file_alive(path) {
+            return Err(tr!("PID 文件 {} 中的进程仍在运行，拒绝重复启动。", "The process recorded in PID file {} is still running; refusing to start another.", path).into());
+        }
+    }
+    let (log_path, log) = open_log_file(config)?;
+    let args: Vec<_> = env::args_os().skip(1).filter(|arg| arg != "--daemon").collect();
+    let mut command = process::Command::new(env::current_exe()?);
+    command.args(args)
+        .stdin(process::Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+    // SAFETY: pre_exec 闭包在 fork 之后、exec 之前执行，只调用异步信号安全的 setsid
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let child = command.spawn().map_err(|e| tr!("启动后台进程失败: {}", "Failed to start background process: {}", e))?;
+    println!("{} {}", "INFO".yellow(), tr!("后台进程 PID: {}", "Background process PID: {}", child.id()));
+    Ok(log_path)
+}
+
+#[cfg(not(unix))]
+fn daemonize(_config: &ServiceConfig, _pid_file: Option<&str>) -> Result<String, Box<dyn std::error::Error>> {
+    Err(tr!("--daemon 仅支持 Unix 系统。", "--daemon is only supported on Unix.").into())
+}
+
+/// Windows 服务：服务管理器启动 `--workdir <安装时目录> service run`，本进程向其报告状态，
+/// 并以子进程运行 `serve`（标准输出与错误写入 log_file 并按 log_rotation 轮转）；收到停止请求时结束子进程。
+/// 子进程意外退出时服务以其退出码停止，可在服务的“恢复”选项中配置自动重启。
+#[cfg(windows)]
+mod windows_service_host {
+    use super::*;
+    use std::ffi::OsString;
+    use std::sync::mpsc as std_mpsc;
+    use windows_service::{
+        define_windows_service,
+        service::{ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode, ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType},
+        service_control_handler::{self, ServiceControlHandlerResult},
+        service_dispatcher,
+        service_manager::{ServiceManager, ServiceManagerAccess},
+    };
+
+    pub const SERVICE_NAME: &str = "cyber_lookup";
+    const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+
+    pub fn install() -> Result<(), Box<dyn std::error::Error>> {
+        let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE)?;
+        let info = ServiceInfo {
+            name: OsString::from(SERVICE_NAME),
+            display_name: OsString::from("Cyber Lookup"),
+            service_type: SERVICE_TYPE,
+            start_type: ServiceStartType::AutoStart,
+            error_control: ServiceErrorControl::Normal,
+            executable_path: env::current_exe()?,
+            launch_arguments: vec![OsString::from("--workdir"), env::current_dir()?.into_os_string(), OsString::from("service"), OsString::from("run")],
+            dependencies: vec![],
+            account_name: None, // LocalSystem
+            account_password: None,
+        };
+        let service = manager.create_service(&info, ServiceAccess::CHANGE_CONFIG)?;
+        service.set_description("UID / 手机号映射查询服务")?;
+        Ok(())
+    }
+
+    pub fn uninstall() -> Result<(), Box<dyn std::error::Error>> {
+        let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+        let service = manager.open_service(SERVICE_NAME, ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE)?;
+        // 先标记删除，停止后由服务管理器移除
+        service.delete()?;
+        if service.query_status()?.current_state != ServiceState::Stopped {
+            service.stop()?;
+        }
+        Ok(())
+    }
+
+    /// 把当前线程交给服务调度器，直到服务停止
+    pub fn run() -> Result<(), Box<dyn std::error::Error>> {
+        service_dispatcher::start(SERVICE_NAME, ffi_service_main)?;
+        Ok(())
+    }
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    fn service_main(_arguments: Vec<OsString>) {
+        // 服务没有控制台，错误只能写入日志文件
+        if let Err(e) = run_service() {
+            if let Ok((_, mut log)) = load_config().map_err(|e| e.to_string().into()).and_then(|config| open_log_file(&config)) {
+                let _ = writeln!(log, "{} {}", "FAIL", tr!("服务运行失败: {}", "Service failed: {}", e));
+            }
+        }
+    }
+
+    /// 启动 `serve` 子进程：配置了 log_file 时经管道写入轮转日志，否则直接追加到 DEFAULT_LOG_FILE
+    fn spawn_server(config: ServiceConfig) -> Result<process::Child, Box<dyn std::error::Error>> {
+        let mut command = process::Command::new(env::current_exe()?);
+        command.arg("serve").stdin(process::Stdio::null());
+        if config.log_file.is_empty() {
+            let (_, log) = open_log_file(&config)?;
+            return Ok(command.stdout(log.try_clone()?).stderr(log).spawn()?);
+        }
+        let log = Arc::new(Mutex::new(LogSink::File(RotatingLog::open(&config.log_file, config.log_rotation.clone())?)));
+        let mut child = command.stdout(process::Stdio::piped()).stderr(process::Stdio::piped()).spawn()?;
+        if let Some(stdout) = child.stdout.take() {
+            let log = log.clone();
+            std::thread::spawn(move || copy_log_lines(&log, stdout));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            std::thread::spawn(move || copy_log_lines(&log, stderr));
+        }
+        Ok(child)
+    }
+
+    fn status(state: ServiceState, controls_accepted: ServiceControlAccept, exit_code: u32) -> ServiceStatus {
+        ServiceStatus {
+            service_type: SERVICE_TYPE,
+            current_state: state,
+            controls_accepted,
+            exit_code: ServiceExitCode::Win32(exit_code),
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        }
+    }
+
+    fn run_service() -> Result<(), Box<dyn std::error::Error>> {
+        let (stop_tx, stop_rx) = std_mpsc::channel();
+        let status_handle = service_control_handler::register(SERVICE_NAME, move |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                let _ = stop_tx.send(());
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        })?;
+
+        let spawned = load_config().map_err(|e| e.to_string().into()).and_then(spawn_server);
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(e) => {
+                status_handle.set_service_status(status(ServiceState::Stopped, ServiceControlAccept::empty(), 1))?;
+                return Err(e);
+            }
+        };
+        status_handle.set_service_status(status(ServiceState::Running, ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN, 0))?;
+
+        let exit_code = loop {
+            match stop_rx.recv_timeout(Duration::from_millis(500)) {
+                Ok(()) | Err(std_mpsc::RecvTimeoutError::Disconnected) => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    break 0;
+                }
+                Err(std_mpsc::RecvTimeoutError::Timeout) => {}
+            }
+            if let Some(exit) = child.try_wait()? {
+                break exit.code().unwrap_or(1) as u32;
+            }
+        };
+        status_handle.set_service_status(status(ServiceState::Stopped, ServiceControlAccept::empty(), exit_code))?;
+        Ok(())
+    }
+}
+
+async fn interactive_manage_loop(state: Arc<AppState>) -> Result<(), Box<dyn std::error::Error>> {
+    println!("\n{}", tr!("--- 欢迎进入交互式服务管理模式 ---", "--- Interactive service management ---").green().bold());
+    println!("{}", tr!("命令: 'start [--supervise]', 'config [rollback [N]]', 'db-manage', 'run <脚本> [--continue]', 'tasks [run <名称>]', 'loglevel [级别] [--save]', 'info', 'exit'", "Commands: 'start [--supervise]', 'config [rollback [N]]', 'db-manage', 'run <script> [--continue]', 'tasks [run <name>]', 'loglevel [level] [--save]', 'info', 'exit'").cyan());
+    
+    loop {
+        let current_config = state.current_config();
+        // 使用防御性读取
+        let line = match read_command(&format!("{} ({}@{}) > ", "MANAGE".magenta(), log_filter(), current_config.bind_address), MANAGE_COMMANDS) {
+            Ok(s) => s,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => {
+                eprintln!("{} {}", "FATAL".red(), tr!("I/O 读取失败: {}", "I/O read failed: {}", e));
+                break;
+            }
+        };
+        let (command, arg) = split_command(&line);
+
+        match command.as_str() {
+            "start" if arg.trim() == "--supervise" => {
+                supervise_server(state.clone()).await;
+            }
+            "start" if !arg.trim().is_empty() => {
+                eprintln!("{} {}", "WARN".yellow(), tr!("用法: start [--supervise]", "Usage: start [--supervise]"));
+            }
+            "start" => {
+                println!("{}", tr!("尝试启动服务...", "Starting server...").yellow());
+                match try_start_server(state.clone()).await {
+                    Ok(_) => {
+                        println!("{}", tr!("服务已停止。", "Server stopped.").red());
+                    }
+                    Err(e) => report_start_error(e),
+                }
+            }
+            "config" if arg.split_whitespace().next() == Some("rollback") => {
+                match rollback_config(arg.trim_start().trim_start_matches("rollback")) {
+                    Ok((n, config)) => {
+                        state.set_config(config);
+                        println!("{} {}", "OK".green(), tr!("已恢复旧配置 #{}，回滚前的配置已保存为 {}", "Restored previous config #{}; the replaced config was kept as {}", n, config_version_path(1)));
+                    }
+                    Err(e) => eprintln!("{} {}", "ERR".red(), e),
+                }
+            }
+            "config" => {
+                edit_config(state.clone());
+            }
+            "db-manage" => {
+                run_db_management(state.clone());
+            }
+            "exit" => {
+                println!("{}", tr!("退出程序。", "Exiting.").red());
+                process::exit(0);
+            }
+            "run" => {
+                let (file, continue_on_error) = parse_run_args(&arg);
+                if file.is_empty() {
+                    eprintln!("{} {}", "WARN".yellow(), tr!("用法: run <脚本文件> [--continue]", "Usage: run <script file> [--continue]"));
+                } else if let Err(e) = run_script(&state, file, continue_on_error) {
+                    eprintln!("{} {}", "ERR".red(), e);
+                }
+            }
+            "tasks" => {
+                manage_tasks(&state, &arg).await;
+            }
+            "loglevel" => {
+                set_log_level_command(&state, &arg);
+            }
+            "info" => {
+                println!("{}", format!("{:#?}", redacted_config(&current_config)).yellow());
+            }
+            _ => {
+                if !command.is_empty() {
+                    println!("{} {}", "WARN".yellow(), tr!("未知命令: {}", "Unknown command: {}", command));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 'loglevel [级别] [--save]'：不带参数时显示当前规则；--save 同时写入配置文件
+fn set_log_level_command(state: &AppState, arg: &str) {
+    let save = arg.split_whitespace().any(|token| token == "--save");
+    let spec: Vec<&str> = arg.split_whitespace().filter(|token| *token != "--save").collect();
+    if spec.is_empty() {
+        println!("{} {}", "INFO".cyan(), tr!("当前日志级别: {} (配置: {})", "Current log level: {} (configured: {})", log_filter(), state.current_config().log_level));
+        return;
+    }
+    let filter = match LogFilter::parse(&spec.join("")) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("{} {}", "ERR".red(), e);
+            return;
+        }
+    };
+    if save {
+        let mut config = state.current_config();
+        config.log_level = filter.to_string();
+        if let Err(e) = save_config(&config) {
+            eprintln!("{} {}", "ERR".red(), tr!("保存配置失败: {:?}", "Failed to save the configuration: {:?}", e));
+            return;
+        }
+        state.set_config(config);
+    }
+    println!("{} {}", "OK".green(), tr!("日志级别已调整为 {}", "Log level changed to {}", filter));
+    set_log_filter(filter);
+}
+
+// --- 本机控制套接字 (cyber_lookup ctl) ---
+// 服务运行时交互式管理模式不可用，运维通过 control_socket 对运行中的实例执行管理命令。
+// 每个连接一条命令：客户端发送一行 JSON (ControlRequest)，服务端应答一行 JSON (ControlReply) 后关闭连接。
+
+/// ctl 发送的命令
+#[derive(Serialize, Deserialize)]
+struct ControlRequest {
+    args: Vec<String>,
+}
+
+/// 控制命令的执行结果：message 供终端显示，data 为 --json 时输出的结构化结果
+#[derive(Serialize, Deserialize)]
+struct ControlReply {
+    ok: bool,
+    message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<serde_json::Value>,
+}
+
+impl ControlReply {
+    fn ok(message: String, data: serde_json::Value) -> Self {
+        ControlReply { ok: true, message, data: Some(data) }
+    }
+
+    fn error(message: String) -> Self {
+        ControlReply { ok: false, message, data: None }
+    }
+}
+
+fn control_help() -> String {
+    tr!(
+        "可用命令:\n  status                      运行状态与监听地址\n  count [uid 前缀]            记录数\n  stats                       缓存、布隆过滤器与慢查询统计\n  cache-flush                 清空缓存并重建布隆过滤器\n  config get [配置项]         查看配置（密钥已脱敏），配置项如 cache.ttl_secs\n  config set <配置项> <值>    修改配置并保存，值按 JSON 解析（失败时按字符串）\n  config rollback [N]         恢复第 N 份旧配置 (config.txt.N，默认 1)\n  loglevel [级别]             查看或临时调整日志级别（不保存）",
+        "Commands:\n  status                      Running state and listen addresses\n  count [uid prefix]          Number of records\n  stats                       Cache, bloom filter and slow query statistics\n  cache-flush                 Flush the cache and rebuild the bloom filter\n  config get [key]            Show the configuration (secrets redacted), keys like cache.ttl_secs\n  config set <key> <value>    Change and save a setting; the value is parsed as JSON (a string otherwise)\n  config rollback [N]         Restore the Nth previous config (config.txt.N, default 1)\n  loglevel [level]            Show or temporarily change the log level (not saved)"
+    ).to_string()
+}
+
+/// 配置项名（以 . 分隔，数组用下标）转为 JSON Pointer
+fn config_pointer(key: &str) -> String {
+    format!("/{}", key.replace('.', "/"))
+}
+
+/// `config set`：修改单个配置项，按 PUT /admin/config 的流程校验、保存并热更新
+fn control_config_set(state: &AppState, key: &str, value: &str) -> Result<ControlReply, String> {
+    let running = state.current_config();
+    let mut document = serde_json::to_value(&running).map_err(|e| e.to_string())?;
+    let slot = document.pointer_mut(&config_pointer(key)).ok_or_else(|| tr!("未知的配置项: {}", "Unknown setting: {}", key))?;
+    *slot = serde_json::from_str(value).unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+    let new_config: ServiceConfig = serde_json::from_value(document).map_err(|e| tr!("{} 的值无效: {}", "Invalid value for {}: {}", key, e))?;
+    new_config.validate().map_err(|e| tr!("配置校验失败: {}", "Config validation failed: {}", e))?;
+    save_config(&new_config).map_err(|e| tr!("保存配置失败: {:?}", "Failed to save the configuration: {:?}", e))?;
+    let (applied, pending_restart) = split_restart_fields(&running, &new_config);
+    state.set_config(applied);
+
+    println!("{} {}", "INFO".yellow(), tr!("配置项 {} 已通过 ctl 修改并保存到 {}", "Setting {} changed via ctl and saved to {}", key, DEFAULT_CONFIG_FILE));
+    let shown = serde_json::to_value(redacted_config(&new_config)).ok().and_then(|v| v.pointer(&config_pointer(key)).cloned()).unwrap_or_default();
+    let mut message = format!("{} = {}", key, shown);
+    if !pending_restart.is_empty() {
+        message = tr!("{}（需重启服务后生效）", "{} (takes effect after a restart)", message);
+    }
+    Ok(ControlReply::ok(message, serde_json::json!({ "key": key, "value": shown, "pending_restart": pending_restart })))
+}
+
+/// 执行一条控制命令
+async fn control_command(state: &Arc<AppState>, args: &[String]) -> Result<ControlReply, String> {
+    let background = |e: task::JoinError| tr!("后台任务异常终止: {}", "Background task aborted: {}", e);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["status"] => {
+            let listening = state.listening.lock().unwrap().clone();
+            let http = listening.http.map(|addr| addr.to_string()).unwrap_or_else(|| "-".to_string());
+            let message = tr!("运行中: PID {}，版本 {}，启动于 {}，HTTP {}", "Running: PID {}, version {}, started {}, HTTP {}", process::id(), env!("CARGO_PKG_VERSION"), state.started_at.to_rfc3339(), http);
+            Ok(ControlReply::ok(message, serde_json::json!({
+                "pid": process::id(),
+                "version": env!("CARGO_PKG_VERSION"),
+                "started_at": state.started_at.to_rfc3339(),
+                "serving": state.server_running.load(Ordering::SeqCst),
+                "listening": listening,
+            })))
+        }
+        ["count", rest @ ..] if rest.len() <= 1 => {
+            let prefix = rest.first().map(|prefix| prefix.to_string());
+            let count = state.store().map_err(|e| e.to_string())?.count(prefix).await.map_err(|e| e.to_string())?;
+            Ok(ControlReply::ok(tr!("总记录数: {}", "Total records: {}", count), serde_json::json!({ "count": count })))
+        }
+        ["stats"] => {
+            let stats_state = state.clone();
+            let stats = task::spawn_blocking(move || stats_state.stats()).await.map_err(background)?;
+            let message = [
+                tr!("缓存: {} 条 (上限 {})，命中率 {:.1}%", "Cache: {} entries (max {}), hit rate {:.1}%", stats.cache.entries, stats.cache.max_entries, stats.cache.hit_rate * 100.0),
+                tr!("布隆过滤器: 已过滤 {} 次不存在的 ID", "Bloom filter: {} missing IDs filtered", stats.bloom.negatives),
+                tr!("慢查询: {} 次 (阈值 {} ms)", "Slow queries: {} (threshold {} ms)", stats.slow_queries.total, stats.slow_queries.threshold_ms),
+            ].join("\n");
+            Ok(ControlReply::ok(message, serde_json::to_value(&stats).map_err(|e| e.to_string())?))
+        }
+        ["cache-flush"] => {
+            let flush_state = state.clone();
+            let flushed = task::spawn_blocking(move || flush_state.flush_cache()).await.map_err(background)?
+                .map_err(|e| tr!("缓存已清空，但布隆过滤器重建失败: {}", "Cache flushed, but rebuilding the bloom filter failed: {}", e))?;
+            println!("{} {}", "INFO".yellow(), tr!("缓存已通过 ctl 清空 ({} 条)", "Cache flushed via ctl ({} entries)", flushed));
+            Ok(ControlReply::ok(tr!("已清空 {} 条缓存并重建布隆过滤器。", "Flushed {} cache entries and rebuilt the bloom filter.", flushed), serde_json::json!({ "flushed": flushed })))
+        }
+        ["config"] | ["config", "get"] => {
+            let config = serde_json::to_value(redacted_config(&state.current_config())).map_err(|e| e.to_string())?;
+            Ok(ControlReply::ok(serde_json::to_string_pretty(&config).unwrap_or_default(), config))
+        }
+        ["config", "get", key] => {
+            let config = serde_json::to_value(redacted_config(&state.current_config())).map_err(|e| e.to_string())?;
+            let value = config.pointer(&config_pointer(key)).cloned().ok_or_else(|| tr!("未知的配置项: {}", "Unknown setting: {}", key))?;
+            Ok(ControlReply::ok(format!("{} = {}", key, value), value))
+        }
+        ["config", "set", key, value @ ..] if !value.is_empty() => {
+            let (set_state, key, value) = (state.clone(), key.to_string(), value.join(" "));
+            task::spawn_blocking(move || control_config_set(&set_state, &key, &value)).await.map_err(background)?
+        }
+        ["config", "rollback", rest @ ..] if rest.len() <= 1 => {
+            let arg = rest.first().map(|n| n.to_string()).unwrap_or_default();
+            let (n, config) = task::spawn_blocking(move || rollback_config(&arg)).await.map_err(background)??;
+            let (applied, pending_restart) = split_restart_fields(&state.current_config(), &config);
+            state.set_config(applied);
+            println!("{} {}", "INFO".yellow(), tr!("配置已通过 ctl 回滚到 {}", "Configuration rolled back via ctl to {}", config_version_path(n)));
+            let mut message = tr!("已恢复旧配置 #{}，回滚前的配置已保存为 {}", "Restored previous config #{}; the replaced config was kept as {}", n, config_version_path(1));
+            if !pending_restart.is_empty() {
+                message = tr!("{}\n以下配置项需重启服务后生效: {}", "{}\nThese settings take effect after a restart: {}", message, pending_restart.join(", "));
+            }
+            Ok(ControlReply::ok(message, serde_json::json!({ "restored": config_version_path(n), "pending_restart": pending_restart })))
+        }
+        ["loglevel"] => {
+            let filter = log_filter().to_string();
+            Ok(ControlReply::ok(tr!("当前日志级别: {} (配置: {})", "Current log level: {} (configured: {})", filter, state.current_config().log_level), serde_json::json!({ "log_level": filter })))
+        }
+        ["loglevel", spec @ ..] => {
+            let filter = LogFilter::parse(&spec.join(""))?;
+            println!("{} {}", "INFO".yellow(), tr!("日志级别已通过 ctl 调整为 {}", "Log level changed via ctl to {}", filter));
+            let message = tr!("日志级别已调整为 {}", "Log level changed to {}", filter);
+            let data = serde_json::json!({ "log_level": filter.to_string() });
+            set_log_filter(filter);
+            Ok(ControlReply::ok(message, data))
+        }
+        ["help"] => Ok(ControlReply::ok(control_help(), serde_json::json!({ "help": control_help() }))),
+        _ => Err(tr!("未知命令: {}（'ctl help' 列出全部命令）", "Unknown command: {} ('ctl help' lists all commands)", args.join(" "))),
+    }
+}
+
+/// 监听中的控制套接字；丢弃时停止接受连接并删除套接字文件
+#[cfg(unix)]
+struct ControlSocket {
+    path: String,
+    _task: AbortOnDrop<()>,
+}
+
+#[cfg(unix)]
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+/// 绑定 control_socket。控制通道不影响对外服务，失败时只输出警告。
+#[cfg(unix)]
+fn start_control_socket(state: &Arc<AppState>, path: &str) -> Option<ControlSocket> {
+    use std::os::unix::fs::PermissionsExt;
+    if path.is_empty() {
+        return None;
+    }
+    // 残留的套接字文件：能连上说明另一个实例正在使用，否则是上次异常退出留下的
+    if FilePath::new(path).exists() {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            eprintln!("{} {}", "WARN".yellow(), tr!("控制套接字 {} 正被其他进程使用，本实例不启用 ctl。", "Control socket {} is in use by another process; ctl is disabled for this instance.", path));
+            return None;
+        }
+        fs::remove_file(path).ok();
+    }
+    let listener = match tokio::net::UnixListener::bind(path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("{} {}", "WARN".yellow(), tr!("无法监听控制套接字 {}，ctl 不可用: {}", "Cannot listen on control socket {}; ctl is unavailable: {}", path, e));
+            return None;
+        }
+    };
+    // 只有同一用户（及 root）可以连接，另见 serve_control_connection 中的对端凭据检查
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600)).ok();
+    println!("{} {}", "INFO".yellow(), tr!("控制套接字: {}（cyber_lookup ctl）", "Control socket: {} (cyber_lookup ctl)", path));
+    let state = state.clone();
+    let task = tokio::spawn(async move {
+        let mut connections = task::JoinSet::new();
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    connections.spawn(serve_control_connection(state.clone(), stream));
+                }
+                Err(e) => {
+                    eprintln!("{} {}", "WARN".yellow(), tr!("接受控制连接失败: {}", "Failed to accept a control connection: {}", e));
+                    sleep(Duration::from_millis(100)).await;
+                }
+            }
+            while connections.try_join_next().is_some() {}
+        }
+    });
+    Some(ControlSocket { path: path.to_string(), _task: AbortOnDrop(task) })
+}
+
+#[cfg(not(unix))]
+fn start_control_socket(_state: &Arc<AppState>, _path: &str) -> Option<()> {
+    None
+}
+
+/// 一个控制连接：读取一条命令，执行后写回结果
+#[cfg(unix)]
+async fn serve_control_connection(state: Arc<AppState>, stream: tokio::net::UnixStream) {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+    // SAFETY: geteuid 没有参数，不会失败
+    let own_uid = unsafe { libc::geteuid() };
+    let reply = match stream.peer_cred() {
+        Ok(cred) if cred.uid() == own_uid || cred.uid() == 0 => None,
+        _ => Some(ControlReply::error(tr!("拒绝其他用户的控制连接。", "Control connections from other users are refused.").to_string())),
+    };
+    let (reader, mut writer) = stream.into_split();
+    let reply = match reply {
+        Some(reply) => reply,
+        None => {
+            let mut line = String::new();
+            let read = tokio::io::BufReader::new(reader).take(CONTROL_MAX_REQUEST_BYTES).read_line(&mut line).await;
+            match read.map(|_| serde_json::from_str::<ControlRequest>(&line)) {
+                Ok(Ok(request)) => {
+                    log_debug("cyber_lookup::ctl", &format!("ctl {}", request.args.join(" ")));
+                    control_command(&state, &request.args).await.unwrap_or_else(ControlReply::error)
+                }
+                Ok(Err(e)) => ControlReply::error(tr!("无效的控制命令: {}", "Invalid control request: {}", e)),
+                Err(_) => return,
+            }
+        }
+    };
+    if let Ok(mut body) = serde_json::to_vec(&reply) {
+        body.push(b'\n');
+        let _ = writer.write_all(&body).await;
+    }
+}
+
+/// `ctl`：把命令发送到运行中实例的控制套接字并输出结果；命令失败时以非零状态退出
+#[cfg(unix)]
+fn ctl_command(config: &ServiceConfig, args: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::BufRead;
+    if config.control_socket.is_empty() {
+        return Err(tr!("未配置 control_socket。", "control_socket is not configured.").into());
+    }
+    let mut stream = std::os::unix::net::UnixStream::connect(&config.control_socket)
+        .map_err(|e| tr!("无法连接控制套接字 {}（服务是否在运行?）: {}", "Cannot connect to control socket {} (is the server running?): {}", config.control_socket, e))?;
+    stream.set_read_timeout(Some(Duration::from_secs(CONTROL_TIMEOUT_SECS)))?;
+    let mut request = serde_json::to_vec(&ControlRequest { args })?;
+    request.push(b'\n');
+    stream.write_all(&request)?;
+    let mut line = String::new();
+    io::BufReader::new(stream).read_line(&mut line)
+        .map_err(|e| tr!("等待服务应答失败: {}", "Failed to read the server's reply: {}", e))?;
+    let reply: ControlReply = serde_json::from_str(&line)
+        .map_err(|e| tr!("服务应答无效: {}", "Invalid reply from the server: {}", e))?;
+    if !reply.ok {
+        return Err(reply.message.into());
+    }
+    match reply.data {
+        Some(data) if json_output() => print_json(data),
+        _ => println!("{}", reply.message),
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn ctl_command(_config: &ServiceConfig, _args: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
+    Err(tr!("ctl 仅支持 Unix 系统；Windows 上请使用管理接口 (/v1/admin/...)。", "ctl is only supported on Unix; use the admin API (/v1/admin/...) on Windows.").into())
+}
+
+// --- 程序主入口点 ---
+
+/// 命令行程序的完整入口：解析参数、加载配置，然后执行子命令、脚本，或启动服务并进入交互式管理模式。
+pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    if let Some(dir) = &cli.workdir {
+        env::set_current_dir(dir).map_err(|e| tr!("无法切换到目录 {}: {}", "Cannot change to directory {}: {}", dir.display(), e))?;
+    }
+    configure_terminal();
+    JSON_OUTPUT.store(cli.json, Ordering::Relaxed);
+    if cli.test_mode {
+        if cli.command.is_some() {
+            return Err(tr!("--test-mode 不能与子命令同时使用。", "--test-mode cannot be combined with a subcommand.").into());
+        }
+        return run_test_mode().await;
+    }
+    fs::create_dir_all(DEFAULT_DATA_DIR).ok();
+
+    // doctor 用于修复无法加载的配置，不能依赖 load_config
+    if let Some(CliCommand::Doctor { fix }) = cli.command {
+        return doctor_command(fix);
+    }
+    let initial_config = match load_config() {
+        Ok(c) => c,
+        Err(e) => {
+            let message = config_load_error(&e);
+            // 交互式终端中先运行诊断向导，修复后重新加载
+            if !is_interactive() || cli.command.is_some() {
+                println!("{} {}", "HINT".yellow(), tr!("可运行 'cyber_lookup doctor' 诊断并修复配置。", "Run 'cyber_lookup doctor' to diagnose and fix the configuration."));
+                return Err(message.into());
+            }
+            println!("{} {}", "INFO".yellow(), tr!("正在运行启动诊断...", "Running startup diagnostics..."));
+            run_doctor(false);
+            load_config().map_err(|e| config_load_error(&e))?
+        }
+    };
+
+    let state = Arc::new(AppState::new(initial_config));
+
+    // 非交互式子命令：执行完毕即退出
+    if let Some(command) = cli.command {
+        return run_cli_command(&state, command).await;
+    }
+    if !cli.exec.is_empty() {
+        run_exec(&state, &cli.exec)?;
+        return Ok(());
+    }
+    if let Some(script) = cli.script {
+        run_script(&state, &script, cli.continue_on_error)?;
+        return Ok(());
+    }
+
+    spawn_background_tasks(&state);
+
+    // 监听或数据库失败时先运行一次诊断，执行了修复就按新配置重试启动
+    let mut diagnosed = false;
+    loop {
+        match try_start_server(state.clone()).await {
+            Ok(_) => {
+                println!("{}", tr!("服务已停止，进入交互式管理模式...", "Server stopped, entering interactive management mode...").yellow());
+                interactive_manage_loop(state).await?;
+            }
+            Err(AppError::NetworkBindError(e)) => {
+                eprintln!("{} {}", "FAIL".red().bold(), tr!("服务启动失败 (网络绑定错误): {}", "Server failed to start (bind error): {}", e));
+                if !diagnosed {
+                    diagnosed = true;
+                    if recover_with_doctor(&state) {
+                        continue;
+                    }
+                }
+                eprintln!("{} {}", "HINT".yellow(), tr!("自动进入交互式管理模式，您可以使用 'config' 命令修改地址。", "Entering interactive management mode; use 'config' to change the address."));
+                sleep(Duration::from_secs(1)).await;
+                interactive_manage_loop(state).await?;
+            }
+            Err(AppError::DbError(_)) => {
+                if !diagnosed {
+                    diagnosed = true;
+                    if recover_with_doctor(&state) {
+                        continue;
+                    }
+                }
+                interactive_manage_loop(state).await?;
+            }
+            Err(AppError::FatalError(_)) => {
+                interactive_manage_loop(state).await?;
+            }
+            Err(e) => {
+                eprintln!("{} {}", "FAIL".red().bold(), tr!("服务启动失败: {:?}", "Server failed to start: {:?}", e));
+                interactive_manage_loop(state).await?;
+            }
+        }
+        break;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker_config() -> CircuitBreakerConfig {
+        CircuitBreakerConfig { enabled: true, failure_threshold: 3, window_secs: 10, cooldown_secs: 5 }
+    }
+
+    #[test]
+    fn circuit_breaker_opens_then_admits_a_single_probe() {
+        let config = breaker_config();
+        let mut breaker = CircuitBreaker::default();
+        let start = Instant::now();
+        assert_eq!(breaker.admit(start), Ok(false));
+        assert!(!breaker.record_failure(&config, start));
+        assert!(!breaker.record_failure(&config, start));
+        assert!(breaker.record_failure(&config, start));
+        assert_eq!(breaker.status(start).state, "open");
+        assert_eq!(breaker.admit(start + Duration::from_secs(1)), Err(4));
+
+        // 冷却结束后半开：只放行一个试探请求，其余请求在试探完成前被拒绝
+        let half_open = start + Duration::from_secs(5);
+        assert_eq!(breaker.status(half_open).state, "half_open");
+        assert_eq!(breaker.admit(half_open), Ok(true));
+        assert_eq!(breaker.admit(half_open), Err(1));
+
+        breaker.record_success();
+        assert_eq!(breaker.status(half_open).state, "closed");
+        assert_eq!(breaker.admit(half_open), Ok(false));
+        assert_eq!(breaker.status(half_open).trips, 1);
+    }
+
+    #[test]
+    fn circuit_breaker_failed_probe_reopens() {
+        let config = breaker_config();
+        let mut breaker = CircuitBreaker::default();
+        let start = Instant::now();
+        for _ in 0..3 {
+            breaker.record_failure(&config, start);
+        }
+        let half_open = start + Duration::from_secs(5);
+        assert_eq!(breaker.admit(half_open), Ok(true));
+        assert!(breaker.record_failure(&config, half_open));
+        assert_eq!(breaker.status(half_open).state, "open");
+        assert_eq!(breaker.status(half_open).trips, 2);
+        assert_eq!(breaker.admit(half_open + Duration::from_secs(5)), Ok(true));
+    }
+
+    #[test]
+    fn circuit_breaker_forgets_failures_outside_the_window() {
+        let config = breaker_config();
+        let mut breaker = CircuitBreaker::default();
+        let start = Instant::now();
+        breaker.record_failure(&config, start);
+        breaker.record_failure(&config, start);
+        assert!(!breaker.record_failure(&config, start + Duration::from_secs(11)));
+        assert_eq!(breaker.status(start + Duration::from_secs(11)).state, "closed");
+    }
+
+    #[test]
+    fn dropped_breaker_probe_releases_the_slot() {
+        let state = AppState::new(ServiceConfig::default());
+        let config = breaker_config();
+        let start = Instant::now();
+        {
+            let mut breaker = state.breaker.lock().unwrap();
+            for _ in 0..3 {
+                breaker.record_failure(&config, start);
+            }
+            breaker.open_until = Some(start);
+        }
+        let probe = state.breaker_check().unwrap();
+        assert!(probe.is_some());
+        assert!(matches!(state.breaker_check(), Err(AppError::CircuitOpen(1))));
+        drop(probe);
+        assert!(state.breaker_check().unwrap().is_some());
+    }
+
+    #[test]
+    fn bloom_filter_has_no_false_negatives() {
+        let mut bloom = BloomFilter::with_capacity(1_000, 0.01);
+        let keys: Vec<String> = (0..1_000).map(|i| format!("1380000{:04}", i)).collect();
+        for key in &keys {
+            bloom.insert(key);
+        }
+        assert!(keys.iter().all(|key| bloom.contains(key)));
+        assert!(bloom.memory_bytes() > 0);
+    }
+
+    #[test]
+    fn bloom_filter_false_positive_rate_stays_near_target() {
+        let rate = 0.01;
+        let mut bloom = BloomFilter::with_capacity(10_000, rate);
+        for i in 0..10_000 {
+            bloom.insert(&format!("uid-{}", i));
+        }
+        let probes = 20_000;
+        let false_positives = (0..probes).filter(|i| bloom.contains(&format!("absent-{}", i))).count();
+        // 固定输入下结果确定，留出两倍余量以容纳哈希分布的波动
+        assert!((false_positives as f64 / probes as f64) <= rate * 2.0, "false positives: {}", false_positives);
+    }
+
+    /// 三条记录经 uid 与手机号传递成一组，另两条因手机号两端空白成一组，其余一条不冲突
+    const MERGE_ROWS: [(&str, &str); 6] = [
+        ("1001", "13800000001"),
+        (" 1001 ", "13800000002"),
+        ("1002", "13800000003"),
+        ("1003", " 13800000002"),
+        ("1004", "13800000005 "),
+        ("1005", "13800000005"),
+    ];
+
+    fn merge_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        register_phone_functions(&conn, None).unwrap();
+        initialize_database(&conn).unwrap();
+        for (uid, phone) in MERGE_ROWS {
+            conn.execute("INSERT INTO user_mapping (uid, phone_number) VALUES (?1, ?2)", [uid, phone]).unwrap();
+        }
+        conn
+    }
+
+    fn scan_merge_groups(conn: &Connection) -> Vec<MergeGroup> {
+        let mut scan = MergeScan::default();
+        let mut stmt = conn.prepare("SELECT uid, phone_dec(phone_number) FROM user_mapping ORDER BY rowid").unwrap();
+        let mut rows = stmt.query([]).unwrap();
+        while let Some(row) = rows.next().unwrap() {
+            scan.add(&row.get::<_, String>(0).unwrap(), &row.get::<_, String>(1).unwrap());
+        }
+        scan.finish()
+    }
+
+    fn record(uid: &str, phone: &str) -> MergeRecord {
+        MergeRecord { uid: uid.to_string(), phone_number: phone.to_string() }
+    }
+
+    #[test]
+    fn merge_scan_groups_transitive_duplicates() {
+        let groups = scan_merge_groups(&merge_db());
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].records, [record("1001", "13800000001"), record(" 1001 ", "13800000002"), record("1003", " 13800000002")]);
+        assert_eq!(groups[0].canonical, record("1001", "13800000001"));
+        assert_eq!(groups[1].records, [record("1004", "13800000005 "), record("1005", "13800000005")]);
+        // 规范记录取最早写入的一条并修剪空白
+        assert_eq!(groups[1].canonical, record("1004", "13800000005"));
+    }
+
+    #[test]
+    fn merge_groups_keeps_canonical_rows_and_records_history() {
+        let conn = merge_db();
+        let groups = scan_merge_groups(&conn);
+        assert_eq!(merge_groups(&conn, &groups).unwrap().unwrap(), 5);
+
+        let mut stmt = conn.prepare("SELECT uid, phone_number FROM user_mapping ORDER BY uid").unwrap();
+        let rows: Vec<(String, String)> = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap().map(Result::unwrap).collect();
+        assert_eq!(rows, [
+            ("1001".to_string(), "13800000001".to_string()),
+            ("1002".to_string(), "13800000003".to_string()),
+            ("1004".to_string(), "13800000005".to_string()),
+        ]);
+
+        let mut stmt = conn.prepare("SELECT merge_id, canonical_uid, uid FROM merge_history ORDER BY rowid").unwrap();
+        let history: Vec<(String, String, String)> = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap().map(Result::unwrap).collect();
+        let uids: Vec<(&str, &str)> = history.iter().map(|(_, canonical, uid)| (canonical.as_str(), uid.as_str())).collect();
+        assert_eq!(uids, [("1001", "1001"), ("1001", " 1001 "), ("1001", "1003"), ("1004", "1004"), ("1004", "1005")]);
+        // 同一组的历史记录共用一个 merge_id
+        assert!(history[..3].iter().all(|(id, _, _)| *id == history[0].0));
+        assert!(history[3..].iter().all(|(id, _, _)| *id == history[3].0));
+        assert_ne!(history[0].0, history[3].0);
+        assert!(scan_merge_groups(&conn).is_empty());
+    }
+
+    #[test]
+    fn merge_groups_reports_conflict_for_stale_records() {
+        let conn = merge_db();
+        let groups = scan_merge_groups(&conn);
+        conn.execute("DELETE FROM user_mapping WHERE uid = '1005'", []).unwrap();
+        let tx = conn.unchecked_transaction().unwrap();
+        assert!(matches!(merge_groups(&tx, &groups).unwrap(), Err(AppError::Conflict(_))));
+        drop(tx);
+        let remaining: i64 = conn.query_row("SELECT COUNT(*) FROM user_mapping", [], |row| row.get(0)).unwrap();
+        assert_eq!(remaining, 5);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn apply_merge_merges_through_the_store() {
+        let dir = env::temp_dir().join(format!("cyber_lookup_merge_{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("merge.db");
+        let config = ServiceConfig { db_path: path.to_string_lossy().into_owned(), ..ServiceConfig::default() };
+        let state = Arc::new(AppState::new(config));
+        {
+            let conn = state.get_db_connection().unwrap();
+            initialize_database(&conn).unwrap();
+            for (uid, phone) in MERGE_ROWS {
+                conn.execute("INSERT INTO user_mapping (uid, phone_number) VALUES (?1, ?2)", [uid, phone]).unwrap();
+            }
+        }
+        let (scanned, groups) = find_merge_groups(&state).unwrap();
+        assert_eq!((scanned, groups.len()), (6, 2));
+
+        let plan = apply_merge(&state).unwrap();
+        assert!(plan.applied);
+        assert_eq!(plan.merged, 5);
+        let (scanned, groups) = find_merge_groups(&state).unwrap();
+        assert_eq!((scanned, groups.len()), (3, 0));
+        drop(state);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,202 @@
+//! RESP (Redis 协议) 兼容监听。
+
+use crate::*;
+
+/// 绑定 resp_bind_address，接受 Redis 客户端连接；返回值被丢弃时停止监听并断开所有连接
+pub(crate) async fn start_resp_server(state: Arc<AppState>, bind_address: &str) -> Result<AbortOnDrop<()>, AppError> {
+    let addr: SocketAddr = bind_address.parse()
+        .map_err(|e| AppError::FatalError(tr!("配置错误: resp_bind_address 格式无效: {}", "Config Error: Invalid resp_bind_address: {}", e)))?;
+    let listener = tokio::net::TcpListener::bind(addr).await.map_err(AppError::NetworkBindError)?;
+    let addr = listener.local_addr().map_err(AppError::NetworkBindError)?;
+    println!("{} {}", "STARTED".green().bold(), tr!("Redis 协议兼容监听地址: {} (GET / MGET / EXISTS)", "Redis-protocol listener on {} (GET / MGET / EXISTS)", addr));
+    state.listening.lock().unwrap().resp = Some(addr);
+    Ok(AbortOnDrop(tokio::spawn(async move {
+        // 连接任务归属于 JoinSet，监听任务被中止时一并中止
+        let mut connections = task::JoinSet::new();
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    let _ = stream.set_nodelay(true);
+                    connections.spawn(serve_resp_connection(state.clone(), stream, peer));
+                }
+                Err(e) => {
+                    eprintln!("{} {}", "WARN".yellow(), tr!("接受 Redis 协议连接失败: {}", "Failed to accept a Redis-protocol connection: {}", e));
+                    sleep(Duration::from_millis(100)).await;
+                }
+            }
+            while connections.try_join_next().is_some() {}
+        }
+    })))
+}
+
+fn resp_simple(text: &str) -> Vec<u8> {
+    format!("+{}\r\n", text).into_bytes()
+}
+
+fn resp_error(text: &str) -> Vec<u8> {
+    // 错误文本不能含换行
+    format!("-{}\r\n", text.replace(['\r', '\n'], " ")).into_bytes()
+}
+
+fn resp_integer(value: usize) -> Vec<u8> {
+    format!(":{}\r\n", value).into_bytes()
+}
+
+fn resp_bulk(value: Option<&str>) -> Vec<u8> {
+    match value {
+        Some(value) => format!("${}\r\n{}\r\n", value.len(), value).into_bytes(),
+        None => b"$-1\r\n".to_vec(),
+    }
+}
+
+fn resp_array(items: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut out = format!("*{}\r\n", items.len()).into_bytes();
+    items.into_iter().for_each(|item| out.extend(item));
+    out
+}
+
+/// 读取一行（不含 \r\n），连接关闭时返回 None
+async fn read_resp_line<R: tokio::io::AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Option<String>> {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt};
+    let mut line = String::new();
+    let read = reader.take(RESP_MAX_ARG_BYTES as u64 + 2).read_line(&mut line).await?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Protocol error: line too long"));
+    }
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+/// 读取一条命令：RESP 数组 (`*N` 后跟 N 个 `$len` 字符串)，或以空白分隔的内联命令。连接关闭时返回 None。
+async fn read_resp_command<R: tokio::io::AsyncBufRead + Unpin>(reader: &mut R, max_args: usize) -> io::Result<Option<Vec<String>>> {
+    use tokio::io::AsyncReadExt;
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, format!("Protocol error: {}", message));
+    let Some(line) = read_resp_line(reader).await? else { return Ok(None) };
+    let Some(count) = line.strip_prefix('*') else {
+        return Ok(Some(line.split_whitespace().map(String::from).collect()));
+    };
+    let count: usize = count.parse().map_err(|_| invalid("invalid multibulk length"))?;
+    if count > max_args {
+        return Err(invalid("too many arguments"));
+    }
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        let line = read_resp_line(reader).await?.ok_or_else(|| invalid("unexpected end of stream"))?;
+        let len: usize = line.strip_prefix('$').and_then(|len| len.parse().ok()).ok_or_else(|| invalid("expected '$'"))?;
+        if len > RESP_MAX_ARG_BYTES {
+            return Err(invalid("invalid bulk length"));
+        }
+        let mut buf = vec![0; len + 2];
+        reader.read_exact(&mut buf).await?;
+        buf.truncate(len);
+        args.push(String::from_utf8(buf).map_err(|_| invalid("argument is not valid UTF-8"))?);
+    }
+    Ok(Some(args))
+}
+
+/// 一个 Redis 客户端连接：逐条读取命令并应答，支持流水线（读缓冲为空时才刷新写缓冲）。
+/// 初始为匿名调用方，`AUTH [用户名] <API 密钥>` 后按该密钥的权限处理。
+async fn serve_resp_connection(state: Arc<AppState>, stream: tokio::net::TcpStream, peer: SocketAddr) {
+    use tokio::io::AsyncWriteExt;
+    let (reader, writer) = stream.into_split();
+    let mut reader = tokio::io::BufReader::new(reader);
+    let mut writer = tokio::io::BufWriter::new(writer);
+    let config = state.current_config();
+    if let Err(e) = check_ip_access(&state, &config, peer.ip()) {
+        let _ = writer.write_all(&resp_error(&format!("ERR {}", e))).await;
+        let _ = writer.flush().await;
+        return;
+    }
+    let mut caller = Caller { key_name: None, scopes: config.anonymous_scopes.clone(), admin: false };
+    loop {
+        let max_args = state.current_config().batch_size_limit as usize + 1;
+        let (reply, quit) = match read_resp_command(&mut reader, max_args).await {
+            Ok(None) => return,
+            Ok(Some(args)) if args.is_empty() => continue,
+            Ok(Some(args)) => {
+                let command = CALLER.scope(caller.clone(), CLIENT_IP.scope(peer.ip(), resp_command(&state, &mut caller, &args)));
+                (command.await, args[0].eq_ignore_ascii_case("quit"))
+            }
+            // 协议错误后无法再定位下一条命令的边界，应答后断开
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => (resp_error(&format!("ERR {}", e)), true),
+            Err(_) => return,
+        };
+        if writer.write_all(&reply).await.is_err() {
+            return;
+        }
+        if quit {
+            let _ = writer.flush().await;
+            return;
+        }
+        // 流水线中还有待处理的命令时暂不刷新，合并应答
+        if reader.buffer().is_empty() && writer.flush().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// 执行一条 RESP 命令并返回编码后的应答。GET 按 uid 返回手机号、按手机号返回 uid，不存在时为 nil。
+async fn resp_command(state: &Arc<AppState>, caller: &mut Caller, args: &[String]) -> Vec<u8> {
+    let name = args[0].to_ascii_uppercase();
+    let keys = &args[1..];
+    match name.as_str() {
+        "PING" => match keys.first() {
+            Some(message) => resp_bulk(Some(message)),
+            None => resp_simple("PONG"),
+        },
+        "ECHO" if keys.len() == 1 => resp_bulk(Some(&keys[0])),
+        "QUIT" => resp_simple("OK"),
+        // 客户端库连接时常发送的命令：给出无害的应答
+        "COMMAND" => resp_array(Vec::new()),
+        "CLIENT" => resp_simple("OK"),
+        "SELECT" if keys.len() == 1 => match keys[0].as_str() {
+            "0" => resp_simple("OK"),
+            _ => resp_error("ERR DB index is out of range"),
+        },
+        "AUTH" if !keys.is_empty() && keys.len() <= 2 => {
+            let config = state.current_config();
+            let presented = &keys[keys.len() - 1];
+            match config.api_keys.iter().find(|k| constant_time_eq(presented.as_bytes(), k.key.as_bytes())) {
+                Some(api_key) => {
+                    *caller = Caller { key_name: Some(api_key.name.clone()), scopes: api_key.scopes.clone(), admin: false };
+                    resp_simple("OK")
+                }
+                None => resp_error("WRONGPASS invalid username-password pair or user is disabled."),
+            }
+        }
+        "GET" | "MGET" | "EXISTS" if (name == "GET" && keys.len() == 1) || (name != "GET" && !keys.is_empty()) => {
+            if !caller.has_scope(ApiScope::Read) {
+                return match caller.key_name {
+                    None => resp_error("NOAUTH Authentication required."),
+                    Some(_) => resp_error(&format!("NOPERM {}", tr!("API 密钥没有 read 权限。", "The API key lacks the read scope."))),
+                };
+            }
+            let config = state.current_config();
+            let resolved = async {
+                check_batch_size(&config, keys.len())?;
+                let mut results = resolve_batch(state, &config, keys).await?;
+                apply_post_process_hook(state, &mut results)?;
+                mask_results(&config, &mut results);
+                Ok::<_, AppError>(results)
+            }.await;
+            let results = match resolved {
+                Ok(results) => results,
+                Err(e) => return resp_error(&format!("ERR {}", e)),
+            };
+            // 查询的是 uid 时返回手机号，否则返回 uid
+            let value = |key: &String, resp: &LookupResponse| match resp.uid.as_ref() {
+                Some(uid) if uid == key => resp.phone_number.clone(),
+                uid => uid.cloned(),
+            };
+            match name.as_str() {
+                "GET" => resp_bulk(value(&keys[0], &results[0]).as_deref()),
+                "MGET" => resp_array(keys.iter().zip(&results).map(|(key, resp)| resp_bulk(value(key, resp).as_deref())).collect()),
+                _ => resp_integer(results.iter().filter(|resp| resp.uid.is_some()).count()),
+            }
+        }
+        "GET" | "MGET" | "EXISTS" | "ECHO" | "AUTH" | "SELECT" => resp_error(&format!("ERR wrong number of arguments for '{}' command", args[0].to_lowercase())),
+        _ => resp_error(&format!("ERR unknown command '{}'", args[0])),
+    }
+}
@@ -0,0 +1,298 @@
+//! 定时任务：按 cron 表达式调度、执行与记录状态，以及后台 WAL 检查点与缓存预热。
+
+use crate::*;
+
+/// 定时任务最近一次执行的状态
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct TaskRun {
+    pub(crate) started_at: String,
+    pub(crate) duration_ms: u64,
+    pub(crate) running: bool,
+    pub(crate) ok: Option<bool>,                // 执行中为 null
+    pub(crate) message: String,
+    pub(crate) runs: u64,                       // 本进程内的累计执行次数
+    pub(crate) failures: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct TaskStatus {
+    pub(crate) name: String,
+    pub(crate) task: TaskKind,
+    pub(crate) schedule: String,
+    pub(crate) next_run: Option<String>,        // 调度器未启用时为 null
+    pub(crate) last_run: Option<TaskRun>,
+}
+
+/// 标记任务开始执行；上一次尚未结束时返回 false
+fn begin_task_run(state: &AppState, name: &str) -> bool {
+    let mut runs = state.task_runs.lock().unwrap();
+    let run = runs.entry(name.to_string()).or_insert_with(|| TaskRun {
+        started_at: String::new(), duration_ms: 0, running: false, ok: None, message: String::new(), runs: 0, failures: 0,
+    });
+    if run.running {
+        return false;
+    }
+    run.started_at = Local::now().to_rfc3339();
+    run.running = true;
+    run.ok = None;
+    run.message.clear();
+    true
+}
+
+/// 执行一个定时任务并记录状态，返回执行结果的说明
+pub(crate) async fn run_scheduled_task(state: &Arc<AppState>, scheduled: &ScheduledTask) -> Result<String, String> {
+    if !begin_task_run(state, &scheduled.name) {
+        return Err(tr!("上一次执行尚未结束", "the previous run is still in progress").to_string());
+    }
+    let started = Instant::now();
+    let result = execute_task(state, scheduled.task).await;
+    let mut runs = state.task_runs.lock().unwrap();
+    if let Some(run) = runs.get_mut(&scheduled.name) {
+        run.running = false;
+        run.duration_ms = started.elapsed().as_millis() as u64;
+        run.runs += 1;
+        run.ok = Some(result.is_ok());
+        match &result {
+            Ok(message) => run.message = message.clone(),
+            Err(message) => {
+                run.failures += 1;
+                run.message = message.clone();
+            }
+        }
+    }
+    result
+}
+
+async fn execute_task(state: &Arc<AppState>, kind: TaskKind) -> Result<String, String> {
+    let config = state.current_config();
+    let task_state = state.clone();
+    let joined = match kind {
+        TaskKind::Backup => task::spawn_blocking(move || {
+            let (dest, removed) = run_backup_once(&task_state, &config.backup, false).map_err(|e| format!("{:?}", e))?;
+            Ok(tr!("已备份到 {}，清理旧备份 {} 份", "backed up to {}, pruned {} old backup(s)", dest.display(), removed.len()))
+        }).await,
+        TaskKind::WalCheckpoint => task::spawn_blocking(move || checkpoint_wal(&task_state, &config).map_err(|e| e.to_string())).await,
+        TaskKind::Retention => task::spawn_blocking(move || {
+            let report = apply_retention(&task_state, &config.retention).map_err(|e| format!("{:?}", e))?;
+            let removed: u64 = report.rules.iter().map(|rule| rule.removed).sum();
+            Ok(if report.dry_run {
+                tr!("将删除 {} 条 (dry run)", "{} record(s) would be removed (dry run)", removed)
+            } else {
+                tr!("已删除 {} 条", "{} record(s) removed", removed)
+            })
+        }).await,
+        TaskKind::CacheWarmup => return warm_cache(state, &config).await,
+        TaskKind::StatsSnapshot => task::spawn_blocking(move || {
+            let snapshot = serde_json::json!({ "time": Local::now().to_rfc3339(), "stats": task_state.stats() });
+            let path = &config.scheduler.stats_snapshot_path;
+            if let Some(dir) = FilePath::new(path).parent().filter(|d| !d.as_os_str().is_empty()) {
+                fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            }
+            let mut file = fs::OpenOptions::new().create(true).append(true).open(path).map_err(|e| e.to_string())?;
+            writeln!(file, "{}", snapshot).map_err(|e| e.to_string())?;
+            Ok(tr!("已写入 {}", "written to {}", path))
+        }).await,
+    };
+    joined.map_err(|e| tr!("任务异常终止: {}", "task panicked: {}", e))?
+}
+
+/// sqlite 后端的全部数据库文件（分片时为各分片）
+pub(crate) fn sqlite_files(config: &ServiceConfig) -> Vec<String> {
+    if config.storage.shards > 1 {
+        (0..config.storage.shards as usize).map(|index| shard_path(&config.db_path, index, config.storage.shards as usize)).collect()
+    } else {
+        vec![config.db_path.clone()]
+    }
+}
+
+/// 对 sqlite 后端的每个数据库文件执行 WAL 检查点并截断 WAL 文件
+fn checkpoint_wal(state: &AppState, config: &ServiceConfig) -> SqlResult<String> {
+    if config.storage_backend() != StorageBackend::Sqlite {
+        return Ok(tr!("当前存储后端没有 WAL 文件，跳过", "the storage backend has no WAL file, skipped").to_string());
+    }
+    let paths = sqlite_files(config);
+    let mut pages = 0;
+    let mut busy = 0;
+    for path in &paths {
+        let conn = state.open_connection(path)?;
+        let (blocked, checkpointed): (i64, i64) = conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| Ok((row.get(0)?, row.get(2)?)))?;
+        busy += blocked;
+        pages += checkpointed.max(0);
+    }
+    Ok(if busy > 0 {
+        tr!("{} 个文件，写回 {} 页，{} 个文件因有读写未完成而未截断", "{} file(s), {} page(s) checkpointed, {} file(s) busy and not truncated", paths.len(), pages, busy)
+    } else {
+        tr!("{} 个文件，写回 {} 页", "{} file(s), {} page(s) checkpointed", paths.len(), pages)
+    })
+}
+
+/// 后台 WAL 检查点任务：每 wal_checkpoint_secs 等到写入空闲后截断 WAL（含各租户的数据库），
+/// 持续写入时最多再等一个间隔便强制执行，避免 WAL 无限增长。每轮重新读取配置。
+pub(crate) async fn run_wal_checkpointer(state: Arc<AppState>) {
+    loop {
+        let config = state.current_config();
+        let pragmas = &config.pragmas;
+        if pragmas.wal_checkpoint_secs == 0 || !pragmas.journal_mode.eq_ignore_ascii_case("wal") || config.storage_backend() != StorageBackend::Sqlite {
+            sleep(Duration::from_secs(BACKUP_POLL_SECS)).await;
+            continue;
+        }
+        let interval = Duration::from_secs(pragmas.wal_checkpoint_secs);
+        let idle = Duration::from_millis(pragmas.wal_checkpoint_idle_ms);
+        sleep(interval).await;
+        let deadline = Instant::now() + interval;
+        let mut targets = vec![state.clone()];
+        targets.extend(state.tenants.get().into_iter().flat_map(|tenants| tenants.values().cloned()));
+        while Instant::now() < deadline && !targets.iter().all(|target| target.write_idle(idle)) {
+            sleep(idle.max(Duration::from_millis(100))).await;
+        }
+        for target in targets {
+            let task_state = target.clone();
+            let result = task::spawn_blocking(move || {
+                let config = task_state.current_config();
+                checkpoint_wal(&task_state, &config).map_err(|e| e.to_string())
+            }).await.unwrap_or_else(|e| Err(tr!("任务异常终止: {}", "task panicked: {}", e)));
+            let mut status = target.wal_checkpoints.lock().unwrap();
+            status.runs += 1;
+            status.last_at = Some(Local::now().to_rfc3339());
+            match result {
+                Ok(message) => status.last_result = Some(message),
+                Err(message) => {
+                    eprintln!("{} {}", "WARN".yellow(), tr!("WAL 检查点失败 ({}): {}", "WAL checkpoint failed ({}): {}", target.current_config().db_path, message));
+                    status.failures += 1;
+                    status.last_result = Some(message);
+                }
+            }
+        }
+    }
+}
+
+/// 预加载映射到查询缓存：先取最近单条查询过的 ID，再按 uid 顺序补足到 warmup_limit 条映射
+async fn warm_cache(state: &Arc<AppState>, config: &ServiceConfig) -> Result<String, String> {
+    if !state.cache_enabled() {
+        return Ok(tr!("查询缓存未启用，跳过", "the query cache is disabled, skipped").to_string());
+    }
+    let limit = config.scheduler.warmup_limit as usize;
+    let mut ids: Vec<String> = Vec::new();
+    let mut seen = HashSet::new();
+    for query in state.recent_queries.lock().unwrap().iter().rev() {
+        if let Some(id) = &query.id {
+            if ids.len() < limit && seen.insert(id.clone()) {
+                ids.push(id.clone());
+            }
+        }
+    }
+    let store = state.store().map_err(|e| format!("{:?}", e))?;
+    let remaining = limit.saturating_sub(ids.len());
+    if remaining > 0 {
+        let found = Arc::new(Mutex::new(Vec::new()));
+        let sink_found = found.clone();
+        // 取够后让 sink 出错以中止导出
+        let exported = store.export(None, Box::new(move |uid, phone| {
+            let mut found = sink_found.lock().unwrap();
+            if found.len() >= remaining {
+                return Err(io::Error::new(io::ErrorKind::Interrupted, "limit reached"));
+            }
+            found.push((uid.to_string(), phone.to_string()));
+            Ok(())
+        })).await;
+        let found = std::mem::take(&mut *found.lock().unwrap());
+        if let Err(e) = exported {
+            if found.len() < remaining {
+                return Err(format!("{:?}", e));
+            }
+        }
+        for (uid, phone) in found {
+            for id in [uid, phone] {
+                if seen.insert(id.clone()) {
+                    ids.push(id);
+                }
+            }
+        }
+    }
+    let warmed = preload_ids(state, &ids, config.batch_chunk_size as usize).await.map_err(|e| format!("{:?}", e))?;
+    Ok(tr!("已预加载 {} 个 ID", "{} ID(s) preloaded", warmed))
+}
+
+/// 后台定时任务调度：每 SCHEDULER_TICK_SECS 检查一次到期的任务并在独立任务中执行。每轮重新读取配置，
+/// 表达式修改后从当前时间重新计算下次执行时间；同名任务上一次尚未结束时跳过本次。
+pub(crate) async fn run_task_scheduler(state: Arc<AppState>) {
+    let mut next_runs: HashMap<String, (String, DateTime<Local>)> = HashMap::new();
+    loop {
+        sleep(Duration::from_secs(SCHEDULER_TICK_SECS)).await;
+        let scheduler = state.current_config().scheduler;
+        if !scheduler.enabled {
+            next_runs.clear();
+            continue;
+        }
+        let now = Local::now();
+        next_runs.retain(|name, _| scheduler.tasks.iter().any(|t| &t.name == name));
+        for scheduled in scheduler.tasks {
+            let due = match next_runs.get(&scheduled.name) {
+                Some((schedule, at)) if *schedule == scheduled.schedule => *at <= now,
+                _ => false,
+            };
+            if due {
+                let task_state = state.clone();
+                let task = scheduled.clone();
+                tokio::spawn(async move {
+                    match run_scheduled_task(&task_state, &task).await {
+                        Ok(message) => println!("{} {}", "TASK".cyan(), tr!("{} 完成: {}", "{} finished: {}", task.name, message)),
+                        Err(message) => eprintln!("{} {}", "ERR".red(), tr!("定时任务 {} 失败: {}", "Scheduled task {} failed: {}", task.name, message)),
+                    }
+                });
+            }
+            if due || next_runs.get(&scheduled.name).map(|(schedule, _)| schedule) != Some(&scheduled.schedule) {
+                match parse_schedule(&scheduled.schedule).and_then(|cron| cron.find_next_occurrence(&now, false)) {
+                    Ok(at) => {
+                        next_runs.insert(scheduled.name.clone(), (scheduled.schedule.clone(), at));
+                    }
+                    Err(e) => {
+                        next_runs.remove(&scheduled.name);
+                        eprintln!("{} {}", "WARN".yellow(), tr!("定时任务 {} 的 cron 表达式无效: {}", "Invalid cron expression for task {}: {}", scheduled.name, e));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 管理模式的 tasks 命令：列出定时任务，或 `tasks run <名称>` 立即执行一次
+pub(crate) async fn manage_tasks(state: &Arc<AppState>, arg: &str) {
+    let config = state.current_config();
+    let mut parts = arg.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (None, _) => {
+            if config.scheduler.tasks.is_empty() {
+                println!("{} {}", "INFO".cyan(), tr!("未配置定时任务 (scheduler.tasks)。", "No scheduled tasks configured (scheduler.tasks)."));
+                return;
+            }
+            if !config.scheduler.enabled {
+                println!("{} {}", "INFO".cyan(), tr!("调度器未启用 (scheduler.enabled = false)，任务只能手动执行。", "The scheduler is disabled (scheduler.enabled = false); tasks only run manually."));
+            }
+            for status in state.task_statuses(&config) {
+                let kind = serde_json::to_value(status.task).ok().and_then(|v| v.as_str().map(String::from)).unwrap_or_default();
+                println!("{} [{}] '{}'", status.name.bold(), kind, status.schedule);
+                if let Some(next) = &status.next_run {
+                    println!("    {}", tr!("下次执行: {}", "Next run: {}", next));
+                }
+                match &status.last_run {
+                    None => println!("    {}", tr!("尚未执行", "Not run yet")),
+                    Some(run) if run.running => println!("    {}", tr!("执行中，开始于 {}", "Running since {}", run.started_at).yellow()),
+                    Some(run) => {
+                        let outcome = if run.ok == Some(true) { "OK".green() } else { "ERR".red() };
+                        println!("    {} {} ({} ms): {}", outcome, run.started_at, run.duration_ms, run.message);
+                        println!("    {}", tr!("累计 {} 次，失败 {} 次", "{} run(s), {} failure(s)", run.runs, run.failures));
+                    }
+                }
+            }
+        }
+        (Some("run"), Some(name)) => match config.scheduler.tasks.iter().find(|t| t.name == name) {
+            Some(scheduled) => match run_scheduled_task(state, scheduled).await {
+                Ok(message) => println!("{} {}", "OK".green(), tr!("{} 完成: {}", "{} finished: {}", name, message)),
+                Err(message) => eprintln!("{} {}", "ERR".red(), tr!("{} 失败: {}", "{} failed: {}", name, message)),
+            },
+            None => eprintln!("{} {}", "ERR".red(), tr!("未找到定时任务: {}", "No such scheduled task: {}", name)),
+        },
+        _ => eprintln!("{} {}", "WARN".yellow(), tr!("用法: tasks [run <名称>]", "Usage: tasks [run <name>]")),
+    }
+}
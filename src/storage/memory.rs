@@ -0,0 +1,218 @@
+//! 纯内存后端。
+
+use crate::*;
+
+/// 内存中的映射表。`rows` 以递增序号保存写入顺序（对应 SQLite 的 rowid），两个索引分别按 uid 与手机号定位序号。
+#[derive(Default)]
+struct MemoryTable {
+    rows: BTreeMap<u64, (String, String)>,
+    by_uid: HashMap<String, u64>,
+    by_phone: HashMap<String, u64>,
+    next_seq: u64,
+}
+
+/// 事务内的单步修改，回滚时逆序撤销
+enum MemoryChange {
+    Added(u64),
+    Removed(u64, String, String),
+}
+
+impl MemoryTable {
+    fn get(&self, seq: u64) -> Option<&(String, String)> {
+        self.rows.get(&seq)
+    }
+    fn add(&mut self, uid: &str, phone: &str) -> u64 {
+        self.next_seq += 1;
+        let seq = self.next_seq;
+        self.restore(seq, uid.to_string(), phone.to_string());
+        seq
+    }
+    fn restore(&mut self, seq: u64, uid: String, phone: String) {
+        self.by_uid.insert(uid.clone(), seq);
+        self.by_phone.insert(phone.clone(), seq);
+        self.rows.insert(seq, (uid, phone));
+    }
+    fn remove(&mut self, seq: u64) -> Option<(String, String)> {
+        let (uid, phone) = self.rows.remove(&seq)?;
+        self.by_uid.remove(&uid);
+        self.by_phone.remove(&phone);
+        Some((uid, phone))
+    }
+    /// uid 等于 `uid` 或手机号等于 `phone` 的记录序号（去重）
+    fn matching(&self, uid: &str, phone: &str) -> Vec<u64> {
+        let mut seqs: Vec<u64> = self.by_uid.get(uid).into_iter().chain(self.by_phone.get(phone)).copied().collect();
+        seqs.dedup();
+        seqs
+    }
+    fn undo(&mut self, changes: Vec<MemoryChange>) {
+        for change in changes.into_iter().rev() {
+            match change {
+                MemoryChange::Added(seq) => {
+                    self.remove(seq);
+                }
+                MemoryChange::Removed(seq, uid, phone) => self.restore(seq, uid, phone),
+            }
+        }
+    }
+    /// 与 `write_mapping` 相同的冲突判定
+    fn write(&mut self, uid: &str, phone: &str, strategy: ConflictStrategy, changes: &mut Vec<MemoryChange>) -> WriteOutcome {
+        let existing = self.matching(uid, phone);
+        if existing.is_empty() {
+            changes.push(MemoryChange::Added(self.add(uid, phone)));
+            return WriteOutcome::Inserted;
+        }
+        if existing.len() == 1 && self.get(existing[0]).is_some_and(|(u, p)| u == uid && p == phone) {
+            return WriteOutcome::Unchanged;
+        }
+        match strategy {
+            ConflictStrategy::Skip => WriteOutcome::Skipped,
+            ConflictStrategy::Error => WriteOutcome::Conflict,
+            ConflictStrategy::Replace => {
+                for seq in existing {
+                    if let Some((u, p)) = self.remove(seq) {
+                        changes.push(MemoryChange::Removed(seq, u, p));
+                    }
+                }
+                changes.push(MemoryChange::Added(self.add(uid, phone)));
+                WriteOutcome::Replaced
+            }
+        }
+    }
+}
+
+/// 纯内存后端：`storage.backend = "memory"` 或 `db_path = ":memory:"` 时使用。
+/// 无需文件系统、启动即用，适合集成测试（配合 [`AppState::with_store`]）与演示环境；进程退出后数据丢失。
+#[derive(Default)]
+pub struct MemoryStore {
+    table: RwLock<MemoryTable>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[axum::async_trait]
+impl MappingStore for MemoryStore {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn initialize(&self) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    async fn check_schema(&self) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    async fn round_trip(&self) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    async fn lookup(&self, id: &str) -> Result<LookupResponse, AppError> {
+        let table = self.table.read().unwrap();
+        if let Some((uid, phone)) = table.by_uid.get(id).and_then(|&seq| table.get(seq)) {
+            return Ok(LookupResponse { status: "found_by_uid".to_string(), uid: Some(uid.clone()), phone_number: Some(phone.clone()), matched_by: None, query: None, error: None });
+        }
+        if let Some((uid, phone)) = table.by_phone.get(id).and_then(|&seq| table.get(seq)) {
+            return Ok(LookupResponse { status: "found_by_phone".to_string(), uid: Some(uid.clone()), phone_number: Some(phone.clone()), matched_by: None, query: None, error: None });
+        }
+        Ok(LookupResponse::not_found())
+    }
+
+    async fn batch_lookup(&self, ids: Vec<String>) -> Result<Vec<LookupResponse>, AppError> {
+        let table = self.table.read().unwrap();
+        Ok(ids.iter().map(|id| {
+            table.by_uid.get(id).or_else(|| table.by_phone.get(id))
+                .and_then(|&seq| table.get(seq))
+                .map(|(uid, phone)| LookupResponse { status: "found".to_string(), uid: Some(uid.clone()), phone_number: Some(phone.clone()), matched_by: None, query: None, error: None })
+                .unwrap_or_else(LookupResponse::not_found)
+        }).collect())
+    }
+
+    async fn insert(&self, mappings: Vec<(String, String)>, strategy: ConflictStrategy, dry_run: bool) -> Result<WriteReport, AppError> {
+        let mut table = self.table.write().unwrap();
+        let mut keys: Vec<String> = Vec::new();
+        for (uid, phone) in &mappings {
+            keys.extend([uid.clone(), phone.clone()]);
+            for seq in table.matching(uid, phone) {
+                if let Some((u, p)) = table.get(seq) {
+                    keys.extend([u.clone(), p.clone()]);
+                }
+            }
+        }
+
+        let mut changes = Vec::new();
+        let mut summary = WriteSummary::default();
+        for (uid, phone) in &mappings {
+            match table.write(uid, phone, strategy, &mut changes) {
+                WriteOutcome::Conflict if !dry_run => {
+                    table.undo(changes);
+                    return Err(conflict_error(uid, phone));
+                }
+                outcome => summary.record(outcome),
+            }
+        }
+        if dry_run {
+            table.undo(changes);
+        }
+        Ok(WriteReport { summary, affected_keys: Some(keys) })
+    }
+
+    async fn delete(&self, ids: Vec<String>, dry_run: bool) -> Result<DeleteReport, AppError> {
+        let mut table = self.table.write().unwrap();
+        let mut seqs: Vec<u64> = ids.iter().flat_map(|id| table.matching(id, id)).collect();
+        seqs.sort_unstable();
+        seqs.dedup();
+        let mut keys = ids;
+        let mut records = Vec::with_capacity(seqs.len());
+        for &seq in &seqs {
+            let removed = if dry_run { table.get(seq).cloned() } else { table.remove(seq) };
+            if let Some((uid, phone)) = removed {
+                keys.extend([uid.clone(), phone.clone()]);
+                records.push((uid, phone));
+            }
+        }
+        Ok(DeleteReport { deleted: seqs.len(), records, affected_keys: Some(keys) })
+    }
+
+    async fn count(&self, uid_prefix: Option<String>) -> Result<u64, AppError> {
+        let table = self.table.read().unwrap();
+        Ok(match uid_prefix {
+            Some(prefix) => table.by_uid.keys().filter(|uid| uid.starts_with(&prefix)).count() as u64,
+            None => table.rows.len() as u64,
+        })
+    }
+
+    async fn export(&self, uid_prefix: Option<String>, mut sink: ExportSink) -> Result<u64, AppError> {
+        let table = self.table.read().unwrap();
+        let mut exported = 0;
+        for (uid, phone) in table.rows.values() {
+            if uid_prefix.as_ref().is_some_and(|prefix| !uid.starts_with(prefix.as_str())) {
+                continue;
+            }
+            sink(uid, phone).map_err(AppError::IoError)?;
+            exported += 1;
+        }
+        Ok(exported)
+    }
+
+    async fn export_snapshot(&self, uid_prefix: Option<String>, on_snapshot: SnapshotHook, mut sink: ExportSink) -> Result<u64, AppError> {
+        // 整个导出期间持有读锁，写入等待导出结束
+        let table = self.table.read().unwrap();
+        let selected = || table.rows.values().filter(|(uid, _)| uid_prefix.as_ref().is_none_or(|prefix| uid.starts_with(prefix.as_str())));
+        on_snapshot(selected().count() as u64).map_err(AppError::IoError)?;
+        let mut exported = 0;
+        for (uid, phone) in selected() {
+            sink(uid, phone).map_err(AppError::IoError)?;
+            exported += 1;
+        }
+        Ok(exported)
+    }
+}
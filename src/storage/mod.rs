@@ -0,0 +1,180 @@
+//! 存储后端抽象 [`MappingStore`] 与写入结果类型，各后端实现见子模块。
+
+use crate::*;
+
+mod sqlite;
+mod sharded;
+mod postgres;
+mod memory;
+mod redis;
+
+pub use self::sqlite::*;
+pub use self::sharded::*;
+pub use self::postgres::*;
+pub use self::memory::*;
+pub use self::redis::*;
+
+/// 写入的 uid 或手机号已属于另一条记录时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictStrategy {
+    Skip,      // 保留已有记录，跳过本条
+    #[default]
+    Replace,   // 删除冲突的已有记录后写入（即 INSERT OR REPLACE）
+    Error,     // 中止并回滚当前事务
+}
+
+/// 写入模式：upsert 按冲突策略处理已有记录；insert 为严格插入，任一 uid 或手机号已存在时整体拒绝 (409)
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WriteMode {
+    #[default]
+    Upsert,
+    Insert,
+}
+
+/// 单条写入的结果
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum WriteOutcome {
+    Inserted,
+    Replaced,
+    Skipped,
+    Unchanged,  // 完全相同的记录已存在
+    Conflict,   // 策略为 error 时遇到冲突，调用方应回滚
+}
+
+/// 批量写入的各类结果计数
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct WriteSummary {
+    pub inserted: u64,
+    pub replaced: u64,
+    pub skipped: u64,
+    pub unchanged: u64,
+    pub conflicts: u64, // 仅 dry-run 下统计；正式写入时策略为 error 的冲突会直接中止
+}
+
+impl WriteSummary {
+    pub(crate) fn record(&mut self, outcome: WriteOutcome) {
+        match outcome {
+            WriteOutcome::Inserted => self.inserted += 1,
+            WriteOutcome::Replaced => self.replaced += 1,
+            WriteOutcome::Skipped => self.skipped += 1,
+            WriteOutcome::Unchanged => self.unchanged += 1,
+            WriteOutcome::Conflict => self.conflicts += 1,
+        }
+    }
+    pub(crate) fn merge(&mut self, other: &WriteSummary) {
+        self.inserted += other.inserted;
+        self.replaced += other.replaced;
+        self.skipped += other.skipped;
+        self.unchanged += other.unchanged;
+        self.conflicts += other.conflicts;
+    }
+    pub(crate) fn total(&self) -> u64 {
+        self.inserted + self.replaced + self.skipped + self.unchanged + self.conflicts
+    }
+}
+
+// --- 存储后端抽象 ---
+
+/// 一次写入的结果：各类计数，以及需要失效的缓存键（含被替换的旧记录的 uid 与手机号；None 表示无法确定，清空整个缓存）。
+#[derive(Debug, Default)]
+pub struct WriteReport {
+    pub summary: WriteSummary,
+    pub affected_keys: Option<Vec<String>>,
+}
+
+/// 一次删除的结果：删除条数、被删除（dry_run 时为将被删除）的记录与需要失效的缓存键。
+#[derive(Debug, Default)]
+pub struct DeleteReport {
+    pub deleted: usize,
+    pub records: Vec<(String, String)>,
+    pub affected_keys: Option<Vec<String>>,
+}
+
+/// 导出时逐条接收 (uid, phone_number) 的回调
+pub type ExportSink = Box<dyn FnMut(&str, &str) -> io::Result<()> + Send>;
+
+/// 快照导出在快照建立后、第一条记录之前以快照中的记录条数调用一次（如写出文件头）
+pub type SnapshotHook = Box<dyn FnOnce(u64) -> io::Result<()> + Send>;
+
+/// 映射数据的存储后端。缓存、布隆过滤器与批量请求去重由 [`AppState`] 负责，实现只需访问底层存储。
+/// HTTP 处理器、[`lookup`] / [`lookup_batch`] 与导出都经由此接口；备份、恢复、VACUUM 等维护命令仍直接操作 SQLite 文件。
+#[axum::async_trait]
+pub trait MappingStore: Send + Sync {
+    /// 后端名称，用于 `/info` 与日志
+    fn name(&self) -> &'static str;
+    /// 建表建索引（已存在时不做修改），服务启动时调用
+    async fn initialize(&self) -> Result<(), AppError>;
+    /// 探测存储是否可用，用于 `/health`
+    async fn ping(&self) -> Result<(), AppError>;
+    /// 确认表结构已创建且为当前版本，用于 `/readyz`
+    async fn check_schema(&self) -> Result<(), AppError>;
+    /// 深度健康检查：在独立的 health_check 表中写入、读回并删除一行哨兵数据，不触及映射数据
+    async fn round_trip(&self) -> Result<(), AppError>;
+    /// 按 uid 或手机号查询单条记录，uid 优先；状态为 found_by_uid / found_by_phone / not_found。
+    async fn lookup(&self, id: &str) -> Result<LookupResponse, AppError>;
+    /// 批量查询，结果与 `ids` 一一对应，状态为 found / not_found。调用方已去重。
+    async fn batch_lookup(&self, ids: Vec<String>) -> Result<Vec<LookupResponse>, AppError>;
+    /// 在一个事务中按冲突策略写入。策略为 error 时遇到冲突整体回滚并返回 `AppError::Conflict`；
+    /// `dry_run` 时事务总是回滚，冲突只计数。
+    async fn insert(&self, mappings: Vec<(String, String)>, strategy: ConflictStrategy, dry_run: bool) -> Result<WriteReport, AppError>;
+    /// 在一个事务中删除 uid 或手机号匹配 `ids` 的记录。`dry_run` 时只统计将被删除的条数。
+    async fn delete(&self, ids: Vec<String>, dry_run: bool) -> Result<DeleteReport, AppError>;
+    /// 记录条数，可按 uid 前缀过滤
+    async fn count(&self, uid_prefix: Option<String>) -> Result<u64, AppError>;
+    /// 按写入顺序把记录逐条交给 `sink`，返回导出条数。`sink` 出错时中止导出。
+    async fn export(&self, uid_prefix: Option<String>, sink: ExportSink) -> Result<u64, AppError>;
+    /// 与 `export` 相同，但计数与全部记录取自同一个只读快照，导出期间的并发写入不可见。
+    /// 默认实现只是先计数再导出，不保证一致；内置后端均已覆盖。
+    async fn export_snapshot(&self, uid_prefix: Option<String>, on_snapshot: SnapshotHook, sink: ExportSink) -> Result<u64, AppError> {
+        on_snapshot(self.count(uid_prefix.clone()).await?).map_err(AppError::IoError)?;
+        self.export(uid_prefix, sink).await
+    }
+    /// 在一个事务中合并重复记录：删除每组的全部记录、写入规范记录并留下合并历史。
+    /// 任一记录已被并发修改或删除时整体回滚并返回 `AppError::Conflict`。默认不支持。
+    async fn merge(&self, groups: Vec<MergeGroup>) -> Result<MergeReport, AppError> {
+        let _ = groups;
+        Err(AppError::Rejected(ApiErrorCode::InvalidRequest, tr!("存储后端 {} 不支持合并重复记录。", "The {} storage backend does not support merging duplicates.", self.name())))
+    }
+}
+
+/// 逐行把查询结果交给 `sink`。回调出错（如磁盘写满）时停止遍历，错误通过内层 Result 传出。
+pub(crate) fn sqlite_export_rows(conn: &Connection, sql: &str, params: &[String], sink: &mut ExportSink) -> SqlResult<io::Result<u64>> {
+    let mut stmt = conn.prepare(sql)?;
+    let mut rows = stmt.query(rusqlite::params_from_iter(params))?;
+    let mut exported = 0;
+    while let Some(row) = rows.next()? {
+        let uid: String = row.get(0)?;
+        let phone: String = row.get(1)?;
+        if let Err(e) = sink(&uid, &phone) {
+            return Ok(Err(e));
+        }
+        exported += 1;
+    }
+    Ok(Ok(exported))
+}
+
+/// 严格插入遇到已有记录：列出已存在的 uid / 手机号（最多 20 个）
+pub(crate) fn insert_conflict_error(mut existing: Vec<String>) -> AppError {
+    const SHOWN: usize = 20;
+    existing.sort();
+    let total = existing.len();
+    let mut shown = existing.into_iter().take(SHOWN).collect::<Vec<_>>().join(", ");
+    if total > SHOWN {
+        shown.push_str(", ...");
+    }
+    AppError::Conflict(tr!("以下 uid / 手机号已存在，严格插入未写入任何数据 (共 {} 个): {}", "These uid / phone values already exist; the strict insert wrote nothing ({} total): {}", total, shown))
+}
+
+pub(crate) fn conflict_error(uid: &str, phone: &str) -> AppError {
+    AppError::Conflict(tr!("({}, {}) 与已有记录冲突，未写入任何数据。", "({}, {}) conflicts with an existing record; nothing was written.", uid, phone))
+}
+
+/// uid 前缀过滤写成范围条件，以便使用 uid 索引
+pub(crate) fn uid_prefix_filter(uid_prefix: Option<String>) -> (&'static str, Vec<String>) {
+    match uid_prefix {
+        Some(prefix) => ("uid >= ?1 AND uid < ?1 || char(1114111)", vec![prefix]),
+        None => ("1", Vec::new()),
+    }
+}
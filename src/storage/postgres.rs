@@ -0,0 +1,225 @@
+//! PostgreSQL 后端。
+
+use crate::*;
+
+/// PostgreSQL 后端：deadpool 连接池 + tokio-postgres。
+/// 表结构与 SQLite 相同，另以自增 id 记录写入顺序（对应 SQLite 的 rowid），批量查询用 `= ANY($1)` 一次绑定整个 ID 数组。
+pub struct PostgresStore {
+    pool: deadpool_postgres::Pool,
+}
+
+/// 连接失败或连接已断开视为不可用 (503)，唯一约束冲突（并发写入同一 uid/手机号）视为冲突 (409)。
+fn pg_error(err: tokio_postgres::Error) -> AppError {
+    if err.code() == Some(&tokio_postgres::error::SqlState::UNIQUE_VIOLATION) {
+        return AppError::Conflict(tr!("并发写入冲突，请重试: {}", "Concurrent write conflict, please retry: {}", err));
+    }
+    if err.is_closed() || err.as_db_error().is_none() {
+        return AppError::StoreUnavailable(err.to_string());
+    }
+    AppError::StoreError(err.to_string())
+}
+
+impl PostgresStore {
+    /// 解析连接串并创建连接池，不会立即连接数据库。
+    pub fn new(url: &str, pool_size: usize) -> Result<Self, AppError> {
+        let pg_config: tokio_postgres::Config = url.parse()
+            .map_err(|e| AppError::ConfigError(tr!("postgres_url 无效: {}", "Invalid postgres_url: {}", e)))?;
+        let manager = deadpool_postgres::Manager::from_config(pg_config, tokio_postgres::NoTls, deadpool_postgres::ManagerConfig {
+            recycling_method: deadpool_postgres::RecyclingMethod::Fast,
+        });
+        let pool = deadpool_postgres::Pool::builder(manager).max_size(pool_size.max(1)).build()
+            .map_err(|e| AppError::ConfigError(e.to_string()))?;
+        Ok(PostgresStore { pool })
+    }
+
+    async fn client(&self) -> Result<deadpool_postgres::Object, AppError> {
+        self.pool.get().await.map_err(|e| AppError::StoreUnavailable(e.to_string()))
+    }
+}
+
+/// 与 `write_mapping` 相同的冲突判定，替换时先删除冲突的已有记录。
+async fn pg_write_mapping(tx: &deadpool_postgres::Transaction<'_>, uid: &str, phone: &str, strategy: ConflictStrategy) -> Result<WriteOutcome, tokio_postgres::Error> {
+    let select = tx.prepare_cached("SELECT uid, phone_number FROM user_mapping WHERE uid = $1 OR phone_number = $2").await?;
+    let existing = tx.query(&select, &[&uid, &phone]).await?;
+    if existing.is_empty() {
+        let insert = tx.prepare_cached("INSERT INTO user_mapping (uid, phone_number) VALUES ($1, $2)").await?;
+        tx.execute(&insert, &[&uid, &phone]).await?;
+        return Ok(WriteOutcome::Inserted);
+    }
+    if existing.len() == 1 && existing[0].get::<_, &str>(0) == uid && existing[0].get::<_, &str>(1) == phone {
+        return Ok(WriteOutcome::Unchanged);
+    }
+    match strategy {
+        ConflictStrategy::Skip => Ok(WriteOutcome::Skipped),
+        ConflictStrategy::Error => Ok(WriteOutcome::Conflict),
+        ConflictStrategy::Replace => {
+            let delete = tx.prepare_cached("DELETE FROM user_mapping WHERE uid = $1 OR phone_number = $2").await?;
+            tx.execute(&delete, &[&uid, &phone]).await?;
+            let insert = tx.prepare_cached("INSERT INTO user_mapping (uid, phone_number) VALUES ($1, $2)").await?;
+            tx.execute(&insert, &[&uid, &phone]).await?;
+            Ok(WriteOutcome::Replaced)
+        }
+    }
+}
+
+#[axum::async_trait]
+impl MappingStore for PostgresStore {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    async fn initialize(&self) -> Result<(), AppError> {
+        self.client().await?.batch_execute(
+            "CREATE TABLE IF NOT EXISTS user_mapping (
+                id BIGSERIAL PRIMARY KEY,
+                uid TEXT NOT NULL UNIQUE,
+                phone_number TEXT NOT NULL UNIQUE
+            )",
+        ).await.map_err(pg_error)
+    }
+
+    async fn ping(&self) -> Result<(), AppError> {
+        self.client().await?.simple_query("SELECT 1").await.map_err(pg_error).map(|_| ())
+    }
+
+    async fn check_schema(&self) -> Result<(), AppError> {
+        let row = self.client().await?.query_one("SELECT to_regclass('user_mapping') IS NOT NULL", &[]).await.map_err(pg_error)?;
+        let missing = if row.get::<_, bool>(0) { Vec::new() } else { vec!["user_mapping".to_string()] };
+        schema_result(missing)
+    }
+
+    async fn round_trip(&self) -> Result<(), AppError> {
+        let client = self.client().await?;
+        client.batch_execute("CREATE TABLE IF NOT EXISTS health_check (key TEXT PRIMARY KEY)").await.map_err(pg_error)?;
+        let key = Uuid::new_v4().to_string();
+        client.execute("INSERT INTO health_check (key) VALUES ($1)", &[&key]).await.map_err(pg_error)?;
+        client.query_one("SELECT 1 FROM health_check WHERE key = $1", &[&key]).await.map_err(pg_error)?;
+        client.execute("DELETE FROM health_check WHERE key = $1", &[&key]).await.map_err(pg_error)?;
+        Ok(())
+    }
+
+    async fn lookup(&self, id: &str) -> Result<LookupResponse, AppError> {
+        let client = self.client().await?;
+        let stmt = client.prepare_cached("SELECT uid, phone_number FROM user_mapping WHERE uid = $1 OR phone_number = $1").await.map_err(pg_error)?;
+        let rows = client.query(&stmt, &[&id]).await.map_err(pg_error)?;
+        let mappings: Vec<(String, String)> = rows.iter().map(|row| (row.get(0), row.get(1))).collect();
+        // 与 SQLite 实现一致：uid 匹配优先于手机号匹配
+        if let Some((uid, phone)) = mappings.iter().find(|(uid, _)| uid == id) {
+            return Ok(LookupResponse { status: "found_by_uid".to_string(), uid: Some(uid.clone()), phone_number: Some(phone.clone()), matched_by: None, query: None, error: None });
+        }
+        Ok(match mappings.into_iter().next() {
+            Some((uid, phone)) => LookupResponse { status: "found_by_phone".to_string(), uid: Some(uid), phone_number: Some(phone), matched_by: None, query: None, error: None },
+            None => LookupResponse::not_found(),
+        })
+    }
+
+    async fn batch_lookup(&self, ids: Vec<String>) -> Result<Vec<LookupResponse>, AppError> {
+        let client = self.client().await?;
+        let stmt = client.prepare_cached("SELECT uid, phone_number FROM user_mapping WHERE uid = ANY($1) OR phone_number = ANY($1)").await.map_err(pg_error)?;
+        let rows = client.query(&stmt, &[&ids]).await.map_err(pg_error)?;
+        let mut map = HashMap::new();
+        for row in rows {
+            let (uid, phone): (String, String) = (row.get(0), row.get(1));
+            let resp = LookupResponse { status: "found".to_string(), uid: Some(uid.clone()), phone_number: Some(phone.clone()), matched_by: None, query: None, error: None };
+            map.insert(uid, resp.clone());
+            map.insert(phone, resp);
+        }
+        Ok(ids.iter().map(|id| map.get(id).cloned().unwrap_or_else(LookupResponse::not_found)).collect())
+    }
+
+    async fn insert(&self, mappings: Vec<(String, String)>, strategy: ConflictStrategy, dry_run: bool) -> Result<WriteReport, AppError> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await.map_err(pg_error)?;
+        let ids: Vec<&str> = mappings.iter().flat_map(|(uid, phone)| [uid.as_str(), phone.as_str()]).collect();
+        let existing = tx.query("SELECT uid, phone_number FROM user_mapping WHERE uid = ANY($1) OR phone_number = ANY($1)", &[&ids]).await.map_err(pg_error)?;
+        let mut keys: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+        keys.extend(existing.iter().flat_map(|row| [row.get::<_, String>(0), row.get::<_, String>(1)]));
+
+        let mut summary = WriteSummary::default();
+        for (uid, phone) in &mappings {
+            match pg_write_mapping(&tx, uid, phone, strategy).await.map_err(pg_error)? {
+                // 未提交的事务在 drop 时回滚
+                WriteOutcome::Conflict if !dry_run => return Err(conflict_error(uid, phone)),
+                outcome => summary.record(outcome),
+            }
+        }
+        if dry_run {
+            tx.rollback().await.map_err(pg_error)?;
+        } else {
+            tx.commit().await.map_err(pg_error)?;
+        }
+        Ok(WriteReport { summary, affected_keys: Some(keys) })
+    }
+
+    async fn delete(&self, ids: Vec<String>, dry_run: bool) -> Result<DeleteReport, AppError> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await.map_err(pg_error)?;
+        let rows = tx.query("DELETE FROM user_mapping WHERE uid = ANY($1) OR phone_number = ANY($1) RETURNING uid, phone_number", &[&ids]).await.map_err(pg_error)?;
+        let records: Vec<(String, String)> = rows.iter().map(|row| (row.get(0), row.get(1))).collect();
+        let mut keys = ids;
+        keys.extend(records.iter().flat_map(|(uid, phone)| [uid.clone(), phone.clone()]));
+        if dry_run {
+            tx.rollback().await.map_err(pg_error)?;
+        } else {
+            tx.commit().await.map_err(pg_error)?;
+        }
+        Ok(DeleteReport { deleted: records.len(), records, affected_keys: Some(keys) })
+    }
+
+    async fn count(&self, uid_prefix: Option<String>) -> Result<u64, AppError> {
+        let client = self.client().await?;
+        let row = match uid_prefix {
+            Some(prefix) => client.query_one("SELECT COUNT(*) FROM user_mapping WHERE starts_with(uid, $1)", &[&prefix]).await,
+            None => client.query_one("SELECT COUNT(*) FROM user_mapping", &[]).await,
+        }.map_err(pg_error)?;
+        Ok(row.get::<_, i64>(0) as u64)
+    }
+
+    async fn export(&self, uid_prefix: Option<String>, mut sink: ExportSink) -> Result<u64, AppError> {
+        let client = self.client().await?;
+        let (sql, params) = match uid_prefix {
+            Some(prefix) => ("SELECT uid, phone_number FROM user_mapping WHERE starts_with(uid, $1) ORDER BY id", vec![prefix]),
+            None => ("SELECT uid, phone_number FROM user_mapping ORDER BY id", Vec::new()),
+        };
+        // 逐行拉取，不把整张表读入内存
+        let rows = client.query_raw(sql, params.iter()).await.map_err(pg_error)?;
+        tokio::pin!(rows);
+        let mut exported = 0;
+        while let Some(row) = rows.next().await {
+            let row = row.map_err(pg_error)?;
+            sink(row.get(0), row.get(1)).map_err(AppError::IoError)?;
+            exported += 1;
+        }
+        Ok(exported)
+    }
+
+    async fn export_snapshot(&self, uid_prefix: Option<String>, on_snapshot: SnapshotHook, mut sink: ExportSink) -> Result<u64, AppError> {
+        let mut client = self.client().await?;
+        // REPEATABLE READ 下事务内的所有语句看到同一快照
+        let tx = client.build_transaction()
+            .isolation_level(tokio_postgres::IsolationLevel::RepeatableRead)
+            .read_only(true)
+            .start().await.map_err(pg_error)?;
+        let (count_sql, sql, params) = match uid_prefix {
+            Some(prefix) => ("SELECT COUNT(*) FROM user_mapping WHERE starts_with(uid, $1)", "SELECT uid, phone_number FROM user_mapping WHERE starts_with(uid, $1) ORDER BY id", vec![prefix]),
+            None => ("SELECT COUNT(*) FROM user_mapping", "SELECT uid, phone_number FROM user_mapping ORDER BY id", Vec::new()),
+        };
+        let total = tx.query_raw(count_sql, params.iter()).await.map_err(pg_error)?;
+        tokio::pin!(total);
+        let total = match total.next().await {
+            Some(row) => row.map_err(pg_error)?.get::<_, i64>(0) as u64,
+            None => 0,
+        };
+        on_snapshot(total).map_err(AppError::IoError)?;
+        let rows = tx.query_raw(sql, params.iter()).await.map_err(pg_error)?;
+        tokio::pin!(rows);
+        let mut exported = 0;
+        while let Some(row) = rows.next().await {
+            let row = row.map_err(pg_error)?;
+            sink(row.get(0), row.get(1)).map_err(AppError::IoError)?;
+            exported += 1;
+        }
+        tx.commit().await.map_err(pg_error)?;
+        Ok(exported)
+    }
+}
@@ -0,0 +1,252 @@
+//! Redis 共享缓存层，包装任一存储后端。
+
+use crate::*;
+
+/// 包装任意存储后端的 Redis 读穿缓存。缓存值为 JSON 编码的 `CachedMapping`（含未命中的负缓存）。
+/// Redis 出错时记录警告并在 `REDIS_RETRY_SECS` 内绕过缓存，请求照常由内层存储处理。
+pub struct RedisCachedStore {
+    inner: Arc<dyn MappingStore>,
+    client: redis::Client,
+    config: RedisConfig,
+    conn: tokio::sync::Mutex<Option<redis::aio::ConnectionManager>>,
+    suspended_until: Mutex<Option<Instant>>,
+}
+
+fn invalidation_channel(key_prefix: &str) -> String {
+    format!("{}invalidate", key_prefix)
+}
+
+impl RedisCachedStore {
+    pub fn new(inner: Arc<dyn MappingStore>, config: RedisConfig) -> Result<Self, AppError> {
+        let client = redis::Client::open(config.url.as_str())
+            .map_err(|e| AppError::ConfigError(tr!("redis.url 无效: {}", "Invalid redis.url: {}", e)))?;
+        Ok(RedisCachedStore { inner, client, config, conn: tokio::sync::Mutex::new(None), suspended_until: Mutex::new(None) })
+    }
+
+    fn key(&self, id: &str) -> String {
+        format!("{}{}", self.config.key_prefix, id)
+    }
+
+    /// 取得（必要时建立）连接；暂停期间或连接失败时返回 None。
+    async fn connection(&self) -> Option<redis::aio::ConnectionManager> {
+        if let Some(until) = *self.suspended_until.lock().unwrap() {
+            if Instant::now() < until {
+                return None;
+            }
+        }
+        let mut conn = self.conn.lock().await;
+        if conn.is_none() {
+            let timeout = Duration::from_millis(REDIS_TIMEOUT_MS);
+            let manager_config = redis::aio::ConnectionManagerConfig::new()
+                .set_connection_timeout(timeout)
+                .set_response_timeout(timeout)
+                .set_number_of_retries(1);
+            match self.client.get_connection_manager_with_config(manager_config).await {
+                Ok(manager) => *conn = Some(manager),
+                Err(e) => {
+                    self.suspend(&e);
+                    return None;
+                }
+            }
+        }
+        conn.clone()
+    }
+
+    /// 出错后暂停使用 Redis 一段时间，避免每个请求都等待超时并刷屏日志。
+    fn suspend(&self, err: &redis::RedisError) {
+        eprintln!("{} {}", "WARN".yellow(), tr!("Redis 缓存不可用，{} 秒内直接访问存储: {}", "Redis cache unavailable, bypassing it for {} s: {}", REDIS_RETRY_SECS, err));
+        *self.suspended_until.lock().unwrap() = Some(Instant::now() + Duration::from_secs(REDIS_RETRY_SECS));
+    }
+
+    async fn get_many(&self, ids: &[String]) -> Vec<Option<CachedMapping>> {
+        let Some(mut conn) = self.connection().await else {
+            return vec![None; ids.len()];
+        };
+        let keys: Vec<String> = ids.iter().map(|id| self.key(id)).collect();
+        // MGET 单个键时也返回数组
+        match redis::cmd("MGET").arg(&keys).query_async::<Vec<Option<String>>>(&mut conn).await {
+            Ok(values) => values.into_iter()
+                .map(|value| value.and_then(|raw| serde_json::from_str::<CachedMapping>(&raw).ok()))
+                .collect(),
+            Err(e) => {
+                self.suspend(&e);
+                vec![None; ids.len()]
+            }
+        }
+    }
+
+    async fn put_many(&self, entries: &[(&str, CachedMapping)]) {
+        let Some(mut conn) = self.connection().await else { return };
+        let mut pipe = redis::pipe();
+        for (id, mapping) in entries {
+            let value = serde_json::to_string(mapping).unwrap_or_default();
+            pipe.cmd("SET").arg(self.key(id)).arg(value).arg("EX").arg(self.config.ttl_secs).ignore();
+        }
+        if let Err(e) = pipe.query_async::<()>(&mut conn).await {
+            self.suspend(&e);
+        }
+    }
+
+    /// 删除受写入影响的键并广播给其他实例。键集合未知时清除本服务的全部缓存键。
+    async fn invalidate(&self, keys: &Option<Vec<String>>) {
+        let Some(mut conn) = self.connection().await else { return };
+        let result: redis::RedisResult<()> = async {
+            match keys {
+                Some(keys) if !keys.is_empty() => {
+                    let redis_keys: Vec<String> = keys.iter().map(|k| self.key(k)).collect();
+                    redis::cmd("DEL").arg(&redis_keys).query_async::<()>(&mut conn).await?;
+                }
+                Some(_) => {}
+                None => {
+                    let pattern = format!("{}*", self.config.key_prefix);
+                    let stale: Vec<String> = {
+                        let mut iter = conn.scan_match::<_, String>(&pattern).await?;
+                        let mut stale = Vec::new();
+                        while let Some(key) = iter.next_item().await {
+                            stale.push(key);
+                        }
+                        stale
+                    };
+                    for batch in stale.chunks(1000) {
+                        redis::cmd("DEL").arg(batch).query_async::<()>(&mut conn).await?;
+                    }
+                }
+            }
+            let payload = serde_json::to_string(keys).unwrap_or_default();
+            redis::cmd("PUBLISH").arg(invalidation_channel(&self.config.key_prefix)).arg(payload).query_async::<()>(&mut conn).await
+        }.await;
+        if let Err(e) = result {
+            self.suspend(&e);
+        }
+    }
+}
+
+#[axum::async_trait]
+impl MappingStore for RedisCachedStore {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    async fn initialize(&self) -> Result<(), AppError> {
+        self.inner.initialize().await
+    }
+
+    async fn ping(&self) -> Result<(), AppError> {
+        // Redis 故障只会降级为直接访问存储，不影响健康状态
+        self.inner.ping().await
+    }
+
+    async fn check_schema(&self) -> Result<(), AppError> {
+        self.inner.check_schema().await
+    }
+
+    async fn round_trip(&self) -> Result<(), AppError> {
+        self.inner.round_trip().await
+    }
+
+    async fn lookup(&self, id: &str) -> Result<LookupResponse, AppError> {
+        if let Some(Some(cached)) = self.get_many(&[id.to_string()]).await.pop() {
+            return Ok(LookupResponse::from_cached_single(id, cached));
+        }
+        let resp = self.inner.lookup(id).await?;
+        self.put_many(&[(id, resp.cache_entry())]).await;
+        Ok(resp)
+    }
+
+    async fn batch_lookup(&self, ids: Vec<String>) -> Result<Vec<LookupResponse>, AppError> {
+        let mut results: Vec<Option<LookupResponse>> = self.get_many(&ids).await.into_iter()
+            .map(|cached| cached.map(LookupResponse::from_cached_batch))
+            .collect();
+        let misses: Vec<String> = ids.iter().zip(&results)
+            .filter(|(_, cached)| cached.is_none())
+            .map(|(id, _)| id.clone())
+            .collect();
+        if misses.is_empty() {
+            return Ok(results.into_iter().flatten().collect());
+        }
+
+        let fetched = self.inner.batch_lookup(misses.clone()).await?;
+        let entries: Vec<(&str, CachedMapping)> = misses.iter().zip(&fetched).map(|(id, resp)| (id.as_str(), resp.cache_entry())).collect();
+        self.put_many(&entries).await;
+        let mut fetched = fetched.into_iter();
+        Ok(results.iter_mut()
+            .map(|slot| slot.take().or_else(|| fetched.next()).unwrap_or_else(LookupResponse::not_found))
+            .collect())
+    }
+
+    async fn insert(&self, mappings: Vec<(String, String)>, strategy: ConflictStrategy, dry_run: bool) -> Result<WriteReport, AppError> {
+        let report = self.inner.insert(mappings, strategy, dry_run).await?;
+        if !dry_run {
+            self.invalidate(&report.affected_keys).await;
+        }
+        Ok(report)
+    }
+
+    async fn delete(&self, ids: Vec<String>, dry_run: bool) -> Result<DeleteReport, AppError> {
+        let report = self.inner.delete(ids, dry_run).await?;
+        if !dry_run {
+            self.invalidate(&report.affected_keys).await;
+        }
+        Ok(report)
+    }
+
+    async fn count(&self, uid_prefix: Option<String>) -> Result<u64, AppError> {
+        self.inner.count(uid_prefix).await
+    }
+
+    async fn export(&self, uid_prefix: Option<String>, sink: ExportSink) -> Result<u64, AppError> {
+        self.inner.export(uid_prefix, sink).await
+    }
+
+    async fn export_snapshot(&self, uid_prefix: Option<String>, on_snapshot: SnapshotHook, sink: ExportSink) -> Result<u64, AppError> {
+        self.inner.export_snapshot(uid_prefix, on_snapshot, sink).await
+    }
+
+    async fn merge(&self, groups: Vec<MergeGroup>) -> Result<MergeReport, AppError> {
+        let report = self.inner.merge(groups).await?;
+        self.invalidate(&report.affected_keys).await;
+        Ok(report)
+    }
+}
+
+/// 后台订阅 Redis 失效频道，把其他实例的写入同步到本进程缓存。每轮重新读取配置，断线后按间隔重连。
+pub(crate) async fn run_cache_invalidation_listener(state: Arc<AppState>) {
+    let mut connected_once = false;
+    loop {
+        let redis = state.current_config().redis;
+        if redis.enabled {
+            match listen_for_invalidations(&state, &redis, &mut connected_once).await {
+                Ok(()) => eprintln!("{} {}", "WARN".yellow(), tr!("Redis 失效订阅连接已关闭，{} 秒后重连。", "Redis invalidation subscription closed, reconnecting in {} s.", REDIS_RETRY_SECS)),
+                Err(e) => eprintln!("{} {}", "WARN".yellow(), tr!("Redis 失效订阅失败，{} 秒后重连: {}", "Redis invalidation subscription failed, reconnecting in {} s: {}", REDIS_RETRY_SECS, e)),
+            }
+            // 断线期间可能漏掉失效消息，进程内缓存已不可信
+            state.invalidate_cache(None);
+        }
+        sleep(Duration::from_secs(REDIS_RETRY_SECS)).await;
+    }
+}
+
+async fn listen_for_invalidations(state: &AppState, redis: &RedisConfig, connected_once: &mut bool) -> redis::RedisResult<()> {
+    let client = redis::Client::open(redis.url.as_str())?;
+    let mut pubsub = client.get_async_pubsub().await?;
+    pubsub.subscribe(invalidation_channel(&redis.key_prefix)).await?;
+    if !*connected_once {
+        println!("{} {}", "INFO".cyan(), tr!("已订阅 Redis 缓存失效频道。", "Subscribed to the Redis cache invalidation channel."));
+        *connected_once = true;
+    }
+    let mut messages = pubsub.into_on_message();
+    while let Some(message) = messages.next().await {
+        let payload: String = message.get_payload()?;
+        // 消息为受影响的键数组，null 表示清空全部
+        match serde_json::from_str::<Option<Vec<String>>>(&payload) {
+            Ok(keys) => state.invalidate_cache(keys),
+            Err(_) => state.invalidate_cache(None),
+        }
+    }
+    Ok(())
+}
+
+/// 在同步代码（db-manage、脚本、子命令）中等待存储后端的异步操作，需运行在多线程 tokio 运行时中。
+pub(crate) fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    task::block_in_place(|| tokio::runtime::Handle::current().block_on(future))
+}
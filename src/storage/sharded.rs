@@ -0,0 +1,525 @@
+//! 按标识哈希分布到多个 SQLite 文件的分片后端与重新分片。
+
+use crate::*;
+
+/// 标识所属的分片：FNV-1a 哈希取模。哈希值写死在数据分布里，不能使用各 Rust 版本间不保证稳定的 `DefaultHasher`。
+fn shard_of(id: &str, shards: usize) -> usize {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in id.as_bytes() {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    (hash % shards.max(1) as u64) as usize
+}
+
+/// 分片文件路径：`data/uid_phone_map.db` 的第 3 个（共 8 个）分片为 `data/uid_phone_map.shard-03-of-08.db`。
+/// 文件名中带有分片总数，不同分片数的文件互不覆盖，重新分片时旧文件原样保留。
+pub fn shard_path(db_path: &str, index: usize, total: usize) -> String {
+    let path = FilePath::new(db_path);
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}.shard-{:02}-of-{:02}.{}", stem, index, total, ext.to_string_lossy()),
+        None => format!("{}.shard-{:02}-of-{:02}", stem, index, total),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// 写连接上第 `index` 个分片的库名：分片 0 为主库，其余以 s1、s2… ATTACH。
+fn shard_schema(index: usize) -> String {
+    if index == 0 { "main".to_string() } else { format!("s{}", index) }
+}
+
+/// 在分片 0 的连接上 ATTACH 其余分片（已附加时跳过），使一个写事务可以跨分片。
+fn attach_shards(conn: &Connection, paths: &[String]) -> SqlResult<()> {
+    if paths.len() < 2 {
+        return Ok(());
+    }
+    let attached: bool = conn.query_row("SELECT COUNT(*) FROM pragma_database_list WHERE name = 's1'", [], |row| row.get::<_, i64>(0))? > 0;
+    if !attached {
+        for (index, path) in paths.iter().enumerate().skip(1) {
+            conn.execute("ATTACH DATABASE ?1 AS ?2", [path.as_str(), shard_schema(index).as_str()])?;
+        }
+    }
+    Ok(())
+}
+
+/// 分片文件的表结构：在普通表结构上增加 uid_home 列。
+/// 每条映射同时存放在 uid 所属与手机号所属的分片中（两者相同时只有一份），按任一标识查询都只需访问一个分片；
+/// uid_home = 1 标记 uid 所属分片中的那一份，计数与导出只统计这一份。
+pub fn initialize_shard(conn: &Connection) -> SqlResult<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS user_mapping (
+            uid TEXT NOT NULL,
+            phone_number TEXT NOT NULL,
+            uid_home INTEGER NOT NULL DEFAULT 1,
+            UNIQUE(uid, phone_number)
+        )",
+        (),
+    )?;
+    initialize_database(conn)
+}
+
+/// 分片模式下的写操作，在已 ATTACH 全部分片的连接上执行。语义与 [`write_mapping`] / [`delete_mappings`] 相同。
+struct ShardWriter<'a> {
+    conn: &'a Connection,
+    shards: usize,
+}
+
+impl ShardWriter<'_> {
+    fn table(&self, id: &str) -> String {
+        format!("{}.user_mapping", shard_schema(shard_of(id, self.shards)))
+    }
+    /// uid 或手机号等于 `id` 的记录，位于 `id` 所属的分片中。
+    fn find(&self, id: &str) -> SqlResult<Vec<(String, String)>> {
+        Ok(self.find_comparing(id, id)?.into_iter().map(|(uid, phone, _)| (uid, phone)).collect())
+    }
+    /// 同 `find`，另按库中保存的形式比较每条记录的号码是否等于 `phone`
+    fn find_comparing(&self, id: &str, phone: &str) -> SqlResult<Vec<(String, String, bool)>> {
+        let mut stmt = self.conn.prepare_cached(&format!("SELECT uid, phone_dec(phone_number), phone_number = phone_enc(?2) FROM {} WHERE uid = ?1 OR phone_number = phone_enc(?1)", self.table(id)))?;
+        let rows = stmt.query_map([id, phone], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+        rows.collect()
+    }
+    fn add(&self, uid: &str, phone: &str) -> SqlResult<()> {
+        self.conn.prepare_cached(&format!("INSERT INTO {} (uid, phone_number, uid_home) VALUES (?1, phone_enc(?2), 1)", self.table(uid)))?
+            .execute([uid, phone])?;
+        if shard_of(uid, self.shards) != shard_of(phone, self.shards) {
+            self.conn.prepare_cached(&format!("INSERT INTO {} (uid, phone_number, uid_home) VALUES (?1, phone_enc(?2), 0)", self.table(phone)))?
+                .execute([uid, phone])?;
+        }
+        Ok(())
+    }
+    fn remove(&self, uid: &str, phone: &str) -> SqlResult<()> {
+        self.conn.prepare_cached(&format!("DELETE FROM {} WHERE uid = ?1", self.table(uid)))?.execute([uid])?;
+        self.conn.prepare_cached(&format!("DELETE FROM {} WHERE phone_number = phone_enc(?1)", self.table(phone)))?.execute([phone])?;
+        Ok(())
+    }
+    fn write(&self, uid: &str, phone: &str, strategy: ConflictStrategy) -> SqlResult<WriteOutcome> {
+        let mut existing = self.find_comparing(uid, phone)?;
+        existing.retain(|(u, _, _)| u == uid);
+        for row in self.find_comparing(phone, phone)? {
+            if row.2 && !existing.contains(&row) {
+                existing.push(row);
+            }
+        }
+        if existing.is_empty() {
+            self.add(uid, phone)?;
+            return Ok(WriteOutcome::Inserted);
+        }
+        if existing.len() == 1 && existing[0].0 == uid && existing[0].2 {
+            return Ok(WriteOutcome::Unchanged);
+        }
+        match strategy {
+            ConflictStrategy::Skip => Ok(WriteOutcome::Skipped),
+            ConflictStrategy::Error => Ok(WriteOutcome::Conflict),
+            ConflictStrategy::Replace => {
+                for (u, p, _) in &existing {
+                    self.remove(u, p)?;
+                }
+                self.add(uid, phone)?;
+                Ok(WriteOutcome::Replaced)
+            }
+        }
+    }
+    /// 删除 uid 或手机号匹配 `id` 的记录，返回被删除的 (uid, 手机号)。
+    fn delete(&self, id: &str) -> SqlResult<Vec<(String, String)>> {
+        let rows = self.find(id)?;
+        for (uid, phone) in &rows {
+            self.remove(uid, phone)?;
+        }
+        Ok(rows)
+    }
+    fn affected_keys(&self, ids: &[&str]) -> SqlResult<Vec<String>> {
+        let mut keys: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+        for id in ids {
+            for (uid, phone) in self.find(id)? {
+                keys.push(uid);
+                keys.push(phone);
+            }
+        }
+        Ok(keys)
+    }
+}
+
+/// 按标识哈希分布到多个 SQLite 文件的后端（`storage.shards` 大于 1 时启用），用于上亿行的数据集。
+/// 单个查询只访问标识所属的分片；批量查询按分片拆分后并发执行。
+/// 写操作在一个 ATTACH 了全部分片的连接上以单个事务执行；WAL 模式下 SQLite 不保证跨文件提交的原子性，进程在提交中途崩溃时可用 `reshard` 重建。
+pub struct ShardedSqliteStore {
+    state: Weak<AppState>,
+    paths: Vec<String>,
+}
+
+impl ShardedSqliteStore {
+    pub(crate) fn new(state: Weak<AppState>, db_path: &str, shards: u32) -> Self {
+        let total = shards as usize;
+        ShardedSqliteStore { state, paths: (0..total).map(|index| shard_path(db_path, index, total)).collect() }
+    }
+    fn state(&self) -> Result<Arc<AppState>, AppError> {
+        self.state.upgrade().ok_or_else(|| AppError::FatalError(tr!("应用状态已释放", "Application state has been dropped").to_string()))
+    }
+    fn shard(&self, id: &str) -> usize {
+        shard_of(id, self.paths.len())
+    }
+    /// 在分片 0 的连接上 ATTACH 全部分片后执行 `op`。
+    async fn on_all<T, F>(&self, mut op: F) -> Result<T, AppError>
+    where
+        T: Send + 'static,
+        F: FnMut(ShardWriter) -> SqlResult<T> + Send + 'static,
+    {
+        let paths = self.paths.clone();
+        run_db_at(self.state()?, Some(paths[0].clone()), move |conn| {
+            attach_shards(conn, &paths)?;
+            op(ShardWriter { conn, shards: paths.len() })
+        }).await
+    }
+}
+
+#[axum::async_trait]
+impl MappingStore for ShardedSqliteStore {
+    fn name(&self) -> &'static str {
+        "sqlite-sharded"
+    }
+
+    async fn initialize(&self) -> Result<(), AppError> {
+        let state = self.state()?;
+        for path in &self.paths {
+            run_db_at(state.clone(), Some(path.clone()), initialize_shard).await?;
+        }
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<(), AppError> {
+        let state = self.state()?;
+        for path in &self.paths {
+            run_db_at(state.clone(), Some(path.clone()), |conn| conn.query_row("SELECT 1", [], |_| Ok(()))).await?;
+        }
+        Ok(())
+    }
+
+    async fn check_schema(&self) -> Result<(), AppError> {
+        let state = self.state()?;
+        let mut missing = Vec::new();
+        for path in &self.paths {
+            let objects = run_db_at(state.clone(), Some(path.clone()), |conn| missing_schema_objects(conn, true)).await?;
+            missing.extend(objects.into_iter().map(|name| format!("{}:{}", path, name)));
+        }
+        schema_result(missing)
+    }
+
+    async fn round_trip(&self) -> Result<(), AppError> {
+        let state = self.state()?;
+        for path in &self.paths {
+            run_db_at(state.clone(), Some(path.clone()), sqlite_round_trip).await?;
+        }
+        Ok(())
+    }
+
+    async fn lookup(&self, id: &str) -> Result<LookupResponse, AppError> {
+        let path = self.paths[self.shard(id)].clone();
+        let id = id.to_string();
+        run_db_at(self.state()?, Some(path), move |conn| lookup_one(conn, &id)).await
+    }
+
+    async fn batch_lookup(&self, ids: Vec<String>) -> Result<Vec<LookupResponse>, AppError> {
+        let state = self.state()?;
+        let config = state.current_config();
+        let mut per_shard: Vec<(Vec<usize>, Vec<String>)> = vec![(Vec::new(), Vec::new()); self.paths.len()];
+        for (position, id) in ids.into_iter().enumerate() {
+            let (positions, shard_ids) = &mut per_shard[self.shard(&id)];
+            positions.push(position);
+            shard_ids.push(id);
+        }
+        let total: usize = per_shard.iter().map(|(positions, _)| positions.len()).sum();
+        let handles: Vec<_> = per_shard.into_iter().enumerate()
+            .filter(|(_, (positions, _))| !positions.is_empty())
+            .map(|(index, (positions, shard_ids))| {
+                let task = batch_lookup(state.clone(), Some(self.paths[index].clone()), shard_ids, config.batch_chunk_size as usize, config.batch_parallelism as usize);
+                (positions, AbortOnDrop(spawn_with_deadline(task)))
+            })
+            .collect();
+        let mut results = vec![LookupResponse::not_found(); total];
+        for (positions, mut handle) in handles {
+            let part = (&mut handle.0).await.map_err(|e| AppError::FatalError(tr!("查询任务异常终止: {}", "Query task aborted: {}", e)))??;
+            for (position, result) in positions.into_iter().zip(part) {
+                results[position] = result;
+            }
+        }
+        Ok(results)
+    }
+
+    async fn insert(&self, mappings: Vec<(String, String)>, strategy: ConflictStrategy, dry_run: bool) -> Result<WriteReport, AppError> {
+        let mappings = Arc::new(mappings);
+        let (affected_keys, result) = self.on_all(move |writer| {
+            let ids: Vec<&str> = mappings.iter().flat_map(|(uid, phone)| [uid.as_str(), phone.as_str()]).collect();
+            let keys = writer.affected_keys(&ids).ok();
+            let tx = writer.conn.unchecked_transaction()?;
+            let mut summary = WriteSummary::default();
+            for (uid, phone) in mappings.iter() {
+                match writer.write(uid, phone, strategy)? {
+                    WriteOutcome::Conflict if !dry_run => return Ok((keys, Err(conflict_error(uid, phone)))),
+                    outcome => summary.record(outcome),
+                }
+            }
+            if !dry_run {
+                tx.commit()?;
+            }
+            Ok((keys, Ok(summary)))
+        }).await?;
+        Ok(WriteReport { summary: result?, affected_keys })
+    }
+
+    async fn delete(&self, ids: Vec<String>, dry_run: bool) -> Result<DeleteReport, AppError> {
+        self.on_all(move |writer| {
+            let refs: Vec<&str> = ids.iter().map(String::as_str).collect();
+            let affected_keys = writer.affected_keys(&refs).ok();
+            let tx = writer.conn.unchecked_transaction()?;
+            let mut records = Vec::new();
+            for id in &ids {
+                records.extend(writer.delete(id)?);
+            }
+            if !dry_run {
+                tx.commit()?;
+            }
+            Ok(DeleteReport { deleted: records.len(), records, affected_keys })
+        }).await
+    }
+
+    async fn count(&self, uid_prefix: Option<String>) -> Result<u64, AppError> {
+        let (filter, params) = uid_prefix_filter(uid_prefix);
+        let state = self.state()?;
+        let mut total = 0;
+        for path in &self.paths {
+            let params = params.clone();
+            total += run_db_at(state.clone(), Some(path.clone()), move |conn| {
+                conn.query_row(&format!("SELECT COUNT(*) FROM user_mapping WHERE uid_home = 1 AND {}", filter), rusqlite::params_from_iter(&params), |row| row.get::<_, i64>(0))
+            }).await? as u64;
+        }
+        Ok(total)
+    }
+
+    /// 依次导出各分片，分片内按写入顺序。
+    async fn export(&self, uid_prefix: Option<String>, mut sink: ExportSink) -> Result<u64, AppError> {
+        let (filter, params) = uid_prefix_filter(uid_prefix);
+        let shards = self.paths.len();
+        self.on_all(move |writer| export_shards(writer.conn, shards, filter, &params, &mut sink)).await?.map_err(AppError::IoError)
+    }
+
+    async fn export_snapshot(&self, uid_prefix: Option<String>, on_snapshot: SnapshotHook, mut sink: ExportSink) -> Result<u64, AppError> {
+        let (filter, params) = uid_prefix_filter(uid_prefix);
+        let shards = self.paths.len();
+        let mut on_snapshot = Some(on_snapshot);
+        self.on_all(move |writer| {
+            // 各分片文件的读快照在事务中第一次读取它时建立：用一条语句同时计数全部分片，使它们对应同一时刻
+            let tx = writer.conn.unchecked_transaction()?;
+            let counts: Vec<String> = (0..shards)
+                .map(|index| format!("(SELECT COUNT(*) FROM {}.user_mapping WHERE uid_home = 1 AND {})", shard_schema(index), filter))
+                .collect();
+            let total = tx.query_row(&format!("SELECT {}", counts.join(" + ")), rusqlite::params_from_iter(&params), |row| row.get::<_, i64>(0))?;
+            if let Some(Err(e)) = on_snapshot.take().map(|hook| hook(total as u64)) {
+                return Ok(Err(e));
+            }
+            export_shards(&tx, shards, filter, &params, &mut sink)
+        }).await?.map_err(AppError::IoError)
+    }
+}
+
+/// 在已 ATTACH 全部分片的连接上依次导出各分片
+fn export_shards(conn: &Connection, shards: usize, filter: &str, params: &[String], sink: &mut ExportSink) -> SqlResult<io::Result<u64>> {
+    let mut exported = 0;
+    for index in 0..shards {
+        let sql = format!("SELECT uid, phone_dec(phone_number) FROM {}.user_mapping WHERE uid_home = 1 AND {} ORDER BY rowid", shard_schema(index), filter);
+        match sqlite_export_rows(conn, &sql, params, sink)? {
+            Ok(rows) => exported += rows,
+            Err(e) => return Ok(Err(e)),
+        }
+    }
+    Ok(Ok(exported))
+}
+
+/// 一次重新分片的结果
+pub(crate) struct ReshardSummary {
+    pub(crate) rows: u64,
+    pub(crate) previous: Vec<String>,       // 原布局的数据文件（保留不删）
+    pub(crate) moved_aside: Vec<PathBuf>,   // 目标位置原有、被改名保留的文件
+}
+
+/// 将数据库文件连同 WAL/SHM 改名为 `路径 + suffix`，文件不存在时返回 None。
+fn move_aside(path: &str, suffix: &str) -> io::Result<Option<PathBuf>> {
+    if !FilePath::new(path).exists() {
+        return Ok(None);
+    }
+    let moved = PathBuf::from(format!("{}{}", path, suffix));
+    fs::rename(path, &moved)?;
+    for extra in ["-wal", "-shm"] {
+        let side = format!("{}{}", path, extra);
+        if FilePath::new(&side).exists() {
+            fs::rename(&side, format!("{}{}", moved.display(), extra))?;
+        }
+    }
+    Ok(Some(moved))
+}
+
+/// 将当前布局（db_path 单文件或现有分片）中的全部映射复制到 `target` 个分片（为 1 时写回 db_path），成功后更新 `storage.shards` 并保存配置。
+/// 源文件保持不变；目标位置已有的文件先改名为 `.pre-reshard-时间戳` 保留。HTTP 服务运行时拒绝执行。
+pub(crate) fn reshard_database(state: &AppState, target: u32) -> Result<ReshardSummary, AppError> {
+    if is_server_running(state) {
+        return Err(AppError::FatalError(tr!("HTTP 服务正在运行，拒绝重新分片。请先停止服务。", "The HTTP server is running; refusing to reshard. Stop the server first.").to_string()));
+    }
+    let mut config = state.current_config();
+    if config.storage_backend() != StorageBackend::Sqlite {
+        return Err(AppError::ConfigError(tr!("重新分片仅适用于 sqlite 存储后端。", "Resharding only applies to the sqlite storage backend.").to_string()));
+    }
+    if target == 0 || target > MAX_SHARDS {
+        return Err(AppError::FatalError(tr!("分片数必须在 1 到 {} 之间。", "The shard count must be between 1 and {}.", MAX_SHARDS)));
+    }
+    let current = config.storage.shards.max(1);
+    if target == current {
+        return Err(AppError::FatalError(tr!("当前已是 {} 个分片。", "The data is already in {} shard(s).", current)));
+    }
+    let layout = |shards: u32| -> Vec<String> {
+        if shards <= 1 {
+            vec![config.db_path.clone()]
+        } else {
+            (0..shards as usize).map(|index| shard_path(&config.db_path, index, shards as usize)).collect()
+        }
+    };
+    let (sources, targets) = (layout(current), layout(target));
+    if let Some(missing) = sources.iter().find(|path| !FilePath::new(path).exists()) {
+        return Err(AppError::FatalError(tr!("数据文件不存在: {}", "Data file not found: {}", missing)));
+    }
+
+    let source = state.open_connection(&sources[0])?;
+    attach_shards(&source, &sources)?;
+    // 分片中每条映射可能有两份，只读 uid 所属分片中的那一份
+    let home = if current > 1 { "uid_home = 1" } else { "1" };
+    let mut total = 0;
+    for index in 0..sources.len() {
+        let sql = format!("SELECT COUNT(*) FROM {}.user_mapping WHERE {}", shard_schema(index), home);
+        total += source.query_row(&sql, [], |row| row.get::<_, i64>(0))? as u64;
+    }
+
+    let suffix = format!(".pre-reshard-{}", Local::now().format("%Y%m%d-%H%M%S"));
+    let mut moved_aside = Vec::new();
+    for path in &targets {
+        moved_aside.extend(move_aside(path, &suffix)?);
+    }
+    let dest = state.open_connection(&targets[0])?;
+    if target > 1 {
+        for path in &targets {
+            initialize_shard(&state.open_connection(path)?)?;
+        }
+        attach_shards(&dest, &targets)?;
+    } else {
+        initialize_database(&dest)?;
+    }
+
+    let mut progress = Progress::new(tr!("重新分片", "Resharding"), ProgressUnit::Rows, total);
+    let writer = ShardWriter { conn: &dest, shards: targets.len() };
+    let mut rows = 0;
+    dest.execute_batch("BEGIN")?;
+    for index in 0..sources.len() {
+        let sql = format!("SELECT uid, phone_dec(phone_number) FROM {}.user_mapping WHERE {} ORDER BY rowid", shard_schema(index), home);
+        let mut stmt = source.prepare(&sql)?;
+        let mut source_rows = stmt.query([])?;
+        while let Some(row) = source_rows.next()? {
+            let uid: String = row.get(0)?;
+            let phone: String = row.get(1)?;
+            if target > 1 {
+                writer.write(&uid, &phone, ConflictStrategy::Replace)?;
+            } else {
+                write_mapping(&dest, &uid, &phone, ConflictStrategy::Replace)?;
+            }
+            rows += 1;
+            progress.advance(1);
+            if rows % IMPORT_CHUNK_SIZE as u64 == 0 {
+                dest.execute_batch("COMMIT; BEGIN")?;
+            }
+        }
+    }
+    dest.execute_batch("COMMIT")?;
+    progress.finish();
+    drop(dest);
+    drop(source);
+
+    config.storage.shards = target;
+    save_config(&config)?;
+    state.set_config(config);
+    state.reset_pool();
+    state.clear_cache();
+    Ok(ReshardSummary { rows, previous: sources, moved_aside })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shard_of_is_stable() {
+        // 分布写死在已有的分片文件中，这些值不能改变
+        assert_eq!(shard_of("1001", 4), 3);
+        assert_eq!(shard_of("1002", 4), 2);
+        assert_eq!(shard_of("13800000001", 4), 0);
+        assert_eq!(shard_of("13800000002", 16), 5);
+        assert_eq!(shard_of("user-42", 16), 11);
+        assert_eq!(shard_of("1001", 1), 0);
+        assert_eq!(shard_of("1001", 0), 0);
+    }
+
+    /// 4 个分片的共享内存库，返回分片 0 的连接（已 ATTACH 其余分片）与保持其余分片存活的连接
+    fn shard_db() -> (Connection, Vec<Connection>) {
+        let id = Uuid::new_v4();
+        let paths: Vec<String> = (0..4).map(|index| format!("file:shard-{}-{}?mode=memory&cache=shared", id, index)).collect();
+        let shards: Vec<Connection> = paths.iter().map(|path| {
+            let conn = Connection::open(path).unwrap();
+            initialize_shard(&conn).unwrap();
+            conn
+        }).collect();
+        let conn = Connection::open(&paths[0]).unwrap();
+        register_phone_functions(&conn, None).unwrap();
+        attach_shards(&conn, &paths).unwrap();
+        (conn, shards)
+    }
+
+    /// 第 `index` 个分片中的 (uid, 手机号, uid_home)
+    fn shard_rows(conn: &Connection, index: usize) -> Vec<(String, String, i64)> {
+        let mut stmt = conn.prepare(&format!("SELECT uid, phone_number, uid_home FROM {}.user_mapping ORDER BY uid", shard_schema(index))).unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap().map(Result::unwrap).collect()
+    }
+
+    fn row(uid: &str, phone: &str, uid_home: i64) -> (String, String, i64) {
+        (uid.to_string(), phone.to_string(), uid_home)
+    }
+
+    #[test]
+    fn shard_writer_routes_copies_by_uid_and_phone() {
+        let (conn, _shards) = shard_db();
+        let writer = ShardWriter { conn: &conn, shards: 4 };
+        // 1001 属于分片 3，13800000001 属于分片 0：两份；1002 与 13800000003 同属分片 2：一份
+        assert_eq!(writer.write("1001", "13800000001", ConflictStrategy::Error).unwrap(), WriteOutcome::Inserted);
+        assert_eq!(writer.write("1002", "13800000003", ConflictStrategy::Error).unwrap(), WriteOutcome::Inserted);
+        assert_eq!(shard_rows(&conn, 0), [row("1001", "13800000001", 0)]);
+        assert_eq!(shard_rows(&conn, 1), []);
+        assert_eq!(shard_rows(&conn, 2), [row("1002", "13800000003", 1)]);
+        assert_eq!(shard_rows(&conn, 3), [row("1001", "13800000001", 1)]);
+        assert_eq!(writer.find("13800000001").unwrap(), [("1001".to_string(), "13800000001".to_string())]);
+        assert_eq!(writer.write("1001", "13800000001", ConflictStrategy::Error).unwrap(), WriteOutcome::Unchanged);
+    }
+
+    #[test]
+    fn shard_writer_conflict_strategies() {
+        let (conn, _shards) = shard_db();
+        let writer = ShardWriter { conn: &conn, shards: 4 };
+        writer.write("1001", "13800000001", ConflictStrategy::Error).unwrap();
+        assert_eq!(writer.write("1001", "13800000002", ConflictStrategy::Skip).unwrap(), WriteOutcome::Skipped);
+        assert_eq!(writer.write("1001", "13800000002", ConflictStrategy::Error).unwrap(), WriteOutcome::Conflict);
+        assert_eq!(shard_rows(&conn, 1), []);
+
+        // 替换时旧记录在两个分片中的副本都被删除
+        assert_eq!(writer.write("1001", "13800000002", ConflictStrategy::Replace).unwrap(), WriteOutcome::Replaced);
+        assert_eq!(shard_rows(&conn, 0), []);
+        assert_eq!(shard_rows(&conn, 1), [row("1001", "13800000002", 0)]);
+        assert_eq!(shard_rows(&conn, 3), [row("1001", "13800000002", 1)]);
+
+        assert_eq!(writer.delete("13800000002").unwrap(), [("1001".to_string(), "13800000002".to_string())]);
+        assert!((0..4).all(|index| shard_rows(&conn, index).is_empty()));
+    }
+}
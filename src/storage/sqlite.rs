@@ -0,0 +1,368 @@
+//! SQLite 后端：单条写入的冲突处理、写入队列 (group commit) 与 [`SqliteStore`]。
+
+use crate::*;
+
+/// 按冲突策略写入一条映射。冲突指 uid 或手机号已出现在另一条不同的记录中。
+/// 号码按库中保存的形式比较（哈希模式下无法解密还原），重复导入相同记录时才能判定为未变化。
+pub(crate) fn write_mapping(conn: &Connection, uid: &str, phone: &str, strategy: ConflictStrategy) -> SqlResult<WriteOutcome> {
+    let mut stmt = conn.prepare_cached("SELECT uid, phone_number = phone_enc(?2) FROM user_mapping WHERE uid = ?1 OR phone_number = phone_enc(?2)")?;
+    let existing: Vec<(String, bool)> = stmt.query_map([uid, phone], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<SqlResult<_>>()?;
+    if existing.is_empty() {
+        conn.prepare_cached("INSERT INTO user_mapping (uid, phone_number) VALUES (?1, phone_enc(?2))")?.execute([uid, phone])?;
+        return Ok(WriteOutcome::Inserted);
+    }
+    if existing.len() == 1 && existing[0].0 == uid && existing[0].1 {
+        return Ok(WriteOutcome::Unchanged);
+    }
+    match strategy {
+        ConflictStrategy::Skip => Ok(WriteOutcome::Skipped),
+        ConflictStrategy::Error => Ok(WriteOutcome::Conflict),
+        ConflictStrategy::Replace => {
+            conn.prepare_cached("INSERT OR REPLACE INTO user_mapping (uid, phone_number) VALUES (?1, phone_enc(?2))")?.execute([uid, phone])?;
+            Ok(WriteOutcome::Replaced)
+        }
+    }
+}
+
+/// 原子地删除所有 uid 或手机号匹配 `ids` 的记录，返回被删除的 (uid, 手机号)。
+/// `dry_run` 时回滚，只返回将被删除的记录。使用保存点而非事务，以便在 db-manage 的 `begin` 会话中嵌套执行。
+pub(crate) fn delete_mappings(conn: &Connection, ids: &[String], dry_run: bool) -> SqlResult<Vec<(String, String)>> {
+    conn.execute_batch("SAVEPOINT delete_mappings")?;
+    let result = (|| {
+        let mut deleted = Vec::new();
+        let mut stmt = conn.prepare("DELETE FROM user_mapping WHERE uid = ?1 OR phone_number = phone_enc(?1) RETURNING uid, phone_dec(phone_number)")?;
+        for id in ids {
+            for row in stmt.query_map([id], |row| Ok((row.get(0)?, row.get(1)?)))? {
+                deleted.push(row?);
+            }
+        }
+        Ok(deleted)
+    })();
+    if result.is_err() || dry_run {
+        conn.execute_batch("ROLLBACK TO delete_mappings; RELEASE delete_mappings")?;
+    } else {
+        conn.execute_batch("RELEASE delete_mappings")?;
+    }
+    result
+}
+
+// --- 写入队列 (group commit) ---
+
+/// 排队等待合并提交的一个写请求
+pub(crate) struct QueuedWrite {
+    mappings: Vec<(String, String)>,
+    strategy: ConflictStrategy,
+    reply: tokio::sync::oneshot::Sender<Result<WriteReport, AppError>>,
+}
+
+/// 写入队列的累计情况 (/stats)
+#[derive(Default)]
+pub(crate) struct WriteQueueCounters {
+    pub(crate) groups: AtomicU64,               // 提交的事务数
+    pub(crate) writes: AtomicU64,               // 经队列完成的写请求数
+    pub(crate) rows: AtomicU64,
+    pub(crate) fallbacks: AtomicU64,            // 合并提交失败、改为逐个提交的次数
+}
+
+/// 经写入队列写入：与同时到达的其他写请求合并为一个事务，提交后才返回。
+/// 调用方放弃等待（如请求超时）时，已入队的写入仍会提交。
+async fn queued_insert(state: &Arc<AppState>, mappings: Vec<(String, String)>, strategy: ConflictStrategy) -> Result<WriteReport, AppError> {
+    let sender = state.write_queue.get_or_init(|| {
+        let (sender, receiver) = tokio::sync::mpsc::channel(state.current_config().write_queue.capacity);
+        tokio::spawn(run_write_queue(Arc::downgrade(state), receiver));
+        sender
+    });
+    let (reply, response) = tokio::sync::oneshot::channel();
+    let closed = || AppError::FatalError(tr!("写入队列已停止", "The write queue has stopped").to_string());
+    sender.send(QueuedWrite { mappings, strategy, reply }).await.map_err(|_| closed())?;
+    response.await.map_err(|_| closed())?
+}
+
+/// 写入队列的工作任务：取出第一个请求后，在 max_delay_ms 内继续收集，直到凑满 max_batch_rows 行，然后一起提交。
+/// 只持有弱引用，AppState 释放后队列的发送端随之关闭，任务退出。
+async fn run_write_queue(state: Weak<AppState>, mut receiver: tokio::sync::mpsc::Receiver<QueuedWrite>) {
+    while let Some(first) = receiver.recv().await {
+        let Some(state) = state.upgrade() else {
+            return;
+        };
+        let config = state.current_config().write_queue;
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(config.max_delay_ms);
+        let mut rows = first.mappings.len();
+        let mut group = vec![first];
+        while rows < config.max_batch_rows {
+            match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(write)) => {
+                    rows += write.mappings.len();
+                    group.push(write);
+                }
+                _ => break,
+            }
+        }
+        commit_write_group(&state, group).await;
+    }
+}
+
+/// 在一个事务中依次执行各写请求，每个请求一个保存点：某个请求冲突时只回滚它自己。
+/// 合并提交失败时逐个单独提交，使每个请求得到自己的结果。
+async fn commit_write_group(state: &Arc<AppState>, group: Vec<QueuedWrite>) {
+    let stats = &state.write_queue_stats;
+    let (requests, replies): (Vec<_>, Vec<_>) = group.into_iter().map(|write| ((write.mappings, write.strategy), write.reply)).unzip();
+    let rows: usize = requests.iter().map(|(mappings, _)| mappings.len()).sum();
+    let requests = Arc::new(requests);
+    let batch = requests.clone();
+    let committed = run_db(state.clone(), move |conn| {
+        let tx = conn.unchecked_transaction()?;
+        let mut results = Vec::with_capacity(batch.len());
+        for (mappings, strategy) in batch.iter() {
+            let ids: Vec<&str> = mappings.iter().flat_map(|(uid, phone)| [uid.as_str(), phone.as_str()]).collect();
+            let keys = affected_keys(&tx, &ids).ok().flatten();
+            tx.execute_batch("SAVEPOINT queued_write")?;
+            let mut summary = WriteSummary::default();
+            let mut conflict = None;
+            for (uid, phone) in mappings {
+                match write_mapping(&tx, uid, phone, *strategy)? {
+                    WriteOutcome::Conflict => {
+                        conflict = Some((uid.clone(), phone.clone()));
+                        break;
+                    }
+                    outcome => summary.record(outcome),
+                }
+            }
+            match conflict {
+                Some(pair) => {
+                    tx.execute_batch("ROLLBACK TO queued_write; RELEASE queued_write")?;
+                    results.push(Err(pair));
+                }
+                None => {
+                    tx.execute_batch("RELEASE queued_write")?;
+                    results.push(Ok(WriteReport { summary, affected_keys: keys }));
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(results)
+    }).await;
+    stats.groups.fetch_add(1, Ordering::Relaxed);
+    match committed {
+        Ok(results) => {
+            stats.writes.fetch_add(replies.len() as u64, Ordering::Relaxed);
+            stats.rows.fetch_add(rows as u64, Ordering::Relaxed);
+            for (reply, result) in replies.into_iter().zip(results) {
+                let _ = reply.send(result.map_err(|(uid, phone)| conflict_error(&uid, &phone)));
+            }
+        }
+        Err(e) if replies.len() == 1 => {
+            stats.writes.fetch_add(1, Ordering::Relaxed);
+            if let Some(reply) = replies.into_iter().next() {
+                let _ = reply.send(Err(e));
+            }
+        }
+        Err(e) => {
+            stats.fallbacks.fetch_add(1, Ordering::Relaxed);
+            log_debug("cyber_lookup::write_queue", &format!("group of {} writes failed, committing them one by one: {}", replies.len(), e));
+            let requests = Arc::try_unwrap(requests).unwrap_or_else(|requests| (*requests).clone());
+            for ((mappings, strategy), reply) in requests.into_iter().zip(replies) {
+                let (reply_once, response) = tokio::sync::oneshot::channel();
+                Box::pin(commit_write_group(state, vec![QueuedWrite { mappings, strategy, reply: reply_once }])).await;
+                if let Ok(result) = response.await {
+                    let _ = reply.send(result);
+                }
+            }
+        }
+    }
+}
+
+/// 默认后端：基于 [`AppState`] 的数据库工作线程，沿用其锁竞争重试、熔断与超时中断。
+/// 故障切换期间查询、计数与导出读取备用库，写入仍访问 db_path。
+/// 只持有弱引用，避免与持有它的 `AppState` 形成循环引用。
+pub struct SqliteStore {
+    pub(crate) state: Weak<AppState>,
+}
+
+impl SqliteStore {
+    fn state(&self) -> Result<Arc<AppState>, AppError> {
+        self.state.upgrade().ok_or_else(|| AppError::FatalError(tr!("应用状态已释放", "Application state has been dropped").to_string()))
+    }
+}
+
+#[axum::async_trait]
+impl MappingStore for SqliteStore {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    async fn initialize(&self) -> Result<(), AppError> {
+        run_db(self.state()?, initialize_database).await
+    }
+
+    async fn ping(&self) -> Result<(), AppError> {
+        run_db(self.state()?, |conn| conn.query_row("SELECT 1", [], |_| Ok(()))).await
+    }
+
+    async fn check_schema(&self) -> Result<(), AppError> {
+        schema_result(run_db(self.state()?, |conn| missing_schema_objects(conn, false)).await?)
+    }
+
+    async fn round_trip(&self) -> Result<(), AppError> {
+        run_db(self.state()?, sqlite_round_trip).await
+    }
+
+    async fn lookup(&self, id: &str) -> Result<LookupResponse, AppError> {
+        let state = self.state()?;
+        let id = id.to_string();
+        run_db_at(state.clone(), state.read_path(), move |conn| lookup_one(conn, &id)).await
+    }
+
+    async fn batch_lookup(&self, ids: Vec<String>) -> Result<Vec<LookupResponse>, AppError> {
+        let state = self.state()?;
+        let config = state.current_config();
+        batch_lookup(state.clone(), state.read_path(), ids, config.batch_chunk_size as usize, config.batch_parallelism as usize).await
+    }
+
+    async fn insert(&self, mappings: Vec<(String, String)>, strategy: ConflictStrategy, dry_run: bool) -> Result<WriteReport, AppError> {
+        let state = self.state()?;
+        if state.current_config().write_queue.enabled && !dry_run {
+            return queued_insert(&state, mappings, strategy).await;
+        }
+        let mappings = Arc::new(mappings);
+        let (affected_keys, result) = run_db(state, move |conn| {
+            let ids: Vec<&str> = mappings.iter().flat_map(|(uid, phone)| [uid.as_str(), phone.as_str()]).collect();
+            let keys = affected_keys(conn, &ids).ok().flatten();
+            let tx = conn.unchecked_transaction()?;
+            let mut summary = WriteSummary::default();
+            for (uid, phone) in mappings.iter() {
+                match write_mapping(&tx, uid, phone, strategy)? {
+                    WriteOutcome::Conflict if !dry_run => return Ok((keys, Err(conflict_error(uid, phone)))),
+                    outcome => summary.record(outcome),
+                }
+            }
+            if !dry_run {
+                tx.commit()?;
+            }
+            Ok((keys, Ok(summary)))
+        }).await?;
+        Ok(WriteReport { summary: result?, affected_keys })
+    }
+
+    async fn delete(&self, ids: Vec<String>, dry_run: bool) -> Result<DeleteReport, AppError> {
+        run_db(self.state()?, move |conn| {
+            let refs: Vec<&str> = ids.iter().map(String::as_str).collect();
+            let affected_keys = affected_keys(conn, &refs).ok().flatten();
+            let records = delete_mappings(conn, &ids, dry_run)?;
+            Ok(DeleteReport { deleted: records.len(), records, affected_keys })
+        }).await
+    }
+
+    async fn count(&self, uid_prefix: Option<String>) -> Result<u64, AppError> {
+        let (filter, params) = uid_prefix_filter(uid_prefix);
+        let state = self.state()?;
+        run_db_at(state.clone(), state.read_path(), move |conn| {
+            conn.query_row(&format!("SELECT COUNT(*) FROM user_mapping WHERE {}", filter), rusqlite::params_from_iter(&params), |row| row.get::<_, i64>(0))
+        }).await.map(|count| count as u64)
+    }
+
+    async fn export(&self, uid_prefix: Option<String>, mut sink: ExportSink) -> Result<u64, AppError> {
+        let (filter, params) = uid_prefix_filter(uid_prefix);
+        let state = self.state()?;
+        run_db_at(state.clone(), state.read_path(), move |conn| {
+            sqlite_export_rows(conn, &format!("SELECT uid, phone_dec(phone_number) FROM user_mapping WHERE {} ORDER BY rowid", filter), &params, &mut sink)
+        }).await?.map_err(AppError::IoError)
+    }
+
+    async fn export_snapshot(&self, uid_prefix: Option<String>, on_snapshot: SnapshotHook, mut sink: ExportSink) -> Result<u64, AppError> {
+        let (filter, params) = uid_prefix_filter(uid_prefix);
+        let state = self.state()?;
+        let mut on_snapshot = Some(on_snapshot);
+        run_db_at(state.clone(), state.read_path(), move |conn| {
+            // 读事务在第一次读取（计数）时建立快照，之后提交的写入对本次导出不可见；只读，结束时直接回滚
+            let tx = conn.unchecked_transaction()?;
+            let total = tx.query_row(&format!("SELECT COUNT(*) FROM user_mapping WHERE {}", filter), rusqlite::params_from_iter(&params), |row| row.get::<_, i64>(0))?;
+            if let Some(Err(e)) = on_snapshot.take().map(|hook| hook(total as u64)) {
+                return Ok(Err(e));
+            }
+            sqlite_export_rows(&tx, &format!("SELECT uid, phone_dec(phone_number) FROM user_mapping WHERE {} ORDER BY rowid", filter), &params, &mut sink)
+        }).await?.map_err(AppError::IoError)
+    }
+
+    async fn merge(&self, groups: Vec<MergeGroup>) -> Result<MergeReport, AppError> {
+        run_db(self.state()?, move |conn| {
+            let tx = conn.unchecked_transaction()?;
+            let merged = match merge_groups(&tx, &groups)? {
+                Ok(merged) => merged,
+                Err(e) => return Ok(Err(e)),
+            };
+            tx.commit()?;
+            Ok(Ok(MergeReport { groups: groups.len(), merged, affected_keys: merge_affected_keys(&groups) }))
+        }).await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        register_phone_functions(&conn, None).unwrap();
+        initialize_database(&conn).unwrap();
+        conn.execute("INSERT INTO user_mapping (uid, phone_number) VALUES ('1001', '13800000001')", []).unwrap();
+        conn
+    }
+
+    fn rows(conn: &Connection) -> Vec<(String, String)> {
+        let mut stmt = conn.prepare("SELECT uid, phone_number FROM user_mapping ORDER BY uid").unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap().map(Result::unwrap).collect()
+    }
+
+    fn pair(uid: &str, phone: &str) -> (String, String) {
+        (uid.to_string(), phone.to_string())
+    }
+
+    #[test]
+    fn write_mapping_outcomes_by_strategy() {
+        use ConflictStrategy::*;
+        for strategy in [Skip, Replace, Error] {
+            let conn = mapping_db();
+            assert_eq!(write_mapping(&conn, "1002", "13800000002", strategy).unwrap(), WriteOutcome::Inserted);
+            assert_eq!(write_mapping(&conn, "1001", "13800000001", strategy).unwrap(), WriteOutcome::Unchanged);
+            let expected = match strategy {
+                Skip => WriteOutcome::Skipped,
+                Replace => WriteOutcome::Replaced,
+                Error => WriteOutcome::Conflict,
+            };
+            // uid 已存在但号码不同
+            assert_eq!(write_mapping(&conn, "1001", "13800000009", strategy).unwrap(), expected, "{:?}", strategy);
+            match strategy {
+                Replace => assert_eq!(rows(&conn), [pair("1001", "13800000009"), pair("1002", "13800000002")]),
+                _ => assert_eq!(rows(&conn), [pair("1001", "13800000001"), pair("1002", "13800000002")]),
+            }
+        }
+    }
+
+    #[test]
+    fn write_mapping_replace_removes_every_conflicting_row() {
+        let conn = mapping_db();
+        write_mapping(&conn, "1002", "13800000002", ConflictStrategy::Replace).unwrap();
+        // uid 与一条记录冲突、号码与另一条记录冲突，两条都被替换
+        assert_eq!(write_mapping(&conn, "1001", "13800000002", ConflictStrategy::Replace).unwrap(), WriteOutcome::Replaced);
+        assert_eq!(rows(&conn), [pair("1001", "13800000002")]);
+    }
+
+    #[test]
+    fn uncommitted_write_and_dry_run_delete_roll_back() {
+        let conn = mapping_db();
+        {
+            let tx = conn.unchecked_transaction().unwrap();
+            assert_eq!(write_mapping(&tx, "1002", "13800000002", ConflictStrategy::Replace).unwrap(), WriteOutcome::Inserted);
+            assert_eq!(write_mapping(&tx, "1001", "13800000003", ConflictStrategy::Replace).unwrap(), WriteOutcome::Replaced);
+        }
+        assert_eq!(rows(&conn), [pair("1001", "13800000001")]);
+
+        let ids = ["13800000001".to_string()];
+        assert_eq!(delete_mappings(&conn, &ids, true).unwrap(), [pair("1001", "13800000001")]);
+        assert_eq!(rows(&conn).len(), 1);
+        assert_eq!(delete_mappings(&conn, &ids, false).unwrap(), [pair("1001", "13800000001")]);
+        assert!(rows(&conn).is_empty());
+    }
+}